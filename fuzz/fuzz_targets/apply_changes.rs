@@ -0,0 +1,47 @@
+//! Apply arbitrary content changes, in any position encoding, to arbitrary text and parse the
+//! result the way the server does after a `didChange`. Errors are fine; panics are not.
+
+#![no_main]
+
+use libfuzzer_sys::{arbitrary::Arbitrary, fuzz_target};
+use merge_conflict_assistant::{parser::PositionEncoding, state::DocumentState};
+
+#[derive(Arbitrary, Debug)]
+struct Change {
+    range: Option<((u32, u32), (u32, u32))>,
+    text: String,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    language_id: String,
+    text: String,
+    encoding: u8,
+    changes: Vec<Change>,
+}
+
+fuzz_target!(|input: Input| {
+    let encoding = [
+        PositionEncoding::Utf8,
+        PositionEncoding::Utf16,
+        PositionEncoding::Utf32,
+    ][usize::from(input.encoding % 3)];
+    let mut document_state =
+        DocumentState::new(input.language_id, input.text, 0).with_encoding(encoding);
+    let position = |(line, character)| lsp_types::Position { line, character };
+    let changes: Vec<_> = input
+        .changes
+        .into_iter()
+        .map(|change| lsp_types::TextDocumentContentChangeEvent {
+            range: change.range.map(|(start, end)| lsp_types::Range {
+                start: position(start),
+                end: position(end),
+            }),
+            range_length: None,
+            text: change.text,
+        })
+        .collect();
+    if document_state.apply_changes(&changes, 1).is_ok() {
+        let _ = document_state.process_update();
+    }
+});
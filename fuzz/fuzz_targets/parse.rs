@@ -0,0 +1,64 @@
+//! Parse arbitrary text with every syntax and conflict style, and resolve each conflict found
+//! every way there is. Errors are fine; panics are not.
+
+#![no_main]
+
+use libfuzzer_sys::{arbitrary::Arbitrary, fuzz_target};
+use merge_conflict_assistant::{
+    parser::{ConflictStyle, ParseOptions, PositionEncoding, Syntax, parse_with_options},
+    resolution::{self, Choice, Resolution},
+};
+
+#[derive(Arbitrary, Debug)]
+struct Input<'a> {
+    text: &'a str,
+    syntax: u8,
+    style: u8,
+    encoding: u8,
+    marker_size: u8,
+}
+
+const RESOLUTIONS: [Resolution; 8] = [
+    Resolution::Ours,
+    Resolution::Theirs,
+    Resolution::Both,
+    Resolution::Ancestor,
+    Resolution::OursAndAncestor,
+    Resolution::AncestorAndTheirs,
+    Resolution::All,
+    Resolution::Neither,
+];
+
+fuzz_target!(|input: Input| {
+    let options = ParseOptions {
+        syntax: [Syntax::Plain, Syntax::Markdown, Syntax::ReStructuredText]
+            [usize::from(input.syntax % 3)],
+        encoding: [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ][usize::from(input.encoding % 3)],
+        style: [
+            ConflictStyle::Auto,
+            ConflictStyle::Merge,
+            ConflictStyle::Perforce,
+        ][usize::from(input.style % 3)],
+        marker_size: usize::from(input.marker_size % 16).max(1),
+    };
+    let Ok(Some(merge_conflict)) = parse_with_options(input.text, options) else {
+        return;
+    };
+    let _ = merge_conflict.diagnostics(
+        input.text,
+        "fuzz",
+        lsp_types::DiagnosticSeverity::ERROR,
+        &Default::default(),
+        &Default::default(),
+    );
+    for conflict in &merge_conflict.conflicts {
+        for resolution in RESOLUTIONS {
+            let _ = resolution::edits(input.text, conflict, resolution);
+            let _ = resolution::resolve(input.text, conflict, &Choice::Named(resolution));
+        }
+    }
+});
@@ -0,0 +1,1115 @@
+//! Parse files for conflict markers outside an editor and print what the server would see.
+//!
+//! Directories are walked, skipping what `.gitignore` and `.ignore` files exclude, hidden files
+//! and files that are not UTF-8 text. Files are read and parsed on `--jobs` threads. `-`, or no
+//! path when input is piped, reads standard input, e.g. `git show :2:notes.txt | parser_driver -`.
+//!
+//! The text format lists each conflict's lines and labels; `--format json` prints the whole
+//! [`MergeConflict`] of each file with markers, keyed by path, and `--format sarif` a SARIF 2.1.0
+//! log for code scanning dashboards. Exits with 1 when conflicts were found and 2 when a file
+//! could not be read.
+//!
+//! `--resolve` resolves every conflict with the same strategy instead, through the resolution
+//! module the server uses, and prints the result or with `--in-place` writes it back.
+//! `--fix-safe` resolves only the conflicts whose sides differ in nothing that matters, writes the
+//! files back and lists the conflicts left. `--stats` sums up how much there is to resolve.
+//!
+//! `--debug` or `RUST_LOG` directives (e.g. `merge_conflict_assistant::parser=trace`) write the
+//! library's trace output to standard error, as the server's `--log` does to its file.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::Context;
+use clap::{CommandFactory, Parser};
+use merge_conflict_assistant::{
+    git,
+    logging::log_filter,
+    parser::{
+        ConflictRegion, DEFAULT_MARKER_SIZE, MergeConflict, ParseOptions, ParseWarning, Syntax,
+        may_have_conflicts, parse_with_options,
+    },
+    resolution::{self, Choice, Resolution},
+};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+enum Format {
+    #[default]
+    Text,
+    Json,
+    Sarif,
+}
+
+/// What `--resolve` keeps of every conflict.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+enum Strategy {
+    Ours,
+    Theirs,
+    Both,
+    /// Both sides with the lines they share kept once.
+    Union,
+}
+
+impl Strategy {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Ours => "ours",
+            Self::Theirs => "theirs",
+            Self::Both => "both",
+            Self::Union => "union",
+        }
+    }
+
+    /// The text replacing `region` of `document`, marker lines included.
+    fn resolve(self, document: &str, region: &ConflictRegion) -> Option<String> {
+        let named = |resolution| {
+            resolution::resolve(document, region, &Choice::Named(resolution))
+                .map(|resolved| resolved.text)
+        };
+        match self {
+            Self::Ours => named(Resolution::Ours),
+            Self::Theirs => named(Resolution::Theirs),
+            Self::Both => named(Resolution::Both),
+            Self::Union => Some(resolution::union(document, region).text),
+        }
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+#[command(
+    name = "parser_driver",
+    version = env!("FULL_VERSION"),
+    about = "Print the merge conflicts in files the way the server finds them",
+    long_about = None
+)]
+struct ArgumentParser {
+    /// Include more debugging information.
+    #[arg(short, long)]
+    debug: bool,
+
+    /// How to print the parse result.
+    #[arg(long, value_enum, default_value_t, conflicts_with = "resolve")]
+    format: Format,
+
+    /// Resolve every conflict with this strategy and print the result instead.
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    resolve: Option<Strategy>,
+
+    /// Write the resolved files back instead of printing them.
+    #[arg(long, requires = "resolve")]
+    in_place: bool,
+
+    /// Resolve only conflicts with identical sides or sides differing only in trailing whitespace
+    /// or line endings, keeping ours, write the files back and list the conflicts left.
+    #[arg(long, conflicts_with_all = ["format", "resolve"])]
+    fix_safe: bool,
+
+    /// With --fix-safe, also resolve conflicts where one side is blank to that side, accepting
+    /// the deletion.
+    #[arg(long, requires = "fix_safe")]
+    allow_deletions: bool,
+
+    /// Print per-file and total numbers of conflicts, conflicted lines and branches instead.
+    #[arg(long, conflicts_with_all = ["format", "resolve", "fix_safe"])]
+    stats: bool,
+
+    /// Parse files on this many threads. 0 picks one per CPU.
+    #[arg(short, long, value_name = "N", default_value_t = 0)]
+    jobs: usize,
+
+    /// The files to parse and directories to search for them. `-` reads standard input, as does
+    /// giving none when it is piped.
+    paths: Vec<PathBuf>,
+}
+
+/// The path standing for standard input.
+const STDIN: &str = "-";
+
+/// A file with markers, its text, how it was parsed and what parsing it found.
+type Found = (PathBuf, String, ParseOptions, MergeConflict);
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args = ArgumentParser::parse();
+
+    // Only set up a tracing subscriber when asked to, so standard error carries nothing but
+    // the files that could not be read.
+    let rust_log = env::var(tracing_subscriber::EnvFilter::DEFAULT_ENV).ok();
+    if args.debug || rust_log.is_some() {
+        tracing_subscriber::fmt::fmt()
+            .with_env_filter(log_filter(args.debug, rust_log.as_deref()))
+            .with_writer(std::io::stderr)
+            .init();
+    }
+
+    let mut paths = args.paths;
+    if paths.is_empty() {
+        if std::io::stdin().is_terminal() {
+            ArgumentParser::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "give the paths to parse, or pipe the text to standard input",
+                )
+                .exit();
+        }
+        paths.push(STDIN.into());
+    }
+    if args.fix_safe {
+        return fix_safe(&paths, args.jobs, args.allow_deletions);
+    }
+    if args.stats {
+        return stats(&paths, args.jobs);
+    }
+    match args.resolve {
+        Some(strategy) if args.in_place => resolve_in_place(&paths, args.jobs, strategy),
+        Some(strategy) => resolve_to_stdout(&paths, strategy),
+        None => report(paths, args.jobs, args.format),
+    }
+}
+
+/// Print the conflicts found in `paths` in `format`.
+fn report(paths: Vec<PathBuf>, jobs: usize, format: Format) -> anyhow::Result<ExitCode> {
+    let (stdin, paths): (Vec<_>, Vec<_>) = paths
+        .into_iter()
+        .partition(|path| path.as_os_str() == STDIN);
+    let (mut found, mut errors) = scan(&paths, jobs);
+    if !stdin.is_empty() {
+        match scan_stdin() {
+            Ok(Some(input)) => found.insert(0, input),
+            Ok(None) => {}
+            Err(error) => errors.push(error),
+        }
+    }
+    match format {
+        Format::Text => print!("{}", text_output(&found)),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&json_output(&found))?),
+        Format::Sarif => println!("{}", serde_json::to_string_pretty(&sarif_log(&found))?),
+    }
+    let conflicted = found
+        .iter()
+        .any(|(.., merge_conflict)| !merge_conflict.conflicts.is_empty());
+    Ok(exit_code(&errors, conflicted))
+}
+
+/// Resolve every conflict in the files under `paths` with `strategy` and write them back.
+fn resolve_in_place(
+    paths: &[PathBuf],
+    jobs: usize,
+    strategy: Strategy,
+) -> anyhow::Result<ExitCode> {
+    if paths.iter().any(|path| path.as_os_str() == STDIN) {
+        anyhow::bail!("standard input cannot be resolved in place");
+    }
+    let (found, mut errors) = scan(paths, jobs);
+    for (path, text, options, _) in found
+        .iter()
+        .filter(|(.., merge_conflict)| !merge_conflict.conflicts.is_empty())
+    {
+        match resolve_file(path, text, *options, strategy) {
+            Ok(resolved) => println!(
+                "{}: resolved {resolved} conflict(s) with {}",
+                path.display(),
+                strategy.name()
+            ),
+            Err(error) => errors.push(error),
+        }
+    }
+    Ok(exit_code(&errors, false))
+}
+
+/// Print the one file or standard input in `paths` with every conflict resolved with `strategy`.
+fn resolve_to_stdout(paths: &[PathBuf], strategy: Strategy) -> anyhow::Result<ExitCode> {
+    let [path] = paths else {
+        anyhow::bail!("--resolve prints a single file; use --in-place for several");
+    };
+    let (text, options) = if path.as_os_str() == STDIN {
+        (
+            read_input(std::io::stdin().lock())?,
+            ParseOptions::default(),
+        )
+    } else {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+        let options = parse_options(path, &text);
+        (text, options)
+    };
+    let (resolved, _) = resolve_text(&text, options, |document, region| {
+        strategy.resolve(document, region)
+    })?;
+    print!("{resolved}");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Resolve the conflicts in the files under `paths` that [`safe_resolution`] knows the answer
+/// to, write them back and print what was fixed and what is left.
+fn fix_safe(paths: &[PathBuf], jobs: usize, allow_deletions: bool) -> anyhow::Result<ExitCode> {
+    if paths.iter().any(|path| path.as_os_str() == STDIN) {
+        anyhow::bail!("standard input cannot be fixed in place");
+    }
+    let (found, mut errors) = scan(paths, jobs);
+    let (mut fixed, mut left) = (0, 0);
+    for (path, text, options, _) in found
+        .iter()
+        .filter(|(.., merge_conflict)| !merge_conflict.conflicts.is_empty())
+    {
+        match fix_file(path, text, *options, allow_deletions) {
+            Ok(outcome) => {
+                print!("{}", outcome.summary(path));
+                fixed += outcome.fixed.values().sum::<usize>();
+                left += outcome.left.len();
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+    println!("fixed {fixed} of {} conflict(s)", fixed + left);
+    Ok(exit_code(&errors, left > 0))
+}
+
+/// What `--fix-safe` did to a file.
+#[derive(Debug, Default, Eq, PartialEq)]
+struct FixOutcome {
+    /// How many conflicts were resolved, by reason.
+    fixed: BTreeMap<&'static str, usize>,
+    /// The conflicts left, with lines in the fixed text.
+    left: Vec<ConflictRegion>,
+}
+
+impl FixOutcome {
+    fn summary(&self, path: &Path) -> String {
+        let path = path.display();
+        let mut summary = String::new();
+        if !self.fixed.is_empty() {
+            let reasons: Vec<_> = self
+                .fixed
+                .iter()
+                .map(|(reason, count)| format!("{count} {reason}"))
+                .collect();
+            summary += &format!(
+                "{path}: fixed {} conflict(s) ({})\n",
+                self.fixed.values().sum::<usize>(),
+                reasons.join(", ")
+            );
+        }
+        for region in &self.left {
+            summary += &format!(
+                "{path}:{}: left {}\n",
+                region.head + 1,
+                conflict_message(region)
+            );
+        }
+        summary
+    }
+}
+
+/// The side resolving `region` of `document` without anyone looking at it, and why, when there
+/// is one. Where the sides differ only in trailing whitespace or line endings ours is kept, as
+/// the branch merged into formats it. Other whitespace changes, such as rewrapped lines, can
+/// matter and are left alone.
+fn safe_resolution(
+    document: &str,
+    region: &ConflictRegion,
+    allow_deletions: bool,
+) -> Option<(Resolution, &'static str)> {
+    if region.sides_identical(document) {
+        return Some((Resolution::Ours, "sides identical"));
+    }
+    if differ_only_at_line_ends(region.ours_text(document), region.theirs_text(document)) {
+        return Some((Resolution::Ours, "trailing whitespace"));
+    }
+    if !allow_deletions {
+        return None;
+    }
+    let blank = |side: &str| side.trim().is_empty();
+    if blank(region.ours_text(document)) {
+        Some((Resolution::Ours, "deletion"))
+    } else if blank(region.theirs_text(document)) {
+        Some((Resolution::Theirs, "deletion"))
+    } else {
+        None
+    }
+}
+
+/// Whether `ours` and `theirs` have the same lines once trailing whitespace, carriage returns
+/// included, is removed from each.
+fn differ_only_at_line_ends(ours: &str, theirs: &str) -> bool {
+    ours != theirs
+        && ours
+            .lines()
+            .map(str::trim_end)
+            .eq(theirs.lines().map(str::trim_end))
+}
+
+/// Resolve the conflicts in `path`, holding `text` parsed with `options`, [`safe_resolution`]
+/// knows the answer to and write it back.
+fn fix_file(
+    path: &Path,
+    text: &str,
+    options: ParseOptions,
+    allow_deletions: bool,
+) -> anyhow::Result<FixOutcome> {
+    let (fixed, outcome) = fix_text(text, options, allow_deletions)
+        .with_context(|| format!("failed to parse '{}'", path.display()))?;
+    if !outcome.fixed.is_empty() {
+        std::fs::write(path, fixed)
+            .with_context(|| format!("failed to write '{}'", path.display()))?;
+    }
+    Ok(outcome)
+}
+
+/// `text` with the conflicts [`safe_resolution`] knows the answer to resolved.
+fn fix_text(
+    text: &str,
+    options: ParseOptions,
+    allow_deletions: bool,
+) -> anyhow::Result<(String, FixOutcome)> {
+    let mut outcome = FixOutcome::default();
+    let (fixed, _) = resolve_text(text, options, |document, region| {
+        let (resolution, reason) = safe_resolution(document, region, allow_deletions)?;
+        let resolved = resolution::resolve(document, region, &Choice::Named(resolution))?;
+        *outcome.fixed.entry(reason).or_default() += 1;
+        Some(resolved.text)
+    })?;
+    outcome.left = parse_text(&fixed, options)?
+        .map(|merge_conflict| merge_conflict.conflicts)
+        .unwrap_or_default();
+    Ok((fixed, outcome))
+}
+
+/// Print [`Stats`] for each file under `paths` with conflicts, and for all of them.
+fn stats(paths: &[PathBuf], jobs: usize) -> anyhow::Result<ExitCode> {
+    let (stdin, paths): (Vec<_>, Vec<_>) = paths
+        .iter()
+        .cloned()
+        .partition(|path| path.as_os_str() == STDIN);
+    let (mut found, mut errors) = scan(&paths, jobs);
+    if !stdin.is_empty() {
+        match scan_stdin() {
+            Ok(Some(input)) => found.insert(0, input),
+            Ok(None) => {}
+            Err(error) => errors.push(error),
+        }
+    }
+    let mut total = Stats::default();
+    for (path, text, _, merge_conflict) in &found {
+        if merge_conflict.conflicts.is_empty() {
+            continue;
+        }
+        let stats = Stats::of(path, text, merge_conflict);
+        println!("{}: {}", path.display(), stats.describe());
+        total.add(stats);
+    }
+    println!("total: {}", total.describe());
+    Ok(exit_code(&errors, total.conflicts > 0))
+}
+
+/// How much resolving one or more files takes.
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Stats {
+    files: usize,
+    conflicts: usize,
+    /// Lines in conflicts, marker lines included. Nested conflicts count once, with the
+    /// conflict around them.
+    lines: usize,
+    /// The length in lines of the largest conflict, its file and 1-based first line.
+    largest: Option<(usize, PathBuf, u32)>,
+    /// Conflicts `--fix-safe` resolves without `--allow-deletions`.
+    trivial: usize,
+    branches: BTreeSet<String>,
+}
+
+impl Stats {
+    fn of(path: &Path, text: &str, merge_conflict: &MergeConflict) -> Self {
+        let mut stats = Self {
+            files: 1,
+            conflicts: merge_conflict.conflicts.len(),
+            ..Self::default()
+        };
+        for region in &merge_conflict.conflicts {
+            let names = [
+                merge_conflict.head_name_for(region),
+                merge_conflict.branch_name_for(region),
+            ];
+            stats
+                .branches
+                .extend(names.into_iter().flatten().map(str::to_owned));
+            if safe_resolution(text, region, false).is_some() {
+                stats.trivial += 1;
+            }
+            if region.depth > 0 {
+                continue;
+            }
+            let lines = (region.end - region.head + 1) as usize;
+            stats.lines += lines;
+            if stats
+                .largest
+                .as_ref()
+                .is_none_or(|(largest, ..)| lines > *largest)
+            {
+                stats.largest = Some((lines, path.to_path_buf(), region.head + 1));
+            }
+        }
+        stats
+    }
+
+    fn add(&mut self, other: Self) {
+        self.files += other.files;
+        self.conflicts += other.conflicts;
+        self.lines += other.lines;
+        if let Some((lines, ..)) = other.largest
+            && self
+                .largest
+                .as_ref()
+                .is_none_or(|(largest, ..)| lines > *largest)
+        {
+            self.largest = other.largest;
+        }
+        self.trivial += other.trivial;
+        self.branches.extend(other.branches);
+    }
+
+    fn describe(&self) -> String {
+        let mut description = format!(
+            "{} conflict(s) in {} file(s), {} line(s), {} trivially resolvable",
+            self.conflicts, self.files, self.lines, self.trivial
+        );
+        if let Some((lines, path, line)) = &self.largest {
+            description += &format!(", largest {lines} line(s) at {}:{line}", path.display());
+        }
+        if !self.branches.is_empty() {
+            let branches: Vec<_> = self.branches.iter().map(String::as_str).collect();
+            description += &format!(", branches {}", branches.join(", "));
+        }
+        description
+    }
+}
+
+/// 2 when there were errors, 1 when conflicts are left and 0 otherwise.
+fn exit_code(errors: &[anyhow::Error], conflicted: bool) -> ExitCode {
+    for error in errors {
+        eprintln!("{error:#}");
+    }
+    if !errors.is_empty() {
+        ExitCode::from(2)
+    } else if conflicted {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Parse every file under `paths` on `jobs` threads. Returns the files with markers, sorted by
+/// path, and the errors met on the way.
+fn scan(paths: &[PathBuf], jobs: usize) -> (Vec<Found>, Vec<anyhow::Error>) {
+    let Some((first, rest)) = paths.split_first() else {
+        return (Vec::new(), Vec::new());
+    };
+    let mut walk = ignore::WalkBuilder::new(first);
+    for path in rest {
+        walk.add(path);
+    }
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    walk.threads(jobs).build_parallel().run(|| {
+        let sender = sender.clone();
+        Box::new(move |entry| {
+            let parsed = entry.map_err(anyhow::Error::from).and_then(|entry| {
+                if !entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_file())
+                {
+                    return Ok(None);
+                }
+                let path = entry.into_path();
+                Ok(parse_file(&path)?
+                    .map(|(text, options, merge_conflict)| (path, text, options, merge_conflict)))
+            });
+            if let Some(parsed) = parsed.transpose() {
+                let _ = sender.send(parsed);
+            }
+            ignore::WalkState::Continue
+        })
+    });
+    drop(sender);
+    let (mut found, mut errors) = (Vec::new(), Vec::new());
+    for parsed in receiver {
+        match parsed {
+            Ok(file) => found.push(file),
+            Err(error) => errors.push(error),
+        }
+    }
+    found.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+    (found, errors)
+}
+
+/// Parse `path` the way the server parses an open document, returning its text and how it was
+/// parsed with what was found. `None` when it has no markers or is not UTF-8 text.
+fn parse_file(path: &Path) -> anyhow::Result<Option<(String, ParseOptions, MergeConflict)>> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("failed to read '{}'", path.display()))?;
+    let Ok(text) = String::from_utf8(bytes) else {
+        return Ok(None);
+    };
+    let options = parse_options(path, &text);
+    let merge_conflict = parse_text(&text, options)
+        .with_context(|| format!("failed to parse '{}'", path.display()))?;
+    Ok(merge_conflict.map(|merge_conflict| (text, options, merge_conflict)))
+}
+
+/// Standard input parsed as plain text, like a file [`scan`] finds. `None` when it has no
+/// markers.
+fn scan_stdin() -> anyhow::Result<Option<Found>> {
+    let text = read_input(std::io::stdin().lock())?;
+    let options = ParseOptions::default();
+    let merge_conflict = parse_text(&text, options).context("failed to parse standard input")?;
+    Ok(merge_conflict.map(|merge_conflict| (STDIN.into(), text, options, merge_conflict)))
+}
+
+/// All of `input`, e.g. standard input. Unlike files it must be UTF-8.
+fn read_input(mut input: impl std::io::Read) -> anyhow::Result<String> {
+    let mut text = String::new();
+    input
+        .read_to_string(&mut text)
+        .context("failed to read standard input")?;
+    Ok(text)
+}
+
+/// Resolve `path`, holding `text` parsed with `options`, with `strategy` and write it back.
+/// Returns how many conflicts it had.
+fn resolve_file(
+    path: &Path,
+    text: &str,
+    options: ParseOptions,
+    strategy: Strategy,
+) -> anyhow::Result<usize> {
+    let (resolved, count) = resolve_text(text, options, |document, region| {
+        strategy.resolve(document, region)
+    })
+    .with_context(|| format!("failed to parse '{}'", path.display()))?;
+    std::fs::write(path, resolved)
+        .with_context(|| format!("failed to write '{}'", path.display()))?;
+    Ok(count)
+}
+
+/// `text` with every conflict `resolve` gives text for replaced by it, and how many there were.
+/// Conflicts nested in others go first, then the text is parsed again for the ones around them.
+fn resolve_text(
+    text: &str,
+    options: ParseOptions,
+    mut resolve: impl FnMut(&str, &ConflictRegion) -> Option<String>,
+) -> anyhow::Result<(String, usize)> {
+    let mut text = text.to_owned();
+    let mut count = 0;
+    while let Some(merge_conflict) = parse_text(&text, options)? {
+        let (resolved, replaced) =
+            resolution::splice(&text, &merge_conflict, |region| resolve(&text, region));
+        if replaced == 0 {
+            break;
+        }
+        text = resolved;
+        count += replaced;
+    }
+    Ok((text, count))
+}
+
+fn parse_text(text: &str, options: ParseOptions) -> anyhow::Result<Option<MergeConflict>> {
+    if !may_have_conflicts(text, options.marker_size) {
+        return Ok(None);
+    }
+    Ok(parse_with_options(text, options)?)
+}
+
+/// How the server would parse `path` holding `text`: in the syntax an editor would likely
+/// pick, with markers as long as git's `conflict-marker-size` attribute makes them.
+fn parse_options(path: &Path, text: &str) -> ParseOptions {
+    ParseOptions {
+        syntax: syntax(path),
+        marker_size: marker_size(path, text),
+        ..ParseOptions::default()
+    }
+}
+
+/// The length git gives the markers of `path`. Git is only asked when `text` has a line that
+/// could open a conflict, with markers of any length.
+fn marker_size(path: &Path, text: &str) -> usize {
+    let opens_conflict = |line: &str| {
+        let label = line.trim_start_matches('<');
+        label.len() < line.len() && (label.is_empty() || label.starts_with(' '))
+    };
+    if !text.lines().any(opens_conflict) {
+        return DEFAULT_MARKER_SIZE;
+    }
+    git::Repository::discover(path)
+        .and_then(|repository| {
+            repository
+                .conflict_marker_size(path)
+                .inspect_err(|e| tracing::warn!("git: {e:#}"))
+                .ok()
+                .flatten()
+        })
+        .unwrap_or(DEFAULT_MARKER_SIZE)
+}
+
+/// The syntax an editor would likely pick for `path`, from its extension.
+fn syntax(path: &Path) -> Syntax {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("md" | "markdown") => Syntax::Markdown,
+        Some("rst") => Syntax::ReStructuredText,
+        _ => Syntax::Plain,
+    }
+}
+
+/// One line per conflict and warning, `path:line: ...` with 1-based lines like compilers print.
+fn text_output(found: &[Found]) -> String {
+    let mut output = String::new();
+    for (path, .., merge_conflict) in found {
+        let path = path.display();
+        for region in &merge_conflict.conflicts {
+            output += &format!("{path}:{}: {}\n", region.head + 1, conflict_message(region));
+        }
+        for warning in &merge_conflict.warnings {
+            let message = warning_message(warning);
+            output += &format!("{path}:{}: warning: {message}\n", warning.line + 1);
+        }
+    }
+    output
+}
+
+/// The parse results keyed by path.
+fn json_output(found: &[Found]) -> serde_json::Value {
+    found
+        .iter()
+        .map(|(path, .., merge_conflict)| {
+            (
+                path.display().to_string(),
+                serde_json::json!(merge_conflict),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// What the text output and SARIF results say about a conflict.
+fn conflict_message(region: &ConflictRegion) -> String {
+    let ancestor = region
+        .ancestor
+        .map(|line| format!(", ancestor at {}", line + 1))
+        .unwrap_or_default();
+    format!(
+        "conflict between {} and {} (lines {}-{}{ancestor})",
+        region.head_label.as_deref().unwrap_or("ours"),
+        region.branch_label.as_deref().unwrap_or("theirs"),
+        region.head + 1,
+        region.end + 1,
+    )
+}
+
+fn warning_message(warning: &ParseWarning) -> String {
+    warning.diagnostic(env!("CARGO_PKG_NAME")).message
+}
+
+/// Rule ids of the SARIF results.
+const CONFLICT_RULE: &str = "merge-conflict";
+const STRAY_MARKER_RULE: &str = "stray-conflict-marker";
+
+/// A SARIF 2.1.0 log with one result per conflict, an error spanning its marker lines, and one
+/// per warning.
+fn sarif_log(found: &[Found]) -> serde_json::Value {
+    let results = found.iter().flat_map(|(path, .., merge_conflict)| {
+        // SARIF wants a URI; forward slashes keep relative paths relative on Windows too.
+        let uri = path.to_string_lossy().replace('\\', "/");
+        let location = move |region: serde_json::Value| {
+            serde_json::json!([{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": uri },
+                    "region": region,
+                }
+            }])
+        };
+        let conflicts = merge_conflict.conflicts.iter().map(|region| {
+            serde_json::json!({
+                "ruleId": CONFLICT_RULE,
+                "level": "error",
+                "message": { "text": conflict_message(region) },
+                "locations": location(serde_json::json!({
+                    "startLine": region.head + 1,
+                    "endLine": region.end + 1,
+                    "byteOffset": region.bytes.head.start,
+                    "byteLength": region.bytes.end.end - region.bytes.head.start,
+                })),
+            })
+        });
+        let warnings = merge_conflict.warnings.iter().map(|warning| {
+            serde_json::json!({
+                "ruleId": STRAY_MARKER_RULE,
+                "level": "warning",
+                "message": { "text": warning_message(warning) },
+                "locations": location(serde_json::json!({ "startLine": warning.line + 1 })),
+            })
+        });
+        conflicts.chain(warnings).collect::<Vec<_>>()
+    });
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": [
+                        {
+                            "id": CONFLICT_RULE,
+                            "shortDescription": { "text": "Unresolved merge conflict" },
+                        },
+                        {
+                            "id": STRAY_MARKER_RULE,
+                            "shortDescription": { "text": "Conflict marker outside a conflict" },
+                        },
+                    ],
+                }
+            },
+            "results": results.collect::<Vec<_>>(),
+        }],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use merge_conflict_assistant::parser::parse;
+    use rstest::*;
+
+    use super::*;
+
+    const CONFLICT: &str = "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\n";
+
+    /// `path`, `text` and its parse result, as scanning a file holding it finds them.
+    fn found(path: &str, text: &str) -> Found {
+        let options = ParseOptions::default();
+        (
+            path.into(),
+            text.to_owned(),
+            options,
+            parse(text).unwrap().unwrap(),
+        )
+    }
+
+    #[rstest]
+    #[case::default(&[], Format::Text, 0)]
+    #[case::json(&["--format", "json"], Format::Json, 0)]
+    #[case::sarif(&["--format", "sarif"], Format::Sarif, 0)]
+    #[case::jobs(&["--jobs", "4"], Format::Text, 4)]
+    fn arguments_accept_format_and_jobs(
+        #[case] flags: &[&str],
+        #[case] format: Format,
+        #[case] jobs: usize,
+    ) {
+        let args = ArgumentParser::try_parse_from(
+            ["parser_driver"].iter().chain(flags).chain(&["notes.txt"]),
+        )
+        .expect("valid arguments");
+        assert_eq!(format, args.format);
+        assert_eq!(jobs, args.jobs);
+    }
+
+    #[rstest]
+    #[case::long("--debug")]
+    #[case::short("-d")]
+    fn arguments_accept_debug(#[case] flag: &str) {
+        let args = ArgumentParser::try_parse_from(["parser_driver", flag, "notes.txt"])
+            .expect("valid arguments");
+        assert!(args.debug);
+    }
+
+    #[rstest]
+    #[case::dash(&["-"], vec!["-"])]
+    #[case::mixed(&["-", "src"], vec!["-", "src"])]
+    #[case::none(&[], vec![])]
+    fn arguments_accept_stdin(#[case] flags: &[&str], #[case] expected: Vec<&str>) {
+        let args = ArgumentParser::try_parse_from(["parser_driver"].iter().chain(flags))
+            .expect("valid arguments");
+        assert_eq!(
+            expected.into_iter().map(PathBuf::from).collect::<Vec<_>>(),
+            args.paths
+        );
+    }
+
+    #[rstest]
+    fn read_input_reads_to_the_end() {
+        assert_eq!(CONFLICT, read_input(CONFLICT.as_bytes()).unwrap());
+    }
+
+    #[rstest]
+    fn read_input_rejects_binary() {
+        assert!(read_input(&[0xff, 0xfe][..]).is_err());
+    }
+
+    #[rstest]
+    #[case::resolve(&["--resolve", "theirs", "a.txt"], true)]
+    #[case::in_place(&["--resolve", "union", "--in-place", "."], true)]
+    #[case::in_place_alone(&["--in-place", "."], false)]
+    #[case::with_format(&["--resolve", "ours", "--format", "json", "a.txt"], false)]
+    #[case::fix_safe(&["--fix-safe", "--allow-deletions", "."], true)]
+    #[case::fix_safe_and_resolve(&["--fix-safe", "--resolve", "ours", "."], false)]
+    #[case::deletions_alone(&["--allow-deletions", "."], false)]
+    #[case::stats(&["--stats", "."], true)]
+    #[case::stats_and_fix_safe(&["--stats", "--fix-safe", "."], false)]
+    fn arguments_combine_resolve_flags(#[case] flags: &[&str], #[case] valid: bool) {
+        let args = ArgumentParser::try_parse_from(["parser_driver"].iter().chain(flags));
+        assert_eq!(valid, args.is_ok());
+    }
+
+    #[rstest]
+    #[case::ours(Strategy::Ours, "a\nx\nshared\nb\n")]
+    #[case::theirs(Strategy::Theirs, "a\ny\nshared\nb\n")]
+    #[case::both(Strategy::Both, "a\nx\ny\nshared\nshared\nb\n")]
+    #[case::union(Strategy::Union, "a\nx\ny\nshared\nb\n")]
+    fn resolve_text_resolves_every_conflict(#[case] strategy: Strategy, #[case] expected: &str) {
+        let text = concat!(
+            "a\n",
+            "<<<<<<< HEAD\nx\n=======\ny\n>>>>>>> feature\n",
+            "<<<<<<< HEAD\nshared\n=======\nshared\n>>>>>>> feature\n",
+            "b\n"
+        );
+        assert_eq!(
+            (expected.to_owned(), 2),
+            resolve_text(text, ParseOptions::default(), |document, region| strategy
+                .resolve(document, region))
+            .unwrap()
+        );
+    }
+
+    #[rstest]
+    fn resolve_text_resolves_nested_conflicts() {
+        let text = concat!(
+            "<<<<<<< HEAD\n",
+            "ours\n",
+            "||||||| base\n",
+            "<<<<<<<<< Temporary merge branch 1\n",
+            "one\n",
+            "=========\n",
+            "two\n",
+            ">>>>>>>>> Temporary merge branch 2\n",
+            "=======\n",
+            "theirs\n",
+            ">>>>>>> feature\n"
+        );
+        assert_eq!(
+            ("theirs\n".to_owned(), 2),
+            resolve_text(text, ParseOptions::default(), |document, region| {
+                Strategy::Theirs.resolve(document, region)
+            })
+            .unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case::without_deletions(
+        false,
+        "a\nx\n",
+        &[("sides identical", 1), ("trailing whitespace", 1)],
+        3
+    )]
+    #[case::with_deletions(
+        true,
+        "a\nx\n",
+        &[("deletion", 1), ("sides identical", 1), ("trailing whitespace", 1)],
+        2
+    )]
+    fn fix_text_resolves_only_safe_conflicts(
+        #[case] allow_deletions: bool,
+        #[case] fixed_start: &str,
+        #[case] reasons: &[(&'static str, usize)],
+        #[case] left: usize,
+    ) {
+        let text = concat!(
+            "a\n",
+            "<<<<<<< HEAD\nx\n=======\nx\n>>>>>>> feature\n",
+            "<<<<<<< HEAD\nf(a, b)\n=======\nf(a, b)  \r\n>>>>>>> feature\n",
+            "<<<<<<< HEAD\ng(a, b)\n=======\ng(a,\n  b)\n>>>>>>> feature\n",
+            "<<<<<<< HEAD\nremoved\n=======\n>>>>>>> feature\n",
+            "<<<<<<< HEAD\nmine\n=======\nyours\n>>>>>>> feature\n",
+        );
+        let (fixed, outcome) = fix_text(text, ParseOptions::default(), allow_deletions).unwrap();
+        assert!(fixed.starts_with(&format!("{fixed_start}f(a, b)\n")));
+        assert_eq!(
+            reasons.iter().copied().collect::<BTreeMap<_, _>>(),
+            outcome.fixed
+        );
+        assert_eq!(left, outcome.left.len());
+    }
+
+    #[rstest]
+    fn stats_add_up_across_files() {
+        let one = concat!(
+            "a\n",
+            "<<<<<<< HEAD\nx\n=======\nx\n>>>>>>> feature\n",
+            "<<<<<<< HEAD\nmine\nmore\n=======\nyours\n>>>>>>> feature\n",
+        );
+        let two = "<<<<<<< main\nmine\n=======\nyours\n>>>>>>> topic\n";
+        let stats = |path: &str, text: &str| {
+            Stats::of(Path::new(path), text, &parse(text).unwrap().unwrap())
+        };
+        let mut total = stats("one.txt", one);
+        assert_eq!(
+            "2 conflict(s) in 1 file(s), 11 line(s), 1 trivially resolvable, largest 6 line(s) at \
+             one.txt:7, branches HEAD, feature",
+            total.describe()
+        );
+        total.add(stats("two.txt", two));
+        assert_eq!(
+            "3 conflict(s) in 2 file(s), 16 line(s), 1 trivially resolvable, largest 6 line(s) at \
+             one.txt:7, branches HEAD, feature, main, topic",
+            total.describe()
+        );
+    }
+
+    #[rstest]
+    fn stats_count_nested_lines_once() {
+        let text = concat!(
+            "<<<<<<< HEAD\n",
+            "ours\n",
+            "||||||| base\n",
+            "<<<<<<<<< Temporary merge branch 1\n",
+            "one\n",
+            "=========\n",
+            "two\n",
+            ">>>>>>>>> Temporary merge branch 2\n",
+            "=======\n",
+            "theirs\n",
+            ">>>>>>> feature\n"
+        );
+        let stats = Stats::of(Path::new("a"), text, &parse(text).unwrap().unwrap());
+        assert_eq!((2, 11), (stats.conflicts, stats.lines));
+    }
+
+    #[rstest]
+    fn fix_summary_names_reasons_and_left_conflicts() {
+        let text = "<<<<<<< HEAD\nmine\n=======\nyours\n>>>>>>> feature\n";
+        let outcome = FixOutcome {
+            fixed: BTreeMap::from([("sides identical", 2)]),
+            left: parse(text).unwrap().unwrap().conflicts,
+        };
+        assert_eq!(
+            concat!(
+                "notes.txt: fixed 2 conflict(s) (2 sides identical)\n",
+                "notes.txt:1: left conflict between HEAD and feature (lines 1-5)\n"
+            ),
+            outcome.summary(Path::new("notes.txt"))
+        );
+    }
+
+    #[rstest]
+    #[case::one_thread(1)]
+    #[case::several_threads(4)]
+    fn scan_skips_ignored_and_binary_files(#[case] jobs: usize) {
+        let root = std::env::temp_dir().join(format!("mca-scan-{jobs}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        // .gitignore files only count inside a git repository.
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join("src").join("generated")).unwrap();
+        std::fs::write(root.join(".gitignore"), "generated/\n").unwrap();
+        std::fs::write(root.join("src").join("notes.txt"), CONFLICT).unwrap();
+        std::fs::write(root.join("src").join("clean.txt"), "a\n").unwrap();
+        std::fs::write(root.join("src").join("generated").join("out.txt"), CONFLICT).unwrap();
+        let binary = [CONFLICT.as_bytes(), &[0xff, 0xfe]].concat();
+        std::fs::write(root.join("src").join("image.bin"), binary).unwrap();
+        let (found, errors) = scan(std::slice::from_ref(&root), jobs);
+        let _ = std::fs::remove_dir_all(&root);
+        assert!(errors.is_empty());
+        // The text is kept, so resolving does not read the file again.
+        assert_eq!(
+            vec![(root.join("src").join("notes.txt"), CONFLICT.to_owned())],
+            found
+                .into_iter()
+                .map(|(path, text, ..)| (path, text))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[rstest]
+    fn scan_uses_the_conflict_marker_size_attribute() {
+        let root = std::env::temp_dir().join(format!("mca-marker-size-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let init = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&root)
+            .args(["init", "--quiet"])
+            .status()
+            .expect("git runs");
+        assert!(init.success());
+        std::fs::write(
+            root.join(".gitattributes"),
+            "*.txt conflict-marker-size=5\n",
+        )
+        .unwrap();
+        let text = "<<<<< HEAD\nours\n=====\ntheirs\n>>>>> feature\n";
+        std::fs::write(root.join("notes.txt"), text).unwrap();
+        let (found, errors) = scan(std::slice::from_ref(&root), 1);
+        let _ = std::fs::remove_dir_all(&root);
+        assert!(errors.is_empty());
+        assert_eq!(1, found.len());
+        assert_eq!(5, found[0].2.marker_size);
+        assert_eq!(1, found[0].3.conflicts.len());
+    }
+
+    #[rstest]
+    fn scan_reports_missing_paths() {
+        let missing = std::env::temp_dir().join(format!("mca-missing-{}", std::process::id()));
+        let (found, errors) = scan(&[missing], 1);
+        assert!(found.is_empty());
+        assert_eq!(1, errors.len());
+    }
+
+    #[rstest]
+    fn json_output_round_trips() {
+        let found = [found("notes.txt", CONFLICT)];
+        let json = serde_json::to_string_pretty(&json_output(&found)).unwrap();
+        let parsed: std::collections::BTreeMap<String, MergeConflict> =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(found[0].3, parsed["notes.txt"]);
+    }
+
+    #[rstest]
+    fn sarif_log_has_a_result_per_conflict_and_warning() {
+        let found = [
+            found("src/notes.txt", &format!("{CONFLICT}=======\n")),
+            found("README", CONFLICT),
+        ];
+        let log = sarif_log(&found);
+        let results = &log["runs"][0]["results"];
+        assert_eq!(3, results.as_array().unwrap().len());
+        assert_eq!(CONFLICT_RULE, results[0]["ruleId"]);
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!("src/notes.txt", location["artifactLocation"]["uri"]);
+        assert_eq!(
+            serde_json::json!({ "startLine": 2, "endLine": 6, "byteOffset": 2, "byteLength": 49 }),
+            location["region"]
+        );
+        assert_eq!(STRAY_MARKER_RULE, results[1]["ruleId"]);
+        assert_eq!(
+            7,
+            results[1]["locations"][0]["physicalLocation"]["region"]["startLine"]
+        );
+        assert_eq!(
+            "README",
+            results[2]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"]
+        );
+    }
+
+    #[rstest]
+    fn text_output_lists_conflicts() {
+        let found = [found("notes.txt", CONFLICT), found("b.txt", CONFLICT)];
+        assert_eq!(
+            concat!(
+                "notes.txt:2: conflict between HEAD and feature (lines 2-6)\n",
+                "b.txt:2: conflict between HEAD and feature (lines 2-6)\n"
+            ),
+            text_output(&found)
+        );
+    }
+}
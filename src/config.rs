@@ -0,0 +1,449 @@
+//! Server configuration read from the client's `initializationOptions`, laid over any
+//! settings files (see [`crate::config_file`]).
+//!
+//! Every field is optional. Missing or malformed options fall back to the
+//! defaults so a bad editor configuration never prevents the server from starting.
+//! Later settings from `workspace/didChangeConfiguration` are laid over the current
+//! configuration with [`Config::updated`].
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    parser::{ConflictStyle, SideLabels},
+    resolution::Resolution,
+};
+
+pub const DEFAULT_DIAGNOSTIC_SOURCE: &str = "merge";
+
+/// Documents larger than this are not parsed unless `maxFileSize` says otherwise.
+pub const DEFAULT_MAX_FILE_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    /// Value reported as the `source` of every published diagnostic.
+    pub diagnostic_source: String,
+    /// Line inserted between the two sides by the "Keep both with separator" action.
+    pub both_separator: String,
+    /// Providers to offer. Clients that support dynamic registration can toggle them at runtime.
+    pub features: Features,
+    /// How the client should send document changes.
+    pub sync: SyncKind,
+    /// Which conflict markers to recognise.
+    pub conflict_style: ConflictStyle,
+    /// Severity of every published diagnostic.
+    pub diagnostic_severity: Severity,
+    /// Milliseconds to wait after a change before parsing. Changes arriving within the
+    /// window are parsed once, as the latest version.
+    pub debounce_ms: u64,
+    /// Documents larger than this many bytes are handled as `large_files` says. `0` removes
+    /// the limit.
+    pub max_file_size: usize,
+    /// What happens to documents over `max_file_size`.
+    pub large_files: LargeFiles,
+    /// Glob patterns of document paths the server ignores, e.g. `**/vendor/**`. A `*` also
+    /// matches `/`, so `*.min.js` excludes such files in every directory.
+    pub exclude: Vec<String>,
+    /// Language ids to handle, e.g. `["rust", "python"]`. Empty handles every language.
+    pub languages: Vec<String>,
+    /// Language ids never handled, e.g. `["git-commit", "diff"]`. Wins over `languages`.
+    pub exclude_languages: Vec<String>,
+    /// Names for sides whose markers carry none, used in action titles and diagnostics.
+    pub labels: SideLabels,
+    /// Resolution whose code action is marked preferred, so clients can apply it directly.
+    /// `"none"`, like leaving it out, marks no action.
+    #[serde(deserialize_with = "deserialize_strategy")]
+    pub default_strategy: Option<Resolution>,
+    /// Resolve conflicts whose sides are identical as soon as they are found, by sending the
+    /// edit with `workspace/applyEdit`.
+    pub auto_resolve_identical: bool,
+    /// Glob patterns of document paths merged like git's `merge=union`, e.g. changelogs. Their
+    /// conflicts offer keeping both sides with the newest entries first.
+    pub union_files: Vec<String>,
+    /// Optional git integration.
+    pub git: GitOptions,
+    /// Optional Mercurial integration.
+    pub hg: HgOptions,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl From<Severity> for lsp_types::DiagnosticSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => Self::ERROR,
+            Severity::Warning => Self::WARNING,
+            Severity::Information => Self::INFORMATION,
+            Severity::Hint => Self::HINT,
+        }
+    }
+}
+
+/// What the server may look up in the workspace's git repository.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GitOptions {
+    /// Run `git` to find the repository and its conflicted files. Off by default so the server
+    /// never starts processes unasked.
+    pub enabled: bool,
+    /// `git add` a conflicted file once it is saved without conflicts.
+    pub auto_stage: bool,
+}
+
+/// What the server may look up in the workspace's Mercurial repository.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HgOptions {
+    /// Run `hg` to list unresolved files and mark them resolved.
+    pub enabled: bool,
+}
+
+/// Handling of documents over the size limit.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LargeFiles {
+    /// Never parse them.
+    #[default]
+    Skip,
+    /// Parse them when opened and saved, but not after every change.
+    OnSave,
+}
+
+/// Document sync mode advertised to the client.
+///
+/// `Full` is for clients that misbehave with incremental changes; every change then
+/// carries the whole document.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncKind {
+    Full,
+    #[default]
+    Incremental,
+}
+
+impl From<SyncKind> for lsp_types::TextDocumentSyncKind {
+    fn from(kind: SyncKind) -> Self {
+        match kind {
+            SyncKind::Full => Self::FULL,
+            SyncKind::Incremental => Self::INCREMENTAL,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Features {
+    pub code_action: bool,
+    pub code_lens: bool,
+    /// Off by default: some clients use a single semantic token provider per document, so ours
+    /// would replace the main language server's highlighting.
+    pub semantic_tokens: bool,
+    /// Serve `textDocument/diagnostic` and `workspace/diagnostic` instead of pushing
+    /// diagnostics. Off by default: clients that support both would show each conflict twice.
+    pub pull_diagnostics: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            code_action: true,
+            code_lens: true,
+            semantic_tokens: false,
+            pull_diagnostics: false,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            diagnostic_source: DEFAULT_DIAGNOSTIC_SOURCE.to_owned(),
+            both_separator: String::new(),
+            features: Features::default(),
+            sync: SyncKind::default(),
+            conflict_style: ConflictStyle::default(),
+            diagnostic_severity: Severity::default(),
+            debounce_ms: 0,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            large_files: LargeFiles::default(),
+            exclude: Vec::new(),
+            languages: Vec::new(),
+            exclude_languages: Vec::new(),
+            labels: SideLabels::default(),
+            default_strategy: None,
+            auto_resolve_identical: false,
+            union_files: vec!["*/CHANGELOG*".to_owned(), "*/NEWS*".to_owned()],
+            git: GitOptions::default(),
+            hg: HgOptions::default(),
+        }
+    }
+}
+
+impl Config {
+    #[cfg(test)]
+    pub fn from_initialization_options(options: Option<serde_json::Value>) -> Self {
+        Self::default().with_initialization_options(options)
+    }
+
+    /// Lay `initializationOptions` over this configuration, e.g. one read from settings
+    /// files. Invalid options are ignored as a whole.
+    pub fn with_initialization_options(self, options: Option<serde_json::Value>) -> Self {
+        let Some(options) = options else {
+            return self;
+        };
+        match self.updated(options) {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("ignoring invalid initialization options: {e}");
+                self
+            }
+        }
+    }
+
+    /// This configuration with `settings` laid over it. Options `settings` leaves out keep
+    /// their current value, including individual `features`.
+    pub fn updated(&self, settings: serde_json::Value) -> Result<Self, serde_json::Error> {
+        let mut merged = serde_json::to_value(self)?;
+        merge_settings(&mut merged, settings);
+        serde_json::from_value(merged)
+    }
+
+    /// Whether the document is ignored: its language is not handled or its path matches one
+    /// of the `exclude` patterns. Invalid patterns never match. An empty `language_id`, for a
+    /// document never opened, is only checked against the paths.
+    ///
+    /// Patterns see the decoded path, so `my dir` rather than `my%20dir`.
+    pub fn is_excluded(&self, uri: &lsp_types::Uri, language_id: &str) -> bool {
+        if !language_id.is_empty() && !self.handles_language(language_id) {
+            return true;
+        }
+        path_matches(&self.exclude, uri)
+    }
+
+    /// Whether the document's path matches one of the `union_files` patterns.
+    pub fn is_union_file(&self, uri: &lsp_types::Uri) -> bool {
+        path_matches(&self.union_files, uri)
+    }
+
+    fn handles_language(&self, language_id: &str) -> bool {
+        !self.exclude_languages.iter().any(|id| id == language_id)
+            && (self.languages.is_empty() || self.languages.iter().any(|id| id == language_id))
+    }
+}
+
+/// Whether the decoded path of `uri` matches any of `patterns`. Invalid patterns never match.
+fn path_matches(patterns: &[String], uri: &lsp_types::Uri) -> bool {
+    if patterns.is_empty() {
+        return false;
+    }
+    let path = uri.path().as_estr().decode().into_string_lossy();
+    patterns
+        .iter()
+        .any(|pattern| match glob::Pattern::new(pattern) {
+            Ok(pattern) => pattern.matches(&path),
+            Err(e) => {
+                tracing::warn!("ignoring invalid pattern {pattern:?}: {e}");
+                false
+            }
+        })
+}
+
+fn deserialize_strategy<'de, D>(deserializer: D) -> Result<Option<Resolution>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    enum Strategy {
+        None,
+        #[serde(untagged)]
+        Resolution(Resolution),
+    }
+    Ok(match Option::<Strategy>::deserialize(deserializer)? {
+        Some(Strategy::Resolution(resolution)) => Some(resolution),
+        Some(Strategy::None) | None => None,
+    })
+}
+
+/// Recursively replace the values in `base` with those in `overlay`.
+fn merge_settings(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_settings(base.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    fn missing_options_use_defaults() {
+        assert_eq!(Config::default(), Config::from_initialization_options(None));
+    }
+
+    #[rstest]
+    fn diagnostic_source_is_read_from_options() {
+        let config = Config::from_initialization_options(Some(json!({
+            "diagnosticSource": "merge-conflict-assistant"
+        })));
+        assert_eq!("merge-conflict-assistant", config.diagnostic_source);
+    }
+
+    #[rstest]
+    fn features_are_read_from_options() {
+        let config = Config::from_initialization_options(Some(json!({
+            "features": { "codeAction": false }
+        })));
+        assert!(!config.features.code_action);
+        assert!(config.features.code_lens);
+        assert_eq!(DEFAULT_DIAGNOSTIC_SOURCE, config.diagnostic_source);
+    }
+
+    #[rstest]
+    fn sync_kind_is_read_from_options() {
+        let config = Config::from_initialization_options(Some(json!({ "sync": "full" })));
+        assert_eq!(SyncKind::Full, config.sync);
+        assert_eq!(SyncKind::Incremental, Config::default().sync);
+    }
+
+    #[rstest]
+    fn resolution_options_are_read_from_options() {
+        let config = Config::from_initialization_options(Some(json!({
+            "conflictStyle": "merge",
+            "diagnosticSeverity": "warning",
+            "debounceMs": 150,
+            "maxFileSize": 0,
+            "largeFiles": "onSave",
+            "exclude": ["**/*.lock"],
+            "defaultStrategy": "theirs"
+        })));
+        assert_eq!(ConflictStyle::Merge, config.conflict_style);
+        assert_eq!(
+            lsp_types::DiagnosticSeverity::WARNING,
+            config.diagnostic_severity.into()
+        );
+        assert_eq!(150, config.debounce_ms);
+        assert_eq!(0, config.max_file_size);
+        assert_eq!(LargeFiles::OnSave, config.large_files);
+        assert_eq!(Some(Resolution::Theirs), config.default_strategy);
+    }
+
+    #[rstest]
+    #[case::ours(json!("ours"), Some(Resolution::Ours))]
+    #[case::both(json!("both"), Some(Resolution::Both))]
+    #[case::none(json!("none"), None)]
+    #[case::null(json!(null), None)]
+    fn default_strategy_is_read_from_options(
+        #[case] value: serde_json::Value,
+        #[case] expected: Option<Resolution>,
+    ) {
+        let config = Config::from_initialization_options(Some(json!({ "defaultStrategy": value })));
+        assert_eq!(expected, config.default_strategy);
+        // Survives being laid over by later settings.
+        let updated = config.updated(json!({})).expect("valid settings");
+        assert_eq!(expected, updated.default_strategy);
+    }
+
+    #[rstest]
+    #[case::matching_glob("**/*.lock", "file:///src/Cargo.lock", true)]
+    #[case::other_extension("**/*.lock", "file:///src/main.rs", false)]
+    #[case::directory("/vendor/**", "file:///vendor/lib/a.c", true)]
+    #[case::nested_directory("**/vendor/**", "file:///src/vendor/lib/a.c", true)]
+    #[case::file_name_anywhere("*.min.js", "file:///web/assets/app.min.js", true)]
+    #[case::unminified("*.min.js", "file:///web/assets/app.js", false)]
+    #[case::decoded_path("**/my dir/*", "file:///home/my%20dir/a.rs", true)]
+    #[case::windows_drive("**/generated/**", "file:///C:/src/generated/a.cs", true)]
+    #[case::invalid_pattern("[", "file:///a", false)]
+    fn exclude_patterns_match_document_paths(
+        #[case] pattern: &str,
+        #[case] uri: &str,
+        #[case] expected: bool,
+    ) {
+        let config = Config {
+            exclude: vec![pattern.to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(expected, config.is_excluded(&uri.parse().unwrap(), "rust"));
+    }
+
+    #[rstest]
+    #[case::changelog("file:///project/CHANGELOG.md", true)]
+    #[case::news("file:///project/docs/NEWS", true)]
+    #[case::source("file:///project/src/changelog.rs", false)]
+    fn union_files_default_to_changelogs(#[case] uri: &str, #[case] expected: bool) {
+        assert_eq!(
+            expected,
+            Config::default().is_union_file(&uri.parse().unwrap())
+        );
+    }
+
+    #[rstest]
+    #[case::everything_by_default(&[], &[], "rust", false)]
+    #[case::listed(&["rust"], &[], "rust", false)]
+    #[case::not_listed(&["rust"], &[], "git-commit", true)]
+    #[case::excluded(&[], &["git-commit"], "git-commit", true)]
+    #[case::excluded_wins(&["diff"], &["diff"], "diff", true)]
+    #[case::unknown_language(&["rust"], &[], "", false)]
+    fn languages_restrict_documents(
+        #[case] languages: &[&str],
+        #[case] exclude_languages: &[&str],
+        #[case] language_id: &str,
+        #[case] expected: bool,
+    ) {
+        let config = Config {
+            languages: languages.iter().map(|id| id.to_string()).collect(),
+            exclude_languages: exclude_languages.iter().map(|id| id.to_string()).collect(),
+            ..Default::default()
+        };
+        let uri = "file:///src/main.rs".parse().unwrap();
+        assert_eq!(expected, config.is_excluded(&uri, language_id));
+    }
+
+    #[rstest]
+    fn updated_keeps_options_not_mentioned() {
+        let config = Config {
+            diagnostic_source: "custom".to_owned(),
+            ..Default::default()
+        };
+        let updated = config
+            .updated(json!({
+                "diagnosticSeverity": "hint",
+                "features": { "codeLens": false }
+            }))
+            .expect("valid settings");
+        assert_eq!("custom", updated.diagnostic_source);
+        assert_eq!(Severity::Hint, updated.diagnostic_severity);
+        assert!(updated.features.code_action);
+        assert!(!updated.features.code_lens);
+    }
+
+    #[rstest]
+    fn updated_rejects_invalid_settings() {
+        let config = Config::default();
+        assert!(config.updated(json!({ "debounceMs": "soon" })).is_err());
+    }
+
+    #[rstest]
+    fn invalid_options_use_defaults() {
+        let config = Config::from_initialization_options(Some(json!({
+            "diagnosticSource": 42
+        })));
+        assert_eq!(Config::default(), config);
+    }
+}
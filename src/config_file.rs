@@ -0,0 +1,252 @@
+//! Settings read from `merge-conflict-assistant.toml` files.
+//!
+//! The files use the same keys as `initializationOptions`. A file in the user's config
+//! directory applies everywhere, one in the workspace root applies to that project and wins
+//! over the user's. `initializationOptions` win over both. `--config <path>` names a single
+//! file to use instead of looking for them.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::config::Config;
+
+pub const FILE_NAME: &str = "merge-conflict-assistant.toml";
+
+/// Read a settings file into the JSON layout of `initializationOptions`.
+pub fn read(path: &Path) -> anyhow::Result<serde_json::Value> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read '{}'", path.display()))?;
+    parse(&text).with_context(|| format!("failed to parse '{}'", path.display()))
+}
+
+fn parse(text: &str) -> anyhow::Result<serde_json::Value> {
+    let document = toml_edit::Document::parse(text)?;
+    Ok(table_to_json(document.as_table().iter()))
+}
+
+/// The configuration from `settings`, read earlier from `--config`, or else from the files
+/// found for `workspace_root`. Files that cannot be used are reported and skipped.
+pub fn load(settings: Option<serde_json::Value>, workspace_root: Option<&Path>) -> Config {
+    let layers = match settings {
+        Some(settings) => vec![settings],
+        None => candidates(user_config_dir(), workspace_root)
+            .into_iter()
+            .filter(|path| path.is_file())
+            .filter_map(|path| {
+                tracing::info!("reading settings from {}", path.display());
+                read(&path)
+                    .inspect_err(|e| tracing::warn!("ignoring settings file: {e:#}"))
+                    .ok()
+            })
+            .collect(),
+    };
+    layered(layers)
+}
+
+/// Defaults with each of `layers` laid over the previous ones.
+fn layered(layers: impl IntoIterator<Item = serde_json::Value>) -> Config {
+    layers
+        .into_iter()
+        .fold(Config::default(), |config, settings| {
+            match config.updated(settings) {
+                Ok(updated) => updated,
+                Err(e) => {
+                    tracing::warn!("ignoring invalid settings file: {e}");
+                    config
+                }
+            }
+        })
+}
+
+/// Where settings files may be, lowest precedence first.
+fn candidates(config_dir: Option<PathBuf>, workspace_root: Option<&Path>) -> Vec<PathBuf> {
+    config_dir
+        .into_iter()
+        .chain(workspace_root.map(Path::to_path_buf))
+        .map(|dir| dir.join(FILE_NAME))
+        .collect()
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config`, or `%APPDATA%` on Windows.
+fn user_config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        return std::env::var_os("APPDATA").map(PathBuf::from);
+    }
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// The local path of a `file:` URI, e.g. a workspace folder.
+pub fn uri_to_path(uri: &lsp_types::Uri) -> Option<PathBuf> {
+    if uri.scheme().is_none_or(|scheme| scheme.as_str() != "file") {
+        return None;
+    }
+    let path = uri.path().as_estr().decode().into_string_lossy();
+    // `file:///C:/src` holds the Windows path `C:/src`.
+    let path = match path.strip_prefix('/') {
+        Some(rest) if cfg!(windows) && rest.as_bytes().get(1) == Some(&b':') => rest,
+        _ => &path,
+    };
+    Some(PathBuf::from(path))
+}
+
+/// The `file:` URI of a local path, the inverse of [`uri_to_path`].
+pub fn path_to_uri(path: &Path) -> Option<lsp_types::Uri> {
+    let path = path.to_str()?.replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri.parse().ok()
+}
+
+fn table_to_json<'a>(
+    entries: impl IntoIterator<Item = (&'a str, &'a toml_edit::Item)>,
+) -> serde_json::Value {
+    serde_json::Value::Object(
+        entries
+            .into_iter()
+            .filter_map(|(key, item)| item_to_json(item).map(|value| (key.to_owned(), value)))
+            .collect(),
+    )
+}
+
+fn item_to_json(item: &toml_edit::Item) -> Option<serde_json::Value> {
+    match item {
+        toml_edit::Item::None => None,
+        toml_edit::Item::Value(value) => Some(value_to_json(value)),
+        toml_edit::Item::Table(table) => Some(table_to_json(table.iter())),
+        toml_edit::Item::ArrayOfTables(tables) => Some(serde_json::Value::Array(
+            tables
+                .iter()
+                .map(|table| table_to_json(table.iter()))
+                .collect(),
+        )),
+    }
+}
+
+fn value_to_json(value: &toml_edit::Value) -> serde_json::Value {
+    match value {
+        toml_edit::Value::String(s) => serde_json::Value::from(s.value().as_str()),
+        toml_edit::Value::Integer(i) => serde_json::Value::from(*i.value()),
+        toml_edit::Value::Float(f) => serde_json::Value::from(*f.value()),
+        toml_edit::Value::Boolean(b) => serde_json::Value::from(*b.value()),
+        toml_edit::Value::Datetime(d) => serde_json::Value::from(d.value().to_string()),
+        toml_edit::Value::Array(array) => array.iter().map(value_to_json).collect(),
+        toml_edit::Value::InlineTable(table) => serde_json::Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+    use serde_json::json;
+
+    use super::*;
+
+    #[rstest]
+    fn toml_is_read_in_the_initialization_options_layout() {
+        let text = r#"
+            diagnosticSeverity = "warning"
+            exclude = ["**/vendor/**", "*.min.js"]
+            debounceMs = 100
+
+            [features]
+            codeLens = false
+        "#;
+        assert_eq!(
+            json!({
+                "diagnosticSeverity": "warning",
+                "exclude": ["**/vendor/**", "*.min.js"],
+                "debounceMs": 100,
+                "features": { "codeLens": false }
+            }),
+            parse(text).unwrap()
+        );
+    }
+
+    #[rstest]
+    fn invalid_toml_is_an_error() {
+        assert!(parse("exclude = [").is_err());
+    }
+
+    #[rstest]
+    fn workspace_file_wins_over_user_file() {
+        let root = std::env::temp_dir().join(format!("mca-config-{}", std::process::id()));
+        let user = root.join("user");
+        let workspace = root.join("workspace");
+        std::fs::create_dir_all(&user).unwrap();
+        std::fs::create_dir_all(&workspace).unwrap();
+        std::fs::write(
+            user.join(FILE_NAME),
+            "diagnosticSource = \"user\"\nbothSeparator = \"---\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            workspace.join(FILE_NAME),
+            "diagnosticSource = \"project\"\n",
+        )
+        .unwrap();
+
+        let config = layered(
+            candidates(Some(user), Some(&workspace))
+                .iter()
+                .map(|path| read(path).unwrap()),
+        );
+        assert_eq!("project", config.diagnostic_source);
+        assert_eq!("---", config.both_separator);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[rstest]
+    fn invalid_layer_keeps_the_ones_before() {
+        let config = layered([
+            json!({ "diagnosticSource": "user" }),
+            json!({ "debounceMs": "soon" }),
+        ]);
+        assert_eq!("user", config.diagnostic_source);
+    }
+
+    #[rstest]
+    fn explicit_settings_replace_discovery() {
+        let config = load(Some(json!({ "diagnosticSource": "explicit" })), None);
+        assert_eq!("explicit", config.diagnostic_source);
+    }
+
+    #[rstest]
+    #[case::plain("/home/me/notes.txt", "file:///home/me/notes.txt")]
+    #[case::space("/home/me/my notes.txt", "file:///home/me/my%20notes.txt")]
+    #[case::percent("/tmp/100%.md", "file:///tmp/100%25.md")]
+    fn paths_become_file_uris(#[case] path: &str, #[case] expected: &str) {
+        let uri = path_to_uri(Path::new(path)).unwrap();
+        assert_eq!(expected, uri.as_str());
+        assert_eq!(Some(PathBuf::from(path)), uri_to_path(&uri));
+    }
+
+    #[rstest]
+    #[case::file("file:///home/me/project", Some("/home/me/project"))]
+    #[case::encoded("file:///home/me/my%20project", Some("/home/me/my project"))]
+    #[case::other_scheme("untitled:Untitled-1", None)]
+    fn workspace_uris_become_paths(#[case] uri: &str, #[case] expected: Option<&str>) {
+        assert_eq!(
+            expected.map(PathBuf::from),
+            uri_to_path(&uri.parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,789 @@
+//! Optional git integration: the repository a document belongs to, the operation that left
+//! it conflicted and which of its paths are unmerged.
+//!
+//! Runs the `git` command line rather than linking a git library, so the server builds
+//! without one and only touches repositories when `git.enabled` is set.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+/// A git work tree.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Repository {
+    root: PathBuf,
+    git_dir: PathBuf,
+}
+
+/// The operation that stopped with conflicts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operation {
+    Merge,
+    Rebase,
+    CherryPick,
+    Revert,
+}
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Merge => "merge",
+            Self::Rebase => "rebase",
+            Self::CherryPick => "cherry-pick",
+            Self::Revert => "revert",
+        }
+    }
+
+    /// The revision their side of the conflicts comes from. Ours is always `HEAD`.
+    pub fn theirs_revision(self) -> &'static str {
+        match self {
+            Self::Merge => "MERGE_HEAD",
+            Self::Rebase => "REBASE_HEAD",
+            Self::CherryPick => "CHERRY_PICK_HEAD",
+            Self::Revert => "REVERT_HEAD",
+        }
+    }
+}
+
+/// The commits `HEAD` and the operation in progress point to, read from the git directory.
+/// Anything found with git for a conflicted file holds until they change.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Heads {
+    operation: Option<Operation>,
+    head: Option<String>,
+    theirs: Option<String>,
+}
+
+/// A conflict over a whole file rather than some of its lines, which leaves no markers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileConflict {
+    /// Their side deleted the file our side modified.
+    DeletedByThem,
+    /// Our side deleted the file their side modified.
+    DeletedByUs,
+    /// Both sides changed a binary file, which git cannot put markers in.
+    Binary,
+}
+
+impl FileConflict {
+    /// What happened to the file, for its diagnostic.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::DeletedByThem => "deleted in theirs, modified in ours",
+            Self::DeletedByUs => "deleted in ours, modified in theirs",
+            Self::Binary => "binary file changed on both sides",
+        }
+    }
+}
+
+/// `git log` format read by [`CommitInfo::parse`].
+const COMMIT_FORMAT: &str = "--format=%h%x00%an%x00%as%x00%s";
+
+/// A commit as shown next to the side it belongs to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CommitInfo {
+    pub short_sha: String,
+    pub author: String,
+    /// The author date, `YYYY-MM-DD`.
+    pub date: String,
+    pub subject: String,
+}
+
+impl CommitInfo {
+    /// Read `git log` output in [`COMMIT_FORMAT`].
+    fn parse(log: &str) -> Option<Self> {
+        let mut fields = log.trim_end_matches('\n').splitn(4, '\0');
+        Some(Self {
+            short_sha: fields.next().filter(|sha| !sha.is_empty())?.to_owned(),
+            author: fields.next()?.to_owned(),
+            date: fields.next()?.to_owned(),
+            subject: fields.next()?.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for CommitInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} ({}, {})",
+            self.short_sha, self.subject, self.author, self.date
+        )
+    }
+}
+
+/// A conflicted file's versions from the index. A stage is missing when that side does not
+/// have the file, e.g. there is no base when both sides added it.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Stages {
+    /// Stage 1, the common ancestor.
+    pub base: Option<String>,
+    /// Stage 2, the version on the current branch.
+    pub ours: Option<String>,
+    /// Stage 3, the version being merged in.
+    pub theirs: Option<String>,
+}
+
+/// One stage of an unmerged path in the index.
+struct UnmergedEntry {
+    stage: u8,
+    object: String,
+    path: PathBuf,
+}
+
+impl Repository {
+    /// The repository containing `path`, a file or directory. `None` outside of a work tree or
+    /// when git cannot be run.
+    pub fn discover(path: &Path) -> Option<Self> {
+        let dir = if path.is_dir() { path } else { path.parent()? };
+        let output = git(dir, &["rev-parse", "--show-toplevel", "--absolute-git-dir"])
+            .inspect_err(|e| tracing::debug!("no git repository for {}: {e:#}", path.display()))
+            .ok()?;
+        let mut lines = output.lines();
+        let root = PathBuf::from(lines.next()?);
+        let git_dir = PathBuf::from(lines.next()?);
+        Some(Self { root, git_dir })
+    }
+
+    /// The operation in progress, if any.
+    pub fn operation(&self) -> Option<Operation> {
+        let exists = |name: &str| self.git_dir.join(name).exists();
+        if exists("rebase-merge") || exists("rebase-apply") {
+            Some(Operation::Rebase)
+        } else if exists("MERGE_HEAD") {
+            Some(Operation::Merge)
+        } else if exists("CHERRY_PICK_HEAD") {
+            Some(Operation::CherryPick)
+        } else if exists("REVERT_HEAD") {
+            Some(Operation::Revert)
+        } else {
+            None
+        }
+    }
+
+    /// Where `HEAD` and the operation in progress point, without running git.
+    pub fn heads(&self) -> Heads {
+        let operation = self.operation();
+        Heads {
+            operation,
+            head: self.read_ref("HEAD"),
+            theirs: operation.and_then(|operation| self.read_ref(operation.theirs_revision())),
+        }
+    }
+
+    /// The commit `name`, a ref such as `HEAD` or `refs/heads/main`, points to, following
+    /// symbolic refs. Linked work trees keep their branches in the common git directory, and
+    /// `git pack-refs` moves them to `packed-refs`.
+    fn read_ref(&self, name: &str) -> Option<String> {
+        let common_dir = std::fs::read_to_string(self.git_dir.join("commondir"))
+            .map(|dir| self.git_dir.join(dir.trim()))
+            .unwrap_or_else(|_| self.git_dir.clone());
+        let mut name = name.to_owned();
+        // Symbolic refs rarely nest; the limit only guards against cycles.
+        for _ in 0..5 {
+            let loose = [&self.git_dir, &common_dir]
+                .into_iter()
+                .find_map(|dir| std::fs::read_to_string(dir.join(&name)).ok());
+            let Some(value) = loose else {
+                let packed = std::fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+                return packed.lines().find_map(|line| {
+                    let (sha, packed_name) = line.split_once(' ')?;
+                    (packed_name == name).then(|| sha.to_owned())
+                });
+            };
+            match value.trim().strip_prefix("ref: ") {
+                Some(target) => name = target.to_owned(),
+                None => return Some(value.trim().to_owned()),
+            }
+        }
+        None
+    }
+
+    /// The branch a rebase in progress replays onto, or the commit's short SHA when no branch
+    /// points at it. `None` when no rebase is in progress.
+    pub fn rebase_onto(&self) -> anyhow::Result<Option<String>> {
+        let Some(onto) = ["rebase-merge", "rebase-apply"]
+            .iter()
+            .find_map(|dir| std::fs::read_to_string(self.git_dir.join(dir).join("onto")).ok())
+        else {
+            return Ok(None);
+        };
+        let onto = onto.trim();
+        let branches = git(
+            &self.root,
+            &[
+                "for-each-ref",
+                "--points-at",
+                onto,
+                "--format=%(refname:short)",
+                "refs/heads",
+                "refs/remotes",
+            ],
+        )?;
+        match branches.lines().next() {
+            Some(branch) => Ok(Some(branch.to_owned())),
+            None => Ok(Some(
+                git(&self.root, &["rev-parse", "--short", onto])?
+                    .trim()
+                    .to_owned(),
+            )),
+        }
+    }
+
+    /// Paths with unmerged entries in the index, in index order.
+    pub fn conflicted_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for entry in self.unmerged_entries(None)? {
+            if paths.last() != Some(&entry.path) {
+                paths.push(entry.path);
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Conflicted paths whose conflict is over the whole file, in index order.
+    pub fn file_conflicts(&self) -> anyhow::Result<Vec<(PathBuf, FileConflict)>> {
+        let mut paths: Vec<Vec<UnmergedEntry>> = Vec::new();
+        for entry in self.unmerged_entries(None)? {
+            match paths.last_mut() {
+                Some(entries) if entries[0].path == entry.path => entries.push(entry),
+                _ => paths.push(vec![entry]),
+            }
+        }
+        let mut conflicts = Vec::new();
+        for entries in paths {
+            let stage = |stage: u8| entries.iter().find(|entry| entry.stage == stage);
+            // Stage 2 is ours, 3 theirs. A side without its stage deleted the file.
+            let conflict = match (stage(2), stage(3)) {
+                (Some(_), None) => FileConflict::DeletedByThem,
+                (None, Some(_)) => FileConflict::DeletedByUs,
+                (Some(ours), Some(theirs))
+                    if self.is_binary(&ours.object)? || self.is_binary(&theirs.object)? =>
+                {
+                    FileConflict::Binary
+                }
+                _ => continue,
+            };
+            conflicts.push((entries[0].path.clone(), conflict));
+        }
+        Ok(conflicts)
+    }
+
+    /// Whether the blob `object` is binary, by git's rule: a NUL in its first 8000 bytes.
+    fn is_binary(&self, object: &str) -> anyhow::Result<bool> {
+        let blob = git_output(&self.root, &["cat-file", "blob", object])?;
+        Ok(blob.iter().take(8000).any(|byte| *byte == 0))
+    }
+
+    /// Replace `path` with its version from index stage `stage`, 1 for the base, 2 for ours
+    /// and 3 for theirs, and stage it. Does nothing and returns `false` when `path` is not
+    /// conflicted or has no such stage.
+    pub fn take_stage(&self, path: &Path, stage: u8) -> anyhow::Result<bool> {
+        let entries = self.unmerged_entries(Some(path))?;
+        if !entries.iter().any(|entry| entry.stage == stage) {
+            return Ok(false);
+        }
+        let path = path.to_string_lossy();
+        git(
+            &self.root,
+            &[
+                "checkout-index",
+                "--force",
+                &format!("--stage={stage}"),
+                "--",
+                &path,
+            ],
+        )?;
+        git(&self.root, &["add", "--", &path])?;
+        Ok(true)
+    }
+
+    /// The newest commit that changed `text`, some whole lines of `path`, as of `revision`.
+    ///
+    /// Falls back to the last commit changing the file when `text` is empty or not found in
+    /// that revision. `None` when the file does not exist in `revision`.
+    pub fn last_commit(
+        &self,
+        revision: &str,
+        path: &Path,
+        text: &str,
+    ) -> anyhow::Result<Option<CommitInfo>> {
+        let relative = self.relative_path(path)?;
+        let Ok(content) = git(&self.root, &["show", &format!("{revision}:{relative}")]) else {
+            tracing::debug!("{relative} not found in {revision}");
+            return Ok(None);
+        };
+        let blamed = match find_lines(&content, text) {
+            Some((first, last)) => {
+                let blame = git(
+                    &self.root,
+                    &[
+                        "blame",
+                        "--porcelain",
+                        "-L",
+                        &format!("{first},{last}"),
+                        revision,
+                        "--",
+                        &relative,
+                    ],
+                )?;
+                newest_blamed_commit(&blame)
+            }
+            None => None,
+        };
+        let commit = blamed.as_deref().unwrap_or(revision);
+        let log = git(
+            &self.root,
+            &["log", "-1", COMMIT_FORMAT, commit, "--", &relative],
+        )?;
+        Ok(CommitInfo::parse(&log))
+    }
+
+    /// The commit a marker label such as `HEAD` or `feature/foo` names. `None` when the label
+    /// is not a revision of this repository.
+    pub fn commit(&self, revision: &str) -> anyhow::Result<Option<CommitInfo>> {
+        // Labels come from the file; never let one pass for an option.
+        if revision.is_empty() || revision.starts_with('-') {
+            return Ok(None);
+        }
+        let Ok(sha) = git(
+            &self.root,
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{revision}^{{commit}}"),
+            ],
+        ) else {
+            tracing::debug!("{revision} is not a commit");
+            return Ok(None);
+        };
+        let log = git(&self.root, &["log", "-1", COMMIT_FORMAT, sha.trim()])?;
+        Ok(CommitInfo::parse(&log))
+    }
+
+    /// The commit `sha` in one of the submodules `.gitmodules` lists, for a conflict over the
+    /// commit a submodule points to. `None` when no checked out submodule has it.
+    pub fn submodule_commit(&self, sha: &str) -> anyhow::Result<Option<CommitInfo>> {
+        if !self.root.join(".gitmodules").exists() {
+            return Ok(None);
+        }
+        // Fails when there are no matching keys.
+        let Ok(paths) = git(
+            &self.root,
+            &[
+                "config",
+                "--file",
+                ".gitmodules",
+                "--get-regexp",
+                r"^submodule\..*\.path$",
+            ],
+        ) else {
+            return Ok(None);
+        };
+        // Each line is `submodule.<name>.path <path>`.
+        for path in paths
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(_, path)| path)
+        {
+            let Some(submodule) = Self::discover(&self.root.join(path)) else {
+                continue;
+            };
+            // Not checked out: the superproject was found instead.
+            if submodule.root == self.root {
+                continue;
+            }
+            if let Some(commit) = submodule.commit(sha)? {
+                return Ok(Some(commit));
+            }
+        }
+        Ok(None)
+    }
+
+    /// `path` relative to the work tree, with `/` separators as git expects.
+    fn relative_path(&self, path: &Path) -> anyhow::Result<String> {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative.to_path_buf(),
+            // The root git reports is canonical, the path may go through a symlink.
+            Err(_) => path
+                .canonicalize()?
+                .strip_prefix(self.root.canonicalize()?)
+                .with_context(|| format!("{} is outside {}", path.display(), self.root.display()))?
+                .to_path_buf(),
+        };
+        Ok(relative.to_string_lossy().replace('\\', "/"))
+    }
+
+    /// The `conflict-marker-size` attribute of `path`, when it is set to a number.
+    pub fn conflict_marker_size(&self, path: &Path) -> anyhow::Result<Option<usize>> {
+        let output = git(
+            &self.root,
+            &[
+                "check-attr",
+                "conflict-marker-size",
+                "--",
+                &path.to_string_lossy(),
+            ],
+        )?;
+        // `<path>: conflict-marker-size: <value>`, where the value may be `unspecified`. Git
+        // uses its default for a size of 0 as well.
+        Ok(output
+            .trim_end()
+            .rsplit(": ")
+            .next()
+            .and_then(|value| value.parse().ok())
+            .filter(|&size| size > 0))
+    }
+
+    /// Mark `path` resolved with `git add`. Does nothing and returns `false` when `path` is not
+    /// conflicted.
+    pub fn stage_resolved(&self, path: &Path) -> anyhow::Result<bool> {
+        if self.unmerged_entries(Some(path))?.is_empty() {
+            return Ok(false);
+        }
+        git(&self.root, &["add", "--", &path.to_string_lossy()])?;
+        Ok(true)
+    }
+
+    /// The base, ours and theirs versions of `path` from the index. `None` when `path` is not
+    /// conflicted.
+    pub fn stage_contents(&self, path: &Path) -> anyhow::Result<Option<Stages>> {
+        let entries = self.unmerged_entries(Some(path))?;
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let mut stages = Stages::default();
+        for entry in entries {
+            let content = git(&self.root, &["cat-file", "blob", &entry.object])?;
+            match entry.stage {
+                1 => stages.base = Some(content),
+                2 => stages.ours = Some(content),
+                3 => stages.theirs = Some(content),
+                stage => tracing::warn!("ignoring unexpected stage {stage} of {}", path.display()),
+            }
+        }
+        Ok(Some(stages))
+    }
+
+    /// The index's unmerged entries, all of them or only those of `path`.
+    fn unmerged_entries(&self, path: Option<&Path>) -> anyhow::Result<Vec<UnmergedEntry>> {
+        let mut args = vec!["ls-files", "--unmerged", "-z"];
+        let path = path.map(|path| path.to_string_lossy());
+        if let Some(path) = &path {
+            args.extend(["--", path]);
+        }
+        let output = git(&self.root, &args)?;
+        // Each entry is `<mode> <object> <stage>\t<path>`, one per stage of a path.
+        output
+            .split('\0')
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let parsed = entry.split_once('\t').and_then(|(info, path)| {
+                    let mut info = info.split(' ').skip(1);
+                    let object = info.next()?.to_owned();
+                    let stage = info.next()?.parse().ok()?;
+                    Some(UnmergedEntry {
+                        stage,
+                        object,
+                        path: self.root.join(path),
+                    })
+                });
+                parsed.with_context(|| format!("unexpected ls-files entry {entry:?}"))
+            })
+            .collect()
+    }
+}
+
+/// The 1-based first and last line where `text`, whole lines, appears in `content`.
+fn find_lines(content: &str, text: &str) -> Option<(usize, usize)> {
+    if text.is_empty() {
+        return None;
+    }
+    let (offset, _) = content
+        .match_indices(text)
+        .find(|(offset, _)| *offset == 0 || content[..*offset].ends_with('\n'))?;
+    let first = content[..offset].matches('\n').count() + 1;
+    let last = first + text.trim_end_matches('\n').matches('\n').count();
+    Some((first, last))
+}
+
+/// The commit with the latest author time in `git blame --porcelain` output.
+fn newest_blamed_commit(blame: &str) -> Option<String> {
+    let mut newest: Option<(u64, &str)> = None;
+    let mut commit = None;
+    for line in blame.lines() {
+        if let Some(time) = line.strip_prefix("author-time ") {
+            if let (Some(commit), Ok(time)) = (commit, time.parse())
+                && newest.is_none_or(|(newest, _)| time > newest)
+            {
+                newest = Some((time, commit));
+            }
+        } else if let Some((sha, _)) = line.split_once(' ')
+            && sha.len() >= 40
+            && sha.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            commit = Some(sha);
+        }
+    }
+    newest.map(|(_, commit)| commit.to_owned())
+}
+
+/// Run git in `dir` and return what it printed.
+fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    Ok(String::from_utf8(git_output(dir, args)?)?)
+}
+
+/// Run git in `dir` and return what it printed, which need not be text.
+fn git_output(dir: &Path, args: &[&str]) -> anyhow::Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+    use crate::test_helpers::{
+        binary_conflict_repository, conflicted_repository, delete_modify_repository,
+        rebasing_repository,
+    };
+
+    #[rstest]
+    fn conflicted_paths_lists_each_unmerged_file_once() {
+        let root = conflicted_repository("conflicted-paths");
+        let repository = Repository::discover(&root.join("notes.txt")).expect("a repository");
+        assert_eq!(Some(Operation::Merge), repository.operation());
+        let paths: Vec<_> = repository
+            .conflicted_paths()
+            .unwrap()
+            .iter()
+            .map(|path| path.canonicalize().unwrap())
+            .collect();
+        assert_eq!(vec![root.join("notes.txt").canonicalize().unwrap()], paths);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[rstest]
+    fn file_conflicts_finds_deleted_files() {
+        let conflicted = conflicted_repository("file-conflicts-modified");
+        let deleted = delete_modify_repository("file-conflicts-deleted");
+        let modified = Repository::discover(&conflicted)
+            .expect("a repository")
+            .file_conflicts();
+        let repository = Repository::discover(&deleted).expect("a repository");
+        let conflicts: Vec<_> = repository
+            .file_conflicts()
+            .unwrap()
+            .into_iter()
+            .map(|(path, conflict)| (path.canonicalize().unwrap(), conflict))
+            .collect();
+        let notes = deleted.join("notes.txt").canonicalize().unwrap();
+        let _ = std::fs::remove_dir_all(&conflicted);
+        let _ = std::fs::remove_dir_all(&deleted);
+        assert_eq!(Vec::<(PathBuf, FileConflict)>::new(), modified.unwrap());
+        assert_eq!(vec![(notes, FileConflict::DeletedByThem)], conflicts);
+    }
+
+    #[rstest]
+    fn take_stage_replaces_a_binary_file() {
+        let root = binary_conflict_repository("take-stage");
+        let notes = root.join("notes.txt");
+        let repository = Repository::discover(&root).expect("a repository");
+        let conflicts = repository.file_conflicts().unwrap();
+        let taken = repository.take_stage(&notes, 3).unwrap();
+        let again = repository.take_stage(&notes, 3).unwrap();
+        let content = std::fs::read(&notes).unwrap();
+        let conflicted = repository.conflicted_paths().unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(vec![(notes, FileConflict::Binary)], conflicts);
+        assert!(taken);
+        assert!(!again);
+        assert_eq!(b"theirs\0".to_vec(), content);
+        assert!(conflicted.is_empty());
+    }
+
+    #[rstest]
+    fn stage_contents_reads_each_side_from_the_index() {
+        let root = conflicted_repository("stage-contents");
+        let repository = Repository::discover(&root).expect("a repository");
+        let stages = repository.stage_contents(&root.join("notes.txt")).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(
+            Some(Stages {
+                base: Some("base\n".to_owned()),
+                ours: Some("ours\n".to_owned()),
+                theirs: Some("theirs\n".to_owned()),
+            }),
+            stages
+        );
+    }
+
+    #[rstest]
+    fn last_commit_names_the_commit_behind_each_side() {
+        let root = conflicted_repository("last-commit");
+        let repository = Repository::discover(&root).expect("a repository");
+        let path = root.join("notes.txt");
+        let operation = repository.operation().expect("a merge");
+        let ours = repository.last_commit("HEAD", &path, "ours\n").unwrap();
+        let theirs = repository
+            .last_commit(operation.theirs_revision(), &path, "theirs\n")
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        let ours = ours.expect("a commit");
+        assert_eq!("Write ours", ours.subject);
+        assert_eq!("Test", ours.author);
+        assert_eq!(10, ours.date.len());
+        assert_eq!("Write theirs", theirs.expect("a commit").subject);
+    }
+
+    #[rstest]
+    #[case::set("9", Some(9))]
+    #[case::zero("0", None)]
+    #[case::not_a_number("big", None)]
+    fn conflict_marker_size_reads_gitattributes(
+        #[case] size: &str,
+        #[case] expected: Option<usize>,
+    ) {
+        let root = conflicted_repository(&format!("marker-size-{size}"));
+        std::fs::write(
+            root.join(".gitattributes"),
+            format!("notes.txt conflict-marker-size={size}\n"),
+        )
+        .unwrap();
+        std::fs::write(root.join("other.txt"), "").unwrap();
+        let repository = Repository::discover(&root).expect("a repository");
+        let notes = repository.conflict_marker_size(&root.join("notes.txt"));
+        let other = repository.conflict_marker_size(&root.join("other.txt"));
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(expected, notes.unwrap());
+        assert_eq!(None, other.unwrap());
+    }
+
+    #[rstest]
+    fn submodule_commit_looks_in_checked_out_submodules() {
+        let root = conflicted_repository("superproject");
+        let submodule = conflicted_repository("submodule");
+        std::fs::create_dir_all(root.join("libs")).unwrap();
+        std::fs::rename(&submodule, root.join("libs").join("widget")).unwrap();
+        std::fs::write(
+            root.join(".gitmodules"),
+            "[submodule \"widget\"]\n\tpath = libs/widget\n",
+        )
+        .unwrap();
+        let head = git(&root.join("libs").join("widget"), &["rev-parse", "HEAD"]).unwrap();
+        let repository = Repository::discover(&root).expect("a repository");
+        let found = repository.submodule_commit(head.trim());
+        let missing = repository.submodule_commit(&"0".repeat(40));
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!("Write ours", found.unwrap().expect("a commit").subject);
+        assert_eq!(None, missing.unwrap());
+    }
+
+    #[rstest]
+    fn heads_follow_the_refs_however_they_are_stored() {
+        let root = conflicted_repository("heads");
+        let repository = Repository::discover(&root).expect("a repository");
+        let head = git(&root, &["rev-parse", "HEAD"])
+            .unwrap()
+            .trim()
+            .to_owned();
+        let merge_head = git(&root, &["rev-parse", "MERGE_HEAD"]).unwrap();
+        let merging = repository.heads();
+        git(&root, &["pack-refs", "--all"]).unwrap();
+        let packed = repository.heads();
+        git(&root, &["merge", "--abort"]).unwrap();
+        let aborted = repository.heads();
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(
+            Heads {
+                operation: Some(Operation::Merge),
+                head: Some(head.clone()),
+                theirs: Some(merge_head.trim().to_owned()),
+            },
+            merging
+        );
+        assert_eq!(merging, packed);
+        assert_eq!(
+            Heads {
+                operation: None,
+                head: Some(head),
+                theirs: None,
+            },
+            aborted
+        );
+    }
+
+    #[rstest]
+    fn rebase_onto_names_the_upstream_branch() {
+        let root = rebasing_repository("rebase-onto");
+        let repository = Repository::discover(&root).expect("a repository");
+        let operation = repository.operation();
+        let onto = repository.rebase_onto().unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(Some(Operation::Rebase), operation);
+        assert_eq!(Some("main".to_owned()), onto);
+    }
+
+    #[rstest]
+    fn rebase_onto_is_none_during_a_merge() {
+        let root = conflicted_repository("merge-onto");
+        let repository = Repository::discover(&root).expect("a repository");
+        let onto = repository.rebase_onto().unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(None, onto);
+    }
+
+    #[rstest]
+    #[case::branch("feature", Some("Write theirs"))]
+    #[case::head("HEAD", Some("Write ours"))]
+    #[case::unknown("no-such-branch", None)]
+    #[case::option("--all", None)]
+    fn commit_resolves_marker_labels(#[case] label: &str, #[case] expected: Option<&str>) {
+        let root = conflicted_repository(&format!("commit-{label}"));
+        let repository = Repository::discover(&root).expect("a repository");
+        let commit = repository.commit(label).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(
+            expected,
+            commit.as_ref().map(|commit| commit.subject.as_str())
+        );
+    }
+
+    #[rstest]
+    #[case::first_line("a\nb\nc\n", "a\nb\n", Some((1, 2)))]
+    #[case::last_line("a\nb\nc\n", "c\n", Some((3, 3)))]
+    #[case::inside_a_line("ab\nb\n", "b\n", Some((2, 2)))]
+    #[case::missing("a\n", "z\n", None)]
+    #[case::empty("a\n", "", None)]
+    fn find_lines_matches_whole_lines(
+        #[case] content: &str,
+        #[case] text: &str,
+        #[case] expected: Option<(usize, usize)>,
+    ) {
+        assert_eq!(expected, find_lines(content, text));
+    }
+
+    #[rstest]
+    fn directories_outside_a_repository_have_none() {
+        assert_eq!(None, Repository::discover(Path::new("/")));
+    }
+}
@@ -0,0 +1,119 @@
+//! Optional Mercurial integration: the repository a document belongs to, whether a merge left
+//! it with unresolved files, and marking those resolved.
+//!
+//! Like the git integration it runs the `hg` command line, and only with `hg.enabled`.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+
+/// A Mercurial working directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Repository {
+    root: PathBuf,
+}
+
+impl Repository {
+    /// The repository containing `path`, a file or directory, found by its `.hg` directory.
+    pub fn discover(path: &Path) -> Option<Self> {
+        let dir = if path.is_dir() { path } else { path.parent()? };
+        dir.ancestors()
+            .find(|dir| dir.join(".hg").is_dir())
+            .map(|root| Self {
+                root: root.to_path_buf(),
+            })
+    }
+
+    /// Whether a merge, graft, rebase or similar stopped with files to resolve.
+    pub fn merging(&self) -> bool {
+        let merge = self.root.join(".hg").join("merge");
+        merge.join("state").exists() || merge.join("state2").exists()
+    }
+
+    /// Files `hg resolve --list` reports unresolved. Empty when no merge is in progress.
+    pub fn unresolved_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if !self.merging() {
+            return Ok(Vec::new());
+        }
+        let list = hg(&self.root, &["resolve", "--list"])?;
+        Ok(unresolved(&list).map(|path| self.root.join(path)).collect())
+    }
+
+    /// Mark `path` resolved with `hg resolve --mark`. Does nothing and returns `false` when
+    /// `path` is not unresolved.
+    pub fn mark_resolved(&self, path: &Path) -> anyhow::Result<bool> {
+        if !self
+            .unresolved_paths()?
+            .iter()
+            .any(|unresolved| unresolved == path)
+        {
+            return Ok(false);
+        }
+        hg(
+            &self.root,
+            &["resolve", "--mark", "--", &path.to_string_lossy()],
+        )?;
+        Ok(true)
+    }
+}
+
+/// The unresolved paths in `hg resolve --list` output, lines of `U path` or `R path`.
+fn unresolved(list: &str) -> impl Iterator<Item = &str> {
+    list.lines().filter_map(|line| line.strip_prefix("U "))
+}
+
+/// Run hg in `dir` and return what it printed.
+fn hg(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("hg")
+        .arg("--cwd")
+        .arg(dir)
+        .args(args)
+        // Output meant for scripts: no localisation, pager or user aliases.
+        .env("HGPLAIN", "1")
+        .output()
+        .context("failed to run hg")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "hg {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn discover_finds_the_merge_state() {
+        let root = std::env::temp_dir().join(format!("mca-hg-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join(".hg").join("merge")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src").join("notes.txt"), "").unwrap();
+        let repository = Repository::discover(&root.join("src").join("notes.txt"));
+        let before = repository.as_ref().map(Repository::merging);
+        std::fs::write(root.join(".hg").join("merge").join("state"), "").unwrap();
+        let after = repository.as_ref().map(Repository::merging);
+        let _ = std::fs::remove_dir_all(&root);
+        assert_eq!(Some(root), repository.map(|repository| repository.root));
+        assert_eq!(Some(false), before);
+        assert_eq!(Some(true), after);
+    }
+
+    #[rstest]
+    fn unresolved_skips_resolved_files() {
+        let list = "U src/notes.txt\nR README\nU with space.txt\n";
+        assert_eq!(
+            vec!["src/notes.txt", "with space.txt"],
+            unresolved(list).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,19 @@
+//! Detecting and resolving merge conflict markers, and the LSP server offering it to editors.
+//!
+//! The `merge-conflict-assistant` binary runs [`server::main_loop`]. The parser and the
+//! document state are public so the fuzz targets under `fuzz/` can drive them directly.
+
+pub mod config;
+pub mod config_file;
+pub mod git;
+pub mod hg;
+pub mod logging;
+pub mod merge;
+pub mod parser;
+pub mod resolution;
+pub mod server;
+pub mod state;
+pub mod svn;
+#[cfg(test)]
+mod test_helpers;
+pub mod transport;
@@ -0,0 +1,42 @@
+//! Tracing setup shared by the server and `parser_driver`.
+
+/// Build the tracing filter: `--debug` picks the default level and `RUST_LOG`-style
+/// directives, when given, add targeted overrides on top of it.
+pub fn log_filter(debug: bool, directives: Option<&str>) -> tracing_subscriber::EnvFilter {
+    let level = if debug {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::INFO
+    };
+    tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(tracing::level_filters::LevelFilter::from_level(level).into())
+        .parse_lossy(directives.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+    use tracing::level_filters::LevelFilter;
+
+    use super::*;
+
+    #[rstest]
+    #[case::default(false, None, LevelFilter::INFO)]
+    #[case::debug(true, None, LevelFilter::DEBUG)]
+    #[case::targeted_override(
+        false,
+        Some("merge_conflict_assistant::parser=trace"),
+        LevelFilter::TRACE
+    )]
+    #[case::invalid_directive_ignored(true, Some("=garbage="), LevelFilter::DEBUG)]
+    fn log_filter_combines_debug_flag_and_directives(
+        #[case] debug: bool,
+        #[case] directives: Option<&str>,
+        #[case] expected: LevelFilter,
+    ) {
+        assert_eq!(
+            Some(expected),
+            log_filter(debug, directives).max_level_hint()
+        );
+    }
+}
@@ -1,21 +1,25 @@
 //! LSP server for detecting and resolving merge conflict markers in any file type.
 //!
-//! Communicates over stdio using the LSP protocol. Runtime messages are sent to
+//! Communicates over stdio using the LSP protocol, over TCP with `--socket <port>` for
+//! clients that connect to an already running server, or over the pipe a client created
+//! with `--pipe <name>`. Runtime messages are sent to
 //! the editor via `window/logMessage`. Use `--log <path>` for detailed trace
-//! output to a file (for debugging the server itself).
-
-mod parser;
-mod server;
-mod state;
-#[cfg(test)]
-mod test_helpers;
+//! output to a file (for debugging the server itself). `RUST_LOG` directives
+//! (e.g. `merge_conflict_assistant::parser=trace`) refine what gets written there.
+//! Settings come from `merge-conflict-assistant.toml` files, or the one given with
+//! `--config <path>`, overridden by the client's initialization options.
 
 use std::env;
 
 use anyhow::Context;
 use clap::Parser;
-use lsp_server::Connection;
-use server::{main_loop, server_capabilities};
+use merge_conflict_assistant::{
+    config_file,
+    logging::log_filter,
+    server::{main_loop, server_capabilities},
+    state::ClientSupport,
+    transport::{self, Transport},
+};
 
 #[derive(clap::Parser, Debug)]
 #[command(version = env!("FULL_VERSION"), about, long_about = None)]
@@ -27,16 +31,35 @@ struct ArgumentParser {
     /// Write detailed trace output to a file (for debugging the server itself).
     #[arg(long)]
     log: Option<std::path::PathBuf>,
+
+    /// Listen on this localhost TCP port and serve the first client that connects instead of
+    /// using stdio.
+    #[arg(long, visible_alias = "port", value_name = "PORT")]
+    socket: Option<u16>,
+
+    /// Connect to the named pipe (Windows) or Unix domain socket the client created instead of
+    /// using stdio.
+    #[arg(long, value_name = "NAME", conflicts_with = "socket")]
+    pipe: Option<std::path::PathBuf>,
+
+    /// Read settings from this file instead of looking for merge-conflict-assistant.toml in the
+    /// workspace root and the user's config directory.
+    #[arg(long, value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = ArgumentParser::parse();
+impl ArgumentParser {
+    fn transport(&self) -> Transport {
+        match (self.socket, &self.pipe) {
+            (Some(port), _) => Transport::Socket(port),
+            (None, Some(name)) => Transport::Pipe(name.clone()),
+            (None, None) => Transport::Stdio,
+        }
+    }
+}
 
-    let level = if args.debug {
-        tracing::Level::DEBUG
-    } else {
-        tracing::Level::INFO
-    };
+fn main() -> anyhow::Result<std::process::ExitCode> {
+    let args = ArgumentParser::parse();
 
     // Only set up a tracing subscriber when --log is passed. Without it, tracing
     // macros are no-ops and runtime messages reach the editor via window/logMessage.
@@ -52,14 +75,22 @@ fn main() -> anyhow::Result<()> {
         let file = std::fs::File::create(&unique_path)
             .with_context(|| format!("failed to create log file '{}'", unique_path.display()))?;
         eprintln!("logging to {}", unique_path.display());
+        let rust_log = env::var(tracing_subscriber::EnvFilter::DEFAULT_ENV).ok();
         tracing_subscriber::fmt::fmt()
-            .with_max_level(level)
+            .with_env_filter(log_filter(args.debug, rust_log.as_deref()))
             .with_writer(std::sync::Mutex::new(file))
             .with_ansi(false)
             .init();
     }
 
-    run_server()
+    // Read before connecting so a bad path fails right away instead of mid-handshake.
+    let settings = args
+        .config
+        .as_deref()
+        .map(|path| config_file::read(&expand_tilde(path)))
+        .transpose()?;
+
+    run_server(&args.transport(), settings)
 }
 
 /// Expand a leading `~` or `~/` to the user's home directory.
@@ -73,10 +104,15 @@ fn expand_tilde(path: &std::path::Path) -> std::path::PathBuf {
     path.to_path_buf()
 }
 
-fn run_server() -> anyhow::Result<()> {
+/// Run the server. `settings` are read from `--config`; without them settings files are
+/// looked for in the workspace.
+fn run_server(
+    transport: &Transport,
+    settings: Option<serde_json::Value>,
+) -> anyhow::Result<std::process::ExitCode> {
     tracing::info!("server initializing");
 
-    let (connection, io_threads) = Connection::stdio();
+    let (connection, io_threads) = transport::connect(transport)?;
     let (initialize_id, initialize_params) = match connection.initialize_start() {
         Ok(it) => it,
         Err(e) => {
@@ -87,13 +123,23 @@ fn run_server() -> anyhow::Result<()> {
             return Err(e.into());
         }
     };
+    let client = ClientSupport::from_capabilities(&initialize_params["capabilities"]);
+    #[allow(deprecated)]
     let lsp_types::InitializeParams {
         initialization_options,
+        workspace_folders,
+        root_uri,
         ..
     } = serde_json::from_value(initialize_params)?;
 
     tracing::info!("initialization options: {:?}", initialization_options);
-    let capabilities = server_capabilities();
+    let workspace_root = workspace_folders
+        .and_then(|folders| folders.into_iter().next().map(|folder| folder.uri))
+        .or(root_uri)
+        .and_then(|uri| config_file::uri_to_path(&uri));
+    let config = config_file::load(settings, workspace_root.as_deref())
+        .with_initialization_options(initialization_options);
+    let capabilities = server_capabilities(&config, &client);
     let server_info = Some(lsp_types::ServerInfo {
         name: env!("CARGO_PKG_NAME").to_string(),
         version: Some(env!("FULL_VERSION").to_string()),
@@ -110,13 +156,81 @@ fn run_server() -> anyhow::Result<()> {
         return Err(e.into());
     }
 
-    match (main_loop(connection), io_threads.join()) {
+    let exit_code = match (
+        main_loop(connection, config, client, workspace_root),
+        io_threads.join(),
+    ) {
         (Err(loop_err), Err(join_err)) => anyhow::bail!("{loop_err}\n{join_err}"),
         (Ok(_), Err(join_err)) => anyhow::bail!("{join_err}"),
         (Err(loop_err), Ok(_)) => anyhow::bail!("{loop_err}"),
-        (Ok(_), Ok(_)) => {}
-    }
+        (Ok(exit_code), Ok(_)) => exit_code,
+    };
 
     tracing::info!("server shut down");
-    Ok(())
+    Ok(exit_code)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn arguments_accept_debug_and_log() {
+        let args = ArgumentParser::try_parse_from([
+            "merge-conflict-assistant",
+            "--debug",
+            "--log",
+            "x.log",
+        ])
+        .expect("valid arguments");
+        assert!(args.debug);
+        assert_eq!(Some(std::path::PathBuf::from("x.log")), args.log);
+    }
+
+    #[rstest]
+    fn arguments_default_to_quiet() {
+        let args = ArgumentParser::try_parse_from(["merge-conflict-assistant"]).expect("valid");
+        assert!(!args.debug);
+        assert!(args.log.is_none());
+        assert_eq!(Transport::Stdio, args.transport());
+    }
+
+    #[rstest]
+    #[case::socket("--socket")]
+    #[case::port("--port")]
+    fn arguments_accept_socket_port(#[case] flag: &str) {
+        let args = ArgumentParser::try_parse_from(["merge-conflict-assistant", flag, "9257"])
+            .expect("valid arguments");
+        assert_eq!(Transport::Socket(9257), args.transport());
+    }
+
+    #[rstest]
+    fn arguments_accept_pipe() {
+        let args =
+            ArgumentParser::try_parse_from(["merge-conflict-assistant", "--pipe=/tmp/lsp.sock"])
+                .expect("valid arguments");
+        assert_eq!(Transport::Pipe("/tmp/lsp.sock".into()), args.transport());
+    }
+
+    #[rstest]
+    fn arguments_accept_config_path() {
+        let args =
+            ArgumentParser::try_parse_from(["merge-conflict-assistant", "--config", "team.toml"])
+                .expect("valid arguments");
+        assert_eq!(Some(std::path::PathBuf::from("team.toml")), args.config);
+    }
+
+    #[rstest]
+    fn arguments_reject_socket_and_pipe_together() {
+        let args = ArgumentParser::try_parse_from([
+            "merge-conflict-assistant",
+            "--socket",
+            "9257",
+            "--pipe",
+            "/tmp/lsp.sock",
+        ]);
+        assert!(args.is_err());
+    }
 }
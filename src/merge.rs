@@ -0,0 +1,609 @@
+//! Combining the sides of a conflict into a single resolution by diffing them.
+//!
+//! The sides are compared line by line, and for a three-way merge word by word where lines
+//! clash. These work on the side texts as returned by `ConflictRegion::ours_text` and friends,
+//! whose lines keep their endings.
+
+use std::{cmp::Ordering, ops::Range};
+
+use similar::{Algorithm, DiffOp, TextDiff, capture_diff_slices};
+
+/// Both sides with the lines they share kept once.
+///
+/// Where the sides differ, our lines come before theirs. `None` when the sides share no lines,
+/// as the result would be the same as keeping both, or when they are identical.
+pub fn union_deduplicated(ours: &str, theirs: &str) -> Option<String> {
+    let (ours_lines, theirs_lines) = (lines(ours), lines(theirs));
+    let diff = TextDiff::from_slices(&ours_lines, &theirs_lines);
+    let mut shared = false;
+    let merged = diff
+        .iter_all_changes()
+        .inspect(|change| shared |= change.tag() == similar::ChangeTag::Equal)
+        .map(|change| change.value())
+        .collect();
+    (shared && ours != theirs).then_some(merged)
+}
+
+/// Both sides' import lines, sorted and with duplicates removed, when the document's language
+/// is one with recognised imports and every non-blank line of both sides is an import.
+///
+/// Recognises `use` and `extern crate` in Rust, `import` and `from ... import` in Python,
+/// `import` in JavaScript and TypeScript, import specs in Go and `#include` in C and C++.
+/// Statements spanning several lines are not recognised.
+pub fn import_union(language_id: &str, ours: &str, theirs: &str) -> Option<String> {
+    let is_import = import_matcher(language_id)?;
+    if ours == theirs {
+        return None;
+    }
+    let mut imports: Vec<&str> = lines(ours)
+        .into_iter()
+        .chain(lines(theirs))
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    if imports.is_empty() || !imports.iter().all(|line| is_import(line.trim())) {
+        return None;
+    }
+    imports.sort_by_key(|line| line.trim());
+    imports.dedup_by_key(|line| line.trim());
+    Some(imports.concat())
+}
+
+fn import_matcher(language_id: &str) -> Option<fn(&str) -> bool> {
+    let matcher: fn(&str) -> bool = match language_id {
+        "rust" => |line| {
+            let line = line
+                .strip_prefix("pub(crate) ")
+                .or_else(|| line.strip_prefix("pub "))
+                .unwrap_or(line);
+            (line.starts_with("use ") || line.starts_with("extern crate ")) && line.ends_with(';')
+        },
+        "python" => |line| {
+            line.starts_with("import ")
+                || (line.starts_with("from ") && line.contains(" import ") && !line.ends_with('('))
+        },
+        "javascript" | "javascriptreact" | "typescript" | "typescriptreact" => |line| {
+            line.starts_with("import ")
+                && (line.contains(" from ")
+                    || line.starts_with("import '")
+                    || line.starts_with("import \""))
+                && !line.ends_with('{')
+        },
+        "go" => |line| {
+            // `"path"` or `name "path"`, inside an import block or after `import`.
+            let spec = line.strip_prefix("import ").unwrap_or(line);
+            let (name, path) = spec.rsplit_once(' ').unwrap_or(("_", spec));
+            let name_ok = name == "."
+                || (name.chars().all(|c| c.is_alphanumeric() || c == '_')
+                    && !["return", "case", "go", "defer", "goto"].contains(&name));
+            name_ok && path.len() > 1 && path.starts_with('"') && path.ends_with('"')
+        },
+        "c" | "cpp" => |line| line.starts_with("#include "),
+        _ => return None,
+    };
+    Some(matcher)
+}
+
+/// Both sides' entries, like git's `merge=union`, with the newest first.
+///
+/// Each side is split into entries at headings: lines starting with `#`, or unindented lines
+/// carrying a date or version. When every entry has one, entries are ordered by it, newest
+/// first, preferring a `YYYY-MM-DD` date over a version. Otherwise our entries come before
+/// theirs. Entries on both sides are kept once.
+pub fn union_newest_first(ours: &str, theirs: &str) -> String {
+    let mut entries: Vec<String> = Vec::new();
+    for entry in changelog_entries(ours)
+        .into_iter()
+        .chain(changelog_entries(theirs))
+    {
+        if !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+    let keys: Option<Vec<Vec<u32>>> = entries
+        .iter()
+        .map(|entry| lines(entry).first().and_then(|heading| entry_key(heading)))
+        .collect();
+    if let Some(keys) = keys {
+        let mut keyed: Vec<_> = keys.into_iter().zip(entries).collect();
+        keyed.sort_by(|(a, _), (b, _)| b.cmp(a));
+        entries = keyed.into_iter().map(|(_, entry)| entry).collect();
+    }
+    entries.concat()
+}
+
+/// Split a side of a changelog conflict into entries, each starting at a heading. Lines before
+/// the first heading form an entry of their own.
+fn changelog_entries(text: &str) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+    for line in lines(text) {
+        match entries.last_mut() {
+            Some(entry) if !is_entry_heading(line) => entry.push_str(line),
+            _ => entries.push(line.to_owned()),
+        }
+    }
+    entries
+}
+
+fn is_entry_heading(line: &str) -> bool {
+    line.starts_with('#')
+        || (!line.starts_with(|c: char| c.is_whitespace() || c == '-' || c == '*')
+            && entry_key(line).is_some())
+}
+
+/// The date, or else the version, in a changelog heading, as numbers to compare.
+fn entry_key(heading: &str) -> Option<Vec<u32>> {
+    let tokens: Vec<&str> = heading
+        .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .map(|token| token.trim_matches(|c| c == '.' || c == '-'))
+        .collect();
+    let numbers = |token: &str, separator| -> Option<Vec<u32>> {
+        token
+            .split(separator)
+            .map(|part| part.parse().ok())
+            .collect()
+    };
+    let date = tokens.iter().find_map(|token| {
+        let parts: Vec<&str> = token.split('-').collect();
+        let is_date =
+            parts.len() == 3 && [4, 2, 2] == [parts[0].len(), parts[1].len(), parts[2].len()];
+        is_date.then(|| numbers(token, '-')).flatten()
+    });
+    date.or_else(|| {
+        tokens
+            .iter()
+            .filter(|token| token.contains('.') && !token.contains('-'))
+            .find_map(|token| numbers(token, '.'))
+    })
+}
+
+/// Our and their version when the sides differ only in one `version` value, as in
+/// package.json's `"version": "1.2.0"` or Cargo.toml's `version = "1.2.0"`.
+pub fn version_change<'a>(ours: &'a str, theirs: &'a str) -> Option<(&'a str, &'a str)> {
+    let (ours, theirs) = (lines(ours), lines(theirs));
+    if ours.len() != theirs.len() {
+        return None;
+    }
+    let mut differing = ours.iter().zip(&theirs).filter(|(a, b)| a != b);
+    let (ours, theirs) = differing.next()?;
+    if differing.next().is_some() {
+        return None;
+    }
+    let (ours_prefix, ours_version, ours_suffix) = version_value(ours)?;
+    let (theirs_prefix, theirs_version, theirs_suffix) = version_value(theirs)?;
+    (ours_prefix == theirs_prefix && ours_suffix == theirs_suffix)
+        .then_some((ours_version, theirs_version))
+}
+
+/// Split a `version` line around its quoted value.
+fn version_value(line: &str) -> Option<(&str, &str, &str)> {
+    let key = line.trim_start();
+    let rest = key
+        .strip_prefix("\"version\"")
+        .and_then(|rest| rest.trim_start().strip_prefix(':'))
+        .or_else(|| {
+            key.strip_prefix("version")
+                .and_then(|rest| rest.trim_start().strip_prefix('='))
+        })?;
+    let value = rest.trim_start().strip_prefix('"')?;
+    let end = value.find('"')?;
+    let start = line.len() - value.len();
+    Some((&line[..start], &value[..end], &value[end..]))
+}
+
+/// Compare two versions the way semver orders them: numerically by each dot separated part,
+/// with a pre-release (`1.0.0-rc.1`) before the release. Build metadata is ignored. `None`
+/// when either is not a version.
+pub fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    fn parse(version: &str) -> Option<(Vec<u64>, Option<&str>)> {
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let version = version
+            .split_once('+')
+            .map_or(version, |(version, _)| version);
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre)),
+            None => (version, None),
+        };
+        let release = release
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        Some((release, pre))
+    }
+    fn compare_pre(a: &str, b: &str) -> Ordering {
+        for (a, b) in a.split('.').zip(b.split('.')) {
+            let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b),
+            };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        a.split('.').count().cmp(&b.split('.').count())
+    }
+    let (a_release, a_pre) = parse(a)?;
+    let (b_release, b_pre) = parse(b)?;
+    let ordering = a_release.cmp(&b_release).then(match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => compare_pre(a, b),
+    });
+    Some(ordering)
+}
+
+/// Merge the changes each side made to the diff3 `ancestor` again, line by line and then word by
+/// word where both sides changed the same lines.
+///
+/// `None` when both sides still make different changes to the same words.
+pub fn three_way(ancestor: &str, ours: &str, theirs: &str) -> Option<String> {
+    let ancestor_lines = lines(ancestor);
+    let mut merged = String::new();
+    for chunk in merge_tokens(&ancestor_lines, &lines(ours), &lines(theirs)) {
+        match chunk {
+            Chunk::Clean(tokens) => merged.extend(tokens),
+            Chunk::Conflict {
+                ancestor,
+                ours,
+                theirs,
+            } => {
+                let (ancestor, ours, theirs) = (ancestor.concat(), ours.concat(), theirs.concat());
+                for chunk in merge_tokens(&words(&ancestor), &words(&ours), &words(&theirs)) {
+                    match chunk {
+                        Chunk::Clean(tokens) => merged.extend(tokens),
+                        Chunk::Conflict { .. } => return None,
+                    }
+                }
+            }
+        }
+    }
+    Some(merged)
+}
+
+/// A stretch of a three-way merge.
+#[derive(Debug, PartialEq)]
+enum Chunk<'a> {
+    /// Tokens both sides agree on once each side's changes are applied.
+    Clean(Vec<&'a str>),
+    /// Tokens both sides changed differently, with the ancestor's tokens they replaced.
+    Conflict {
+        ancestor: Vec<&'a str>,
+        ours: Vec<&'a str>,
+        theirs: Vec<&'a str>,
+    },
+}
+
+/// One side's replacement of `ancestor[removed]` with `side[added]`.
+#[derive(Clone, Debug)]
+struct Change {
+    removed: Range<usize>,
+    added: Range<usize>,
+}
+
+fn changes<'a>(ancestor: &[&'a str], side: &[&'a str]) -> Vec<Change> {
+    capture_diff_slices(Algorithm::Myers, ancestor, side)
+        .into_iter()
+        .filter(|op| !matches!(op, DiffOp::Equal { .. }))
+        .map(|op| Change {
+            removed: op.old_range(),
+            added: op.new_range(),
+        })
+        .collect()
+}
+
+/// Split the ancestor into stretches changed by at most one side, or the same way by both, and
+/// stretches where the sides' changes overlap. Changes touching each other count as
+/// overlapping, as in diff3.
+fn merge_tokens<'a>(ancestor: &[&'a str], ours: &[&'a str], theirs: &[&'a str]) -> Vec<Chunk<'a>> {
+    let ours_changes = changes(ancestor, ours);
+    let theirs_changes = changes(ancestor, theirs);
+    let (mut next_ours, mut next_theirs) = (0, 0);
+    let mut chunks = Vec::new();
+    let mut clean = Vec::new();
+    let mut position = 0;
+    loop {
+        let start = match (ours_changes.get(next_ours), theirs_changes.get(next_theirs)) {
+            (None, None) => break,
+            (Some(change), None) | (None, Some(change)) => change.removed.start,
+            (Some(a), Some(b)) => a.removed.start.min(b.removed.start),
+        };
+        clean.extend_from_slice(&ancestor[position..start]);
+        // Grow the stretch until no further change of either side touches it.
+        let (first_ours, first_theirs) = (next_ours, next_theirs);
+        let mut end = start;
+        loop {
+            if let Some(change) = ours_changes.get(next_ours)
+                && change.removed.start <= end
+            {
+                end = end.max(change.removed.end);
+                next_ours += 1;
+            } else if let Some(change) = theirs_changes.get(next_theirs)
+                && change.removed.start <= end
+            {
+                end = end.max(change.removed.end);
+                next_theirs += 1;
+            } else {
+                break;
+            }
+        }
+        let ours_version = apply(
+            ancestor,
+            ours,
+            &ours_changes[first_ours..next_ours],
+            start..end,
+        );
+        let theirs_version = apply(
+            ancestor,
+            theirs,
+            &theirs_changes[first_theirs..next_theirs],
+            start..end,
+        );
+        if first_theirs == next_theirs {
+            clean.extend(ours_version);
+        } else if first_ours == next_ours || ours_version == theirs_version {
+            clean.extend(theirs_version);
+        } else {
+            chunks.push(Chunk::Clean(std::mem::take(&mut clean)));
+            chunks.push(Chunk::Conflict {
+                ancestor: ancestor[start..end].to_vec(),
+                ours: ours_version,
+                theirs: theirs_version,
+            });
+        }
+        position = end;
+    }
+    clean.extend_from_slice(&ancestor[position..]);
+    chunks.push(Chunk::Clean(clean));
+    chunks.retain(|chunk| chunk != &Chunk::Clean(Vec::new()));
+    chunks
+}
+
+/// `ancestor[span]` with one side's `changes` to it applied.
+fn apply<'a>(
+    ancestor: &[&'a str],
+    side: &[&'a str],
+    changes: &[Change],
+    span: Range<usize>,
+) -> Vec<&'a str> {
+    let mut tokens = Vec::new();
+    let mut position = span.start;
+    for change in changes {
+        tokens.extend_from_slice(&ancestor[position..change.removed.start]);
+        tokens.extend_from_slice(&side[change.added.clone()]);
+        position = change.removed.end;
+    }
+    tokens.extend_from_slice(&ancestor[position..span.end]);
+    tokens
+}
+
+/// Split `text` into lines, keeping their endings. Like LSP, `\n`, `\r\n` and a bare `\r` all
+/// end a line.
+fn lines(text: &str) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+        let ends_line = match byte {
+            b'\n' => true,
+            b'\r' => bytes.get(index + 1) != Some(&b'\n'),
+            _ => false,
+        };
+        if ends_line {
+            lines.push(&text[start..=index]);
+            start = index + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+/// Split `text` into runs of whitespace and runs of everything else.
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut in_space = None;
+    for (offset, c) in text.char_indices() {
+        let space = c.is_whitespace();
+        if in_space.is_some_and(|in_space| in_space != space) {
+            words.push(&text[start..offset]);
+            start = offset;
+        }
+        in_space = Some(space);
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case::appended("a\nb\n", "a\nb\nc\n", Some("a\nb\nc\n"))]
+    #[case::both_appended("a\nb\n", "a\nc\n", Some("a\nb\nc\n"))]
+    #[case::interleaved("a\nx\nc\n", "a\nc\ny\n", Some("a\nx\nc\ny\n"))]
+    #[case::replaced_middle("a\nb\nc\n", "a\nB\nc\n", Some("a\nb\nB\nc\n"))]
+    #[case::nothing_shared("a\n", "b\n", None)]
+    #[case::identical("a\n", "a\n", None)]
+    #[case::crlf("a\r\nb\r\n", "a\r\nc\r\n", Some("a\r\nb\r\nc\r\n"))]
+    #[case::bare_cr("a\rb\r", "a\rc\r", Some("a\rb\rc\r"))]
+    fn shared_lines_are_kept_once(
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(
+            expected.map(str::to_owned),
+            union_deduplicated(ours, theirs)
+        );
+    }
+
+    #[rstest]
+    #[case::separate_lines("a\nb\nc\n", "A\nb\nc\n", "a\nb\nC\n", Some("A\nb\nC\n"))]
+    #[case::one_side_only("a\nb\n", "a\nb\n", "a\nB\n", Some("a\nB\n"))]
+    #[case::same_change("a\nb\n", "a\nX\n", "a\nX\n", Some("a\nX\n"))]
+    #[case::separate_words_on_one_line(
+        "let x = f(1);\n",
+        "let y = f(1);\n",
+        "let x = f(2);\n",
+        Some("let y = f(2);\n")
+    )]
+    #[case::same_word("let x = 1;\n", "let x = 2;\n", "let x = 3;\n", None)]
+    #[case::both_append("a\n", "a\nb\n", "a\nc\n", None)]
+    #[case::empty_ancestor("", "a\n", "b\n", None)]
+    fn three_way_merges_non_overlapping_changes(
+        #[case] ancestor: &str,
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(
+            expected.map(str::to_owned),
+            three_way(ancestor, ours, theirs)
+        );
+    }
+
+    #[rstest]
+    #[case::rust(
+        "rust",
+        "use std::fmt;\nuse crate::b;\n",
+        "use crate::a;\nuse std::fmt;\n",
+        Some("use crate::a;\nuse crate::b;\nuse std::fmt;\n")
+    )]
+    #[case::rust_multi_line("rust", "use a::{\n    b,\n", "use a::c;\n", None)]
+    #[case::python(
+        "python",
+        "import os\nfrom typing import Any\n",
+        "import sys\n\nimport os\n",
+        Some("from typing import Any\nimport os\nimport sys\n")
+    )]
+    #[case::typescript(
+        "typescript",
+        "import { b } from './b';\n",
+        "import { a } from './a';\n",
+        Some("import { a } from './a';\nimport { b } from './b';\n")
+    )]
+    #[case::go_block(
+        "go",
+        "\t\"fmt\"\n\t\"os\"\n",
+        "\t\"fmt\"\n\tlog \"github.com/x/log\"\n",
+        Some("\t\"fmt\"\n\t\"os\"\n\tlog \"github.com/x/log\"\n")
+    )]
+    #[case::c(
+        "c",
+        "#include <stdio.h>\n",
+        "#include \"local.h\"\n",
+        Some("#include \"local.h\"\n#include <stdio.h>\n")
+    )]
+    #[case::go_statement("go", "\treturn \"a\"\n", "\treturn \"b\"\n", None)]
+    #[case::not_only_imports("rust", "use a;\nfn f() {}\n", "use b;\n", None)]
+    #[case::unknown_language("plaintext", "use a;\n", "use b;\n", None)]
+    #[case::identical("rust", "use a;\n", "use a;\n", None)]
+    fn import_conflicts_merge_into_a_sorted_union(
+        #[case] language_id: &str,
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(
+            expected.map(str::to_owned),
+            import_union(language_id, ours, theirs)
+        );
+    }
+
+    #[rstest]
+    #[case::dated_headings(
+        "## 2024-03-01\n- ours\n",
+        "## 2024-05-10\n- theirs\n",
+        "## 2024-05-10\n- theirs\n## 2024-03-01\n- ours\n"
+    )]
+    #[case::versions(
+        "## [1.10.0]\n- ours\n\n",
+        "## [1.9.2]\n- theirs\n\n",
+        "## [1.10.0]\n- ours\n\n## [1.9.2]\n- theirs\n\n"
+    )]
+    #[case::date_wins_over_version(
+        "## [2.0.0] - 2023-12-01\n- ours\n",
+        "## [1.9.0] - 2024-01-15\n- theirs\n",
+        "## [1.9.0] - 2024-01-15\n- theirs\n## [2.0.0] - 2023-12-01\n- ours\n"
+    )]
+    #[case::news_style(
+        "Version 0.4 (2024-02-02)\n  * ours\n",
+        "Version 0.5 (2024-06-30)\n  * theirs\n",
+        "Version 0.5 (2024-06-30)\n  * theirs\nVersion 0.4 (2024-02-02)\n  * ours\n"
+    )]
+    #[case::no_headings("- ours\n", "- theirs\n", "- ours\n- theirs\n")]
+    #[case::shared_entry(
+        "## 1.1\n- a\n## 1.0\n- b\n",
+        "## 1.2\n- c\n## 1.0\n- b\n",
+        "## 1.2\n- c\n## 1.1\n- a\n## 1.0\n- b\n"
+    )]
+    fn union_orders_changelog_entries_newest_first(
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(expected, union_newest_first(ours, theirs));
+    }
+
+    #[rstest]
+    #[case::package_json(
+        "  \"version\": \"1.2.0\",\n",
+        "  \"version\": \"1.3.0-beta.1\",\n",
+        Some(("1.2.0", "1.3.0-beta.1"))
+    )]
+    #[case::cargo_toml(
+        "name = \"x\"\nversion = \"0.5.2\"\n",
+        "name = \"x\"\nversion = \"0.6.0\"\n",
+        Some(("0.5.2", "0.6.0"))
+    )]
+    #[case::other_key("name = \"a\"\n", "name = \"b\"\n", None)]
+    #[case::more_changes(
+        "version = \"1.0.0\"\nedition = \"2021\"\n",
+        "version = \"1.1.0\"\nedition = \"2024\"\n",
+        None
+    )]
+    #[case::different_punctuation(
+        "  \"version\": \"1.0.0\",\n",
+        "  \"version\": \"1.1.0\"\n",
+        None
+    )]
+    fn version_change_finds_the_two_versions(
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: Option<(&str, &str)>,
+    ) {
+        assert_eq!(expected, version_change(ours, theirs));
+    }
+
+    #[rstest]
+    #[case::minor("1.2.0", "1.10.0", Some(Ordering::Less))]
+    #[case::prefix("v2.0.0", "1.9.9", Some(Ordering::Greater))]
+    #[case::pre_release("1.0.0-rc.1", "1.0.0", Some(Ordering::Less))]
+    #[case::pre_release_numbers("1.0.0-rc.2", "1.0.0-rc.10", Some(Ordering::Less))]
+    #[case::build_metadata("1.0.0+abc", "1.0.0", Some(Ordering::Equal))]
+    #[case::not_a_version("next", "1.0.0", None)]
+    fn versions_compare_like_semver(
+        #[case] a: &str,
+        #[case] b: &str,
+        #[case] expected: Option<Ordering>,
+    ) {
+        assert_eq!(expected, compare_versions(a, b));
+    }
+
+    #[rstest]
+    fn words_alternate_between_text_and_whitespace() {
+        assert_eq!(
+            vec!["let", " ", "x", "  ", "=", " ", "1;", "\n"],
+            words("let x  = 1;\n")
+        );
+    }
+}
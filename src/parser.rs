@@ -5,13 +5,43 @@
 //! ancestor names following the markers are captured when present.
 //!
 //! All line numbers stored are 0-based indexes (line 100 in the file is stored as 99).
+//! Lines are counted the way LSP does: `\n`, `\r\n` and bare `\r` all end a line.
 //! Content for a region is the lines *after* its opening marker and *before* its
 //! closing marker.
+//!
+//! [`conflicts`] yields the regions one at a time as the text is scanned; [`parse`] collects
+//! them along with the document's names.
+//!
+//! Perforce writes conflicts as `>>>> ORIGINAL`, `==== THEIRS`, `==== YOURS` and `<<<<`,
+//! the ancestor first and our side last. Those are recognised too, see [`ConflictStyle`].
+//!
+//! Markdown documents get one extra rule: marker lines inside fenced code blocks are
+//! examples rather than conflicts, so they are skipped.
+
+use serde::{Deserialize, Serialize};
+
+/// Marker length git uses unless the `conflict-marker-size` attribute sets another.
+pub const DEFAULT_MARKER_SIZE: usize = 7;
 
-pub const MARKER_HEAD: &str = "<<<<<<<";
-pub const MARKER_ANCESTOR: &str = "|||||||";
-pub const MARKER_SEPARATOR: &str = "=======";
-pub const MARKER_END: &str = ">>>>>>>";
+/// The four markers at a given length.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Markers {
+    pub head: String,
+    pub ancestor: String,
+    pub separator: String,
+    pub end: String,
+}
+
+impl Markers {
+    pub fn new(size: usize) -> Self {
+        Self {
+            head: "<".repeat(size),
+            ancestor: "|".repeat(size),
+            separator: "=".repeat(size),
+            end: ">".repeat(size),
+        }
+    }
+}
 
 /// Strips exactly the marker prefix from a line, returning the label (if any).
 /// Rejects lines where the marker is followed by a non-space character (e.g. 8+ repeated chars).
@@ -26,39 +56,425 @@ fn strip_marker<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
     }
 }
 
+/// Splits an opening marker of at least `min_size` `<` characters into its length and label.
+fn opening_marker(line: &str, min_size: usize) -> Option<(usize, &str)> {
+    let size = line.len() - line.trim_start_matches('<').len();
+    if size < min_size {
+        return None;
+    }
+    strip_marker(line, &line[..size]).map(|label| (size, label))
+}
+
+/// Reduce a marker label to a short name suitable for display.
+///
+/// Keeps the first whitespace-separated token and drops a `:path` suffix, so
+/// `1a2b3c4 (Fix the widget)` becomes `1a2b3c4` and `theirs:path/to/file` becomes `theirs`.
+/// Subversion's `.mine`, `.working` and `.r1234` style labels lose their leading dot, and
+/// Mercurial's `working copy: 1a2b3c4d5e6f - user: message` keeps its two-word name.
+pub fn short_label(label: &str) -> &str {
+    if let Some((name, _)) = label.split_once(':')
+        && HG_LABELS.contains(&name)
+    {
+        return name;
+    }
+    let token = label.split_whitespace().next().unwrap_or(label);
+    if let Some(name) = token.strip_prefix('.')
+        && is_svn_label(name)
+    {
+        return name;
+    }
+    match token.split_once(':') {
+        Some((name, _)) if !name.is_empty() => name,
+        _ => token,
+    }
+}
+
+/// The `:path` suffix of a marker label, as in `HEAD:src/old_name.rs` or, when rebasing,
+/// `1a2b3c4 (Fix the widget):src/old_name.rs`.
+fn label_path(label: &str) -> Option<&str> {
+    if HG_LABELS.iter().any(|name| label.starts_with(name)) {
+        return None;
+    }
+    let path = match label.find("):") {
+        Some(index) => &label[index + 2..],
+        None if label.contains('(') => return None,
+        None => label.split_once(':')?.1,
+    };
+    (!path.is_empty()).then_some(path)
+}
+
+/// The names Mercurial puts before the changeset on its markers that hold a space.
+const HG_LABELS: [&str; 2] = ["working copy", "merge rev"];
+
+/// Whether `name` is one of the labels Subversion puts on markers, without the leading dot.
+fn is_svn_label(name: &str) -> bool {
+    let revision = |name: &str| {
+        name.strip_prefix('r')
+            .is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+    };
+    name == "mine"
+        || name == "working"
+        || revision(name)
+        || name.strip_prefix("merge-left.").is_some_and(revision)
+        || name.strip_prefix("merge-right.").is_some_and(revision)
+}
+
 /// A single conflict region within a file.
 ///
-/// Each field holds the 0-based line number of the corresponding marker.
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// The marker fields hold the 0-based line number of the corresponding marker.
+/// `last_char` is the length of the end marker line in the position encoding, i.e. the
+/// LSP character position just past the marker and its label.
+///
+/// `head_label`, `ancestor_label` and `branch_label` are the full labels of this region's own
+/// markers. They can differ between regions, e.g. when a rebase stopped in a file that still
+/// held conflicts from an earlier merge, or after a recursive merge.
+///
+/// `yours` is the line of Perforce's `==== YOURS` marker. Perforce puts the ancestor first and
+/// our side last, so in its conflicts `head` and `ancestor` are both the opening `>>>> ORIGINAL`
+/// line, `branch` is the `==== THEIRS` line and our side runs from `yours` to `end`.
+///
+/// `bytes` holds the byte offsets of the same markers, for slicing the text without counting
+/// lines again. Regions built by hand rather than parsed leave it empty.
+///
+/// `head_path` and `branch_path` are the `:path` suffixes of the opening and end markers. Git
+/// adds them when a file was renamed, e.g. `<<<<<<< HEAD:src/old_name.rs`.
+///
+/// `marker_size` is the length of the region's markers. Git lengthens them for conflicts nested
+/// in another, and the `conflict-marker-size` attribute can set any length.
+///
+/// `depth` counts the conflicts this one is nested in. A recursive merge whose merge bases
+/// conflicted leaves those conflicts inside the ancestor section of the outer one.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ConflictRegion {
     pub head: u32,
     pub branch: u32,
     pub ancestor: Option<u32>,
     pub end: u32,
+    pub last_char: u32,
+    pub ancestor_label: Option<String>,
+    pub yours: Option<u32>,
+    pub depth: u32,
+    pub marker_size: usize,
+    pub head_path: Option<String>,
+    pub branch_path: Option<String>,
+    pub head_label: Option<String>,
+    pub branch_label: Option<String>,
+    pub bytes: MarkerBytes,
+}
+
+/// Byte offsets of a region's marker lines in the text it was parsed from, each running from
+/// the line's first byte to just past its line ending. Perforce's opening line is both `head`
+/// and `ancestor`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkerBytes {
+    pub head: std::ops::Range<usize>,
+    pub ancestor: Option<std::ops::Range<usize>>,
+    pub branch: std::ops::Range<usize>,
+    pub yours: Option<std::ops::Range<usize>>,
+    pub end: std::ops::Range<usize>,
+}
+
+/// The side of a conflict that contains the other side's change.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Superset {
+    Ours,
+    Theirs,
 }
 
 impl ConflictRegion {
     pub fn head_range(&self) -> (u32, u32) {
-        let end = self.ancestor.unwrap_or(self.branch);
-        (self.head, end)
+        match self.yours {
+            Some(yours) => (yours, self.end),
+            None => (self.head, self.ancestor.unwrap_or(self.branch)),
+        }
     }
 
     pub fn branch_range(&self) -> (u32, u32) {
-        (self.branch, self.end)
+        (self.branch, self.yours.unwrap_or(self.end))
     }
 
     pub fn ancestor_range(&self) -> Option<(u32, u32)> {
         self.ancestor.map(|pos| (pos, self.branch))
     }
+
+    /// The lines of our side, between the head marker and the ancestor (or separator) marker,
+    /// or Perforce's `==== YOURS` and `<<<<`.
+    ///
+    /// Like the other `*_text` accessors this is the exact slice of `content`, the document the
+    /// region was parsed from, with each line's ending and without the markers.
+    pub fn ours_text<'a>(&self, content: &'a str) -> &'a str {
+        text_between(content, self.head_range())
+    }
+
+    /// The lines of their side, between the separator and the end marker, or Perforce's
+    /// `==== THEIRS` and `==== YOURS`.
+    pub fn theirs_text<'a>(&self, content: &'a str) -> &'a str {
+        text_between(content, self.branch_range())
+    }
+
+    /// The paths each side's marker names when they differ, as when one branch renamed the file.
+    pub fn renamed_paths(&self) -> Option<(&str, &str)> {
+        match (&self.head_path, &self.branch_path) {
+            (Some(head), Some(branch)) if head != branch => Some((head, branch)),
+            _ => None,
+        }
+    }
+
+    /// The bytes of the whole conflict, from its opening marker through the end marker's line
+    /// ending.
+    pub fn byte_range(&self) -> std::ops::Range<usize> {
+        self.bytes.head.start..self.bytes.end.end
+    }
+
+    /// The bytes of our side, as [`ConflictRegion::ours_text`] slices them.
+    pub fn ours_bytes(&self) -> std::ops::Range<usize> {
+        let bytes = &self.bytes;
+        match &bytes.yours {
+            Some(yours) => yours.end..bytes.end.start,
+            None => bytes.head.end..bytes.ancestor.as_ref().unwrap_or(&bytes.branch).start,
+        }
+    }
+
+    /// The bytes of their side, as [`ConflictRegion::theirs_text`] slices them.
+    pub fn theirs_bytes(&self) -> std::ops::Range<usize> {
+        let bytes = &self.bytes;
+        bytes.branch.end..bytes.yours.as_ref().unwrap_or(&bytes.end).start
+    }
+
+    /// The bytes of the diff3 ancestor section, if the conflict has one.
+    pub fn ancestor_bytes(&self) -> Option<std::ops::Range<usize>> {
+        let bytes = &self.bytes;
+        bytes
+            .ancestor
+            .as_ref()
+            .map(|ancestor| ancestor.end..bytes.branch.start)
+    }
+
+    /// Whether our side has no lines, i.e. our branch deleted the block.
+    pub fn ours_empty(&self) -> bool {
+        let (start, end) = self.head_range();
+        start + 1 == end
+    }
+
+    /// Whether their side has no lines, i.e. their branch deleted the block.
+    pub fn theirs_empty(&self) -> bool {
+        let (start, end) = self.branch_range();
+        start + 1 == end
+    }
+
+    /// Whether both sides hold the same bytes, as often happens after a rebase.
+    pub fn sides_identical(&self, content: &str) -> bool {
+        self.ours_text(content) == self.theirs_text(content)
+    }
+
+    /// Whether the sides differ, but only in whitespace: the same words in the same order,
+    /// indented or wrapped differently.
+    pub fn sides_differ_only_in_whitespace(&self, content: &str) -> bool {
+        let ours = self.ours_text(content);
+        let theirs = self.theirs_text(content);
+        ours != theirs && ours.split_whitespace().eq(theirs.split_whitespace())
+    }
+
+    /// The side whose lines include all of the other side's lines, in the same order, when the
+    /// sides differ and neither is empty.
+    pub fn superset_side(&self, content: &str) -> Option<Superset> {
+        let ours = self.ours_text(content);
+        let theirs = self.theirs_text(content);
+        if ours == theirs || ours.is_empty() || theirs.is_empty() {
+            return None;
+        }
+        let contains = |outer: &str, inner: &str| {
+            let mut outer = lsp_lines(outer).map(|(_, line)| line);
+            lsp_lines(inner).all(|(_, line)| outer.any(|candidate| candidate == line))
+        };
+        if contains(ours, theirs) {
+            Some(Superset::Ours)
+        } else if contains(theirs, ours) {
+            Some(Superset::Theirs)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the end marker is the last line of `content` and has no line ending, so there is
+    /// no line after the conflict for edits to end on. Relies on [`ConflictRegion::bytes`], so
+    /// is always `false` for regions built by hand.
+    pub fn missing_newline(&self, content: &str) -> bool {
+        let end = &self.bytes.end;
+        end.end == content.len()
+            && content
+                .get(end.clone())
+                .is_some_and(|line| !line.is_empty() && !line.ends_with(['\n', '\r']))
+    }
+
+    /// The commit ids of both sides when each is a single full commit id, as git writes a
+    /// conflicting submodule pointer, with or without its `Subproject commit` prefix.
+    pub fn submodule_commits<'a>(&self, content: &'a str) -> Option<(&'a str, &'a str)> {
+        let commit = |side: &'a str| {
+            let line = side.trim();
+            let sha = line.strip_prefix("Subproject commit ").unwrap_or(line);
+            (matches!(sha.len(), 40 | 64) && sha.bytes().all(|b| b.is_ascii_hexdigit()))
+                .then_some(sha)
+        };
+        let ours = commit(self.ours_text(content))?;
+        let theirs = commit(self.theirs_text(content))?;
+        (ours != theirs).then_some((ours, theirs))
+    }
+
+    /// The lines of the diff3 ancestor section, if the conflict has one.
+    pub fn ancestor_text<'a>(&self, content: &'a str) -> Option<&'a str> {
+        self.ancestor_range()
+            .map(|range| text_between(content, range))
+    }
 }
 
-/// Parse result for a document: the branch/ancestor names and all conflict regions found.
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// Iterator over the lines of a text as LSP counts them.
+///
+/// `\n`, `\r\n` and a bare `\r` (classic Mac) all end a line. Yields the byte offset where
+/// each line starts along with the line without its terminator. Like `str::lines`, a
+/// trailing terminator does not produce an extra empty line.
+pub struct LspLines<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Iterator for LspLines<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.text.len() {
+            return None;
+        }
+        let start = self.offset;
+        let rest = &self.text[start..];
+        match rest.find(['\r', '\n']) {
+            Some(index) => {
+                let terminator = if rest[index..].starts_with("\r\n") {
+                    2
+                } else {
+                    1
+                };
+                self.offset = start + index + terminator;
+                Some((start, &rest[..index]))
+            }
+            None => {
+                self.offset = self.text.len();
+                Some((start, rest))
+            }
+        }
+    }
+}
+
+pub fn lsp_lines(text: &str) -> LspLines<'_> {
+    LspLines { text, offset: 0 }
+}
+
+/// Units LSP `character` offsets count in, negotiated with the client.
+///
+/// UTF-16 is the protocol default and what `FullTextDocument` works in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Pick the first encoding the client lists that the server supports. Clients that list
+    /// none only support UTF-16.
+    pub fn negotiate(client_encodings: Option<&[lsp_types::PositionEncodingKind]>) -> Self {
+        client_encodings
+            .unwrap_or_default()
+            .iter()
+            .find_map(|kind| match kind.as_str() {
+                "utf-8" => Some(Self::Utf8),
+                "utf-16" => Some(Self::Utf16),
+                "utf-32" => Some(Self::Utf32),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Length of `text` in this encoding's code units.
+    pub fn len(self, text: &str) -> usize {
+        match self {
+            Self::Utf8 => text.len(),
+            Self::Utf16 => text.encode_utf16().count(),
+            Self::Utf32 => text.chars().count(),
+        }
+    }
+
+    /// Convert a `character` offset into `line` from this encoding to UTF-16. Offsets inside
+    /// a character move to its end; offsets past the end of the line stop there, except in
+    /// UTF-16 where they are left as they are.
+    pub fn to_utf16(self, line: &str, character: u32) -> u32 {
+        let mut units = 0;
+        let mut utf16 = 0;
+        for c in line.chars() {
+            if units >= character as usize {
+                break;
+            }
+            units += self.len(c.encode_utf8(&mut [0; 4]));
+            utf16 += c.len_utf16() as u32;
+        }
+        if self == Self::Utf16 && units < character as usize {
+            character
+        } else {
+            utf16
+        }
+    }
+}
+
+impl From<PositionEncoding> for lsp_types::PositionEncodingKind {
+    fn from(encoding: PositionEncoding) -> Self {
+        match encoding {
+            PositionEncoding::Utf8 => Self::UTF8,
+            PositionEncoding::Utf16 => Self::UTF16,
+            PositionEncoding::Utf32 => Self::UTF32,
+        }
+    }
+}
+
+/// The line terminator used by the text, judged by its first line break. Defaults to `\n`.
+pub fn line_ending(text: &str) -> &'static str {
+    match text.find(['\r', '\n']) {
+        Some(index) if text[index..].starts_with("\r\n") => "\r\n",
+        Some(index) if text[index..].starts_with('\r') => "\r",
+        _ => "\n",
+    }
+}
+
+/// Byte offset of the start of the given 0-based line.
+fn line_offset(content: &str, line: u32) -> usize {
+    lsp_lines(content)
+        .nth(line as usize)
+        .map_or(content.len(), |(start, _)| start)
+}
+
+/// The text strictly between two marker lines, as returned by the `*_range` methods.
+///
+/// Includes the newline ending the last content line, so joining regions reproduces the file's lines.
+pub fn text_between(content: &str, (start, end): (u32, u32)) -> &str {
+    &content[line_offset(content, start + 1)..line_offset(content, end)]
+}
+
+/// Parse result for a document: the branch/ancestor names, all conflict regions found and
+/// warnings about markers that do not form a conflict.
+///
+/// The names are the full labels following the markers. Use the `*_name` accessors for display.
+/// A document whose only opening marker is never closed has warnings but no conflicts.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct MergeConflict {
     pub head: Option<String>,
     pub branch: Option<String>,
     pub ancestor: Option<String>,
     pub conflicts: Vec<ConflictRegion>,
+    pub warnings: Vec<ParseWarning>,
 }
 
 impl MergeConflict {
@@ -66,119 +482,842 @@ impl MergeConflict {
         self.conflicts.iter()
     }
 
+    /// Whether nothing is left to resolve: no conflicts, and no opening marker left unclosed or
+    /// markers out of order, which are the remains of one.
+    pub fn is_resolved(&self) -> bool {
+        self.conflicts.is_empty()
+            && !self.warnings.iter().any(|warning| {
+                matches!(
+                    warning.kind,
+                    WarningKind::Unterminated | WarningKind::OutOfOrder
+                )
+            })
+    }
+
+    pub fn head_name(&self) -> Option<&str> {
+        self.head.as_deref().map(short_label)
+    }
+
+    pub fn branch_name(&self) -> Option<&str> {
+        self.branch.as_deref().map(short_label)
+    }
+
+    pub fn ancestor_name(&self) -> Option<&str> {
+        self.ancestor.as_deref().map(short_label)
+    }
+
+    /// Display name of the given region's ours side, falling back to the document-wide name.
+    pub fn head_name_for<'a>(&'a self, region: &'a ConflictRegion) -> Option<&'a str> {
+        region
+            .head_label
+            .as_deref()
+            .map(short_label)
+            .or_else(|| self.head_name())
+    }
+
+    /// Display name of the given region's theirs side, falling back to the document-wide name.
+    pub fn branch_name_for<'a>(&'a self, region: &'a ConflictRegion) -> Option<&'a str> {
+        region
+            .branch_label
+            .as_deref()
+            .map(short_label)
+            .or_else(|| self.branch_name())
+    }
+
+    /// Display name of the given region's ancestor, falling back to the document-wide name.
+    pub fn ancestor_name_for<'a>(&'a self, region: &'a ConflictRegion) -> Option<&'a str> {
+        region
+            .ancestor_label
+            .as_deref()
+            .map(short_label)
+            .or_else(|| self.ancestor_name())
+    }
+
+    /// Name of `region`'s ours side in messages: the one `names` gives, else its marker's, else
+    /// the configured label.
+    pub fn ours_name<'a>(
+        &'a self,
+        region: &'a ConflictRegion,
+        names: &'a SideNames,
+        labels: &'a SideLabels,
+    ) -> Option<&'a str> {
+        names
+            .ours
+            .as_deref()
+            .or_else(|| self.head_name_for(region))
+            .or(labels.ours.as_deref())
+    }
+
+    /// Name of `region`'s theirs side in messages, like [`MergeConflict::ours_name`].
+    pub fn theirs_name<'a>(
+        &'a self,
+        region: &'a ConflictRegion,
+        names: &'a SideNames,
+        labels: &'a SideLabels,
+    ) -> Option<&'a str> {
+        names
+            .theirs
+            .as_deref()
+            .or_else(|| self.branch_name_for(region))
+            .or(labels.theirs.as_deref())
+    }
+
+    /// Whether other conflicts are nested in `region`. Those have to be resolved first.
+    pub fn has_nested(&self, region: &ConflictRegion) -> bool {
+        self.conflicts.iter().any(|other| {
+            other.depth > region.depth && region.head < other.head && other.end < region.end
+        })
+    }
+
+    /// The conflicts without others nested in them, the ones that can be resolved now.
+    pub fn resolvable(&self) -> impl Iterator<Item = &ConflictRegion> {
+        self.conflicts().filter(|region| !self.has_nested(region))
+    }
+
     #[allow(unused)]
     pub fn exists(&self) -> bool {
         !self.conflicts.is_empty()
     }
 }
 
+/// Reasons a document's conflict markers could not be parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// A line number or line length does not fit in the `u32` LSP positions use.
+    PositionOverflow,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PositionOverflow => write!(f, "document too large for LSP positions"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A marker line that is not part of a conflict. Parsing carries on past it.
+///
+/// `line` is the 0-based line of the marker and `last_char` its length in the position
+/// encoding, like [`ConflictRegion::last_char`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseWarning {
+    pub kind: WarningKind,
+    pub line: u32,
+    pub last_char: u32,
+}
+
+/// What is wrong with a [`ParseWarning`]'s marker.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WarningKind {
+    /// An opening marker that no end marker closes. The lines after it are parsed as if it
+    /// were not there.
+    Unterminated,
+    /// A separator outside any conflict.
+    StraySeparator,
+    /// An end marker outside any conflict.
+    StrayEnd,
+    /// A marker in the wrong place within a conflict, e.g. an end marker before the separator.
+    OutOfOrder,
+}
+
+impl ParseWarning {
+    /// A hint diagnostic on the marker line.
+    pub fn diagnostic(&self, source: &str) -> lsp_types::Diagnostic {
+        let message = match self.kind {
+            WarningKind::Unterminated => "conflict marker without an end marker",
+            WarningKind::StraySeparator => "conflict separator outside a conflict",
+            WarningKind::StrayEnd => "conflict end marker outside a conflict",
+            WarningKind::OutOfOrder => "conflict marker out of order",
+        };
+        lsp_types::Diagnostic {
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: self.line,
+                    character: 0,
+                },
+                end: lsp_types::Position {
+                    line: self.line,
+                    character: self.last_char,
+                },
+            },
+            message: message.to_owned(),
+            source: Some(source.to_owned()),
+            severity: Some(lsp_types::DiagnosticSeverity::HINT),
+            ..Default::default()
+        }
+    }
+}
+
+fn position(value: usize) -> Result<u32, ParseError> {
+    value.try_into().map_err(|_| ParseError::PositionOverflow)
+}
+
+/// Document flavours that change which lines may hold conflict markers.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Syntax {
+    #[default]
+    Plain,
+    /// Markers inside fenced code blocks are ignored.
+    Markdown,
+    /// A bare `<<<<<<<` directly under a line of text underlines a section title.
+    ReStructuredText,
+}
+
+impl Syntax {
+    /// Pick the syntax for an LSP `languageId`.
+    pub fn from_language_id(language_id: &str) -> Self {
+        match language_id {
+            "markdown" => Self::Markdown,
+            "restructuredtext" | "rst" => Self::ReStructuredText,
+            _ => Self::Plain,
+        }
+    }
+
+    /// Whether `line` is a title adornment rather than a conflict's opening marker.
+    ///
+    /// Git always labels the opening marker, so only a bare one can be an adornment. A bare
+    /// `=======` or `>>>>>>>` needs an open conflict to count, so those never start one.
+    fn is_title_adornment(self, previous: Option<&str>, line: &str) -> bool {
+        self == Self::ReStructuredText
+            && line.bytes().all(|b| b == b'<')
+            && previous.is_some_and(|previous| !previous.trim().is_empty())
+    }
+}
+
+/// Which conflict markers a document may contain.
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictStyle {
+    /// Git's two-way and diff3 layouts, and Perforce's.
+    #[default]
+    Auto,
+    /// Only the two-way layout git writes with `merge.conflictStyle = merge`. A `|||||||`
+    /// line is then ordinary content of our side.
+    Merge,
+    /// Only Perforce's `>>>> ORIGINAL`, `==== THEIRS`, `==== YOURS`, `<<<<` layout.
+    Perforce,
+}
+
+impl ConflictStyle {
+    fn git(self) -> bool {
+        self != Self::Perforce
+    }
+
+    fn perforce(self) -> bool {
+        matches!(self, Self::Auto | Self::Perforce)
+    }
+}
+
+/// Whether `line` starts like a marker of any length or style, git's or Perforce's.
+pub fn may_be_marker(line: &str) -> bool {
+    matches!(line.as_bytes().first(), Some(b'<' | b'|' | b'=' | b'>'))
+}
+
+/// Quick check whether `text` may hold conflicts at all, before parsing it.
+pub fn may_have_conflicts(text: &str, marker_size: usize) -> bool {
+    text.contains(&Markers::new(marker_size).head)
+        || text.contains(&format!("{PERFORCE_ORIGINAL} ORIGINAL"))
+}
+
+/// Perforce's markers. Their length is fixed.
+const PERFORCE_ORIGINAL: &str = ">>>>";
+const PERFORCE_SEPARATOR: &str = "====";
+const PERFORCE_END: &str = "<<<<";
+
+/// The label of a Perforce marker line when it is `marker` followed by `keyword`, e.g.
+/// `THEIRS //depot/file#3` for `==== THEIRS //depot/file#3`.
+fn perforce_label<'a>(line: &'a str, marker: &str, keyword: &str) -> Option<&'a str> {
+    strip_marker(line, marker).filter(|label| short_label(label) == keyword)
+}
+
+/// Names shown for conflict sides whose markers carry none.
+#[derive(Clone, Debug, Default, serde::Deserialize, Eq, PartialEq, serde::Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SideLabels {
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+    pub ancestor: Option<String>,
+}
+
+/// Names replacing the ones on the markers of every conflict in a document, as during a
+/// rebase, where `HEAD` is the upstream branch and the other side the commit being replayed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SideNames {
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+/// Everything besides the text that decides how it is parsed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseOptions {
+    pub syntax: Syntax,
+    /// Units `last_char` is counted in.
+    pub encoding: PositionEncoding,
+    pub style: ConflictStyle,
+    /// Length of the markers, from git's `conflict-marker-size` attribute.
+    pub marker_size: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            syntax: Syntax::default(),
+            encoding: PositionEncoding::default(),
+            style: ConflictStyle::default(),
+            marker_size: DEFAULT_MARKER_SIZE,
+        }
+    }
+}
+
+/// An open Markdown code fence: the fence character and how many of them opened it.
 #[derive(Debug)]
-enum ParseState {
+struct Fence {
+    marker: u8,
+    len: usize,
+}
+
+impl Fence {
+    /// Splits a line into its fence run and the remainder, allowing up to three spaces of indent.
+    fn split(line: &str) -> Option<(u8, usize, &str)> {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if indent > 3 {
+            return None;
+        }
+        let line = &line[indent..];
+        let marker = *line.as_bytes().first()?;
+        if marker != b'`' && marker != b'~' {
+            return None;
+        }
+        let len = line.len() - line.trim_start_matches(marker as char).len();
+        (len >= 3).then(|| (marker, len, &line[len..]))
+    }
+
+    fn open(line: &str) -> Option<Self> {
+        let (marker, len, info) = Self::split(line)?;
+        // Backtick fences cannot have backticks in their info string.
+        if marker == b'`' && info.contains('`') {
+            return None;
+        }
+        Some(Self { marker, len })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        matches!(
+            Self::split(line),
+            Some((marker, len, rest)) if marker == self.marker && len >= self.len && rest.trim().is_empty()
+        )
+    }
+}
+
+#[derive(Debug)]
+enum ParseState<'a> {
     Scanning,
     ExpectAncestorOrBranch(u32),
     ExpectEnd(u32, u32),
-    ExpectBranchFromAncestor(u32, u32),
-    ExpectEndWithAncestor(u32, u32, u32),
+    ExpectBranchFromAncestor(u32, u32, Option<&'a str>),
+    ExpectEndWithAncestor(u32, u32, u32, Option<&'a str>),
+    ExpectPerforceTheirs(u32, Option<&'a str>),
+    ExpectPerforceYours(u32, u32, Option<&'a str>),
+    ExpectPerforceEnd(u32, u32, u32, Option<&'a str>),
+}
+
+impl ParseState<'_> {
+    /// Line of the opening marker of the conflict being read.
+    fn head(&self) -> Option<u32> {
+        match *self {
+            ParseState::Scanning => None,
+            ParseState::ExpectPerforceTheirs(head, _)
+            | ParseState::ExpectPerforceYours(head, _, _)
+            | ParseState::ExpectPerforceEnd(head, _, _, _)
+            | ParseState::ExpectAncestorOrBranch(head)
+            | ParseState::ExpectEnd(head, _)
+            | ParseState::ExpectBranchFromAncestor(head, _, _)
+            | ParseState::ExpectEndWithAncestor(head, _, _, _) => Some(head),
+        }
+    }
 }
 
 /// Parse all merge conflict regions from the given document text.
-pub fn parse(text: &str) -> anyhow::Result<Option<MergeConflict>> {
-    let mut conflicts = Vec::new();
-    let mut state = ParseState::Scanning;
-
-    // Only need to capture the first name for each marker. The names are the same in each region.
-    let mut head_name = None;
-    let mut ancestor_name = None;
-    let mut branch_name = None;
-
-    for (lineno, line) in text.lines().enumerate() {
-        let first = line.as_bytes().first();
-        match state {
-            ParseState::Scanning => {
-                if first == Some(&b'<')
-                    && let Some(name) = strip_marker(line, MARKER_HEAD)
-                {
-                    let head = lineno.try_into()?;
-                    if !name.is_empty() && head_name.is_none() {
-                        head_name.replace(name);
+pub fn parse(text: &str) -> Result<Option<MergeConflict>, ParseError> {
+    parse_with_options(text, ParseOptions::default())
+}
+
+/// Parse all merge conflict regions, applying the rules for the document's syntax and the
+/// expected conflict style.
+pub fn parse_with_options(
+    text: &str,
+    options: ParseOptions,
+) -> Result<Option<MergeConflict>, ParseError> {
+    let mut parsed = conflicts(text, options);
+    let mut conflicts = parsed.by_ref().collect::<Result<Vec<_>, _>>()?;
+    let warnings = parsed.into_warnings();
+    // Stray markers alone are more likely content, like a heading's underline, than a conflict
+    // gone wrong.
+    if conflicts.is_empty()
+        && !warnings
+            .iter()
+            .any(|warning| warning.kind == WarningKind::Unterminated)
+    {
+        return Ok(None);
+    }
+    // Nested conflicts are closed before the ones around them.
+    conflicts.sort_by_key(|region| region.head);
+    // The document's names are the first ones on the outermost markers. Regions keep their own,
+    // which can differ.
+    let first = |label: fn(&ConflictRegion) -> Option<&String>| {
+        conflicts
+            .iter()
+            .filter(|region| region.depth == 0)
+            .find_map(label)
+            .cloned()
+    };
+    Ok(Some(MergeConflict {
+        head: first(|region| region.head_label.as_ref()),
+        branch: first(|region| region.branch_label.as_ref()),
+        ancestor: first(|region| region.ancestor_label.as_ref()),
+        conflicts,
+        warnings,
+    }))
+}
+
+/// Parse conflict regions lazily, each as soon as its end marker is read.
+///
+/// Regions come in the order their end markers appear, so a nested conflict comes before the
+/// one around it, once that is closed too. Markers that do not form a conflict are collected as
+/// warnings instead; an opening marker that is never closed is skipped and the lines after it
+/// parsed again, once. Stopping early skips the rest of the text, e.g.
+/// `conflicts(text, options).next().is_some()` only reads up to the first conflict.
+pub fn conflicts(text: &str, options: ParseOptions) -> Conflicts<'_> {
+    Conflicts {
+        text,
+        options,
+        lines: lsp_lines(text),
+        lineno: 0,
+        marker_lines: Vec::new(),
+        previous_line: None,
+        fence: None,
+        markers: Markers::new(options.marker_size),
+        default_markers: Markers::new(options.marker_size),
+        state: ParseState::Scanning,
+        outer: Vec::new(),
+        head_label: None,
+        perforce_theirs: None,
+        warnings: Vec::new(),
+        warnings_at_open: 0,
+        candidates: Vec::new(),
+        unterminated: std::collections::BTreeSet::new(),
+        pending: std::collections::VecDeque::new(),
+        done: false,
+    }
+}
+
+/// Iterator over the conflict regions of a text, see [`conflicts`].
+pub struct Conflicts<'a> {
+    text: &'a str,
+    options: ParseOptions,
+    lines: LspLines<'a>,
+    /// How many lines were read so far.
+    lineno: usize,
+    /// The number and bytes, with the line ending, of each line read so far that may be a
+    /// marker, one starting with `<`, `|`, `=` or `>`. Only those are looked up again, so other
+    /// lines are not kept.
+    marker_lines: Vec<(u32, std::ops::Range<usize>)>,
+    previous_line: Option<&'a str>,
+    fence: Option<Fence>,
+    /// The markers of the conflict being read, as long as its opening marker.
+    markers: Markers,
+    /// The markers at the expected length, for spotting stray ones between conflicts.
+    default_markers: Markers,
+    state: ParseState<'a>,
+    /// The conflicts the current one is nested in, innermost last.
+    outer: Vec<(ParseState<'a>, Markers, Option<&'a str>)>,
+    /// The label of the current conflict's opening marker, or Perforce's `==== YOURS`.
+    head_label: Option<&'a str>,
+    /// The label of Perforce's `==== THEIRS`, which comes before ours.
+    perforce_theirs: Option<&'a str>,
+    warnings: Vec<ParseWarning>,
+    /// How many warnings there were when the outermost open conflict was opened. Those found
+    /// inside it are dropped if it turns out to be unterminated.
+    warnings_at_open: usize,
+    /// Opening markers read as content of an open conflict that could not close if that
+    /// conflict never does, with the nesting depth of that conflict: its state ignores the
+    /// marker, so neither sees an end marker the other does not.
+    candidates: Vec<(usize, u32)>,
+    /// Opening markers known not to be closed, from `candidates` and the conflicts nested in one
+    /// that was not. Scanning warns about them instead of reading on to the end once again.
+    unterminated: std::collections::BTreeSet<u32>,
+    /// Regions read but not yielded yet. Nested ones wait for the conflict around them to
+    /// close, as it may turn out to be unterminated.
+    pending: std::collections::VecDeque<ConflictRegion>,
+    done: bool,
+}
+
+impl Iterator for Conflicts<'_> {
+    type Item = Result<ConflictRegion, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_region().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+impl<'a> Conflicts<'a> {
+    /// The warnings about markers read so far.
+    pub fn into_warnings(self) -> Vec<ParseWarning> {
+        self.warnings
+    }
+
+    /// Read lines up to the end of the next conflict.
+    fn next_region(&mut self) -> Result<Option<ConflictRegion>, ParseError> {
+        loop {
+            if let Some(region) = self.pending.pop_front() {
+                return Ok(Some(region));
+            }
+            if self.read_lines()? {
+                continue;
+            }
+            // An unclosed conflict is reported where the outermost one opened.
+            let state = match self.outer.first() {
+                Some((state, ..)) => state,
+                None => &self.state,
+            };
+            let Some(head) = state.head() else {
+                return Ok(None);
+            };
+            tracing::warn!("incomplete conflict found: {:?}", state);
+            // Every conflict still open was not closed either, nor any candidate left.
+            let nested = self.outer.iter().skip(1).map(|(state, ..)| state);
+            let nested = nested.chain((!self.outer.is_empty()).then_some(&self.state));
+            let nested: Vec<u32> = nested.filter_map(ParseState::head).collect();
+            self.unterminated.extend(nested);
+            self.unterminated
+                .extend(self.candidates.drain(..).map(|(_, line)| line));
+            self.reopen_after(head)?;
+        }
+    }
+
+    /// Give up on the conflict opened at `head`: warn about its opening marker and go back to
+    /// scanning from the line after it.
+    fn reopen_after(&mut self, head: u32) -> Result<(), ParseError> {
+        let text = self.text;
+        let range = self.marker_line_bytes(head);
+        let line = lsp_lines(&text[range.clone()])
+            .next()
+            .map_or("", |(_, line)| line);
+        self.warnings.truncate(self.warnings_at_open);
+        self.pending.clear();
+        self.warn(WarningKind::Unterminated, head as usize, line)?;
+        self.lines = LspLines {
+            text,
+            offset: range.end,
+        };
+        self.lineno = head as usize + 1;
+        let kept = self.marker_lines.partition_point(|(line, _)| *line <= head);
+        self.marker_lines.truncate(kept);
+        self.previous_line = Some(line);
+        self.state = ParseState::Scanning;
+        self.outer.clear();
+        Ok(())
+    }
+
+    fn warn(&mut self, kind: WarningKind, lineno: usize, line: &str) -> Result<(), ParseError> {
+        tracing::debug!("{kind:?} marker at {lineno}");
+        self.warnings.push(ParseWarning {
+            kind,
+            line: position(lineno)?,
+            last_char: position(self.options.encoding.len(line))?,
+        });
+        Ok(())
+    }
+
+    /// Read lines until an outermost conflict ends, leaving it and those nested in it pending.
+    /// `false` at the end of the text.
+    fn read_lines(&mut self) -> Result<bool, ParseError> {
+        let ParseOptions {
+            syntax,
+            encoding,
+            style,
+            marker_size,
+        } = self.options;
+        while let Some((start, line)) = self.lines.next() {
+            let lineno = self.lineno;
+            self.lineno += 1;
+            if may_be_marker(line) {
+                self.marker_lines
+                    .push((position(lineno)?, start..self.lines.offset));
+            }
+            let previous = self.previous_line.replace(line);
+            // Fences are only tracked between conflicts. A fence line inside a conflict is part
+            // of one side's content, not a boundary.
+            if syntax == Syntax::Markdown && matches!(self.state, ParseState::Scanning) {
+                if let Some(open) = &self.fence {
+                    if open.is_closed_by(line) {
+                        self.fence = None;
                     }
-                    tracing::debug!("Found conflict, {:?}, {:?}", head_name, head);
-                    state = ParseState::ExpectAncestorOrBranch(head);
+                    continue;
                 }
+                if let Some(opened) = Fence::open(line) {
+                    self.fence = Some(opened);
+                    continue;
+                }
+            }
+            let first = line.as_bytes().first();
+            if first == Some(&b'<')
+                && matches!(
+                    self.state,
+                    ParseState::ExpectAncestorOrBranch(_)
+                        | ParseState::ExpectEnd(..)
+                        | ParseState::ExpectEndWithAncestor(..)
+                )
+                && !syntax.is_title_adornment(previous, line)
+                && opening_marker(line, marker_size)
+                    .is_some_and(|(size, _)| size == self.markers.head.len())
+            {
+                self.candidates.push((self.outer.len(), position(lineno)?));
             }
-            ParseState::ExpectAncestorOrBranch(head) => {
-                if first == Some(&b'|')
-                    && let Some(name) = strip_marker(line, MARKER_ANCESTOR)
-                {
-                    let ancestor = lineno.try_into()?;
-                    if !name.is_empty() && ancestor_name.is_none() {
-                        ancestor_name.replace(name);
+            // An opening marker in an ancestor section starts a conflict nested in it.
+            if first == Some(&b'<')
+                && matches!(self.state, ParseState::ExpectBranchFromAncestor(..))
+                && !syntax.is_title_adornment(previous, line)
+                && let Some((size, name)) = opening_marker(line, marker_size)
+            {
+                let head = position(lineno)?;
+                tracing::debug!("Found nested conflict, {:?}", head);
+                self.outer.push((
+                    std::mem::replace(&mut self.state, ParseState::ExpectAncestorOrBranch(head)),
+                    std::mem::replace(&mut self.markers, Markers::new(size)),
+                    std::mem::replace(&mut self.head_label, (!name.is_empty()).then_some(name)),
+                ));
+                continue;
+            }
+            match self.state {
+                ParseState::Scanning => {
+                    if first == Some(&b'>')
+                        && style.perforce()
+                        && let Some(label) = perforce_label(line, PERFORCE_ORIGINAL, "ORIGINAL")
+                    {
+                        let head = position(lineno)?;
+                        tracing::debug!("Found Perforce conflict, {:?}", head);
+                        self.warnings_at_open = self.warnings.len();
+                        self.state = ParseState::ExpectPerforceTheirs(head, Some(label));
+                    } else if first == Some(&b'<')
+                        && style.git()
+                        && !syntax.is_title_adornment(previous, line)
+                        && let Some((size, name)) = opening_marker(line, marker_size)
+                    {
+                        if self.unterminated.remove(&position(lineno)?) {
+                            self.warn(WarningKind::Unterminated, lineno, line)?;
+                            continue;
+                        }
+                        self.markers = Markers::new(size);
+                        self.head_label = (!name.is_empty()).then_some(name);
+                        let head = position(lineno)?;
+                        tracing::debug!("Found conflict, {:?}, {:?}", self.head_label, head);
+                        self.warnings_at_open = self.warnings.len();
+                        self.state = ParseState::ExpectAncestorOrBranch(head);
+                    } else if style.git()
+                        && syntax == Syntax::Plain
+                        && first == Some(&b'=')
+                        && line == self.default_markers.separator
+                    {
+                        self.warn(WarningKind::StraySeparator, lineno, line)?;
+                    } else if style.git()
+                        && first == Some(&b'>')
+                        && strip_marker(line, &self.default_markers.end).is_some()
+                    {
+                        self.warn(WarningKind::StrayEnd, lineno, line)?;
                     }
-                    tracing::debug!("Found ancestor, {:?}, {:?}", ancestor_name, ancestor);
-                    state = ParseState::ExpectBranchFromAncestor(head, ancestor);
-                } else if first == Some(&b'=') && line == MARKER_SEPARATOR {
-                    let branch = lineno.try_into()?;
-                    tracing::debug!("Found branch, {:?}", branch);
-                    state = ParseState::ExpectEnd(head, branch);
                 }
-            }
-            ParseState::ExpectEnd(head, branch) => {
-                if first == Some(&b'>')
-                    && let Some(name) = strip_marker(line, MARKER_END)
-                {
-                    if !name.is_empty() && branch_name.is_none() {
-                        branch_name.replace(name);
+                ParseState::ExpectAncestorOrBranch(head) => {
+                    if first == Some(&b'|')
+                        && style != ConflictStyle::Merge
+                        && let Some(name) = strip_marker(line, &self.markers.ancestor)
+                    {
+                        let ancestor = position(lineno)?;
+                        tracing::debug!("Found ancestor, {:?}, {:?}", name, ancestor);
+                        let label = (!name.is_empty()).then_some(name);
+                        self.state = ParseState::ExpectBranchFromAncestor(head, ancestor, label);
+                    } else if first == Some(&b'=') && line == self.markers.separator {
+                        let branch = position(lineno)?;
+                        tracing::debug!("Found branch, {:?}", branch);
+                        self.state = ParseState::ExpectEnd(head, branch);
+                    } else if first == Some(&b'>')
+                        && strip_marker(line, &self.markers.end).is_some()
+                    {
+                        self.warn(WarningKind::OutOfOrder, lineno, line)?;
                     }
-                    tracing::debug!("Found end, {:?} {:?}", branch_name, lineno);
-                    conflicts.push(ConflictRegion {
-                        head,
-                        branch,
-                        ancestor: None,
-                        end: lineno.try_into()?,
-                    });
-                    state = ParseState::Scanning;
                 }
-            }
-            ParseState::ExpectBranchFromAncestor(head, ancestor) => {
-                if first == Some(&b'=') && line == "=======" {
-                    let branch = lineno.try_into()?;
-                    tracing::debug!("Found branch, {:?}", branch);
-                    state = ParseState::ExpectEndWithAncestor(head, ancestor, branch);
+                ParseState::ExpectEnd(head, branch) => {
+                    if first == Some(&b'>')
+                        && let Some(name) = strip_marker(line, &self.markers.end)
+                    {
+                        tracing::debug!("Found end, {:?} {:?}", name, lineno);
+                        let region = ConflictRegion {
+                            head,
+                            branch,
+                            ancestor: None,
+                            end: position(lineno)?,
+                            last_char: position(encoding.len(line))?,
+                            ancestor_label: None,
+                            yours: None,
+                            depth: position(self.outer.len())?,
+                            marker_size: self.markers.head.len(),
+                            head_path: self.head_label.and_then(label_path).map(String::from),
+                            branch_path: label_path(name).map(String::from),
+                            head_label: self.head_label.map(String::from),
+                            branch_label: (!name.is_empty()).then(|| name.to_owned()),
+                            bytes: MarkerBytes::default(),
+                        };
+                        let region = self.finish(region);
+                        self.pending.push_back(region);
+                        self.close();
+                        if matches!(self.state, ParseState::Scanning) {
+                            return Ok(true);
+                        }
+                    } else if first == Some(&b'|')
+                        && style != ConflictStyle::Merge
+                        && strip_marker(line, &self.markers.ancestor).is_some()
+                    {
+                        self.warn(WarningKind::OutOfOrder, lineno, line)?;
+                    }
                 }
-            }
-            ParseState::ExpectEndWithAncestor(head, ancestor, branch) => {
-                if first == Some(&b'>')
-                    && let Some(name) = strip_marker(line, MARKER_END)
-                {
-                    if !name.is_empty() && branch_name.is_none() {
-                        branch_name.replace(name);
+                ParseState::ExpectBranchFromAncestor(head, ancestor, label) => {
+                    if first == Some(&b'=') && line == self.markers.separator {
+                        let branch = position(lineno)?;
+                        tracing::debug!("Found branch, {:?}", branch);
+                        self.state =
+                            ParseState::ExpectEndWithAncestor(head, ancestor, branch, label);
+                    } else if first == Some(&b'>')
+                        && strip_marker(line, &self.markers.end).is_some()
+                    {
+                        self.warn(WarningKind::OutOfOrder, lineno, line)?;
+                    }
+                }
+                ParseState::ExpectEndWithAncestor(head, ancestor, branch, label) => {
+                    if first == Some(&b'>')
+                        && let Some(name) = strip_marker(line, &self.markers.end)
+                    {
+                        tracing::debug!("Found end, {:?} {:?}", name, lineno);
+                        let region = ConflictRegion {
+                            head,
+                            branch,
+                            ancestor: Some(ancestor),
+                            end: position(lineno)?,
+                            last_char: position(encoding.len(line))?,
+                            ancestor_label: label.map(String::from),
+                            yours: None,
+                            depth: position(self.outer.len())?,
+                            marker_size: self.markers.head.len(),
+                            head_path: self.head_label.and_then(label_path).map(String::from),
+                            branch_path: label_path(name).map(String::from),
+                            head_label: self.head_label.map(String::from),
+                            branch_label: (!name.is_empty()).then(|| name.to_owned()),
+                            bytes: MarkerBytes::default(),
+                        };
+                        let region = self.finish(region);
+                        self.pending.push_back(region);
+                        self.close();
+                        if matches!(self.state, ParseState::Scanning) {
+                            return Ok(true);
+                        }
+                    } else if first == Some(&b'|')
+                        && style != ConflictStyle::Merge
+                        && strip_marker(line, &self.markers.ancestor).is_some()
+                    {
+                        self.warn(WarningKind::OutOfOrder, lineno, line)?;
+                    }
+                }
+                ParseState::ExpectPerforceTheirs(head, label) => {
+                    if first == Some(&b'=')
+                        && let Some(name) = perforce_label(line, PERFORCE_SEPARATOR, "THEIRS")
+                    {
+                        self.perforce_theirs = Some(name);
+                        let branch = position(lineno)?;
+                        self.state = ParseState::ExpectPerforceYours(head, branch, label);
+                    }
+                }
+                ParseState::ExpectPerforceYours(head, branch, label) => {
+                    if first == Some(&b'=')
+                        && let Some(name) = perforce_label(line, PERFORCE_SEPARATOR, "YOURS")
+                    {
+                        self.head_label = Some(name);
+                        let yours = position(lineno)?;
+                        self.state = ParseState::ExpectPerforceEnd(head, branch, yours, label);
+                    }
+                }
+                ParseState::ExpectPerforceEnd(head, branch, yours, label) => {
+                    if first == Some(&b'<') && strip_marker(line, PERFORCE_END).is_some() {
+                        tracing::debug!("Found Perforce end, {:?}", lineno);
+                        let region = ConflictRegion {
+                            head,
+                            branch,
+                            ancestor: Some(head),
+                            end: position(lineno)?,
+                            last_char: position(encoding.len(line))?,
+                            ancestor_label: label.map(String::from),
+                            yours: Some(yours),
+                            depth: 0,
+                            marker_size: PERFORCE_END.len(),
+                            head_path: None,
+                            branch_path: None,
+                            head_label: self.head_label.map(String::from),
+                            branch_label: self.perforce_theirs.map(String::from),
+                            bytes: MarkerBytes::default(),
+                        };
+                        let region = self.finish(region);
+                        self.pending.push_back(region);
+                        self.state = ParseState::Scanning;
+                        return Ok(true);
                     }
-                    tracing::debug!("Found end, {:?} {:?}", branch_name, lineno);
-                    conflicts.push(ConflictRegion {
-                        head,
-                        branch,
-                        ancestor: Some(ancestor),
-                        end: lineno.try_into()?,
-                    });
-                    state = ParseState::Scanning;
                 }
             }
         }
+        Ok(false)
     }
-    if !matches!(state, ParseState::Scanning) {
-        tracing::warn!("incomplete conflict found: {:?}", state);
-        anyhow::bail!("Error: incomplete conflict found: {:?}", state);
+
+    /// The bytes of marker line `line`, with its line ending.
+    fn marker_line_bytes(&self, line: u32) -> std::ops::Range<usize> {
+        self.marker_lines
+            .binary_search_by_key(&line, |(marker_line, _)| *marker_line)
+            .map(|index| self.marker_lines[index].1.clone())
+            .unwrap_or_default()
     }
 
-    if conflicts.is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(MergeConflict {
-            head: head_name.map(String::from),
-            branch: branch_name.map(String::from),
-            ancestor: ancestor_name.map(String::from),
-            conflicts,
-        }))
+    /// Go back to the conflict the one just closed was nested in, or to scanning.
+    fn close(&mut self) {
+        // What it ignored may still close.
+        let depth = self.outer.len();
+        self.candidates
+            .retain(|(candidate_depth, _)| *candidate_depth < depth);
+        self.state = match self.outer.pop() {
+            Some((state, markers, head_label)) => {
+                self.markers = markers;
+                self.head_label = head_label;
+                state
+            }
+            None => ParseState::Scanning,
+        };
+    }
+
+    /// Fill in what is learned from the region's lines once its end marker is read.
+    fn finish(&self, mut region: ConflictRegion) -> ConflictRegion {
+        let line_bytes = |line: u32| self.marker_line_bytes(line);
+        region.bytes = MarkerBytes {
+            head: line_bytes(region.head),
+            ancestor: region.ancestor.map(line_bytes),
+            branch: line_bytes(region.branch),
+            yours: region.yours.map(line_bytes),
+            end: line_bytes(region.end),
+        };
+        region
     }
 }
 
@@ -201,23 +1340,56 @@ impl ConflictRegion {
     }
 }
 
-/// Build the LSP range covering the entire conflict, including the end marker line.
+/// Build the LSP range a code action replaces: the entire conflict, including the end marker line.
 ///
 /// The range extends to `end + 1` so that applying a replacement removes the
-/// trailing newline of the end marker rather than leaving a blank line behind.
-pub fn range_for_diagnostic_conflict(conflict: &ConflictRegion) -> lsp_types::Range {
+/// trailing newline of the end marker rather than leaving a blank line behind. An end marker
+/// without a line ending has no line after it, so the range stops at the end of the marker.
+/// `content` is the text the conflict was parsed from.
+pub fn range_for_conflict_edit(content: &str, conflict: &ConflictRegion) -> lsp_types::Range {
     let start = lsp_types::Position {
         line: conflict.head,
         character: 0,
     };
-    let end = lsp_types::Position {
+    lsp_types::Range {
+        start,
+        end: conflict_edit_end(content, conflict),
+    }
+}
+
+/// Where an edit removing everything through the conflict's end marker line ends.
+pub fn conflict_edit_end(content: &str, conflict: &ConflictRegion) -> lsp_types::Position {
+    if conflict.missing_newline(content) {
+        lsp_types::Position {
+            line: conflict.end,
+            character: conflict.last_char,
+        }
+    } else {
         // This is a product of the code action not wanting to leave a dangling new line behind.
-        line: conflict.end + 1,
+        lsp_types::Position {
+            line: conflict.end + 1,
+            character: 0,
+        }
+    }
+}
+
+/// Build the LSP range reported for the conflict's diagnostic.
+///
+/// Unlike [`range_for_conflict_edit`] this ends on the end marker line itself so
+/// editors do not extend the squiggle into the following line.
+pub fn range_for_diagnostic_conflict(conflict: &ConflictRegion) -> lsp_types::Range {
+    let start = lsp_types::Position {
+        line: conflict.head,
         character: 0,
     };
+    let end = lsp_types::Position {
+        line: conflict.end,
+        character: conflict.last_char,
+    };
     lsp_types::Range { start, end }
 }
 
+/// A diagnostic covering the conflict, with the region itself, as JSON, in its `data`.
 impl From<&ConflictRegion> for lsp_types::Diagnostic {
     fn from(conflict: &ConflictRegion) -> Self {
         let range = range_for_diagnostic_conflict(conflict);
@@ -228,11 +1400,106 @@ impl From<&ConflictRegion> for lsp_types::Diagnostic {
             message: message.to_owned(),
             source: Some(source.to_owned()),
             severity: Some(lsp_types::DiagnosticSeverity::ERROR),
+            data: serde_json::to_value(conflict).ok(),
             ..Default::default()
         }
     }
 }
 
+impl MergeConflict {
+    /// Diagnostic message for one of this document's conflicts.
+    ///
+    /// Includes both sides' names when `names`, the markers or `labels` provide them, e.g.
+    /// "merge conflict: main vs feature-x".
+    pub fn diagnostic_message(
+        &self,
+        region: &ConflictRegion,
+        names: &SideNames,
+        labels: &SideLabels,
+    ) -> String {
+        let head = self.ours_name(region, names, labels);
+        let branch = self.theirs_name(region, names, labels);
+        match (head, branch) {
+            (Some(head), Some(branch)) => format!("merge conflict: {head} vs {branch}"),
+            _ => "merge conflict".to_owned(),
+        }
+    }
+
+    /// Diagnostics for every region, and hints for every warning, in document order with
+    /// exact-range duplicates removed. `content` is the text the conflicts were parsed from.
+    ///
+    /// The order does not depend on how the parser found the regions, so clients do not
+    /// redraw identical diagnostics.
+    pub fn diagnostics(
+        &self,
+        content: &str,
+        source: &str,
+        severity: lsp_types::DiagnosticSeverity,
+        names: &SideNames,
+        labels: &SideLabels,
+    ) -> Vec<lsp_types::Diagnostic> {
+        let mut diagnostics: Vec<lsp_types::Diagnostic> = self
+            .conflicts()
+            .map(|region| self.diagnostic(content, region, source, severity, names, labels))
+            .chain(
+                self.warnings
+                    .iter()
+                    .map(|warning| warning.diagnostic(source)),
+            )
+            .collect();
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.range.start, diagnostic.range.end));
+        diagnostics.dedup_by_key(|diagnostic| diagnostic.range);
+        diagnostics
+    }
+
+    /// Build the diagnostic for one of this document's conflict regions, found in `content`.
+    pub fn diagnostic(
+        &self,
+        content: &str,
+        region: &ConflictRegion,
+        source: &str,
+        severity: lsp_types::DiagnosticSeverity,
+        names: &SideNames,
+        labels: &SideLabels,
+    ) -> lsp_types::Diagnostic {
+        let mut message = self.diagnostic_message(region, names, labels);
+        if region.sides_differ_only_in_whitespace(content) {
+            message.push_str(" (whitespace only)");
+        }
+        if region.submodule_commits(content).is_some() {
+            message
+                .push_str(" (submodule pointer: each side is a commit the submodule can point to)");
+        }
+        let ours = || self.ours_name(region, names, labels).unwrap_or("HEAD");
+        let theirs = || self.theirs_name(region, names, labels).unwrap_or("branch");
+        if let Some(side) = region.superset_side(content) {
+            let name = match side {
+                Superset::Ours => ours(),
+                Superset::Theirs => theirs(),
+            };
+            message.push_str(&format!(" ({name} contains the other change)"));
+        }
+        if let Some((head, branch)) = region.renamed_paths() {
+            message.push_str(&format!(
+                " (renamed: {head} in {}, {branch} in {})",
+                ours(),
+                theirs()
+            ));
+        }
+        match (region.ours_empty(), region.theirs_empty()) {
+            (true, false) => message.push_str(&format!(" ({} deleted this block)", ours())),
+            (false, true) => message.push_str(&format!(" ({} deleted this block)", theirs())),
+            _ => {}
+        }
+        lsp_types::Diagnostic {
+            message,
+            source: Some(source.to_owned()),
+            severity: Some(severity),
+            ..lsp_types::Diagnostic::from(region)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use rstest::*;
@@ -240,13 +1507,79 @@ mod test {
     use super::*;
     #[allow(unused_imports)]
     use crate::test_helpers::init_logging;
+    use crate::test_helpers::{TEXT2_WITH_CONFLICTS, conflicts_for_text2_with_conflicts};
     use crate::{conflict_text, diff3_conflict_text};
 
+    /// A warning of `kind` about the 7 character marker on `line`.
+    fn warning(kind: WarningKind, line: u32) -> ParseWarning {
+        ParseWarning {
+            kind,
+            line,
+            last_char: 7,
+        }
+    }
+
     #[rstest]
     fn incomplete_conflict_markers() {
         let text = "foo\n<<<<<<<\nbar\nbaz\n";
-        let result = parse(text);
-        assert!(result.is_err());
+        let merge_conflict = parse(text).expect("successful parse").unwrap();
+        assert!(merge_conflict.conflicts.is_empty());
+        assert_eq!(
+            vec![warning(WarningKind::Unterminated, 1)],
+            merge_conflict.warnings
+        );
+    }
+
+    #[rstest]
+    fn incomplete_conflict_keeps_the_others() {
+        let text = concat!(conflict_text!("ours", "theirs"), "<<<<<<<\nours\n=======\n");
+        let merge_conflict = parse(text).expect("successful parse").unwrap();
+        assert_eq!(1, merge_conflict.conflicts.len());
+        assert_eq!(
+            vec![
+                warning(WarningKind::Unterminated, 5),
+                warning(WarningKind::StraySeparator, 7)
+            ],
+            merge_conflict.warnings
+        );
+        let diagnostics = merge_conflict.diagnostics(
+            text,
+            "merge",
+            lsp_types::DiagnosticSeverity::ERROR,
+            &SideNames::default(),
+            &SideLabels::default(),
+        );
+        let hints: Vec<_> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Some(lsp_types::DiagnosticSeverity::HINT))
+            .map(|diagnostic| (diagnostic.range.start.line, diagnostic.message.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (5, "conflict marker without an end marker"),
+                (7, "conflict separator outside a conflict")
+            ],
+            hints
+        );
+    }
+
+    #[rstest]
+    #[case::end_before_separator("<<<<<<<\na\n>>>>>>>\n=======\nb\n>>>>>>>\n", 2)]
+    #[case::ancestor_after_separator("<<<<<<<\na\n=======\n|||||||\nb\n>>>>>>>\n", 3)]
+    fn out_of_order_markers_are_warnings(#[case] text: &str, #[case] line: u32) {
+        let merge_conflict = parse(text).expect("successful parse").unwrap();
+        assert_eq!(1, merge_conflict.conflicts.len());
+        assert_eq!(
+            vec![warning(WarningKind::OutOfOrder, line)],
+            merge_conflict.warnings
+        );
+    }
+
+    #[rstest]
+    #[case::separator("Title\n=======\n")]
+    #[case::end(">>>>>>> quoted\n")]
+    fn stray_markers_alone_are_not_reported(#[case] text: &str) {
+        assert_eq!(Ok(None), parse(text));
     }
 
     #[fixture]
@@ -256,6 +1589,16 @@ mod test {
             branch: 10,
             ancestor: Some(6),
             end: 12,
+            last_char: 7,
+            ancestor_label: None,
+            yours: None,
+            depth: 0,
+            marker_size: 7,
+            head_path: None,
+            branch_path: None,
+            head_label: None,
+            branch_label: None,
+            bytes: MarkerBytes::default(),
         }
     }
 
@@ -299,6 +1642,39 @@ mod test {
         assert!(conflict.is_in_range(&range), "{conflict:?} v. {range:?}");
     }
 
+    #[rstest]
+    fn edit_range_matching_conflict_is_in_conflict(conflict: ConflictRegion) {
+        let range = range_for_conflict_edit("", &conflict);
+        assert!(conflict.is_in_range(&range), "{conflict:?} v. {range:?}");
+    }
+
+    #[rstest]
+    fn diagnostic_range_stops_at_end_marker_while_edit_range_consumes_newline(
+        conflict: ConflictRegion,
+    ) {
+        let diagnostic_range = range_for_diagnostic_conflict(&conflict);
+        let edit_range = range_for_conflict_edit("", &conflict);
+        assert_eq!(diagnostic_range.start, edit_range.start);
+        assert_eq!(
+            lsp_types::Position {
+                line: conflict.end,
+                character: conflict.last_char,
+            },
+            diagnostic_range.end
+        );
+        assert_eq!(
+            lsp_types::Position {
+                line: conflict.end + 1,
+                character: 0,
+            },
+            edit_range.end
+        );
+        assert_eq!(
+            diagnostic_range,
+            lsp_types::Diagnostic::from(&conflict).range
+        );
+    }
+
     #[rstest]
     fn range_wider_than_conflict_is_not_in_conflict(conflict: ConflictRegion) {
         let range = lsp_types::Range {
@@ -327,7 +1703,23 @@ mod test {
             head: 1,
             branch: 4,
             end: 7,
+            last_char: 7,
             ancestor: None,
+            ancestor_label: None,
+            yours: None,
+            depth: 0,
+            marker_size: 7,
+            head_path: None,
+            branch_path: None,
+            head_label: None,
+            branch_label: None,
+            bytes: MarkerBytes {
+                head: 10..18,
+                ancestor: None,
+                branch: 41..49,
+                yours: None,
+                end: 75..83,
+            },
         };
         assert_eq!(expected, merge_conflict.conflicts[0]);
     }
@@ -354,14 +1746,46 @@ mod test {
             head: 1,
             branch: 4,
             end: 7,
+            last_char: 14,
             ancestor: None,
+            ancestor_label: None,
+            yours: None,
+            depth: 0,
+            marker_size: 7,
+            head_path: None,
+            branch_path: None,
+            head_label: Some("thing1".to_owned()),
+            branch_label: Some("thing2".to_owned()),
+            bytes: MarkerBytes {
+                head: 10..25,
+                ancestor: None,
+                branch: 48..56,
+                yours: None,
+                end: 82..97,
+            },
         };
         assert_eq!(expected, merge_conflict.conflicts[0]);
         let expected = ConflictRegion {
             head: 9,
             branch: 13,
             end: 17,
+            last_char: 14,
             ancestor: None,
+            ancestor_label: None,
+            yours: None,
+            depth: 0,
+            marker_size: 7,
+            head_path: None,
+            branch_path: None,
+            head_label: Some("thing1".to_owned()),
+            branch_label: Some("thing2".to_owned()),
+            bytes: MarkerBytes {
+                head: 98..113,
+                ancestor: None,
+                branch: 126..134,
+                yours: None,
+                end: 146..161,
+            },
         };
         assert_eq!(expected, merge_conflict.conflicts[1]);
     }
@@ -385,10 +1809,365 @@ mod test {
             ancestor: Some(4),
             branch: 6,
             end: 9,
+            last_char: 7,
+            ancestor_label: None,
+            yours: None,
+            depth: 0,
+            marker_size: 7,
+            head_path: None,
+            branch_path: None,
+            head_label: None,
+            branch_label: None,
+            bytes: MarkerBytes {
+                head: 10..18,
+                ancestor: Some(41..49),
+                branch: 64..72,
+                yours: None,
+                end: 98..106,
+            },
         };
         assert_eq!(expected, merge_conflict.conflicts[0]);
     }
 
+    #[rstest]
+    #[case::longer(
+        9,
+        "<<<<<<<<< ours\na\n|||||||||\nb\n=========\nc\n>>>>>>>>> theirs\n",
+        1
+    )]
+    #[case::shorter(5, "<<<<< ours\na\n=====\nc\n>>>>> theirs\n", 1)]
+    #[case::default_length_is_content(9, conflict_text!("ours", "a", "theirs", "c"), 0)]
+    fn marker_size_sets_marker_length(
+        #[case] marker_size: usize,
+        #[case] input: &str,
+        #[case] count: usize,
+    ) {
+        let options = ParseOptions {
+            marker_size,
+            ..Default::default()
+        };
+        let merge_conflict = parse_with_options(input, options).expect("successful parse");
+        assert_eq!(
+            count,
+            merge_conflict.map_or(0, |merge_conflict| merge_conflict.conflicts.len())
+        );
+    }
+
+    #[rstest]
+    #[case::default_length(conflict_text!("ours", "a", "theirs", "c"), 7, 14)]
+    #[case::longer(
+        "<<<<<<<<<<<<<<< ours\na\n===============\nc\n>>>>>>>>>>>>>>> theirs\n",
+        15,
+        22
+    )]
+    #[case::unlabelled("<<<<<<<<<\na\n=========\nc\n>>>>>>>>>\n", 9, 9)]
+    fn longer_markers_set_the_conflict_width(
+        #[case] input: &str,
+        #[case] marker_size: usize,
+        #[case] last_char: u32,
+    ) {
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let region = &merge_conflict.conflicts[0];
+        assert_eq!(marker_size, region.marker_size);
+        assert_eq!(last_char, region.last_char);
+        assert_eq!("a\n", region.ours_text(input));
+    }
+
+    #[rstest]
+    fn markers_of_another_width_are_content() {
+        let input = "<<<<<<<<< ours\na\n=======\n>>>>>>>\n=========\nc\n>>>>>>>>> theirs\n";
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let region = &merge_conflict.conflicts[0];
+        assert_eq!((4, 6), (region.branch, region.end));
+        assert_eq!("a\n=======\n>>>>>>>\n", region.ours_text(input));
+    }
+
+    #[rstest]
+    fn nested_conflicts_can_use_longer_markers() {
+        let input = concat!(
+            "<<<<<<< HEAD\n",
+            "ours\n",
+            "||||||| merged common ancestors\n",
+            "<<<<<<<<< Temporary merge branch 1\n",
+            "base 1\n",
+            "=========\n",
+            "base 2\n",
+            ">>>>>>>>> Temporary merge branch 2\n",
+            "=======\n",
+            "theirs\n",
+            ">>>>>>> feature\n",
+        );
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let widths: Vec<_> = merge_conflict
+            .conflicts()
+            .map(|region| (region.head, region.end, region.marker_size))
+            .collect();
+        assert_eq!(vec![(0, 10, 7), (3, 7, 9)], widths);
+    }
+
+    const PERFORCE_TEXT: &str = concat!(
+        "before\n",
+        ">>>> ORIGINAL //depot/notes.txt#1\n",
+        "base\n",
+        "==== THEIRS //depot/notes.txt#2\n",
+        "theirs\n",
+        "==== YOURS //client/notes.txt\n",
+        "ours\n",
+        "<<<<\n",
+    );
+
+    const OURS_SHA: &str = "a1b2c3d4e5f60718293a4b5c6d7e8f9012345678";
+    const THEIRS_SHA: &str = "d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8091a2b3c4d";
+
+    #[rstest]
+    #[case::bare(OURS_SHA, THEIRS_SHA, true)]
+    #[case::subproject_prefix(
+        &format!("Subproject commit {OURS_SHA}"),
+        &format!("Subproject commit {THEIRS_SHA}"),
+        true
+    )]
+    #[case::abbreviated(&OURS_SHA[..7], &THEIRS_SHA[..7], false)]
+    #[case::same_commit(OURS_SHA, OURS_SHA, false)]
+    #[case::more_lines(&format!("{OURS_SHA}\n{OURS_SHA}"), THEIRS_SHA, false)]
+    fn submodule_pointer_conflicts_are_recognised(
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] submodule: bool,
+    ) {
+        let text = format!("<<<<<<< HEAD\n{ours}\n=======\n{theirs}\n>>>>>>> feature\n");
+        let merge_conflict = parse(&text).expect("successful parse").unwrap();
+        let region = &merge_conflict.conflicts[0];
+        assert_eq!(submodule, region.submodule_commits(&text).is_some());
+        let diagnostic = merge_conflict.diagnostic(
+            &text,
+            region,
+            "merge",
+            lsp_types::DiagnosticSeverity::ERROR,
+            &SideNames::default(),
+            &SideLabels::default(),
+        );
+        assert_eq!(submodule, diagnostic.message.contains("submodule pointer"));
+    }
+
+    #[rstest]
+    #[case::merge("HEAD:src/old_name.rs", Some("src/old_name.rs"))]
+    #[case::rebase("1a2b3c4 (Fix: the widget):src/new_name.rs", Some("src/new_name.rs"))]
+    #[case::subject_with_colon("1a2b3c4 (Fix: the widget)", None)]
+    #[case::mercurial("working copy: 1a2b3c4d5e6f - user: message", None)]
+    #[case::plain("feature", None)]
+    #[case::empty_path("feature:", None)]
+    fn label_path_finds_the_path_suffix(#[case] label: &str, #[case] expected: Option<&str>) {
+        assert_eq!(expected, label_path(label));
+    }
+
+    #[rstest]
+    fn renamed_paths_are_shown_in_the_diagnostic() {
+        let input = conflict_text!(
+            "HEAD:src/old_name.rs",
+            "ours",
+            "feature:src/new_name.rs",
+            "theirs"
+        );
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let region = &merge_conflict.conflicts[0];
+        let diagnostic = merge_conflict.diagnostic(
+            input,
+            region,
+            "merge",
+            lsp_types::DiagnosticSeverity::ERROR,
+            &SideNames::default(),
+            &SideLabels::default(),
+        );
+        assert_eq!(
+            Some(("src/old_name.rs", "src/new_name.rs")),
+            region.renamed_paths()
+        );
+        assert_eq!(
+            "merge conflict: HEAD vs feature (renamed: src/old_name.rs in HEAD, src/new_name.rs in feature)",
+            diagnostic.message
+        );
+    }
+
+    /// A recursive merge's conflict whose virtual ancestor holds a conflict of its own.
+    const NESTED_TEXT: &str = concat!(
+        "<<<<<<< HEAD\n",
+        "ours\n",
+        "||||||| merged common ancestors\n",
+        "<<<<<<< Temporary merge branch 1\n",
+        "base 1\n",
+        "=======\n",
+        "base 2\n",
+        ">>>>>>> Temporary merge branch 2\n",
+        "=======\n",
+        "theirs\n",
+        ">>>>>>> feature\n",
+    );
+
+    #[rstest]
+    fn nested_conflicts_pair_their_own_markers() {
+        let merge_conflict = parse(NESTED_TEXT).expect("successful parse").unwrap();
+        let lines: Vec<_> = merge_conflict
+            .conflicts()
+            .map(|region| (region.head, region.branch, region.end, region.depth))
+            .collect();
+        assert_eq!(vec![(0, 8, 10, 0), (3, 5, 7, 1)], lines);
+        assert_eq!(Some("HEAD"), merge_conflict.head_name());
+        assert_eq!(Some("feature"), merge_conflict.branch_name());
+        assert!(merge_conflict.has_nested(&merge_conflict.conflicts[0]));
+        assert!(!merge_conflict.has_nested(&merge_conflict.conflicts[1]));
+        let resolvable: Vec<_> = merge_conflict
+            .resolvable()
+            .map(|region| region.head)
+            .collect();
+        assert_eq!(vec![3], resolvable);
+    }
+
+    #[rstest]
+    fn conflicts_are_yielded_as_their_end_markers_are_read() {
+        let text = format!("{NESTED_TEXT}<<<<<<< HEAD\nunfinished\n");
+        let mut conflicts = conflicts(&text, ParseOptions::default());
+        let heads: Vec<_> = conflicts
+            .by_ref()
+            .take(2)
+            .map(|region| region.map(|region| region.head))
+            .collect();
+        assert_eq!(vec![Ok(3), Ok(0)], heads);
+        assert_eq!(None, conflicts.next());
+        assert_eq!(
+            vec![ParseWarning {
+                kind: WarningKind::Unterminated,
+                line: 11,
+                last_char: 12,
+            }],
+            conflicts.into_warnings()
+        );
+    }
+
+    #[rstest]
+    fn unclosed_outer_conflict_reports_its_start() {
+        let text = NESTED_TEXT.trim_end_matches(">>>>>>> feature\n");
+        let merge_conflict = parse(text).expect("successful parse").unwrap();
+        let lines: Vec<_> = merge_conflict
+            .conflicts()
+            .map(|region| (region.head, region.depth))
+            .collect();
+        assert_eq!(vec![(3, 0)], lines);
+        assert_eq!(
+            vec![
+                ParseWarning {
+                    kind: WarningKind::Unterminated,
+                    line: 0,
+                    last_char: 12,
+                },
+                warning(WarningKind::StraySeparator, 8)
+            ],
+            merge_conflict.warnings
+        );
+    }
+
+    #[rstest]
+    #[case::lone_openers("<<<<<<< HEAD\n", 1)]
+    #[case::openers_with_separators("<<<<<<< HEAD\n=======\n", 2)]
+    #[case::nested_in_ancestors("<<<<<<< HEAD\n||||||| base\n", 1)]
+    fn unclosed_openers_are_parsed_in_linear_time(#[case] lines: &str, #[case] per_repeat: usize) {
+        let text = lines.repeat(20_000);
+        let started = std::time::Instant::now();
+        let merge_conflict = parse(&text).expect("successful parse").unwrap();
+        // Rereading the rest of the text after each opener took minutes.
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert!(merge_conflict.conflicts.is_empty());
+        assert_eq!(20_000 * per_repeat, merge_conflict.warnings.len());
+    }
+
+    #[rstest]
+    fn perforce_conflicts_put_our_side_last() {
+        let merge_conflict = parse(PERFORCE_TEXT).expect("successful parse").unwrap();
+        let region = &merge_conflict.conflicts[0];
+        assert_eq!(
+            ConflictRegion {
+                head: 1,
+                branch: 3,
+                ancestor: Some(1),
+                end: 7,
+                last_char: 4,
+                ancestor_label: Some("ORIGINAL //depot/notes.txt#1".to_owned()),
+                yours: Some(5),
+                depth: 0,
+                marker_size: 4,
+                head_path: None,
+                branch_path: None,
+                head_label: Some("YOURS //client/notes.txt".to_owned()),
+                branch_label: Some("THEIRS //depot/notes.txt#2".to_owned()),
+                bytes: MarkerBytes {
+                    head: 7..41,
+                    ancestor: Some(7..41),
+                    branch: 46..78,
+                    yours: Some(85..115),
+                    end: 120..125
+                },
+            },
+            *region
+        );
+        assert_eq!("ours\n", region.ours_text(PERFORCE_TEXT));
+        assert_eq!("theirs\n", region.theirs_text(PERFORCE_TEXT));
+        assert_eq!(Some("base\n"), region.ancestor_text(PERFORCE_TEXT));
+        assert_eq!(Some("YOURS"), merge_conflict.head_name());
+        assert_eq!(Some("THEIRS"), merge_conflict.branch_name());
+        assert_eq!(Some("ORIGINAL"), merge_conflict.ancestor_name());
+    }
+
+    #[rstest]
+    #[case::auto_git(ConflictStyle::Auto, conflict_text!("ours", "theirs"), true)]
+    #[case::auto_perforce(ConflictStyle::Auto, PERFORCE_TEXT, true)]
+    #[case::merge_perforce(ConflictStyle::Merge, PERFORCE_TEXT, false)]
+    #[case::perforce_git(ConflictStyle::Perforce, conflict_text!("ours", "theirs"), false)]
+    #[case::perforce_perforce(ConflictStyle::Perforce, PERFORCE_TEXT, true)]
+    fn conflict_style_picks_the_marker_grammar(
+        #[case] style: ConflictStyle,
+        #[case] input: &str,
+        #[case] found: bool,
+    ) {
+        let options = ParseOptions {
+            style,
+            ..Default::default()
+        };
+        let merge_conflict = parse_with_options(input, options).expect("successful parse");
+        assert_eq!(found, merge_conflict.is_some());
+    }
+
+    #[rstest]
+    fn incomplete_perforce_conflict_is_a_warning() {
+        let input = ">>>> ORIGINAL\nbase\n==== THEIRS\ntheirs\n";
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        assert!(merge_conflict.conflicts.is_empty());
+        assert_eq!(
+            vec![ParseWarning {
+                kind: WarningKind::Unterminated,
+                line: 0,
+                last_char: 13,
+            }],
+            merge_conflict.warnings
+        );
+    }
+
+    #[rstest]
+    fn merge_style_treats_ancestor_marker_as_content() {
+        let input = diff3_conflict_text!("ours", "base", "theirs");
+        let options = ParseOptions {
+            style: ConflictStyle::Merge,
+            ..Default::default()
+        };
+        let merge_conflict = parse_with_options(input, options)
+            .expect("unsuccessful parse")
+            .unwrap();
+        assert_eq!(1, merge_conflict.conflicts.len());
+        assert_eq!(None, merge_conflict.conflicts[0].ancestor);
+        assert_eq!(
+            "ours\n|||||||\nbase\n",
+            text_between(input, merge_conflict.conflicts[0].head_range())
+        );
+    }
+
     #[rstest]
     fn finds_diff3_conflict_with_names() {
         let input = concat!(
@@ -410,7 +2189,534 @@ mod test {
             ancestor: Some(4),
             branch: 6,
             end: 9,
+            last_char: 13,
+            ancestor_label: Some("ancestor".to_owned()),
+            yours: None,
+            depth: 0,
+            marker_size: 7,
+            head_path: None,
+            branch_path: None,
+            head_label: Some("original".to_owned()),
+            branch_label: Some("other".to_owned()),
+            bytes: MarkerBytes {
+                head: 10..27,
+                ancestor: Some(50..67),
+                branch: 82..90,
+                yours: None,
+                end: 116..130,
+            },
         };
         assert_eq!(expected, merge_conflict.conflicts[0]);
     }
+
+    #[rstest]
+    fn side_texts_are_exact_slices() {
+        let input =
+            "<<<<<<< HEAD\r\nours\r\n||||||| base\r\nbase\r\n=======\r\n>>>>>>> feature\r\n";
+        let region = &parse(input).expect("successful parse").unwrap().conflicts[0];
+        assert_eq!("ours\r\n", region.ours_text(input));
+        assert_eq!(Some("base\r\n"), region.ancestor_text(input));
+        assert_eq!("", region.theirs_text(input));
+    }
+
+    #[rstest]
+    #[case::crlf_diff3(
+        "before\r\n<<<<<<< HEAD\r\nours\r\n||||||| base\r\nbase\r\n=======\r\n>>>>>>> feature\r\nafter"
+    )]
+    #[case::perforce(PERFORCE_TEXT)]
+    #[case::no_final_newline("<<<<<<<\na\n=======\nb\n>>>>>>>")]
+    fn byte_ranges_slice_the_same_text(#[case] input: &str) {
+        let region = &parse(input).expect("successful parse").unwrap().conflicts[0];
+        assert_eq!(region.ours_text(input), &input[region.ours_bytes()]);
+        assert_eq!(region.theirs_text(input), &input[region.theirs_bytes()]);
+        assert_eq!(
+            region.ancestor_text(input),
+            region.ancestor_bytes().map(|range| &input[range])
+        );
+        let conflict = &input[region.byte_range()];
+        assert_eq!(
+            (region.end - region.head + 1) as usize,
+            lsp_lines(conflict).count()
+        );
+        assert!(conflict.starts_with(['<', '>']));
+    }
+
+    #[rstest]
+    #[case::newline("a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> b\n", false, (6, 0))]
+    #[case::crlf("a\r\n<<<<<<< HEAD\r\nours\r\n=======\r\ntheirs\r\n>>>>>>> b\r\n", false, (6, 0))]
+    #[case::bare_cr("a\r<<<<<<< HEAD\rours\r=======\rtheirs\r>>>>>>> b\r", false, (6, 0))]
+    #[case::no_final_newline("a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> b", true, (5, 9))]
+    fn edit_range_ends_after_the_end_marker_line(
+        #[case] input: &str,
+        #[case] missing_newline: bool,
+        #[case] (line, character): (u32, u32),
+    ) {
+        let region = &parse(input).expect("successful parse").unwrap().conflicts[0];
+        assert_eq!(missing_newline, region.missing_newline(input));
+        assert_eq!(
+            lsp_types::Position { line, character },
+            range_for_conflict_edit(input, region).end
+        );
+    }
+
+    #[rstest]
+    fn conflicts_round_trip_through_json() {
+        let merge_conflict = parse(PERFORCE_TEXT).expect("successful parse").unwrap();
+        let json = serde_json::to_value(&merge_conflict).unwrap();
+        assert_eq!(json["conflicts"][0]["yours"], 5);
+        assert_eq!(json["conflicts"][0]["bytes"]["end"]["start"], 120);
+        assert_eq!(
+            merge_conflict,
+            serde_json::from_value::<MergeConflict>(json).unwrap()
+        );
+        let diagnostic = lsp_types::Diagnostic::from(&merge_conflict.conflicts[0]);
+        assert_eq!(
+            Some(merge_conflict.conflicts[0].clone()),
+            diagnostic
+                .data
+                .and_then(|data| serde_json::from_value(data).ok())
+        );
+    }
+
+    #[rstest]
+    fn each_conflict_keeps_its_own_names() {
+        let input = concat!(
+            conflict_text!("HEAD", "ours", "feature", "theirs"),
+            conflict_text!("1a2b3c4 (Fix the widget)", "ours", "main", "theirs"),
+        );
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let messages: Vec<_> = merge_conflict
+            .conflicts()
+            .map(|region| {
+                merge_conflict.diagnostic_message(
+                    region,
+                    &SideNames::default(),
+                    &SideLabels::default(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "merge conflict: HEAD vs feature",
+                "merge conflict: 1a2b3c4 vs main"
+            ],
+            messages
+        );
+        assert_eq!(Some("HEAD"), merge_conflict.head_name());
+    }
+
+    #[rstest]
+    fn diagnostic_message_without_names() {
+        let input = conflict_text!("ours", "theirs");
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        assert_eq!(
+            "merge conflict",
+            merge_conflict.diagnostic_message(
+                &merge_conflict.conflicts[0],
+                &SideNames::default(),
+                &SideLabels::default()
+            )
+        );
+    }
+
+    #[rstest]
+    fn diagnostic_message_falls_back_to_labels() {
+        let input = conflict_text!("main", "ours", "", "theirs");
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let labels = SideLabels {
+            ours: Some("Current change".to_owned()),
+            theirs: Some("Incoming change".to_owned()),
+            ancestor: None,
+        };
+        assert_eq!(
+            "merge conflict: main vs Incoming change",
+            merge_conflict.diagnostic_message(
+                &merge_conflict.conflicts[0],
+                &SideNames::default(),
+                &labels
+            )
+        );
+    }
+
+    #[rstest]
+    fn diagnostic_names_replace_marker_names() {
+        let input = "<<<<<<< HEAD\n=======\ntheirs\n>>>>>>> 1a2b3c4 (Fix the widget)\n";
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let names = SideNames {
+            ours: Some("upstream (main)".to_owned()),
+            theirs: Some("your commit (1a2b3c4)".to_owned()),
+        };
+        let diagnostic = merge_conflict.diagnostic(
+            input,
+            &merge_conflict.conflicts[0],
+            "merge",
+            lsp_types::DiagnosticSeverity::ERROR,
+            &names,
+            &SideLabels::default(),
+        );
+        assert_eq!(
+            "merge conflict: upstream (main) vs your commit (1a2b3c4) \
+             (upstream (main) deleted this block)",
+            diagnostic.message
+        );
+    }
+
+    #[rstest]
+    #[case::reindented("fn main() {\n    run();\n}", "fn main() {\n\trun();\n}", true)]
+    #[case::rewrapped("call(a, b)", "call(a,\n     b)", true)]
+    #[case::joined_words("call(a, b)", "call(a,b)", false)]
+    #[case::trailing_space("value", "value  ", true)]
+    #[case::different_words("run();", "walk();", false)]
+    #[case::identical("same", "same", false)]
+    fn whitespace_only_conflicts_are_flagged(
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: bool,
+    ) {
+        let input = format!("<<<<<<< HEAD\n{ours}\n=======\n{theirs}\n>>>>>>> feature\n");
+        let merge_conflict = parse(&input).expect("successful parse").unwrap();
+        assert_eq!(
+            expected,
+            merge_conflict.conflicts[0].sides_differ_only_in_whitespace(&input)
+        );
+    }
+
+    #[rstest]
+    #[case::ours_adds_a_line("a\nb\nc", "a\nc", Some(Superset::Ours))]
+    #[case::theirs_adds_lines("a", "x\na\ny", Some(Superset::Theirs))]
+    #[case::reordered("a\nb\nc", "c\na", None)]
+    #[case::both_add("a\nb", "a\nc", None)]
+    #[case::identical("a", "a", None)]
+    #[case::one_side_empty("a", "", None)]
+    fn superset_side_holds_every_line_of_the_other(
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: Option<Superset>,
+    ) {
+        let theirs = if theirs.is_empty() {
+            String::new()
+        } else {
+            format!("{theirs}\n")
+        };
+        let input = format!("<<<<<<< HEAD\n{ours}\n=======\n{theirs}>>>>>>> feature\n");
+        let merge_conflict = parse(&input).expect("successful parse").unwrap();
+        assert_eq!(expected, merge_conflict.conflicts[0].superset_side(&input));
+    }
+
+    #[rstest]
+    fn superset_diagnostic_names_the_side() {
+        let input = conflict_text!("HEAD", "a", "feature", "a\nb");
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let diagnostic = merge_conflict.diagnostic(
+            input,
+            &merge_conflict.conflicts[0],
+            "merge",
+            lsp_types::DiagnosticSeverity::ERROR,
+            &SideNames::default(),
+            &SideLabels::default(),
+        );
+        assert_eq!(
+            "merge conflict: HEAD vs feature (feature contains the other change)",
+            diagnostic.message
+        );
+    }
+
+    #[rstest]
+    #[case::ours_deleted(
+        "<<<<<<< HEAD\n=======\nkept\n>>>>>>> feature\n",
+        "merge conflict: HEAD vs feature (HEAD deleted this block)"
+    )]
+    #[case::theirs_deleted(
+        "<<<<<<< HEAD\nkept\n=======\n>>>>>>> feature\n",
+        "merge conflict: HEAD vs feature (feature deleted this block)"
+    )]
+    fn deletion_diagnostic_names_the_deleting_side(#[case] input: &str, #[case] expected: &str) {
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let diagnostic = merge_conflict.diagnostic(
+            input,
+            &merge_conflict.conflicts[0],
+            "merge",
+            lsp_types::DiagnosticSeverity::ERROR,
+            &SideNames::default(),
+            &SideLabels::default(),
+        );
+        assert_eq!(expected, diagnostic.message);
+    }
+
+    #[rstest]
+    fn whitespace_only_diagnostic_says_so() {
+        let input = conflict_text!("HEAD", "  value", "feature", "value");
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let diagnostic = merge_conflict.diagnostic(
+            input,
+            &merge_conflict.conflicts[0],
+            "merge",
+            lsp_types::DiagnosticSeverity::ERROR,
+            &SideNames::default(),
+            &SideLabels::default(),
+        );
+        assert_eq!(
+            "merge conflict: HEAD vs feature (whitespace only)",
+            diagnostic.message
+        );
+    }
+
+    #[rstest]
+    #[case::plain("feature-x", "feature-x")]
+    #[case::commit_subject("1a2b3c4 (Fix the widget)", "1a2b3c4")]
+    #[case::path_ref("theirs:path/to/file", "theirs")]
+    #[case::leading_colon(":path/to/file", ":path/to/file")]
+    #[case::svn_mine(".mine", "mine")]
+    #[case::svn_revision(".r1234", "r1234")]
+    #[case::svn_merge(".merge-right.r7", "merge-right.r7")]
+    #[case::dotfile(".gitignore", ".gitignore")]
+    #[case::hg_local("local: 1a2b3c4d5e6f - test: Write ours", "local")]
+    #[case::hg_working_copy("working copy: 1a2b3c4d5e6f - test: Write ours", "working copy")]
+    #[case::hg_merge_rev("merge rev:    6f5e4d3c2b1a - test: Write theirs", "merge rev")]
+    fn short_label_extracts_display_name(#[case] label: &str, #[case] expected: &str) {
+        assert_eq!(expected, short_label(label));
+    }
+
+    #[rstest]
+    fn region_text_helpers_extract_each_side_of_diff3_conflict() {
+        let input = concat!(
+            "some test\n",
+            diff3_conflict_text!(
+                "other text.\nmore text.",
+                "original text.",
+                "replaced text.\nlast text."
+            ),
+            "\nthe end.\n",
+        );
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let region = &merge_conflict.conflicts[0];
+        assert_eq!("other text.\nmore text.\n", region.ours_text(input));
+        assert_eq!(Some("original text.\n"), region.ancestor_text(input));
+        assert_eq!("replaced text.\nlast text.\n", region.theirs_text(input));
+    }
+
+    #[rstest]
+    fn region_text_helpers_without_ancestor() {
+        let input = conflict_text!("ours", "theirs");
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        let region = &merge_conflict.conflicts[0];
+        assert_eq!("ours\n", region.ours_text(input));
+        assert_eq!(None, region.ancestor_text(input));
+        assert_eq!("theirs\n", region.theirs_text(input));
+    }
+
+    #[rstest]
+    #[case::lf("a\nb\n", vec![(0, "a"), (2, "b")])]
+    #[case::crlf("a\r\nb\r\n", vec![(0, "a"), (3, "b")])]
+    #[case::cr("a\rb\r", vec![(0, "a"), (2, "b")])]
+    #[case::no_trailing_terminator("a\rb", vec![(0, "a"), (2, "b")])]
+    #[case::blank_lines("\r\n\n", vec![(0, ""), (2, "")])]
+    fn lsp_lines_splits_on_every_line_ending(
+        #[case] text: &str,
+        #[case] expected: Vec<(usize, &str)>,
+    ) {
+        assert_eq!(expected, lsp_lines(text).collect::<Vec<_>>());
+    }
+
+    #[rstest]
+    fn finds_conflicts_with_carriage_return_line_endings() {
+        let input = TEXT2_WITH_CONFLICTS.replace('\n', "\r");
+        let merge_conflict = parse(&input).expect("successful parse").unwrap();
+        assert_eq!(conflicts_for_text2_with_conflicts(), merge_conflict);
+        let region = &merge_conflict.conflicts[0];
+        assert_eq!("plain old\r", region.ours_text(&input));
+        assert_eq!("new and improved\r", region.theirs_text(&input));
+        assert_eq!("\r", line_ending(&input));
+    }
+
+    #[rstest]
+    fn keeps_each_regions_ancestor_label() {
+        let input = concat!(
+            diff3_conflict_text!(
+                "HEAD",
+                "ours",
+                "1111111 base one",
+                "base",
+                "feature",
+                "theirs"
+            ),
+            "between\n",
+            diff3_conflict_text!(
+                "HEAD",
+                "ours",
+                "2222222 base two",
+                "base",
+                "feature",
+                "theirs"
+            ),
+        );
+        let merge_conflict = parse(input).expect("successful parse").unwrap();
+        assert_eq!(Some("1111111 base one".to_owned()), merge_conflict.ancestor);
+        let labels: Vec<_> = merge_conflict
+            .conflicts()
+            .map(|region| region.ancestor_label.as_deref())
+            .collect();
+        assert_eq!(
+            vec![Some("1111111 base one"), Some("2222222 base two")],
+            labels
+        );
+        let names: Vec<_> = merge_conflict
+            .conflicts()
+            .map(|region| merge_conflict.ancestor_name_for(region))
+            .collect();
+        assert_eq!(vec![Some("1111111"), Some("2222222")], names);
+    }
+
+    #[rstest]
+    fn markdown_fenced_markers_are_ignored() {
+        let input = concat!(
+            "# Resolving conflicts\n",
+            "```text\n",
+            conflict_text!("HEAD", "ours", "feature", "theirs"),
+            "```\n",
+            "~~~~\n",
+            diff3_conflict_text!("HEAD", "ours", "base", "base", "feature", "theirs"),
+            "```\n",
+            "~~~~\n",
+        );
+        assert_eq!(
+            Ok(None),
+            parse_with_options(
+                input,
+                ParseOptions {
+                    syntax: Syntax::Markdown,
+                    ..Default::default()
+                },
+            )
+        );
+        assert!(parse(input).unwrap().is_some());
+    }
+
+    #[rstest]
+    fn markdown_conflicts_outside_fences_are_found() {
+        let input = concat!(
+            "```\n",
+            "<<<<<<< example\n",
+            "```\n",
+            conflict_text!("HEAD", "ours", "feature", "```\ntheirs"),
+            "    ```\n",
+        );
+        let merge_conflict = parse_with_options(
+            input,
+            ParseOptions {
+                syntax: Syntax::Markdown,
+                ..Default::default()
+            },
+        )
+        .expect("successful parse")
+        .unwrap();
+        let heads: Vec<_> = merge_conflict
+            .conflicts()
+            .map(|region| region.head)
+            .collect();
+        assert_eq!(vec![3], heads);
+    }
+
+    #[rstest]
+    #[case::markdown(Syntax::Markdown)]
+    #[case::restructured_text(Syntax::ReStructuredText)]
+    fn setext_underlines_outside_conflicts_are_not_separators(#[case] syntax: Syntax) {
+        let input = "Title\n=======\n\nSection\n-------\n";
+        let options = ParseOptions {
+            syntax,
+            ..Default::default()
+        };
+        assert_eq!(Ok(None), parse_with_options(input, options));
+    }
+
+    #[rstest]
+    fn restructured_text_adornments_do_not_open_conflicts() {
+        let input = concat!(
+            "Example\n",
+            "<<<<<<<\n",
+            "\n",
+            conflict_text!("HEAD", "ours", "feature", "theirs"),
+            "Closing\n",
+            ">>>>>>>\n",
+        );
+        let options = ParseOptions {
+            syntax: Syntax::ReStructuredText,
+            ..Default::default()
+        };
+        let merge_conflict = parse_with_options(input, options)
+            .expect("successful parse")
+            .unwrap();
+        let heads: Vec<_> = merge_conflict
+            .conflicts()
+            .map(|region| region.head)
+            .collect();
+        assert_eq!(vec![3], heads);
+        // As plain text the underline opens the conflict.
+        assert_eq!(1, parse(input).unwrap().unwrap().conflicts[0].head);
+    }
+
+    #[rstest]
+    #[case("markdown", Syntax::Markdown)]
+    #[case("restructuredtext", Syntax::ReStructuredText)]
+    #[case("rust", Syntax::Plain)]
+    #[case("", Syntax::Plain)]
+    fn syntax_from_language_id(#[case] language_id: &str, #[case] expected: Syntax) {
+        assert_eq!(expected, Syntax::from_language_id(language_id));
+    }
+
+    #[rstest]
+    #[case::omitted(None, PositionEncoding::Utf16)]
+    #[case::first_supported(Some(vec!["utf-7", "utf-8", "utf-16"]), PositionEncoding::Utf8)]
+    #[case::utf32(Some(vec!["utf-32"]), PositionEncoding::Utf32)]
+    #[case::none_supported(Some(vec!["utf-7"]), PositionEncoding::Utf16)]
+    fn position_encoding_negotiation(
+        #[case] offered: Option<Vec<&'static str>>,
+        #[case] expected: PositionEncoding,
+    ) {
+        let offered: Option<Vec<lsp_types::PositionEncodingKind>> = offered.map(|kinds| {
+            kinds
+                .into_iter()
+                .map(lsp_types::PositionEncodingKind::new)
+                .collect()
+        });
+        assert_eq!(expected, PositionEncoding::negotiate(offered.as_deref()));
+    }
+
+    #[rstest]
+    #[case::utf8(PositionEncoding::Utf8, 7, 4)]
+    #[case::utf8_inside_character(PositionEncoding::Utf8, 3, 3)]
+    #[case::utf16(PositionEncoding::Utf16, 4, 4)]
+    #[case::utf32(PositionEncoding::Utf32, 3, 4)]
+    #[case::past_end(PositionEncoding::Utf32, 10, 5)]
+    #[case::utf16_inside_character(PositionEncoding::Utf16, 2, 3)]
+    #[case::utf16_past_end(PositionEncoding::Utf16, 10, 10)]
+    fn position_encoding_converts_to_utf16(
+        #[case] encoding: PositionEncoding,
+        #[case] character: u32,
+        #[case] expected: u32,
+    ) {
+        // 'é' is 2 UTF-8 bytes and 1 UTF-16 unit; '𝄞' is 4 bytes and 2 units.
+        assert_eq!(expected, encoding.to_utf16("é𝄞xy", character));
+    }
+
+    #[rstest]
+    #[case::utf8(PositionEncoding::Utf8, 14)]
+    #[case::utf16(PositionEncoding::Utf16, 11)]
+    #[case::utf32(PositionEncoding::Utf32, 10)]
+    fn last_char_is_counted_in_position_encoding(
+        #[case] encoding: PositionEncoding,
+        #[case] expected: u32,
+    ) {
+        let input = conflict_text!("HEAD", "ours", "é𝄞", "theirs");
+        let merge_conflict = parse_with_options(
+            input,
+            ParseOptions {
+                encoding,
+                ..Default::default()
+            },
+        )
+        .expect("successful parse")
+        .unwrap();
+        assert_eq!(expected, merge_conflict.conflicts[0].last_char);
+    }
 }
@@ -0,0 +1,453 @@
+//! Resolving a conflict: which sides to keep, the text that replaces the conflict block and
+//! the edits turning the document into it.
+//!
+//! Code actions, the resolve commands and "fix all" all go through here, so every way of
+//! resolving a conflict produces the same text.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    merge,
+    parser::{ConflictRegion, MergeConflict, conflict_edit_end, range_for_conflict_edit},
+};
+
+/// Which side(s) of a conflict the resolve command keeps.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    Both,
+    Ancestor,
+    OursAndAncestor,
+    AncestorAndTheirs,
+    All,
+    Neither,
+}
+
+impl Resolution {
+    /// The name used in settings and commands, e.g. `oursAndAncestor`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ours => "ours",
+            Self::Theirs => "theirs",
+            Self::Both => "both",
+            Self::Ancestor => "ancestor",
+            Self::OursAndAncestor => "oursAndAncestor",
+            Self::AncestorAndTheirs => "ancestorAndTheirs",
+            Self::All => "all",
+            Self::Neither => "neither",
+        }
+    }
+
+    /// The sides kept, in the order they end up in, which is git's order in the document.
+    pub fn sides(self) -> &'static [Side] {
+        match self {
+            Self::Ours => &[Side::Ours],
+            Self::Theirs => &[Side::Theirs],
+            Self::Both => &[Side::Ours, Side::Theirs],
+            Self::Ancestor => &[Side::Ancestor],
+            Self::OursAndAncestor => &[Side::Ours, Side::Ancestor],
+            Self::AncestorAndTheirs => &[Side::Ancestor, Side::Theirs],
+            Self::All => &[Side::Ours, Side::Ancestor, Side::Theirs],
+            Self::Neither => &[],
+        }
+    }
+}
+
+/// One side of a conflict, for picking individual lines or a conflicted file's version.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Side {
+    Ours,
+    Theirs,
+    Ancestor,
+}
+
+impl Side {
+    /// The index stage holding this side of a conflicted file.
+    pub fn stage(self) -> u8 {
+        match self {
+            Self::Ancestor => 1,
+            Self::Ours => 2,
+            Self::Theirs => 3,
+        }
+    }
+
+    /// The lines of this side in `region`, exclusive of the marker lines around them.
+    fn range(self, region: &ConflictRegion) -> Option<(u32, u32)> {
+        match self {
+            Self::Ours => Some(region.head_range()),
+            Self::Theirs => Some(region.branch_range()),
+            Self::Ancestor => region.ancestor_range(),
+        }
+    }
+
+    /// This side's text in `region`, read from `content`.
+    pub fn text<'a>(self, region: &ConflictRegion, content: &'a str) -> Option<&'a str> {
+        match self {
+            Self::Ours => Some(region.ours_text(content)),
+            Self::Theirs => Some(region.theirs_text(content)),
+            Self::Ancestor => region.ancestor_text(content),
+        }
+    }
+}
+
+/// What a resolve command keeps: a named [`Resolution`] or sides in an order of the caller's
+/// choosing, e.g. theirs before ours.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Choice {
+    Named(Resolution),
+    Custom(Vec<Side>),
+}
+
+impl Choice {
+    /// The sides kept, in the order they end up in. A custom order may repeat a side.
+    pub fn sides(&self) -> &[Side] {
+        match self {
+            Self::Named(resolution) => resolution.sides(),
+            Self::Custom(sides) => sides,
+        }
+    }
+}
+
+/// A resolved conflict: the text replacing the whole conflict block, end marker line included.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Resolved {
+    pub text: String,
+    pub replaced_range: lsp_types::Range,
+}
+
+impl From<Resolved> for lsp_types::TextEdit {
+    fn from(resolved: Resolved) -> Self {
+        Self {
+            range: resolved.replaced_range,
+            new_text: resolved.text,
+        }
+    }
+}
+
+/// Resolve `conflict` in `document` to the sides `choice` keeps, one after the other.
+///
+/// `None` when the conflict lacks one of them, i.e. an ancestor without diff3 markers.
+pub fn resolve(document: &str, conflict: &ConflictRegion, choice: &Choice) -> Option<Resolved> {
+    let text = choice
+        .sides()
+        .iter()
+        .map(|side| side.text(conflict, document))
+        .collect::<Option<String>>()?;
+    Some(Resolved {
+        text,
+        replaced_range: range_for_conflict_edit(document, conflict),
+    })
+}
+
+/// Resolve `conflict` in `document` to both sides with the lines they share kept once, as
+/// [`merge::union_deduplicated`] merges them. Identical sides are kept once, and sides sharing
+/// no lines are both kept whole.
+pub fn union(document: &str, conflict: &ConflictRegion) -> Resolved {
+    let (ours, theirs) = (conflict.ours_text(document), conflict.theirs_text(document));
+    let text = if ours == theirs {
+        ours.to_owned()
+    } else {
+        merge::union_deduplicated(ours, theirs).unwrap_or_else(|| format!("{ours}{theirs}"))
+    };
+    Resolved {
+        text,
+        replaced_range: range_for_conflict_edit(document, conflict),
+    }
+}
+
+/// The edits resolving `conflict` in `document` to `choice`: deletions of the marker lines and
+/// of the sides it drops. The kept lines are left untouched, so changes made to them since the
+/// conflict was parsed survive. Applied to `document` they give the text of [`resolve`].
+///
+/// Perforce puts our side last, so keeping it in place with another side would change their
+/// order: such conflicts are replaced as a whole instead.
+///
+/// `None` when the conflict lacks the side, i.e. an ancestor without diff3 markers.
+pub fn edits(
+    document: &str,
+    conflict: &ConflictRegion,
+    choice: Resolution,
+) -> Option<Vec<lsp_types::TextEdit>> {
+    if conflict.yours.is_some() && choice.sides().len() > 1 {
+        return resolve(document, conflict, &Choice::Named(choice))
+            .map(|resolved| vec![resolved.into()]);
+    }
+    let kept_regions = choice
+        .sides()
+        .iter()
+        .map(|side| side.range(conflict))
+        .collect::<Option<Vec<_>>>()?;
+    let is_kept = |line: u32| {
+        kept_regions
+            .iter()
+            .any(|(start, end)| *start < line && line < *end)
+    };
+    let deletion = |start: u32, end: u32| lsp_types::TextEdit {
+        range: lsp_types::Range {
+            start: lsp_types::Position {
+                line: start,
+                character: 0,
+            },
+            end: if end > conflict.end {
+                conflict_edit_end(document, conflict)
+            } else {
+                lsp_types::Position {
+                    line: end,
+                    character: 0,
+                }
+            },
+        },
+        new_text: String::new(),
+    };
+    let mut edits = Vec::new();
+    let mut removed_from = None;
+    // One past the end marker closes the last run, matching `range_for_conflict_edit`.
+    for line in conflict.head..=conflict.end + 1 {
+        let removed = line <= conflict.end && !is_kept(line);
+        match (removed, removed_from) {
+            (true, None) => removed_from = Some(line),
+            (false, Some(start)) => {
+                edits.push(deletion(start, line));
+                removed_from = None;
+            }
+            _ => {}
+        }
+    }
+    Some(edits)
+}
+
+/// The edits resolving `conflict` in `document` to `choice`. Named resolutions keep their
+/// lines in place as [`edits`] does; a custom order replaces the whole block.
+pub fn choice_edits(
+    document: &str,
+    conflict: &ConflictRegion,
+    choice: &Choice,
+) -> Option<Vec<lsp_types::TextEdit>> {
+    match choice {
+        Choice::Named(resolution) => edits(document, conflict, *resolution),
+        Choice::Custom(_) => {
+            resolve(document, conflict, choice).map(|resolved| vec![resolved.into()])
+        }
+    }
+}
+
+/// `document` with the conflicts of `merge_conflict` that can be resolved now replaced by the
+/// text `resolve` gives for them, and how many were replaced. Conflicts it gives no text for stay.
+///
+/// A conflict with others nested in it is left for once those are gone: parse the result again
+/// to resolve it.
+pub fn splice(
+    document: &str,
+    merge_conflict: &MergeConflict,
+    mut resolve: impl FnMut(&ConflictRegion) -> Option<String>,
+) -> (String, usize) {
+    let mut regions: Vec<_> = merge_conflict.resolvable().collect();
+    regions.sort_by_key(|region| region.bytes.head.start);
+    let mut spliced = String::with_capacity(document.len());
+    let mut copied_to = 0;
+    let mut replaced = 0;
+    for region in regions {
+        let Some(text) = resolve(region) else {
+            continue;
+        };
+        spliced.push_str(&document[copied_to..region.bytes.head.start]);
+        spliced.push_str(&text);
+        copied_to = region.bytes.end.end;
+        replaced += 1;
+    }
+    spliced.push_str(&document[copied_to..]);
+    (spliced, replaced)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+    use crate::{diff3_conflict_text, parser::parse, test_helpers::apply_edits};
+
+    #[rstest]
+    #[case::shared_lines("x\nshared", "y\nshared", "before\nx\ny\nshared\nafter\n")]
+    #[case::identical("same", "same", "before\nsame\nafter\n")]
+    #[case::nothing_shared("x", "y", "before\nx\ny\nafter\n")]
+    fn union_keeps_shared_lines_once(
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: &str,
+    ) {
+        let text =
+            format!("before\n<<<<<<< HEAD\n{ours}\n=======\n{theirs}\n>>>>>>> feature\nafter\n");
+        let merge_conflict = parse(&text).unwrap().unwrap();
+        let resolved = union(&text, &merge_conflict.conflicts[0]);
+        assert_eq!(expected, apply_edits(&text, &[resolved.into()]));
+    }
+
+    #[rstest]
+    #[case::ours(Resolution::Ours, "before\nours\nafter\n")]
+    #[case::theirs(Resolution::Theirs, "before\ntheirs\nafter\n")]
+    #[case::both(Resolution::Both, "before\nours\ntheirs\nafter\n")]
+    #[case::ancestor(Resolution::Ancestor, "before\nbase\nafter\n")]
+    #[case::ours_and_ancestor(Resolution::OursAndAncestor, "before\nours\nbase\nafter\n")]
+    #[case::ancestor_and_theirs(Resolution::AncestorAndTheirs, "before\nbase\ntheirs\nafter\n")]
+    #[case::all(Resolution::All, "before\nours\nbase\ntheirs\nafter\n")]
+    #[case::neither(Resolution::Neither, "before\nafter\n")]
+    fn edits_and_resolved_text_agree(#[case] resolution: Resolution, #[case] expected: &str) {
+        let text = concat!(
+            "before\n",
+            diff3_conflict_text!("HEAD", "ours", "base", "base", "feature", "theirs"),
+            "after\n"
+        );
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let conflict = &merge_conflict.conflicts[0];
+        let resolved = resolve(text, conflict, &Choice::Named(resolution)).unwrap();
+        let edits = edits(text, conflict, resolution).unwrap();
+        assert_eq!(expected, apply_edits(text, &[resolved.into()]));
+        assert_eq!(expected, apply_edits(text, &edits));
+    }
+
+    #[rstest]
+    #[case::ours(Resolution::Ours, "before\nours\nafter\n")]
+    #[case::both(Resolution::Both, "before\nours\ntheirs\nafter\n")]
+    #[case::ours_and_ancestor(Resolution::OursAndAncestor, "before\nours\nbase\nafter\n")]
+    #[case::ancestor_and_theirs(Resolution::AncestorAndTheirs, "before\nbase\ntheirs\nafter\n")]
+    #[case::all(Resolution::All, "before\nours\nbase\ntheirs\nafter\n")]
+    fn perforce_edits_and_resolved_text_agree(
+        #[case] resolution: Resolution,
+        #[case] expected: &str,
+    ) {
+        let text = concat!(
+            "before\n",
+            ">>>> ORIGINAL //depot/notes.txt#1\n",
+            "base\n",
+            "==== THEIRS //depot/notes.txt#2\n",
+            "theirs\n",
+            "==== YOURS //client/notes.txt\n",
+            "ours\n",
+            "<<<<\n",
+            "after\n",
+        );
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let conflict = &merge_conflict.conflicts[0];
+        let resolved = resolve(text, conflict, &Choice::Named(resolution)).unwrap();
+        let edits = edits(text, conflict, resolution).unwrap();
+        assert_eq!(expected, apply_edits(text, &[resolved.into()]));
+        assert_eq!(expected, apply_edits(text, &edits));
+    }
+
+    #[rstest]
+    #[case::ours(Resolution::Ours, "before\nours, edited\nafter\n")]
+    #[case::theirs(Resolution::Theirs, "before\ntheirs, edited\nafter\n")]
+    #[case::both(Resolution::Both, "before\nours, edited\ntheirs, edited\nafter\n")]
+    fn edits_leave_kept_lines_untouched(#[case] resolution: Resolution, #[case] expected: &str) {
+        let text = concat!(
+            "before\n",
+            diff3_conflict_text!("HEAD", "ours", "base", "base", "feature", "theirs"),
+            "after\n"
+        );
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let edits = edits(text, &merge_conflict.conflicts[0], resolution).unwrap();
+        assert!(edits.iter().all(|edit| edit.new_text.is_empty()));
+
+        // Changes made inside the block after the edits were computed survive them.
+        let edited = text
+            .replace("ours\n", "ours, edited\n")
+            .replace("theirs\n", "theirs, edited\n");
+        assert_eq!(expected, apply_edits(&edited, &edits));
+    }
+
+    #[rstest]
+    fn custom_order_replaces_the_block() {
+        let text = concat!(
+            "before\n",
+            diff3_conflict_text!("HEAD", "ours", "base", "base", "feature", "theirs"),
+            "after\n"
+        );
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let choice: Choice = serde_json::from_value(serde_json::json!(["theirs", "ours"])).unwrap();
+        let edits = choice_edits(text, &merge_conflict.conflicts[0], &choice).unwrap();
+        assert_eq!("before\ntheirs\nours\nafter\n", apply_edits(text, &edits));
+    }
+
+    #[rstest]
+    #[case::crlf(
+        "before\r\n<<<<<<< HEAD\r\nours\r\n=======\r\ntheirs\r\n>>>>>>> b\r\nafter\r\n",
+        "before\r\ntheirs\r\nafter\r\n",
+        "before\r\ntheirs\r\nours\r\nafter\r\n"
+    )]
+    #[case::bare_cr(
+        "before\r<<<<<<< HEAD\rours\r=======\rtheirs\r>>>>>>> b\rafter\r",
+        "before\rtheirs\rafter\r",
+        "before\rtheirs\rours\rafter\r"
+    )]
+    #[case::no_final_newline(
+        "before\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> b",
+        "before\ntheirs\n",
+        "before\ntheirs\nours\n"
+    )]
+    fn resolutions_keep_line_endings(
+        #[case] text: &str,
+        #[case] theirs: &str,
+        #[case] reordered: &str,
+    ) {
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let conflict = &merge_conflict.conflicts[0];
+        let edits = edits(text, conflict, Resolution::Theirs).unwrap();
+        assert_eq!(theirs, apply_edits(text, &edits));
+        let choice = Choice::Custom(vec![Side::Theirs, Side::Ours]);
+        let edits = choice_edits(text, conflict, &choice).unwrap();
+        assert_eq!(reordered, apply_edits(text, &edits));
+    }
+
+    #[rstest]
+    fn splice_replaces_each_conflict_it_has_text_for() {
+        let text = concat!(
+            "before\n",
+            diff3_conflict_text!("HEAD", "one", "base", "base", "feature", "uno"),
+            "middle\n",
+            diff3_conflict_text!("HEAD", "two", "base", "base", "feature", "dos"),
+            "after\n"
+        );
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let (spliced, replaced) = splice(text, &merge_conflict, |region| {
+            (region.head == 1).then(|| Side::Theirs.text(region, text).unwrap().to_owned())
+        });
+        assert_eq!(1, replaced);
+        assert_eq!(
+            concat!(
+                "before\nuno\nmiddle\n",
+                diff3_conflict_text!("HEAD", "two", "base", "base", "feature", "dos"),
+                "after\n"
+            ),
+            spliced
+        );
+    }
+
+    #[rstest]
+    fn missing_ancestor_cannot_be_kept() {
+        let text = concat!(
+            "<<<<<<< HEAD\n",
+            "ours\n",
+            "=======\n",
+            "theirs\n",
+            ">>>>>>> feature\n"
+        );
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let conflict = &merge_conflict.conflicts[0];
+        assert_eq!(
+            None,
+            resolve(text, conflict, &Choice::Named(Resolution::Ancestor))
+        );
+        assert_eq!(
+            None,
+            resolve(
+                text,
+                conflict,
+                &Choice::Custom(vec![Side::Theirs, Side::Ancestor])
+            )
+        );
+    }
+}
@@ -5,61 +5,183 @@
 //! loop responsive. Publishes diagnostics and generates quickfix code actions.
 
 use std::{
-    sync::{Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
     thread,
 };
 
 use crate::{
-    parser::MergeConflict,
-    state::{ServerState, ServerStatus},
+    config::Config,
+    config_file, git, hg,
+    parser::{DEFAULT_MARKER_SIZE, MergeConflict, SideNames},
+    resolution::{Choice, Resolution, Side},
+    state::{
+        ClientSupport, CodeActionData, ConflictCommits, DocumentState, PendingRequest,
+        SEMANTIC_TOKENS_METHOD, ServerState, ServerStatus, add_commit_information,
+        code_action_options, code_lens_options, diagnostic_options, rebase_names,
+        semantic_tokens_options,
+    },
+    svn,
 };
 
 pub type LSPResult = anyhow::Result<Option<(lsp_types::Uri, i32)>>;
 
-pub fn main_loop(connection: lsp_server::Connection) -> LSPResult {
-    let mut state = ServerState::new(connection.sender);
-    let mut handles: Vec<thread::JoinHandle<()>> = Vec::new();
+/// `workspace/executeCommand` command returning every conflict in a document.
+pub const LIST_CONFLICTS_COMMAND: &str = "mergeConflict.listConflicts";
+
+/// `workspace/executeCommand` command resolving one conflict through `workspace/applyEdit`.
+///
+/// Arguments are the document URI, the conflict's index in the document and the
+/// [`Resolution`] to apply, or a list of sides to keep in that order.
+pub const RESOLVE_COMMAND: &str = "mergeConflict.resolve";
+
+/// `workspace/executeCommand` command building a resolution from individual lines.
+///
+/// Arguments are the document URI, the conflict's index in the document and a list of
+/// `[side, line]` selections. Returns the `WorkspaceEdit` without applying it.
+pub const RESOLVE_LINES_COMMAND: &str = "mergeConflict.resolveLines";
+
+/// `workspace/executeCommand` commands resolving one conflict to a fixed side, for keybindings.
+///
+/// Arguments are the document URI and the conflict's index in the document. They behave
+/// like [`RESOLVE_COMMAND`] with the matching [`Resolution`].
+pub const ACCEPT_OURS_COMMAND: &str = "mergeConflict.acceptOurs";
+pub const ACCEPT_THEIRS_COMMAND: &str = "mergeConflict.acceptTheirs";
+pub const ACCEPT_BOTH_COMMAND: &str = "mergeConflict.acceptBoth";
+
+/// `workspace/executeCommand` command running `git add` on a conflicted file once none of its
+/// conflicts are left.
+///
+/// The only argument is the document URI. Returns whether the file was staged.
+pub const STAGE_FILE_COMMAND: &str = "mergeConflict.stageFile";
+
+/// `workspace/executeCommand` command running `hg resolve --mark` on an unresolved file once
+/// none of its conflicts are left.
+///
+/// The only argument is the document URI. Returns whether the file was marked.
+pub const MARK_RESOLVED_COMMAND: &str = "mergeConflict.markResolved";
+
+/// `workspace/executeCommand` command replacing a conflicted file with one side's version from
+/// the git index and staging it, for binary files git cannot put markers in.
+///
+/// The arguments are the document URI and the side, `"ours"`, `"theirs"` or `"ancestor"`.
+/// Returns whether the file was replaced.
+pub const TAKE_VERSION_COMMAND: &str = "mergeConflict.takeVersion";
+
+/// Request returning a conflicted file's base, ours and theirs versions from the git index.
+///
+/// Takes `{ "textDocument": { "uri": ... } }` and answers with [`git::Stages`], or null when
+/// the file is not conflicted.
+pub const STAGE_CONTENTS_METHOD: &str = "mergeConflict/stageContents";
+
+/// Request returning the versions Subversion left next to a conflicted file, e.g.
+/// `file.mine`, `file.r1` and `file.r2`.
+///
+/// Takes the same parameters as [`STAGE_CONTENTS_METHOD`] and answers in the same shape, or
+/// null when there are no such files.
+pub const SVN_FILES_METHOD: &str = "mergeConflict/svnFiles";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StageContentsParams {
+    text_document: lsp_types::TextDocumentIdentifier,
+}
+
+/// Action offered by `window/showMessageRequest` when the client rejects an edit.
+const RETRY_ACTION: &str = "Retry";
+
+/// Documents per `$/progress` notification when streaming workspace diagnostics.
+const WORKSPACE_DIAGNOSTIC_BATCH_SIZE: usize = 32;
+
+pub fn main_loop(
+    connection: lsp_server::Connection,
+    config: Config,
+    client: ClientSupport,
+    workspace_root: Option<PathBuf>,
+) -> anyhow::Result<std::process::ExitCode> {
+    let mut state = ServerState::new(connection.sender, config);
+    state.client = client;
+    state.workspace_root = workspace_root;
 
     send_log_message(
         state.sender.clone(),
         lsp_types::MessageType::INFO,
         format!("{} {} ready", env!("CARGO_PKG_NAME"), env!("FULL_VERSION")),
     );
+    state.sync_registrations()?;
+    if state.config().git.enabled {
+        let worker_state = state.clone();
+        let handle = thread::spawn(move || {
+            if let Err(err) = publish_conflicted_files(&worker_state) {
+                tracing::error!("From publish_conflicted_files: {err:?}");
+            }
+        });
+        state.track_update(handle);
+    }
+    if state.config().hg.enabled {
+        let worker_state = state.clone();
+        let handle = thread::spawn(move || {
+            if let Err(err) = publish_unresolved_hg_files(&worker_state) {
+                tracing::error!("From publish_unresolved_hg_files: {err:?}");
+            }
+        });
+        state.track_update(handle);
+    }
 
     for msg in &connection.receiver {
-        // Clean up finished handles periodically.
-        handles.retain(|h| !h.is_finished());
-        handle_message(&mut handles, &mut state, msg)?;
-        if state.status == ServerStatus::ExitReceived {
+        // Whatever a document or client throws at it, the server stays up for the rest of the
+        // session: failures are logged and requests answered with an error. Locks a panic
+        // poisoned are taken over as they are.
+        match panic::catch_unwind(AssertUnwindSafe(|| handle_message(&mut state, msg))) {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => tracing::error!("From handle_message: {err:?}"),
+            Err(payload) => tracing::error!("handle_message panicked: {}", panic_text(&*payload)),
+        }
+        if matches!(state.status, ServerStatus::ExitReceived { .. }) {
             break;
         }
     }
 
-    for handle in handles {
-        let _ = handle.join();
-    }
+    state.wait_for_pending_updates();
 
+    if state.status.exit_code() != std::process::ExitCode::SUCCESS {
+        tracing::warn!("exiting without a shutdown request: {:?}", state.status);
+    }
     tracing::debug!("shutting down server");
-    Ok(None)
+    Ok(state.status.exit_code())
 }
 
-fn handle_message(
-    handles: &mut Vec<thread::JoinHandle<()>>,
-    state: &mut ServerState,
-    message: lsp_server::Message,
-) -> LSPResult {
+fn handle_message(state: &mut ServerState, message: lsp_server::Message) -> LSPResult {
     tracing::debug!("got msg: {message:?}");
     match message {
         lsp_server::Message::Notification(notification) => {
-            if let Some((uri, version)) = on_notification_message(state, notification)? {
-                let state = (*state).clone();
-                let handle = thread::spawn(move || document_update_thread(uri, version, state));
-                handles.push(handle);
+            if let Some((uri, version)) = on_notification_message(state, notification)?
+                && state.queue_update(uri, version)
+            {
+                let worker_state = (*state).clone();
+                let handle = thread::spawn(move || update_worker(&worker_state));
+                state.track_update(handle);
             }
         }
         lsp_server::Message::Request(request) => {
-            if let Some(message) = on_request(state, request)? {
-                let sender = state.sender.lock().expect("lock on sender");
+            let id = request.id.clone();
+            let method = request.method.clone();
+            let response =
+                match panic::catch_unwind(AssertUnwindSafe(|| on_request(state, request))) {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(err)) => {
+                        tracing::error!("{method} failed: {err:?}");
+                        Some(internal_error(id, format!("{method} failed: {err}")))
+                    }
+                    Err(payload) => {
+                        let message = panic_text(&*payload);
+                        tracing::error!("{method} panicked: {message}");
+                        Some(internal_error(id, format!("{method} failed: {message}")))
+                    }
+                };
+            if let Some(message) = response {
+                let sender = state.sender.lock().unwrap_or_else(PoisonError::into_inner);
                 if let Err(e) = sender.send(message.into()) {
                     tracing::error!("Failed to send message: {e}");
                 }
@@ -67,11 +189,26 @@ fn handle_message(
         }
         lsp_server::Message::Response(response) => {
             tracing::debug!("got response: {response:?}");
+            on_response(state, response)?;
         }
     }
     Ok(None)
 }
 
+/// An `InternalError` response to request `id`.
+fn internal_error(id: lsp_server::RequestId, message: String) -> lsp_server::Response {
+    lsp_server::Response::new_err(id, lsp_server::ErrorCode::InternalError as i32, message)
+}
+
+/// The message a panic was raised with.
+fn panic_text(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
 fn on_notification_message(
     state: &mut ServerState,
     notification: lsp_server::Notification,
@@ -80,12 +217,16 @@ fn on_notification_message(
     match notification.method.as_ref() {
         "exit" => {
             tracing::debug!("exit notification received");
-            state.status = ServerStatus::ExitReceived;
+            state.status = ServerStatus::ExitReceived {
+                after_shutdown: state.status == ServerStatus::ShutdownRequested,
+            };
             Ok(None)
         }
         "textDocument/didOpen" => on_did_open_text_document(state, notification),
         "textDocument/didClose" => on_did_close_text_document(state, notification),
         "textDocument/didChange" => on_did_change_text_document(state, notification),
+        "textDocument/didSave" => on_did_save_text_document(state, notification),
+        "workspace/didChangeConfiguration" => on_did_change_configuration(state, notification),
         unhandled => {
             tracing::debug!("notification: ignored: {unhandled:?}");
             Ok(None)
@@ -105,7 +246,18 @@ fn on_did_open_text_document(
         lsp_types::MessageType::INFO,
         format!("opened: {}", text_document.uri.as_str()),
     );
-    state.add_document(text_document)
+    let uri = text_document.uri.clone();
+    let was_unopened = state
+        .unopened
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&uri);
+    let result = state.add_document(text_document)?;
+    // Documents that are parsed publish over what was read from disk, excluded ones do not.
+    if was_unopened && result.is_none() {
+        publish_empty_diagnostics(state, uri);
+    }
+    Ok(result)
 }
 
 fn on_did_close_text_document(
@@ -132,7 +284,128 @@ fn on_did_change_text_document(
         text_document.uri,
         text_document.version
     );
-    state.document_did_change(text_document, content_changes)
+    let uri = text_document.uri.clone();
+    let result = state.document_did_change(text_document, content_changes)?;
+    if result.is_some() && state.parse_deferred(&uri)? {
+        tracing::debug!("{:?}: over the size limit, parsing on save", uri);
+        return Ok(None);
+    }
+    if result.is_some() && !state.needs_update(&uri) {
+        tracing::debug!("{:?}: conflicts still current, nothing to publish", uri);
+        // An update queued for the text this change undid would publish it as current.
+        state.schedule_update(&uri);
+        return Ok(None);
+    }
+    if result.is_none() {
+        send_log_message(
+            state.sender.clone(),
+            lsp_types::MessageType::WARNING,
+            format!(
+                "{}: received changes for a document that is not open; reopen it to resync",
+                uri.as_str()
+            ),
+        );
+    }
+    Ok(result)
+}
+
+/// Parse documents whose changes wait for a save. Others are already up to date.
+fn on_did_save_text_document(
+    state: &mut ServerState,
+    notification: lsp_server::Notification,
+) -> LSPResult {
+    let lsp_types::DidSaveTextDocumentParams { text_document, .. } =
+        serde_json::from_value(notification.params)?;
+    tracing::info!("did save: {:?}", text_document.uri);
+    let git = &state.config().git;
+    if git.enabled
+        && git.auto_stage
+        && let Err(err) = stage_resolved_file(state, &text_document.uri)
+    {
+        tracing::error!("From stage_resolved_file: {err:?}");
+    }
+    if !state.parse_deferred(&text_document.uri)? {
+        return Ok(None);
+    }
+    Ok(state
+        .document_version(&text_document.uri)?
+        .map(|version| (text_document.uri, version)))
+}
+
+fn on_did_change_configuration(
+    state: &mut ServerState,
+    notification: lsp_server::Notification,
+) -> LSPResult {
+    let lsp_types::DidChangeConfigurationParams { settings } =
+        serde_json::from_value(notification.params)?;
+    tracing::info!("did change configuration: {settings:?}");
+    if settings
+        .as_object()
+        .is_some_and(|settings| !settings.is_empty())
+    {
+        apply_settings(state, settings)?;
+    } else if state.client.configuration {
+        // Clients using the pull model only signal that something changed.
+        state.send_tracked_request(
+            <lsp_types::request::WorkspaceConfiguration as lsp_types::request::Request>::METHOD,
+            lsp_types::ConfigurationParams {
+                items: vec![lsp_types::ConfigurationItem {
+                    scope_uri: None,
+                    section: None,
+                }],
+            },
+            PendingRequest::Configuration,
+        )?;
+    }
+    Ok(None)
+}
+
+/// Lay `settings` over the current configuration and bring every open document up to date
+/// with the result. Invalid settings are ignored as a whole.
+fn apply_settings(state: &ServerState, settings: serde_json::Value) -> anyhow::Result<()> {
+    let config = match state.config().updated(settings) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::warn!("ignoring invalid settings: {e}");
+            send_log_message(
+                state.sender.clone(),
+                lsp_types::MessageType::WARNING,
+                format!("ignoring invalid settings: {e}"),
+            );
+            return Ok(());
+        }
+    };
+    let updates = state.set_config(config)?;
+    if !state.push_diagnostics() {
+        if state.client.diagnostic_refresh {
+            state.refresh(
+                <lsp_types::request::WorkspaceDiagnosticRefresh as lsp_types::request::Request>::METHOD,
+            )?;
+        }
+        return Ok(());
+    }
+    let config = state.config();
+    // Prepared first: finding the commits may run git, which must not hold up other senders.
+    let messages: Vec<_> = updates
+        .into_iter()
+        .map(|(uri, version, content, merge_conflict)| {
+            prepare_diagnostics(
+                &uri,
+                Some(version),
+                &content,
+                &merge_conflict,
+                &git_details(state, &uri),
+                &config,
+            )
+        })
+        .collect();
+    let sender = state.sender.lock().unwrap_or_else(PoisonError::into_inner);
+    for message in messages {
+        if let Err(e) = sender.send(message.into()) {
+            tracing::error!("Failed to send message: {e}");
+        }
+    }
+    Ok(())
 }
 
 fn on_request(
@@ -151,6 +424,18 @@ fn on_request(
 
     match request.method.as_ref() {
         "textDocument/codeAction" => on_code_action_request(state, request),
+        "codeAction/resolve" => on_code_action_resolve_request(state, request),
+        "textDocument/codeLens" => on_code_lens_request(state, request),
+        "textDocument/documentHighlight" => on_document_highlight_request(state, request),
+        "textDocument/hover" => on_hover_request(state, request),
+        "textDocument/selectionRange" => on_selection_range_request(state, request),
+        "textDocument/semanticTokens/full" => on_semantic_tokens_full_request(state, request),
+        "textDocument/semanticTokens/range" => on_semantic_tokens_range_request(state, request),
+        "textDocument/diagnostic" => on_document_diagnostic_request(state, request),
+        "workspace/diagnostic" => on_workspace_diagnostic_request(state, request),
+        "workspace/executeCommand" => on_execute_command_request(state, request),
+        STAGE_CONTENTS_METHOD => on_stage_contents_request(state, request),
+        SVN_FILES_METHOD => on_svn_files_request(state, request),
         "shutdown" => on_shutdown(state, request),
         unhandled => {
             tracing::debug!("request: ignored: {unhandled:?}");
@@ -168,31 +453,684 @@ fn on_code_action_request(
     request: lsp_server::Request,
 ) -> anyhow::Result<Option<lsp_server::Response>> {
     tracing::debug!("code action");
-    let (id, params): (lsp_server::RequestId, lsp_types::CodeActionParams) = request
-        .extract(<lsp_types::request::CodeActionRequest as lsp_types::request::Request>::METHOD)?;
+    let method = <lsp_types::request::CodeActionRequest as lsp_types::request::Request>::METHOD;
+    let (id, params): (lsp_server::RequestId, lsp_types::CodeActionParams) =
+        request.extract(method)?;
+    if !state.feature_enabled(method) {
+        tracing::debug!("code actions are disabled");
+        return Ok(Some(lsp_server::Response::new_ok(
+            id,
+            serde_json::Value::Null,
+        )));
+    }
     let actions = state.code_action(params)?;
     Ok(Some(lsp_server::Response::new_ok(id, actions)))
 }
 
+fn on_code_action_resolve_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    let (id, action): (lsp_server::RequestId, lsp_types::CodeAction) = request.extract(
+        <lsp_types::request::CodeActionResolveRequest as lsp_types::request::Request>::METHOD,
+    )?;
+    tracing::debug!("resolve code action: {}", action.title);
+    let data = action
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<CodeActionData>(data).ok());
+    let Some(data) = data else {
+        return Ok(Some(lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::InvalidParams as i32,
+            format!("code action {:?} has no resolve data", action.title),
+        )));
+    };
+    match state.resolve_code_action(action, data)? {
+        Some(action) => Ok(Some(lsp_server::Response::new_ok(id, action))),
+        None => Ok(Some(lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::ContentModified as i32,
+            "document changed since the code action was offered".to_owned(),
+        ))),
+    }
+}
+
+fn on_code_lens_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    tracing::debug!("code lens");
+    let method = <lsp_types::request::CodeLensRequest as lsp_types::request::Request>::METHOD;
+    let (id, params): (lsp_server::RequestId, lsp_types::CodeLensParams) =
+        request.extract(method)?;
+    if !state.feature_enabled(method) {
+        tracing::debug!("code lenses are disabled");
+        return Ok(Some(lsp_server::Response::new_ok(
+            id,
+            serde_json::Value::Null,
+        )));
+    }
+    let lenses = state.code_lens(&params.text_document.uri)?;
+    Ok(Some(lsp_server::Response::new_ok(id, lenses)))
+}
+
+fn on_document_highlight_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    tracing::debug!("document highlight");
+    let (id, params): (lsp_server::RequestId, lsp_types::DocumentHighlightParams) = request
+        .extract(
+            <lsp_types::request::DocumentHighlightRequest as lsp_types::request::Request>::METHOD,
+        )?;
+    let position = params.text_document_position_params;
+    let highlights = state.document_highlights(&position.text_document.uri, position.position)?;
+    Ok(Some(lsp_server::Response::new_ok(id, highlights)))
+}
+
+fn on_hover_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    tracing::debug!("hover");
+    let (id, params): (lsp_server::RequestId, lsp_types::HoverParams) = request
+        .extract(<lsp_types::request::HoverRequest as lsp_types::request::Request>::METHOD)?;
+    let position = params.text_document_position_params;
+    let hover = state.hover(&position.text_document.uri, position.position)?;
+    Ok(Some(lsp_server::Response::new_ok(id, hover)))
+}
+
+fn on_selection_range_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    tracing::debug!("selection range");
+    let (id, params): (lsp_server::RequestId, lsp_types::SelectionRangeParams) = request.extract(
+        <lsp_types::request::SelectionRangeRequest as lsp_types::request::Request>::METHOD,
+    )?;
+    let ranges = state.selection_ranges(&params.text_document.uri, &params.positions)?;
+    Ok(Some(lsp_server::Response::new_ok(id, ranges)))
+}
+
+fn on_document_diagnostic_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    let method =
+        <lsp_types::request::DocumentDiagnosticRequest as lsp_types::request::Request>::METHOD;
+    let (id, params): (lsp_server::RequestId, lsp_types::DocumentDiagnosticParams) =
+        request.extract(method)?;
+    tracing::debug!("document diagnostic: {:?}", params.text_document.uri);
+    if !state.feature_enabled(method) {
+        tracing::debug!("pull diagnostics are disabled");
+        return Ok(Some(lsp_server::Response::new_ok(
+            id,
+            serde_json::Value::Null,
+        )));
+    }
+    let items = state
+        .document_diagnostics(&params.text_document.uri)?
+        .map(|(_, items)| items)
+        .unwrap_or_default();
+    let report = lsp_types::DocumentDiagnosticReportResult::Report(
+        lsp_types::DocumentDiagnosticReport::Full(lsp_types::RelatedFullDocumentDiagnosticReport {
+            related_documents: None,
+            full_document_diagnostic_report: lsp_types::FullDocumentDiagnosticReport {
+                result_id: None,
+                items,
+            },
+        }),
+    );
+    Ok(Some(lsp_server::Response::new_ok(id, report)))
+}
+
+/// Report every tracked document's conflicts.
+///
+/// With a partial result token the reports are streamed in batches through `$/progress` and
+/// the final response is empty, as the protocol requires.
+fn on_workspace_diagnostic_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    let (id, params): (lsp_server::RequestId, lsp_types::WorkspaceDiagnosticParams) = request
+        .extract(
+            <lsp_types::request::WorkspaceDiagnosticRequest as lsp_types::request::Request>::METHOD,
+        )?;
+    tracing::debug!("workspace diagnostic");
+    if !state.feature_enabled(
+        <lsp_types::request::DocumentDiagnosticRequest as lsp_types::request::Request>::METHOD,
+    ) {
+        tracing::debug!("pull diagnostics are disabled");
+        return Ok(Some(lsp_server::Response::new_ok(
+            id,
+            serde_json::Value::Null,
+        )));
+    }
+    let mut items = state.workspace_diagnostics()?;
+    tracing::info!("reporting diagnostics for {} document(s)", items.len());
+    if let Some(token) = params.partial_result_params.partial_result_token {
+        let sender = state.sender.lock().unwrap_or_else(PoisonError::into_inner);
+        for batch in items.chunks(WORKSPACE_DIAGNOSTIC_BATCH_SIZE) {
+            let partial = lsp_types::WorkspaceDiagnosticReportPartialResult {
+                items: batch.to_vec(),
+            };
+            let notification = lsp_server::Notification::new(
+                <lsp_types::notification::Progress as lsp_types::notification::Notification>::METHOD
+                    .to_owned(),
+                serde_json::json!({ "token": token, "value": partial }),
+            );
+            if let Err(e) = sender.send(notification.into()) {
+                tracing::error!("Failed to send message: {e}");
+            }
+        }
+        items = Vec::new();
+    }
+    Ok(Some(lsp_server::Response::new_ok(
+        id,
+        lsp_types::WorkspaceDiagnosticReportResult::Report(lsp_types::WorkspaceDiagnosticReport {
+            items,
+        }),
+    )))
+}
+
+fn on_semantic_tokens_full_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    tracing::debug!("semantic tokens");
+    let (id, params): (lsp_server::RequestId, lsp_types::SemanticTokensParams) = request.extract(
+        <lsp_types::request::SemanticTokensFullRequest as lsp_types::request::Request>::METHOD,
+    )?;
+    if !state.feature_enabled(SEMANTIC_TOKENS_METHOD) {
+        tracing::debug!("semantic tokens are disabled");
+        return Ok(Some(lsp_server::Response::new_ok(
+            id,
+            serde_json::Value::Null,
+        )));
+    }
+    let data = state.semantic_tokens(&params.text_document.uri, None)?;
+    Ok(Some(lsp_server::Response::new_ok(
+        id,
+        lsp_types::SemanticTokens {
+            result_id: None,
+            data,
+        },
+    )))
+}
+
+fn on_semantic_tokens_range_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    tracing::debug!("semantic tokens range");
+    let (id, params): (lsp_server::RequestId, lsp_types::SemanticTokensRangeParams) = request
+        .extract(
+            <lsp_types::request::SemanticTokensRangeRequest as lsp_types::request::Request>::METHOD,
+        )?;
+    if !state.feature_enabled(SEMANTIC_TOKENS_METHOD) {
+        tracing::debug!("semantic tokens are disabled");
+        return Ok(Some(lsp_server::Response::new_ok(
+            id,
+            serde_json::Value::Null,
+        )));
+    }
+    let data = state.semantic_tokens(&params.text_document.uri, Some(params.range))?;
+    Ok(Some(lsp_server::Response::new_ok(
+        id,
+        lsp_types::SemanticTokens {
+            result_id: None,
+            data,
+        },
+    )))
+}
+
+fn on_stage_contents_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    let (id, params): (lsp_server::RequestId, StageContentsParams) =
+        request.extract(STAGE_CONTENTS_METHOD)?;
+    tracing::debug!("stage contents: {:?}", params.text_document.uri);
+    if !state.config().git.enabled {
+        return Ok(Some(lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::InvalidRequest as i32,
+            format!("{STAGE_CONTENTS_METHOD} needs git.enabled"),
+        )));
+    }
+    let Some(path) = config_file::uri_to_path(&params.text_document.uri) else {
+        return Ok(Some(lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::InvalidParams as i32,
+            format!("{} is not a local file", params.text_document.uri.as_str()),
+        )));
+    };
+    let stages = match git::Repository::discover(&path) {
+        Some(repository) => repository.stage_contents(&path)?,
+        None => None,
+    };
+    Ok(Some(lsp_server::Response::new_ok(id, stages)))
+}
+
+fn on_svn_files_request(
+    _state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    let (id, params): (lsp_server::RequestId, StageContentsParams) =
+        request.extract(SVN_FILES_METHOD)?;
+    tracing::debug!("svn files: {:?}", params.text_document.uri);
+    let Some(path) = config_file::uri_to_path(&params.text_document.uri) else {
+        return Ok(Some(lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::InvalidParams as i32,
+            format!("{} is not a local file", params.text_document.uri.as_str()),
+        )));
+    };
+    let files = svn::conflict_files(&path)?;
+    Ok(Some(lsp_server::Response::new_ok(id, files)))
+}
+
+fn on_execute_command_request(
+    state: &mut ServerState,
+    request: lsp_server::Request,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    let (id, params): (lsp_server::RequestId, lsp_types::ExecuteCommandParams) = request
+        .extract(<lsp_types::request::ExecuteCommand as lsp_types::request::Request>::METHOD)?;
+    tracing::debug!("execute command: {}", params.command);
+    match params.command.as_ref() {
+        LIST_CONFLICTS_COMMAND => {
+            let uri = params
+                .arguments
+                .into_iter()
+                .next()
+                .and_then(|argument| serde_json::from_value::<lsp_types::Uri>(argument).ok());
+            let Some(uri) = uri else {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidParams as i32,
+                    format!("{LIST_CONFLICTS_COMMAND} expects a document URI"),
+                )));
+            };
+            let conflicts = state.list_conflicts(&uri)?;
+            Ok(Some(lsp_server::Response::new_ok(id, conflicts)))
+        }
+        RESOLVE_COMMAND => {
+            let arguments = serde_json::from_value::<(lsp_types::Uri, usize, Choice)>(
+                serde_json::Value::Array(params.arguments),
+            );
+            let Ok((uri, index, choice)) = arguments else {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidParams as i32,
+                    format!(
+                        "{RESOLVE_COMMAND} expects a document URI, a conflict index and a resolution"
+                    ),
+                )));
+            };
+            apply_resolution(state, id, uri, index, choice)
+        }
+        ACCEPT_OURS_COMMAND | ACCEPT_THEIRS_COMMAND | ACCEPT_BOTH_COMMAND => {
+            let resolution = match params.command.as_ref() {
+                ACCEPT_OURS_COMMAND => Resolution::Ours,
+                ACCEPT_THEIRS_COMMAND => Resolution::Theirs,
+                _ => Resolution::Both,
+            };
+            let choice = Choice::Named(resolution);
+            let arguments = serde_json::from_value::<(lsp_types::Uri, usize)>(
+                serde_json::Value::Array(params.arguments),
+            );
+            let Ok((uri, index)) = arguments else {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidParams as i32,
+                    format!(
+                        "{} expects a document URI and a conflict index",
+                        params.command
+                    ),
+                )));
+            };
+            apply_resolution(state, id, uri, index, choice)
+        }
+        MARK_RESOLVED_COMMAND => {
+            if !state.config().hg.enabled {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidRequest as i32,
+                    format!("{MARK_RESOLVED_COMMAND} needs hg.enabled"),
+                )));
+            }
+            let uri = params
+                .arguments
+                .into_iter()
+                .next()
+                .and_then(|argument| serde_json::from_value::<lsp_types::Uri>(argument).ok());
+            let Some(uri) = uri else {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidParams as i32,
+                    format!("{MARK_RESOLVED_COMMAND} expects a document URI"),
+                )));
+            };
+            let marked = mark_resolved_hg_file(state, &uri)?;
+            Ok(Some(lsp_server::Response::new_ok(id, marked)))
+        }
+        STAGE_FILE_COMMAND => {
+            if !state.config().git.enabled {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidRequest as i32,
+                    format!("{STAGE_FILE_COMMAND} needs git.enabled"),
+                )));
+            }
+            let uri = params
+                .arguments
+                .into_iter()
+                .next()
+                .and_then(|argument| serde_json::from_value::<lsp_types::Uri>(argument).ok());
+            let Some(uri) = uri else {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidParams as i32,
+                    format!("{STAGE_FILE_COMMAND} expects a document URI"),
+                )));
+            };
+            let staged = stage_resolved_file(state, &uri)?;
+            Ok(Some(lsp_server::Response::new_ok(id, staged)))
+        }
+        TAKE_VERSION_COMMAND => {
+            if !state.config().git.enabled {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidRequest as i32,
+                    format!("{TAKE_VERSION_COMMAND} needs git.enabled"),
+                )));
+            }
+            let arguments = serde_json::from_value::<(lsp_types::Uri, Side)>(
+                serde_json::Value::Array(params.arguments),
+            );
+            let Ok((uri, side)) = arguments else {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidParams as i32,
+                    format!("{TAKE_VERSION_COMMAND} expects a document URI and a side"),
+                )));
+            };
+            let taken = take_version(state, &uri, side)?;
+            Ok(Some(lsp_server::Response::new_ok(id, taken)))
+        }
+        RESOLVE_LINES_COMMAND => {
+            let arguments = serde_json::from_value::<(lsp_types::Uri, usize, Vec<(Side, usize)>)>(
+                serde_json::Value::Array(params.arguments),
+            );
+            let Ok((uri, index, selections)) = arguments else {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidParams as i32,
+                    format!(
+                        "{RESOLVE_LINES_COMMAND} expects a document URI, a conflict index and [side, line] selections"
+                    ),
+                )));
+            };
+            let Some(edit) = state.resolve_lines(&uri, index, &selections)? else {
+                return Ok(Some(lsp_server::Response::new_err(
+                    id,
+                    lsp_server::ErrorCode::InvalidParams as i32,
+                    format!(
+                        "selections {selections:?} do not fit conflict {index} in {}",
+                        uri.as_str()
+                    ),
+                )));
+            };
+            let edit = lsp_types::WorkspaceEdit {
+                changes: Some(std::collections::HashMap::from([(uri, vec![edit])])),
+                ..Default::default()
+            };
+            Ok(Some(lsp_server::Response::new_ok(id, edit)))
+        }
+        unknown => Ok(Some(lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::InvalidParams as i32,
+            format!("unknown command: {unknown}"),
+        ))),
+    }
+}
+
+/// Send the edit resolving conflict `index` of `uri` to the client with `workspace/applyEdit`.
+///
+/// The diagnostics are refreshed once the client reports the edit applied.
+fn apply_resolution(
+    state: &mut ServerState,
+    id: lsp_server::RequestId,
+    uri: lsp_types::Uri,
+    index: usize,
+    choice: Choice,
+) -> anyhow::Result<Option<lsp_server::Response>> {
+    let Some((version, edits)) = state.resolve_conflict(&uri, index, &choice)? else {
+        return Ok(Some(lsp_server::Response::new_err(
+            id,
+            lsp_server::ErrorCode::InvalidParams as i32,
+            format!(
+                "no conflict {index} to resolve with {choice:?} in {}",
+                uri.as_str()
+            ),
+        )));
+    };
+    send_apply_edit(state, uri, version, edits)?;
+    Ok(Some(lsp_server::Response::new_ok(
+        id,
+        serde_json::Value::Null,
+    )))
+}
+
+/// Send `edits` to the client with `workspace/applyEdit`, tracking the request so the
+/// result can be acted on.
+fn send_apply_edit(
+    state: &ServerState,
+    uri: lsp_types::Uri,
+    version: i32,
+    edits: Vec<lsp_types::TextEdit>,
+) -> anyhow::Result<()> {
+    let params = lsp_types::ApplyWorkspaceEditParams {
+        label: Some("Resolve merge conflict".to_owned()),
+        edit: lsp_types::WorkspaceEdit {
+            changes: Some(std::collections::HashMap::from([(
+                uri.clone(),
+                edits.clone(),
+            )])),
+            ..Default::default()
+        },
+    };
+    state.send_tracked_request(
+        <lsp_types::request::ApplyWorkspaceEdit as lsp_types::request::Request>::METHOD,
+        params,
+        PendingRequest::ApplyEdit {
+            uri,
+            version,
+            edits,
+        },
+    )?;
+    Ok(())
+}
+
+fn on_response(state: &mut ServerState, response: lsp_server::Response) -> anyhow::Result<()> {
+    let Some(pending) = state.take_pending_request(&response.id)? else {
+        tracing::debug!("no pending request for response {:?}", response.id);
+        return Ok(());
+    };
+    match pending {
+        PendingRequest::ApplyEdit {
+            uri,
+            version,
+            edits,
+        } => {
+            let result = response.result.and_then(|result| {
+                serde_json::from_value::<lsp_types::ApplyWorkspaceEditResponse>(result).ok()
+            });
+            let applied = result.as_ref().is_some_and(|result| result.applied);
+            if !applied {
+                let reason = response
+                    .error
+                    .map(|error| error.message)
+                    .or_else(|| result.and_then(|result| result.failure_reason))
+                    .unwrap_or_else(|| "no reason given".to_owned());
+                let message = format!("{}: edit was not applied: {reason}", uri.as_str());
+                if !state.client.show_message_request {
+                    send_log_message(
+                        state.sender.clone(),
+                        lsp_types::MessageType::WARNING,
+                        message,
+                    );
+                    return Ok(());
+                }
+                state.send_tracked_request(
+                    <lsp_types::request::ShowMessageRequest as lsp_types::request::Request>::METHOD,
+                    lsp_types::ShowMessageRequestParams {
+                        typ: lsp_types::MessageType::WARNING,
+                        message,
+                        actions: Some(vec![lsp_types::MessageActionItem {
+                            title: RETRY_ACTION.to_owned(),
+                            properties: Default::default(),
+                        }]),
+                    },
+                    PendingRequest::RetryApplyEdit {
+                        uri,
+                        version,
+                        edits,
+                    },
+                )?;
+                return Ok(());
+            }
+            // Publish right away instead of waiting for the client's didChange. Pulling clients
+            // are asked to refresh instead, when they support it.
+            if !state.push_diagnostics() {
+                if state.client.diagnostic_refresh {
+                    state.refresh(
+                        <lsp_types::request::WorkspaceDiagnosticRefresh as lsp_types::request::Request>::METHOD,
+                    )?;
+                }
+                return Ok(());
+            }
+            if let Some((content, merge_conflict)) =
+                state.conflicts_after_edit(&uri, version, &edits)?
+            {
+                let message = prepare_diagnostics(
+                    &uri,
+                    Some(version),
+                    &content,
+                    &merge_conflict,
+                    &git_details(state, &uri),
+                    &state.config(),
+                );
+                let sender = state.sender.lock().unwrap_or_else(PoisonError::into_inner);
+                if let Err(e) = sender.send(message.into()) {
+                    tracing::error!("Failed to send message: {e}");
+                }
+            }
+        }
+        PendingRequest::RetryApplyEdit {
+            uri,
+            version,
+            edits,
+        } => {
+            let action = response.result.and_then(|result| {
+                serde_json::from_value::<Option<lsp_types::MessageActionItem>>(result)
+                    .ok()
+                    .flatten()
+            });
+            if action.is_none_or(|action| action.title != RETRY_ACTION) {
+                tracing::debug!("{}: edit retry dismissed", uri.as_str());
+                return Ok(());
+            }
+            // The edit's ranges are only valid for the version it was computed from.
+            if state.document_version(&uri)? != Some(version) {
+                send_log_message(
+                    state.sender.clone(),
+                    lsp_types::MessageType::WARNING,
+                    format!("{}: document changed, edit not retried", uri.as_str()),
+                );
+                return Ok(());
+            }
+            send_apply_edit(state, uri, version, edits)?;
+        }
+        PendingRequest::Acknowledged { method } => {
+            if let Some(error) = response.error {
+                tracing::warn!("{method} failed: {}", error.message);
+            }
+        }
+        PendingRequest::Configuration => {
+            if let Some(error) = response.error {
+                tracing::warn!("pulling configuration failed: {}", error.message);
+                return Ok(());
+            }
+            // One item was asked for, so the result is a one element array.
+            let settings = response
+                .result
+                .and_then(|result| serde_json::from_value::<Vec<serde_json::Value>>(result).ok())
+                .and_then(|items| items.into_iter().next())
+                .filter(serde_json::Value::is_object);
+            match settings {
+                Some(settings) => apply_settings(state, settings)?,
+                None => tracing::debug!("client has no settings for the server"),
+            }
+        }
+    }
+    Ok(())
+}
+
 fn on_shutdown(
     state: &mut ServerState,
     request: lsp_server::Request,
 ) -> anyhow::Result<Option<lsp_server::Response>> {
     tracing::info!("shutdown requested");
     state.status = ServerStatus::ShutdownRequested;
+    // Let in-flight updates publish before the client considers us shut down.
+    state.wait_for_pending_updates();
     Ok(Some(lsp_server::Response::new_ok(
         request.id,
         serde_json::Value::Null,
     )))
 }
 
-fn document_update_thread(uri: lsp_types::Uri, version: i32, state: ServerState) {
+/// Run queued document updates until none are left.
+fn update_worker(state: &ServerState) {
+    tracing::debug!("document update worker started");
+    while let Some((uri, update)) = state.next_update() {
+        let debounce = std::time::Duration::from_millis(state.config().debounce_ms);
+        if let Some(wait) = debounce.checked_sub(update.queued.elapsed()) {
+            // A newer change arriving meanwhile supersedes this update and skips its parse.
+            thread::sleep(wait);
+        }
+        // A document the parser chokes on must not take the worker, and with it the count of
+        // running workers, down.
+        let updated = panic::catch_unwind(AssertUnwindSafe(|| {
+            update_document(uri, update.version, update.generation, state)
+        }));
+        if let Err(payload) = updated {
+            tracing::error!("document update panicked: {}", panic_text(&*payload));
+        }
+    }
+    tracing::debug!("document update worker finished");
+}
+
+/// Parse `uri` and publish its diagnostics, unless an update scheduled after `generation`
+/// supersedes this one.
+fn update_document(uri: lsp_types::Uri, version: i32, generation: u64, state: &ServerState) {
     tracing::debug!(
-        "document update worker started for {:?} version {}",
+        "updating {:?} version {} generation {}",
         uri,
-        version
+        version,
+        generation
     );
+    if !state.is_latest_update(&uri, generation) {
+        tracing::debug!("update {generation} of {uri:?} superseded, not parsing");
+        return;
+    }
     match state.on_document_update(&uri, version) {
+        Ok(_) if !state.is_latest_update(&uri, generation) => {
+            // The newer update publishes what it parses.
+            tracing::debug!("update {generation} of {uri:?} superseded, not publishing");
+        }
         Ok(conflicts) => {
             let count = conflicts.as_ref().map_or(0, |mc| mc.conflicts().count());
             tracing::info!("{:?}: parsed {} conflict(s)", uri, count);
@@ -204,41 +1142,417 @@ fn document_update_thread(uri: lsp_types::Uri, version: i32, state: ServerState)
                     format!("{}: found {count} merge conflict(s)", uri.as_str()),
                 );
             }
-            let message = prepare_diagnostics(&uri, version, &conflicts);
-            let sender = state.sender.lock().expect("lock on sender");
-            if let Err(e) = sender.send(message.into()) {
-                tracing::error!("Failed to send message: {e}");
+            if state.push_diagnostics() {
+                // Conflicts the parse left unchanged come back as `None` too, and an update this
+                // one superseded may have published something else since.
+                let (content, current) = state.document_conflicts(&uri).unwrap_or_default();
+                let message = prepare_diagnostics(
+                    &uri,
+                    Some(version),
+                    &content,
+                    &current,
+                    &git_details(state, &uri),
+                    &state.config(),
+                );
+                if !state.send_if_latest(&uri, generation, message.into()) {
+                    tracing::debug!("update {generation} of {uri:?} superseded, not publishing");
+                }
+            }
+            if count > 0
+                && let Err(err) = auto_resolve_identical(state, &uri, version)
+            {
+                tracing::error!("From auto_resolve_identical: {err:?}");
             }
         }
         Err(err) => {
             tracing::error!("From on_document_update: {err:?}");
         }
     }
-    tracing::debug!("document update worker finished for {:?}", uri);
+    tracing::debug!("update of {:?} finished", uri);
 }
 
-fn prepare_diagnostics(
-    uri: &lsp_types::Uri,
-    version: i32,
-    merge_conflict: &Option<MergeConflict>,
-) -> lsp_server::Notification {
-    let diagnostics: Vec<lsp_types::Diagnostic> = match merge_conflict {
-        Some(current_conflict) => current_conflict
-            .conflicts()
-            .map(lsp_types::Diagnostic::from)
-            .collect(),
-        None => Vec::new(),
+/// Publish diagnostics for the conflicted files of the workspace's repository that are not
+/// open, so they are listed before the user gets to them.
+fn publish_conflicted_files(state: &ServerState) -> anyhow::Result<()> {
+    let Some(repository) = state
+        .workspace_root
+        .as_deref()
+        .and_then(git::Repository::discover)
+    else {
+        tracing::debug!("git: no repository for the workspace");
+        return Ok(());
     };
-    tracing::info!(
-        "publishing {} diagnostic(s) for {:?} version {}",
-        diagnostics.len(),
-        uri,
-        version
+    let paths = repository.conflicted_paths()?;
+    #[allow(clippy::mutable_key_type)]
+    let file_conflicts = repository
+        .file_conflicts()?
+        .into_iter()
+        .filter_map(|(path, conflict)| Some((config_file::path_to_uri(&path)?, conflict)))
+        .collect();
+    *state
+        .file_conflicts
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = file_conflicts;
+    if let Some(operation) = repository.operation() {
+        send_log_message(
+            state.sender.clone(),
+            lsp_types::MessageType::INFO,
+            format!(
+                "git: {} in progress, {} conflicted file(s)",
+                operation.name(),
+                paths.len()
+            ),
+        );
+    }
+    let names = rebase_names(
+        &repository,
+        &mut std::collections::HashMap::new(),
+        &mut None,
+    );
+    publish_unopened_files(state, paths, &names, |path| {
+        Ok(repository
+            .conflict_marker_size(path)?
+            .unwrap_or(DEFAULT_MARKER_SIZE))
+    })
+}
+
+/// Publish diagnostics for the files `hg resolve --list` reports unresolved, like
+/// [`publish_conflicted_files`] does for git.
+fn publish_unresolved_hg_files(state: &ServerState) -> anyhow::Result<()> {
+    let Some(repository) = state
+        .workspace_root
+        .as_deref()
+        .and_then(hg::Repository::discover)
+    else {
+        tracing::debug!("hg: no repository for the workspace");
+        return Ok(());
+    };
+    if !repository.merging() {
+        return Ok(());
+    }
+    let paths = repository.unresolved_paths()?;
+    send_log_message(
+        state.sender.clone(),
+        lsp_types::MessageType::INFO,
+        format!("hg: merge in progress, {} unresolved file(s)", paths.len()),
+    );
+    publish_unopened_files(state, paths, &SideNames::default(), |_| {
+        Ok(DEFAULT_MARKER_SIZE)
+    })
+}
+
+/// Publish diagnostics for those of `paths` that are not open, reading them from disk with
+/// markers of the length `marker_size` gives and sides called `names`.
+fn publish_unopened_files(
+    state: &ServerState,
+    paths: Vec<PathBuf>,
+    names: &SideNames,
+    marker_size: impl Fn(&Path) -> anyhow::Result<usize>,
+) -> anyhow::Result<()> {
+    if !state.push_diagnostics() {
+        return Ok(());
+    }
+    let config = state.config();
+    for path in paths {
+        let Some(uri) = config_file::path_to_uri(&path) else {
+            continue;
+        };
+        if is_open(state, &uri)? || config.is_excluded(&uri, "") {
+            continue;
+        }
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::warn!("cannot read {}: {e}", path.display());
+                // Binary files have no markers, but may have a file conflict to report.
+                String::new()
+            }
+        };
+        let document_state = DocumentState::new(String::new(), content, 0)
+            .with_encoding(state.client.position_encoding)
+            .with_marker_size(marker_size(&path)?)
+            .with_config(&config);
+        let Ok(merge_conflict) = document_state.parse() else {
+            continue;
+        };
+        // Unopened files have no commits looked up for them.
+        let git = GitDetails {
+            names: names.clone(),
+            file_diagnostic: file_conflict_diagnostic(state, &uri),
+            ..Default::default()
+        };
+        let message = prepare_diagnostics(
+            &uri,
+            None,
+            document_state.document.get_content(None),
+            &merge_conflict,
+            &git,
+            &config,
+        );
+        let sender = state.sender.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = sender.send(message.into()) {
+            tracing::error!("Failed to send message: {e}");
+        }
+        state
+            .unopened
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(uri);
+    }
+    Ok(())
+}
+
+/// Clear the diagnostics of `uri`, published before from disk.
+fn publish_empty_diagnostics(state: &ServerState, uri: lsp_types::Uri) {
+    if !state.push_diagnostics() {
+        return;
+    }
+    let message = lsp_server::Notification::new(
+        <lsp_types::notification::PublishDiagnostics as lsp_types::notification::Notification>::METHOD.to_owned(),
+        lsp_types::PublishDiagnosticsParams {
+            uri,
+            diagnostics: Vec::new(),
+            version: None,
+        },
+    );
+    let sender = state.sender.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Err(e) = sender.send(message.into()) {
+        tracing::error!("Failed to send message: {e}");
+    }
+}
+
+/// Whether the client has the document of `uri` open.
+fn is_open(state: &ServerState, uri: &lsp_types::Uri) -> anyhow::Result<bool> {
+    Ok(state
+        .documents
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .contains_key(uri))
+}
+
+/// Whether the file of `uri` has no conflicts left. Open documents are checked as the client
+/// has them, others as saved.
+fn conflicts_resolved(
+    state: &ServerState,
+    uri: &lsp_types::Uri,
+    path: &Path,
+    marker_size: usize,
+) -> anyhow::Result<bool> {
+    if let Some(resolved) = state.document_resolved(uri)? {
+        return Ok(resolved);
+    }
+    let content = std::fs::read_to_string(path)?;
+    Ok(DocumentState::new(String::new(), content, 0)
+        .with_marker_size(marker_size)
+        .with_config(&state.config())
+        .parse()?
+        .is_none_or(|merge_conflict| merge_conflict.is_resolved()))
+}
+
+/// `git add` the file of `uri` when it is conflicted in the index but has no conflicts left,
+/// and tell the user.
+fn stage_resolved_file(state: &ServerState, uri: &lsp_types::Uri) -> anyhow::Result<bool> {
+    let Some(path) = config_file::uri_to_path(uri) else {
+        return Ok(false);
+    };
+    let Some(repository) = git::Repository::discover(&path) else {
+        return Ok(false);
+    };
+    let marker_size = repository
+        .conflict_marker_size(&path)?
+        .unwrap_or(DEFAULT_MARKER_SIZE);
+    // A file deleted to resolve a file conflict has nothing left to check.
+    let resolved = !path.exists() || conflicts_resolved(state, uri, &path, marker_size)?;
+    if !resolved || !repository.stage_resolved(&path)? {
+        return Ok(false);
+    }
+    tracing::info!("git: staged {}", path.display());
+    forget_resolved_file(state, uri)?;
+    send_show_message(
+        state.sender.clone(),
+        lsp_types::MessageType::INFO,
+        format!("Staged {}, no merge conflicts left", path.display()),
+    );
+    Ok(true)
+}
+
+/// Drop the file conflict over `uri` once it is resolved, and the diagnostics it or a file read
+/// from disk had with it. An open document keeps the rest of its own diagnostics.
+fn forget_resolved_file(state: &ServerState, uri: &lsp_types::Uri) -> anyhow::Result<()> {
+    let had_file_conflict = state
+        .file_conflicts
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(uri)
+        .is_some();
+    let was_unopened = state
+        .unopened
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(uri);
+    if (had_file_conflict || was_unopened) && state.push_diagnostics() {
+        let (version, diagnostics) = match state.document_diagnostics(uri)? {
+            Some((version, diagnostics)) => (Some(version), diagnostics),
+            None => (None, Vec::new()),
+        };
+        let message = lsp_server::Notification::new(
+            <lsp_types::notification::PublishDiagnostics as lsp_types::notification::Notification>::METHOD.to_owned(),
+            lsp_types::PublishDiagnosticsParams {
+                uri: uri.clone(),
+                diagnostics,
+                version,
+            },
+        );
+        let sender = state.sender.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = sender.send(message.into()) {
+            tracing::error!("Failed to send message: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Replace the file of `uri` with `side`'s version from the git index and stage it, and tell
+/// the user.
+fn take_version(state: &ServerState, uri: &lsp_types::Uri, side: Side) -> anyhow::Result<bool> {
+    let Some(path) = config_file::uri_to_path(uri) else {
+        return Ok(false);
+    };
+    let Some(repository) = git::Repository::discover(&path) else {
+        return Ok(false);
+    };
+    if !repository.take_stage(&path, side.stage())? {
+        return Ok(false);
+    }
+    let name = match side {
+        Side::Ours => "our",
+        Side::Theirs => "their",
+        Side::Ancestor => "ancestor",
+    };
+    tracing::info!("git: took {name} version of {}", path.display());
+    forget_resolved_file(state, uri)?;
+    send_show_message(
+        state.sender.clone(),
+        lsp_types::MessageType::INFO,
+        format!("Took the {name} version of {}", path.display()),
+    );
+    Ok(true)
+}
+
+/// `hg resolve --mark` the file of `uri` when it is unresolved but has no conflicts left, and
+/// tell the user.
+fn mark_resolved_hg_file(state: &ServerState, uri: &lsp_types::Uri) -> anyhow::Result<bool> {
+    let Some(path) = config_file::uri_to_path(uri) else {
+        return Ok(false);
+    };
+    let Some(repository) = hg::Repository::discover(&path) else {
+        return Ok(false);
+    };
+    if !conflicts_resolved(state, uri, &path, DEFAULT_MARKER_SIZE)?
+        || !repository.mark_resolved(&path)?
+    {
+        return Ok(false);
+    }
+    tracing::info!("hg: marked {} resolved", path.display());
+    forget_resolved_file(state, uri)?;
+    send_show_message(
+        state.sender.clone(),
+        lsp_types::MessageType::INFO,
+        format!(
+            "Marked {} resolved, no merge conflicts left",
+            path.display()
+        ),
+    );
+    Ok(true)
+}
+
+/// Send the edit resolving conflicts with identical sides, when configured to.
+fn auto_resolve_identical(
+    state: &ServerState,
+    uri: &lsp_types::Uri,
+    version: i32,
+) -> anyhow::Result<()> {
+    if !state.config().auto_resolve_identical || !state.client.apply_edit {
+        return Ok(());
+    }
+    if let Some(edits) = state.identical_sides_edits(uri, version)? {
+        tracing::info!("{:?}: resolving conflicts with identical sides", uri);
+        send_apply_edit(state, uri.clone(), version, edits)?;
+    }
+    Ok(())
+}
+
+/// What git says about a document besides its markers, shown with its diagnostics.
+#[derive(Debug, Default)]
+struct GitDetails {
+    /// The commits behind the sides of the conflicts.
+    commits: Vec<ConflictCommits>,
+    /// Names replacing the marker names during a rebase.
+    names: SideNames,
+    /// The whole-file conflict git reports, see [`ServerState::file_conflict_diagnostic`].
+    file_diagnostic: Option<lsp_types::Diagnostic>,
+}
+
+/// What git says about the open document `uri`. Lookup failures are logged and leave the
+/// diagnostics without what they would have added.
+fn git_details(state: &ServerState, uri: &lsp_types::Uri) -> GitDetails {
+    GitDetails {
+        commits: conflict_commits(state, uri),
+        names: state.side_names(uri),
+        file_diagnostic: file_conflict_diagnostic(state, uri),
+    }
+}
+
+/// The whole-file diagnostic of `uri`, see [`ServerState::file_conflict_diagnostic`].
+fn file_conflict_diagnostic(
+    state: &ServerState,
+    uri: &lsp_types::Uri,
+) -> Option<lsp_types::Diagnostic> {
+    state.file_conflict_diagnostic(uri).unwrap_or_else(|e| {
+        tracing::warn!("no file conflict for {:?}: {e:#}", uri);
+        None
+    })
+}
+
+fn conflict_commits(state: &ServerState, uri: &lsp_types::Uri) -> Vec<ConflictCommits> {
+    state.conflict_commits(uri, |_| true).unwrap_or_else(|e| {
+        tracing::warn!("git: no commits for {:?}: {e:#}", uri);
+        Vec::new()
+    })
+}
+
+/// The `textDocument/publishDiagnostics` notification for a document, with `merge_conflict`
+/// found in `content` and what `git` says about it. `version` is `None` for files that are not
+/// open.
+fn prepare_diagnostics(
+    uri: &lsp_types::Uri,
+    version: Option<i32>,
+    content: &str,
+    merge_conflict: &Option<MergeConflict>,
+    git: &GitDetails,
+    config: &Config,
+) -> lsp_server::Notification {
+    let mut diagnostics = match merge_conflict {
+        Some(current_conflict) => current_conflict.diagnostics(
+            content,
+            &config.diagnostic_source,
+            config.diagnostic_severity.into(),
+            &git.names,
+            &config.labels,
+        ),
+        None => Vec::new(),
+    };
+    add_commit_information(&mut diagnostics, uri, &git.commits);
+    diagnostics.splice(0..0, git.file_diagnostic.clone());
+    tracing::info!(
+        "publishing {} diagnostic(s) for {:?} version {:?}",
+        diagnostics.len(),
+        uri,
+        version
     );
     let publish_diagnostics_params = lsp_types::PublishDiagnosticsParams {
         uri: uri.clone(),
         diagnostics,
-        version: Some(version),
+        version,
     };
     lsp_server::Notification::new(
                 <lsp_types::notification::PublishDiagnostics as lsp_types::notification::Notification>::METHOD.to_owned(),
@@ -246,27 +1560,91 @@ fn prepare_diagnostics(
             )
 }
 
-pub fn server_capabilities() -> lsp_types::ServerCapabilities {
+/// Capabilities announced in the initialize response.
+///
+/// Clients that support dynamic registration get the code action provider later through
+/// `client/registerCapability`, so it can be toggled without a restart.
+pub fn server_capabilities(
+    config: &Config,
+    client: &ClientSupport,
+) -> lsp_types::ServerCapabilities {
+    let dynamic_registration = client.dynamic_registration;
     let text_document_sync = Some(lsp_types::TextDocumentSyncCapability::Options(
         lsp_types::TextDocumentSyncOptions {
             open_close: Some(true),
-            change: Some(lsp_types::TextDocumentSyncKind::INCREMENTAL),
-            ..Default::default()
-        },
-    ));
-    let code_action_provider = Some(lsp_types::CodeActionProviderCapability::Options(
-        lsp_types::CodeActionOptions {
-            code_action_kinds: Some(vec![lsp_types::CodeActionKind::QUICKFIX]),
+            change: Some(config.sync.into()),
+            // Documents over the size limit may be parsed on save only.
+            save: Some(lsp_types::TextDocumentSyncSaveOptions::Supported(true)),
             ..Default::default()
         },
     ));
+    let code_action_provider = (config.features.code_action && !dynamic_registration)
+        .then(|| lsp_types::CodeActionProviderCapability::Options(code_action_options()));
+    let code_lens_provider =
+        (config.features.code_lens && !dynamic_registration).then(code_lens_options);
+    let diagnostic_provider = (config.features.pull_diagnostics && !dynamic_registration)
+        .then(|| lsp_types::DiagnosticServerCapabilities::Options(diagnostic_options()));
+    let semantic_tokens_provider =
+        (config.features.semantic_tokens && !dynamic_registration).then(|| {
+            lsp_types::SemanticTokensServerCapabilities::SemanticTokensOptions(
+                semantic_tokens_options(),
+            )
+        });
+    let document_highlight_provider = Some(lsp_types::OneOf::Left(true));
+    let hover_provider = config
+        .git
+        .enabled
+        .then_some(lsp_types::HoverProviderCapability::Simple(true));
+    let selection_range_provider = Some(lsp_types::SelectionRangeProviderCapability::Simple(true));
+    let execute_command_provider = Some(lsp_types::ExecuteCommandOptions {
+        commands: vec![
+            LIST_CONFLICTS_COMMAND.to_owned(),
+            RESOLVE_COMMAND.to_owned(),
+            RESOLVE_LINES_COMMAND.to_owned(),
+            ACCEPT_OURS_COMMAND.to_owned(),
+            ACCEPT_THEIRS_COMMAND.to_owned(),
+            ACCEPT_BOTH_COMMAND.to_owned(),
+            STAGE_FILE_COMMAND.to_owned(),
+            MARK_RESOLVED_COMMAND.to_owned(),
+            TAKE_VERSION_COMMAND.to_owned(),
+        ],
+        ..Default::default()
+    });
     lsp_types::ServerCapabilities {
+        position_encoding: Some(client.position_encoding.into()),
         text_document_sync,
         code_action_provider,
+        code_lens_provider,
+        document_highlight_provider,
+        hover_provider,
+        selection_range_provider,
+        semantic_tokens_provider,
+        diagnostic_provider,
+        execute_command_provider,
         ..Default::default()
     }
 }
 
+pub fn send_show_message(
+    sender: Arc<Mutex<crossbeam_channel::Sender<lsp_server::Message>>>,
+    typ: lsp_types::MessageType,
+    message: impl Into<String>,
+) {
+    let params = lsp_types::ShowMessageParams {
+        typ,
+        message: message.into(),
+    };
+    let notification = lsp_server::Notification::new(
+        <lsp_types::notification::ShowMessage as lsp_types::notification::Notification>::METHOD
+            .to_owned(),
+        params,
+    );
+    let locked_sender = sender.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Err(e) = locked_sender.send(notification.into()) {
+        tracing::error!("Failed to send showMessage: {e}");
+    }
+}
+
 pub fn send_log_message(
     sender: Arc<Mutex<crossbeam_channel::Sender<lsp_server::Message>>>,
     typ: lsp_types::MessageType,
@@ -281,7 +1659,7 @@ pub fn send_log_message(
             .to_owned(),
         params,
     );
-    let locked_sender = sender.lock().expect("lock on sender");
+    let locked_sender = sender.lock().unwrap_or_else(PoisonError::into_inner);
     if let Err(e) = locked_sender.send(notification.into()) {
         tracing::error!("Failed to send logMessage: {e}");
     }
@@ -295,16 +1673,24 @@ mod test {
     use super::*;
 
     use crate::test_helpers::{
-        TEXT1_RESOLVED, TEXT1_WITH_CONFLICTS, TEXT2_RESOLVED, TEXT2_WITH_CONFLICTS,
+        TEXT1_RESOLVED, TEXT1_WITH_CONFLICTS, TEXT2_RESOLVED, TEXT2_WITH_CONFLICTS, apply_edits,
         conflicts_for_text2_with_conflicts, populated_state, state, uri, version,
     };
-    use crate::{parser::parse, state::DocumentState};
+    use crate::{
+        config::SyncKind,
+        parser::{ConflictRegion, MarkerBytes, parse},
+        state::DocumentState,
+    };
 
     #[fixture]
-    fn did_open(version: i32, #[default("")] text: &str) -> lsp_server::Notification {
+    fn did_open(
+        version: i32,
+        #[default("")] text: &str,
+        #[default("")] language_id: &str,
+    ) -> lsp_server::Notification {
         let text_document = lsp_types::TextDocumentItem {
             uri: uri().clone(),
-            language_id: "".to_string(),
+            language_id: language_id.to_string(),
             version,
             text: text.to_string(),
         };
@@ -455,6 +1841,38 @@ mod test {
         };
     }
 
+    #[rstest]
+    fn change_whole_document_before_open_creates_document(
+        uri: lsp_types::Uri,
+        mut state: ServerState,
+        #[with(3, TEXT2_WITH_CONFLICTS)] did_change_whole_document: lsp_server::Notification,
+    ) {
+        let result = on_did_change_text_document(&mut state, did_change_whole_document);
+        let (_uri, version) = result.unwrap().unwrap();
+        assert_eq!(3, version);
+        {
+            let documents = state.documents.lock().unwrap();
+            let document_state = documents.get(&uri).unwrap();
+            let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+            assert_eq!(3, locked_document_state.version());
+            assert_eq!(TEXT2_WITH_CONFLICTS, locked_document_state.content());
+        }
+        let conflicts = state.on_document_update(&uri, version).unwrap();
+        assert_eq!(Some(conflicts_for_text2_with_conflicts()), conflicts);
+    }
+
+    #[rstest]
+    fn change_incrementally_before_open_is_dropped(
+        uri: lsp_types::Uri,
+        mut state: ServerState,
+        #[with(2, &[insert!(line: 0, character: 0, "!")])]
+        did_change_incrementally: lsp_server::Notification,
+    ) {
+        let result = on_did_change_text_document(&mut state, did_change_incrementally);
+        assert!(result.unwrap().is_none());
+        assert!(state.documents.lock().unwrap().get(&uri).is_none());
+    }
+
     #[rstest]
     fn change_document_with_markers_incrementally_changed_outside_of_markers_returns_document_data(
         uri: lsp_types::Uri,
@@ -498,12 +1916,127 @@ mod test {
             format!("!\n# Just a comment.\n{}@", TEXT2_WITH_CONFLICTS),
             locked_document_state.content()
         );
+        // The conflicts moved along with the lines inserted before them.
+        assert!(!locked_document_state.is_stale());
         assert_eq!(
             locked_document_state.merge_conflict,
-            Some(conflicts_for_text2_with_conflicts())
+            locked_document_state.parse().unwrap()
         );
     }
 
+    #[rstest]
+    fn change_document_with_two_inserts_on_the_same_line_applies_them_in_order(
+        #[with(1, TEXT2_RESOLVED)] mut populated_state: ServerState,
+        #[with(
+                2,
+                 &[insert!(line: 2, character: 0, "a"),
+                   insert!(line: 2, character: 1, "b")
+                  ])
+            ]
+        did_change_incrementally: lsp_server::Notification,
+    ) {
+        let result = on_did_change_text_document(&mut populated_state, did_change_incrementally);
+        let (_uri, version) = result.unwrap().unwrap();
+        assert_eq!(2, version);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri()).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        let new_text = TEXT2_RESOLVED.replace("plain old", "abplain old");
+        assert_eq!(new_text, locked_document_state.content());
+    }
+
+    #[rstest]
+    fn change_document_with_inserts_at_increasing_characters_uses_offsets_after_prior_changes(
+        #[with(1, TEXT2_RESOLVED)] mut populated_state: ServerState,
+        #[with(
+                2,
+                 &[insert!(line: 2, character: 5, "X"),
+                   // "plainX old" is now 10 characters long; the second insert targets its end.
+                   insert!(line: 2, character: 10, "Y")
+                  ])
+            ]
+        did_change_incrementally: lsp_server::Notification,
+    ) {
+        let result = on_did_change_text_document(&mut populated_state, did_change_incrementally);
+        let (_uri, version) = result.unwrap().unwrap();
+        assert_eq!(2, version);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri()).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        let new_text = TEXT2_RESOLVED.replace("plain old", "plainX oldY");
+        assert_eq!(new_text, locked_document_state.content());
+    }
+
+    #[rstest]
+    fn full_sync_mode_replaces_whole_document(
+        #[with(1, TEXT2_WITH_CONFLICTS)] mut populated_state: ServerState,
+        #[with(2, TEXT2_RESOLVED)] did_change_whole_document: lsp_server::Notification,
+    ) {
+        let config = Config {
+            sync: SyncKind::Full,
+            ..Default::default()
+        };
+        let Some(lsp_types::TextDocumentSyncCapability::Options(options)) =
+            server_capabilities(&config, &ClientSupport::default()).text_document_sync
+        else {
+            panic!("expected sync options");
+        };
+        assert_eq!(Some(lsp_types::TextDocumentSyncKind::FULL), options.change);
+
+        let result = on_did_change_text_document(&mut populated_state, did_change_whole_document);
+        let (_uri, version) = result.unwrap().unwrap();
+        assert_eq!(2, version);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri()).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(2, locked_document_state.version());
+        assert_eq!(TEXT2_RESOLVED, locked_document_state.content());
+    }
+
+    #[rstest]
+    fn ranged_changes_before_whole_document_replacement_are_skipped(
+        #[with(1, TEXT2_WITH_CONFLICTS)] mut populated_state: ServerState,
+    ) {
+        // This ranged change would be out of bounds for the document; it must not be applied.
+        let params = lsp_types::DidChangeTextDocumentParams {
+            text_document: lsp_types::VersionedTextDocumentIdentifier {
+                uri: uri(),
+                version: 2,
+            },
+            content_changes: vec![
+                lsp_types::TextDocumentContentChangeEvent {
+                    range: Some(lsp_types::Range {
+                        start: lsp_types::Position {
+                            line: 500,
+                            character: 0,
+                        },
+                        end: lsp_types::Position {
+                            line: 500,
+                            character: 0,
+                        },
+                    }),
+                    range_length: None,
+                    text: "ignored".to_string(),
+                },
+                lsp_types::TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: TEXT1_RESOLVED.to_string(),
+                },
+            ],
+        };
+        let notification = lsp_server::Notification {
+            method: <DidChangeTextDocument as lsp_types::notification::Notification>::METHOD
+                .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        };
+        on_did_change_text_document(&mut populated_state, notification).unwrap();
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri()).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(TEXT1_RESOLVED, locked_document_state.content());
+    }
+
     macro_rules! replace {
         (line: $line:expr, character: $char:expr, old: $old_s:expr, new: $new_s:expr) => {
             (
@@ -621,7 +2154,8 @@ mod test {
         let actions: Vec<lsp_types::CodeAction> =
             serde_json::from_value(response.result.unwrap()).unwrap();
 
-        assert_eq!(4, actions.len());
+        // The conflict's own actions, then those resolving both conflicts in the file.
+        assert_eq!(8, actions.len());
 
         let replacement = |action: &lsp_types::CodeAction| -> String {
             // the HashMap definition for `changes` is not owned by this project. It comes from the LSP crate.
@@ -633,8 +2167,11 @@ mod test {
                 .changes
                 .as_ref()
                 .expect("valid changes");
-            let item = changes.values().next().expect("there is an initial change");
-            item[0].new_text.clone()
+            let edits = changes.values().next().expect("there is an initial change");
+            // What is left where the first conflict was.
+            let resolved = apply_edits(TEXT1_WITH_CONFLICTS, edits);
+            let start = "\nThis is some\n".len();
+            resolved[start..resolved.find("text.\n").unwrap()].to_owned()
         };
 
         assert_eq!("Keep OURS", actions[0].title);
@@ -645,6 +2182,9 @@ mod test {
 
         assert_eq!("Keep both", actions[2].title);
         assert_eq!("plain old\nnew and improved\n", replacement(&actions[2]));
+
+        assert_eq!("Keep both with separator", actions[3].title);
+        assert_eq!("plain old\n\nnew and improved\n", replacement(&actions[3]));
     }
 
     #[rstest]
@@ -692,8 +2232,10 @@ mod test {
         let actions: Vec<lsp_types::CodeAction> =
             serde_json::from_value(response.result.unwrap()).unwrap();
 
-        let drop_all = actions.last().expect("at least one action");
-        assert_eq!("Drop all", drop_all.title);
+        let drop_all = actions
+            .iter()
+            .find(|action| action.title == "Drop all")
+            .expect("a drop all action");
 
         #[allow(clippy::mutable_key_type)]
         let changes = drop_all
@@ -706,4 +2248,1953 @@ mod test {
         let edits = changes.values().next().expect("there is a change");
         assert_eq!("", edits[0].new_text);
     }
+
+    #[rstest]
+    fn prepare_diagnostics_uses_configured_source_and_branch_names(uri: lsp_types::Uri) {
+        let merge_conflict = parse(TEXT1_WITH_CONFLICTS).expect("successful parse");
+        let config = Config {
+            diagnostic_source: "merge-conflict-assistant".to_owned(),
+            ..Default::default()
+        };
+        let notification = prepare_diagnostics(
+            &uri,
+            Some(1),
+            TEXT1_WITH_CONFLICTS,
+            &merge_conflict,
+            &GitDetails::default(),
+            &config,
+        );
+        let params: lsp_types::PublishDiagnosticsParams =
+            serde_json::from_value(notification.params).unwrap();
+
+        assert_eq!(2, params.diagnostics.len());
+        for diagnostic in params.diagnostics {
+            assert_eq!("merge conflict: OURS vs THEIRS", diagnostic.message);
+            assert_eq!(
+                Some("merge-conflict-assistant".to_owned()),
+                diagnostic.source
+            );
+        }
+    }
+
+    #[rstest]
+    fn prepare_diagnostics_sorts_and_dedupes_by_range(uri: lsp_types::Uri) {
+        let region = |head, branch, end| ConflictRegion {
+            head,
+            branch,
+            ancestor: None,
+            end,
+            last_char: 7,
+            ancestor_label: None,
+            yours: None,
+            depth: 0,
+            marker_size: 7,
+            head_path: None,
+            branch_path: None,
+            head_label: None,
+            branch_label: None,
+            bytes: MarkerBytes::default(),
+        };
+        let merge_conflict = MergeConflict {
+            head: None,
+            branch: None,
+            ancestor: None,
+            conflicts: vec![region(8, 10, 12), region(2, 4, 6), region(8, 10, 12)],
+            warnings: Vec::new(),
+        };
+        let notification = prepare_diagnostics(
+            &uri,
+            Some(1),
+            "",
+            &Some(merge_conflict),
+            &GitDetails::default(),
+            &Config::default(),
+        );
+        let params: lsp_types::PublishDiagnosticsParams =
+            serde_json::from_value(notification.params).unwrap();
+
+        let lines: Vec<_> = params
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.range.start.line)
+            .collect();
+        assert_eq!(vec![2, 8], lines);
+    }
+
+    #[rstest]
+    fn shutdown_waits_for_pending_document_updates(
+        #[with(1, TEXT1_WITH_CONFLICTS)] did_open: lsp_server::Notification,
+    ) {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(sender, Config::default());
+
+        handle_message(&mut state, did_open.into()).expect("didOpen handled");
+        let request = lsp_server::Request {
+            id: 1.into(),
+            method: <lsp_types::request::Shutdown as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::Value::Null,
+        };
+        let response = on_shutdown(&mut state, request)
+            .expect("successful response")
+            .expect("a response");
+
+        assert!(response.error.is_none());
+        assert_eq!(ServerStatus::ShutdownRequested, state.status);
+        assert!(state.pending_updates.lock().unwrap().is_empty());
+        let publish_method = <lsp_types::notification::PublishDiagnostics as lsp_types::notification::Notification>::METHOD;
+        let published = receiver.try_iter().any(|message| {
+            matches!(message, lsp_server::Message::Notification(notification)
+                if notification.method == publish_method)
+        });
+        assert!(published, "diagnostics published before shutdown returned");
+    }
+
+    #[rstest]
+    fn only_requests_after_the_first_shutdown_are_rejected(mut state: ServerState) {
+        let shutdown = |id: i32| lsp_server::Request {
+            id: id.into(),
+            method: <lsp_types::request::Shutdown as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::Value::Null,
+        };
+
+        let first = on_request(&mut state, shutdown(1))
+            .expect("successful response")
+            .expect("a response");
+        assert_eq!(Some(serde_json::Value::Null), first.result);
+        assert!(first.error.is_none());
+
+        let second = on_request(&mut state, shutdown(2))
+            .expect("successful response")
+            .expect("a response");
+        assert_eq!(
+            Some(lsp_server::ErrorCode::InvalidRequest as i32),
+            second.error.map(|error| error.code)
+        );
+    }
+
+    #[rstest]
+    #[case::after_shutdown(true, std::process::ExitCode::SUCCESS)]
+    #[case::without_shutdown(false, std::process::ExitCode::FAILURE)]
+    fn exit_notification_ends_main_loop_with_exit_code(
+        #[case] shutdown: bool,
+        #[case] expected: std::process::ExitCode,
+    ) {
+        let (server, client) = lsp_server::Connection::memory();
+        if shutdown {
+            let request = lsp_server::Request {
+                id: 1.into(),
+                method: <lsp_types::request::Shutdown as lsp_types::request::Request>::METHOD
+                    .to_owned(),
+                params: serde_json::Value::Null,
+            };
+            client.sender.send(request.into()).unwrap();
+        }
+        let exit = lsp_server::Notification::new(
+            <lsp_types::notification::Exit as lsp_types::notification::Notification>::METHOD
+                .to_owned(),
+            serde_json::Value::Null,
+        );
+        client.sender.send(exit.into()).unwrap();
+
+        let exit_code = main_loop(server, Config::default(), ClientSupport::default(), None)
+            .expect("clean loop");
+        assert_eq!(expected, exit_code);
+    }
+
+    #[rstest]
+    fn markdown_fenced_example_conflict_has_no_diagnostics(
+        #[with(
+            1,
+            concat!(
+                "# Resolving conflicts\n",
+                "```\n",
+                crate::conflict_text!("HEAD", "ours", "feature", "theirs"),
+                "```\n",
+            ),
+            "markdown"
+        )]
+        did_open: lsp_server::Notification,
+    ) {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(sender, Config::default());
+
+        handle_message(&mut state, did_open.into()).expect("didOpen handled");
+        state.wait_for_pending_updates();
+
+        let publish_method = <lsp_types::notification::PublishDiagnostics as lsp_types::notification::Notification>::METHOD;
+        let diagnostics: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                lsp_server::Message::Notification(notification)
+                    if notification.method == publish_method =>
+                {
+                    serde_json::from_value::<lsp_types::PublishDiagnosticsParams>(
+                        notification.params,
+                    )
+                    .ok()
+                }
+                _ => None,
+            })
+            .flat_map(|params| params.diagnostics)
+            .collect();
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+    }
+
+    fn list_conflicts_request(uri: &lsp_types::Uri) -> lsp_server::Request {
+        let params = lsp_types::ExecuteCommandParams {
+            command: LIST_CONFLICTS_COMMAND.to_owned(),
+            arguments: vec![serde_json::to_value(uri).unwrap()],
+            work_done_progress_params: Default::default(),
+        };
+        lsp_server::Request {
+            id: 1.into(),
+            method: <lsp_types::request::ExecuteCommand as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    #[rstest]
+    fn list_conflicts_command_returns_every_conflict(mut state: ServerState) {
+        let uri_value = uri();
+        let merge_conflict = parse(TEXT1_WITH_CONFLICTS)
+            .expect("successful parse")
+            .unwrap();
+        {
+            let mut documents = state.documents.lock().unwrap();
+            documents.insert(
+                uri_value.clone(),
+                Arc::new(Mutex::new(DocumentState::new_with_conflict(
+                    TEXT1_WITH_CONFLICTS.to_string(),
+                    0,
+                    merge_conflict,
+                ))),
+            );
+        }
+
+        let response = on_execute_command_request(&mut state, list_conflicts_request(&uri_value))
+            .expect("successful response")
+            .expect("a response");
+        let conflicts: Vec<serde_json::Value> =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+
+        assert_eq!(2, conflicts.len());
+        let ranges: Vec<lsp_types::Range> = conflicts
+            .iter()
+            .map(|conflict| serde_json::from_value(conflict["range"].clone()).unwrap())
+            .collect();
+        assert_eq!(
+            vec![Range!((2, 0), (6, 14)), Range!((8, 0), (12, 14))],
+            ranges
+        );
+        for conflict in conflicts {
+            assert_eq!("OURS", conflict["ours_name"]);
+            assert_eq!("THEIRS", conflict["theirs_name"]);
+            assert_eq!(false, conflict["has_ancestor"]);
+        }
+    }
+
+    #[rstest]
+    fn list_conflicts_command_returns_empty_array_for_clean_document(
+        #[with(1, TEXT1_RESOLVED)] mut populated_state: ServerState,
+    ) {
+        let response =
+            on_execute_command_request(&mut populated_state, list_conflicts_request(&uri()))
+                .expect("successful response")
+                .expect("a response");
+        assert_eq!(Some(serde_json::json!([])), response.result);
+    }
+
+    fn sent_requests(
+        receiver: &crossbeam_channel::Receiver<lsp_server::Message>,
+    ) -> Vec<lsp_server::Request> {
+        receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                lsp_server::Message::Request(request) => Some(request),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn resolve_request(arguments: Vec<serde_json::Value>) -> lsp_server::Request {
+        let params = lsp_types::ExecuteCommandParams {
+            command: RESOLVE_COMMAND.to_owned(),
+            arguments,
+            work_done_progress_params: Default::default(),
+        };
+        lsp_server::Request {
+            id: 1.into(),
+            method: <lsp_types::request::ExecuteCommand as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    fn state_with_conflicts(
+        text: &str,
+    ) -> (
+        ServerState,
+        crossbeam_channel::Receiver<lsp_server::Message>,
+    ) {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let state = ServerState::new(sender, Config::default());
+        let merge_conflict = parse(text).expect("successful parse").unwrap();
+        state.documents.lock().unwrap().insert(
+            uri(),
+            Arc::new(Mutex::new(DocumentState::new_with_conflict(
+                text.to_string(),
+                0,
+                merge_conflict,
+            ))),
+        );
+        (state, receiver)
+    }
+
+    #[rstest]
+    fn resolve_command_sends_apply_edit_request() {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let arguments = vec![
+            serde_json::to_value(uri()).unwrap(),
+            serde_json::json!(1),
+            serde_json::json!("theirs"),
+        ];
+
+        let response = on_execute_command_request(&mut state, resolve_request(arguments))
+            .expect("successful response")
+            .expect("a response");
+        assert!(response.error.is_none());
+
+        let requests = sent_requests(&receiver);
+        assert_eq!(1, requests.len());
+        assert_eq!(
+            <lsp_types::request::ApplyWorkspaceEdit as lsp_types::request::Request>::METHOD,
+            requests[0].method
+        );
+        let params: lsp_types::ApplyWorkspaceEditParams =
+            serde_json::from_value(requests[0].params.clone()).unwrap();
+        #[allow(clippy::mutable_key_type)]
+        let changes = params.edit.changes.expect("valid changes");
+        // Only the markers and our side are deleted, their line stays as it is.
+        assert_eq!(
+            Some(&vec![
+                lsp_types::TextEdit {
+                    range: Range!((8, 0), (11, 0)),
+                    new_text: String::new(),
+                },
+                lsp_types::TextEdit {
+                    range: Range!((12, 0), (13, 0)),
+                    new_text: String::new(),
+                },
+            ]),
+            changes.get(&uri())
+        );
+        assert!(
+            state
+                .pending_requests
+                .lock()
+                .unwrap()
+                .contains_key(&requests[0].id)
+        );
+    }
+
+    fn accept_request(command: &str, arguments: Vec<serde_json::Value>) -> lsp_server::Request {
+        let mut request = resolve_request(arguments);
+        request.params["command"] = serde_json::json!(command);
+        request
+    }
+
+    #[rstest]
+    #[case::ours(ACCEPT_OURS_COMMAND, "plain old\n")]
+    #[case::theirs(ACCEPT_THEIRS_COMMAND, "new and improved\n")]
+    #[case::both(ACCEPT_BOTH_COMMAND, "plain old\nnew and improved\n")]
+    fn accept_commands_send_apply_edit_request(#[case] command: &str, #[case] new_text: &str) {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let arguments = vec![serde_json::to_value(uri()).unwrap(), serde_json::json!(0)];
+
+        let response = on_execute_command_request(&mut state, accept_request(command, arguments))
+            .expect("successful response")
+            .expect("a response");
+        assert_eq!(Some(serde_json::Value::Null), response.result);
+
+        let request = sent_requests(&receiver).pop().expect("applyEdit sent");
+        let params: lsp_types::ApplyWorkspaceEditParams =
+            serde_json::from_value(request.params).unwrap();
+        #[allow(clippy::mutable_key_type)]
+        let changes = params.edit.changes.expect("valid changes");
+        let first_conflict =
+            crate::conflict_text!("OURS", "plain old", "THEIRS", "new and improved");
+        assert_eq!(
+            TEXT1_WITH_CONFLICTS.replacen(first_conflict, new_text, 1),
+            apply_edits(TEXT1_WITH_CONFLICTS, &changes[&uri()])
+        );
+    }
+
+    #[rstest]
+    #[case::missing_index(vec![serde_json::json!("file://foo.txt")])]
+    #[case::extra_resolution(vec![serde_json::json!("file://foo.txt"), serde_json::json!(0), serde_json::json!("ours")])]
+    #[case::unknown_index(vec![serde_json::json!("file://foo.txt"), serde_json::json!(5)])]
+    fn accept_command_rejects_invalid_arguments(#[case] arguments: Vec<serde_json::Value>) {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let response =
+            on_execute_command_request(&mut state, accept_request(ACCEPT_OURS_COMMAND, arguments))
+                .expect("successful response")
+                .expect("a response");
+        assert_eq!(
+            Some(lsp_server::ErrorCode::InvalidParams as i32),
+            response.error.map(|error| error.code)
+        );
+        assert!(sent_requests(&receiver).is_empty());
+    }
+
+    #[rstest]
+    fn applied_edit_response_republishes_diagnostics() {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let arguments = vec![
+            serde_json::to_value(uri()).unwrap(),
+            serde_json::json!(0),
+            serde_json::json!("ours"),
+        ];
+        on_execute_command_request(&mut state, resolve_request(arguments))
+            .expect("successful response");
+        let request = sent_requests(&receiver).pop().expect("applyEdit sent");
+
+        let response = lsp_server::Response::new_ok(
+            request.id,
+            lsp_types::ApplyWorkspaceEditResponse {
+                applied: true,
+                failure_reason: None,
+                failed_change: None,
+            },
+        );
+        handle_message(&mut state, response.into()).expect("response handled");
+
+        let params = receiver
+            .try_iter()
+            .find_map(|message| match message {
+                lsp_server::Message::Notification(notification)
+                    if notification.method
+                        == <lsp_types::notification::PublishDiagnostics as lsp_types::notification::Notification>::METHOD =>
+                {
+                    serde_json::from_value::<lsp_types::PublishDiagnosticsParams>(
+                        notification.params,
+                    )
+                    .ok()
+                }
+                _ => None,
+            })
+            .expect("diagnostics published");
+        let ranges: Vec<_> = params
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.range)
+            .collect();
+        assert_eq!(vec![Range!((4, 0), (8, 14))], ranges);
+        assert!(state.pending_requests.lock().unwrap().is_empty());
+        // The tracked document waits for the client's didChange.
+        let documents = state.documents.lock().unwrap();
+        let document_state = documents.get(&uri()).unwrap().lock().unwrap();
+        assert_eq!(TEXT1_WITH_CONFLICTS, document_state.content());
+    }
+
+    /// Run the resolve command for conflict 0 and return the `workspace/applyEdit` it sent.
+    fn send_resolution(
+        state: &mut ServerState,
+        receiver: &crossbeam_channel::Receiver<lsp_server::Message>,
+    ) -> lsp_server::Request {
+        let arguments = vec![
+            serde_json::to_value(uri()).unwrap(),
+            serde_json::json!(0),
+            serde_json::json!("ours"),
+        ];
+        on_execute_command_request(state, resolve_request(arguments)).expect("successful response");
+        sent_requests(receiver).pop().expect("applyEdit sent")
+    }
+
+    fn apply_edit_response(id: lsp_server::RequestId, applied: bool) -> lsp_server::Message {
+        lsp_server::Response::new_ok(
+            id,
+            lsp_types::ApplyWorkspaceEditResponse {
+                applied,
+                failure_reason: (!applied).then(|| "read only".to_owned()),
+                failed_change: None,
+            },
+        )
+        .into()
+    }
+
+    #[rstest]
+    fn rejected_edit_is_retried_when_the_user_asks() {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        state.client.show_message_request = true;
+        let apply_edit = send_resolution(&mut state, &receiver);
+        handle_message(
+            &mut state,
+            apply_edit_response(apply_edit.id.clone(), false),
+        )
+        .expect("response handled");
+
+        let show_message = sent_requests(&receiver)
+            .pop()
+            .expect("showMessageRequest sent");
+        assert_eq!(
+            <lsp_types::request::ShowMessageRequest as lsp_types::request::Request>::METHOD,
+            show_message.method
+        );
+        let params: lsp_types::ShowMessageRequestParams =
+            serde_json::from_value(show_message.params).unwrap();
+        assert_eq!(
+            format!("{}: edit was not applied: read only", uri().as_str()),
+            params.message
+        );
+
+        let answer = lsp_server::Response::new_ok(
+            show_message.id,
+            lsp_types::MessageActionItem {
+                title: RETRY_ACTION.to_owned(),
+                properties: Default::default(),
+            },
+        );
+        handle_message(&mut state, answer.into()).expect("response handled");
+
+        let retried = sent_requests(&receiver)
+            .pop()
+            .expect("applyEdit sent again");
+        assert_eq!(apply_edit.method, retried.method);
+        assert_eq!(apply_edit.params, retried.params);
+        assert_ne!(apply_edit.id, retried.id);
+    }
+
+    #[rstest]
+    #[case::dismissed(serde_json::Value::Null, 0)]
+    #[case::document_changed(serde_json::json!({ "title": RETRY_ACTION }), 1)]
+    fn rejected_edit_is_not_retried(#[case] answer: serde_json::Value, #[case] version: i32) {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        state.client.show_message_request = true;
+        let apply_edit = send_resolution(&mut state, &receiver);
+        handle_message(&mut state, apply_edit_response(apply_edit.id, false))
+            .expect("response handled");
+        let show_message = sent_requests(&receiver)
+            .pop()
+            .expect("showMessageRequest sent");
+        if version != 0 {
+            state
+                .document_did_change(
+                    lsp_types::VersionedTextDocumentIdentifier {
+                        uri: uri(),
+                        version,
+                    },
+                    vec![lsp_types::TextDocumentContentChangeEvent {
+                        range: Some(Range!((0, 0), (0, 0))),
+                        range_length: None,
+                        text: "new line\n".to_owned(),
+                    }],
+                )
+                .unwrap();
+        }
+
+        let answer = lsp_server::Response::new_ok(show_message.id, answer);
+        handle_message(&mut state, answer.into()).expect("response handled");
+
+        assert!(sent_requests(&receiver).is_empty());
+        assert!(state.pending_requests.lock().unwrap().is_empty());
+    }
+
+    #[rstest]
+    fn applied_edit_refreshes_pulled_diagnostics() {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        state.client.diagnostic_refresh = true;
+        state.features.lock().unwrap().pull_diagnostics = true;
+        let apply_edit = send_resolution(&mut state, &receiver);
+        handle_message(&mut state, apply_edit_response(apply_edit.id, true))
+            .expect("response handled");
+
+        let methods: Vec<_> = receiver
+            .try_iter()
+            .map(|message| match message {
+                lsp_server::Message::Request(request) => request.method,
+                lsp_server::Message::Notification(notification) => notification.method,
+                lsp_server::Message::Response(_) => panic!("unexpected response"),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                <lsp_types::request::WorkspaceDiagnosticRefresh as lsp_types::request::Request>::METHOD
+            ],
+            methods
+        );
+    }
+
+    #[rstest]
+    #[case(vec![])]
+    #[case(vec![serde_json::json!("file://foo.txt"), serde_json::json!(0)])]
+    #[case(vec![serde_json::json!("file://foo.txt"), serde_json::json!(0), serde_json::json!("mine")])]
+    #[case(vec![serde_json::json!("file://foo.txt"), serde_json::json!(5), serde_json::json!("ours")])]
+    #[case(vec![serde_json::json!("file://foo.txt"), serde_json::json!(0), serde_json::json!("ancestor")])]
+    fn resolve_command_rejects_invalid_arguments(#[case] arguments: Vec<serde_json::Value>) {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let response = on_execute_command_request(&mut state, resolve_request(arguments))
+            .expect("successful response")
+            .expect("a response");
+        assert_eq!(
+            Some(lsp_server::ErrorCode::InvalidParams as i32),
+            response.error.map(|error| error.code)
+        );
+        assert!(sent_requests(&receiver).is_empty());
+    }
+
+    #[rstest]
+    fn disabled_code_action_is_not_registered_and_returns_null() {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let config = Config::from_initialization_options(Some(serde_json::json!({
+            "features": { "codeAction": false }
+        })));
+        assert!(
+            server_capabilities(&config, &ClientSupport::default())
+                .code_action_provider
+                .is_none()
+        );
+        let mut state = ServerState::new(sender, config);
+        state.client.dynamic_registration = true;
+
+        state.sync_registrations().unwrap();
+        let registered_methods: Vec<String> = sent_requests(&receiver)
+            .into_iter()
+            .flat_map(|request| {
+                serde_json::from_value::<lsp_types::RegistrationParams>(request.params)
+                    .unwrap()
+                    .registrations
+            })
+            .map(|registration| registration.method)
+            .collect();
+        assert_eq!(vec!["textDocument/codeLens"], registered_methods);
+        assert!(
+            !state
+                .registered
+                .lock()
+                .unwrap()
+                .contains("textDocument/codeAction")
+        );
+
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: uri() },
+            range: Range!((2, 0), (2, 1)),
+            context: Default::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let request = lsp_server::Request {
+            id: 1.into(),
+            method: <lsp_types::request::CodeActionRequest as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        };
+        let response = on_code_action_request(&mut state, request)
+            .expect("successful response")
+            .expect("a response");
+        assert_eq!(Some(serde_json::Value::Null), response.result);
+    }
+
+    #[rstest]
+    fn code_action_registration_follows_configuration_changes() {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        assert!(
+            server_capabilities(
+                &Config::default(),
+                &ClientSupport {
+                    dynamic_registration: true,
+                    ..Default::default()
+                }
+            )
+            .code_action_provider
+            .is_none()
+        );
+        let mut state = ServerState::new(sender, Config::default());
+        state.client.dynamic_registration = true;
+
+        state.sync_registrations().unwrap();
+        let requests = sent_requests(&receiver);
+        assert_eq!(1, requests.len());
+        assert_eq!("client/registerCapability", requests[0].method);
+        let params: lsp_types::RegistrationParams =
+            serde_json::from_value(requests[0].params.clone()).unwrap();
+        assert_eq!("textDocument/codeAction", params.registrations[0].method);
+
+        let notification = lsp_server::Notification {
+            method: "workspace/didChangeConfiguration".to_owned(),
+            params: serde_json::json!({ "settings": { "features": { "codeAction": false } } }),
+        };
+        on_notification_message(&mut state, notification).unwrap();
+        let requests = sent_requests(&receiver);
+        assert_eq!(1, requests.len());
+        assert_eq!("client/unregisterCapability", requests[0].method);
+        assert!(!state.feature_enabled("textDocument/codeAction"));
+    }
+
+    fn published_diagnostics(
+        receiver: &crossbeam_channel::Receiver<lsp_server::Message>,
+    ) -> Vec<lsp_types::PublishDiagnosticsParams> {
+        receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                lsp_server::Message::Notification(notification)
+                    if notification.method
+                        == <lsp_types::notification::PublishDiagnostics as lsp_types::notification::Notification>::METHOD =>
+                {
+                    serde_json::from_value(notification.params).ok()
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn bursts_of_changes_publish_the_last_version(#[with(0)] did_open: lsp_server::Notification) {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(sender, Config::default());
+        handle_message(&mut state, did_open.into()).expect("didOpen handled");
+        for version in 1..=50 {
+            let text = if version % 2 == 0 {
+                TEXT1_WITH_CONFLICTS
+            } else {
+                TEXT2_RESOLVED
+            };
+            // Different every time, so none is skipped as unchanged.
+            let text = format!("{text}\n{version}\n");
+            let did_change = did_change_whole_document(version, &text);
+            handle_message(&mut state, did_change.into()).expect("didChange handled");
+        }
+        state.wait_for_pending_updates();
+
+        // Every worker finished, so the next change starts one again.
+        assert!(state.queue_update(uri(), 51));
+        let published = published_diagnostics(&receiver);
+        let last = published.last().expect("diagnostics published");
+        assert_eq!(Some(50), last.version);
+        assert_eq!(2, last.diagnostics.len());
+    }
+
+    #[rstest]
+    #[case::unchanged(TEXT1_WITH_CONFLICTS, false)]
+    #[case::changed(TEXT2_WITH_CONFLICTS, true)]
+    fn resent_text_is_only_parsed_when_changed(
+        #[with(1, TEXT1_WITH_CONFLICTS)] did_open: lsp_server::Notification,
+        #[case] text: &str,
+        #[case] parsed: bool,
+    ) {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(sender, Config::default());
+        handle_message(&mut state, did_open.into()).expect("didOpen handled");
+        state.wait_for_pending_updates();
+        assert_eq!(1, published_diagnostics(&receiver).len());
+
+        let did_change = did_change_whole_document(2, text);
+        handle_message(&mut state, did_change.into()).expect("didChange handled");
+        state.wait_for_pending_updates();
+
+        assert_eq!(parsed, !published_diagnostics(&receiver).is_empty());
+        let documents = state.documents.lock().unwrap();
+        let document_state = documents.get(&uri()).unwrap().lock().unwrap();
+        assert_eq!(Some(2), document_state.parsed_version);
+    }
+
+    #[rstest]
+    fn undone_changes_are_not_published() {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(sender, Config::default());
+        state
+            .add_document(lsp_types::TextDocumentItem {
+                uri: uri(),
+                language_id: String::new(),
+                version: 0,
+                text: TEXT1_WITH_CONFLICTS.to_owned(),
+            })
+            .unwrap();
+        state.on_document_update(&uri(), 0).unwrap();
+        let change = did_change_whole_document(1, TEXT2_WITH_CONFLICTS);
+        let changed = on_did_change_text_document(&mut state, change).unwrap();
+        assert_eq!(Some((uri(), 1)), changed);
+        let generation = state.schedule_update(&uri());
+
+        let undo = did_change_whole_document(2, TEXT1_WITH_CONFLICTS);
+        assert_eq!(None, on_did_change_text_document(&mut state, undo).unwrap());
+        update_document(uri(), 1, generation, &state);
+        assert!(published_diagnostics(&receiver).is_empty());
+    }
+
+    #[rstest]
+    fn superseded_updates_are_not_published() {
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let state = ServerState::new(sender, Config::default());
+        state
+            .add_document(lsp_types::TextDocumentItem {
+                uri: uri(),
+                language_id: String::new(),
+                version: 0,
+                text: TEXT1_WITH_CONFLICTS.to_owned(),
+            })
+            .unwrap();
+        let first = state.schedule_update(&uri());
+        let second = state.schedule_update(&uri());
+
+        update_document(uri(), 0, first, &state);
+        assert!(published_diagnostics(&receiver).is_empty());
+        update_document(uri(), 0, second, &state);
+        let published = published_diagnostics(&receiver);
+        assert_eq!(1, published.len());
+        assert_eq!(2, published[0].diagnostics.len());
+    }
+
+    #[rstest]
+    #[case::enabled(true, true)]
+    #[case::disabled(false, false)]
+    fn identical_sides_are_resolved_when_parsed(#[case] enabled: bool, #[case] sent: bool) {
+        let text = concat!(
+            crate::conflict_text!("OURS", "same", "THEIRS", "same"),
+            crate::conflict_text!("OURS", "mine", "THEIRS", "yours"),
+        );
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(
+            sender,
+            Config {
+                auto_resolve_identical: enabled,
+                ..Default::default()
+            },
+        );
+        state.client.apply_edit = true;
+        state
+            .add_document(lsp_types::TextDocumentItem {
+                uri: uri(),
+                language_id: String::new(),
+                version: 0,
+                text: text.to_owned(),
+            })
+            .unwrap();
+        update_document(uri(), 0, 0, &state);
+
+        let requests = sent_requests(&receiver);
+        assert_eq!(sent, !requests.is_empty());
+        if let Some(request) = requests.first() {
+            let params: lsp_types::ApplyWorkspaceEditParams =
+                serde_json::from_value(request.params.clone()).unwrap();
+            #[allow(clippy::mutable_key_type)]
+            let changes = params.edit.changes.expect("valid changes");
+            assert_eq!(
+                concat!(
+                    "same\n",
+                    crate::conflict_text!("OURS", "mine", "THEIRS", "yours")
+                ),
+                apply_edits(text, &changes[&uri()])
+            );
+        }
+    }
+
+    #[rstest]
+    fn conflicted_files_are_published_before_they_are_opened() {
+        let root = crate::test_helpers::conflicted_repository("unopened");
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(sender, Config::default());
+        state.workspace_root = Some(root.clone());
+        publish_conflicted_files(&state).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+
+        let published = published_diagnostics(&receiver);
+        assert_eq!(1, published.len());
+        assert!(published[0].uri.as_str().ends_with("/notes.txt"));
+        assert_eq!(None, published[0].version);
+        assert_eq!(1, published[0].diagnostics.len());
+    }
+
+    #[rstest]
+    #[case::resolved(false)]
+    #[case::opened_excluded(true)]
+    fn diagnostics_read_from_disk_are_cleared(#[case] opened: bool) {
+        let root = crate::test_helpers::conflicted_repository(&format!("cleared-{opened}"));
+        let path = root.join("notes.txt");
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(
+            sender,
+            Config {
+                exclude_languages: vec!["plaintext".to_owned()],
+                ..Default::default()
+            },
+        );
+        state.workspace_root = Some(root.clone());
+        publish_conflicted_files(&state).unwrap();
+        let uri = published_diagnostics(&receiver)[0].uri.clone();
+        if opened {
+            let notification = lsp_server::Notification::new(
+                "textDocument/didOpen".to_owned(),
+                lsp_types::DidOpenTextDocumentParams {
+                    text_document: lsp_types::TextDocumentItem {
+                        uri: uri.clone(),
+                        language_id: "plaintext".to_owned(),
+                        version: 1,
+                        text: std::fs::read_to_string(&path).unwrap(),
+                    },
+                },
+            );
+            on_did_open_text_document(&mut state, notification).unwrap();
+        } else {
+            std::fs::write(&path, "theirs\n").unwrap();
+            assert!(stage_resolved_file(&state, &uri).unwrap());
+        }
+        let _ = std::fs::remove_dir_all(&root);
+
+        let published = published_diagnostics(&receiver);
+        assert_eq!(1, published.len());
+        assert_eq!(uri, published[0].uri);
+        assert!(published[0].diagnostics.is_empty());
+        assert!(state.unopened.lock().unwrap().is_empty());
+    }
+
+    fn stage_contents_request(path: &std::path::Path) -> lsp_server::Request {
+        lsp_server::Request {
+            id: 1.into(),
+            method: STAGE_CONTENTS_METHOD.to_owned(),
+            params: serde_json::json!({
+                "textDocument": { "uri": config_file::path_to_uri(path).unwrap() }
+            }),
+        }
+    }
+
+    #[rstest]
+    #[case::enabled(true)]
+    #[case::disabled(false)]
+    fn stage_contents_request_returns_the_index_stages(#[case] enabled: bool) {
+        let root = crate::test_helpers::conflicted_repository(&format!("stages-{enabled}"));
+        let (sender, _receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(
+            sender,
+            Config {
+                git: crate::config::GitOptions {
+                    enabled,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let response = on_request(&mut state, stage_contents_request(&root.join("notes.txt")))
+            .unwrap()
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        if enabled {
+            assert_eq!(
+                Some(
+                    serde_json::json!({ "base": "base\n", "ours": "ours\n", "theirs": "theirs\n" })
+                ),
+                response.result
+            );
+        } else {
+            assert_eq!(
+                lsp_server::ErrorCode::InvalidRequest as i32,
+                response.error.unwrap().code
+            );
+        }
+    }
+
+    #[rstest]
+    fn hover_and_diagnostics_name_the_commit_behind_each_side() {
+        let root = crate::test_helpers::conflicted_repository("hover");
+        let path = root.join("notes.txt");
+        let uri = config_file::path_to_uri(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let (sender, _receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let state = ServerState::new(
+            sender,
+            Config {
+                git: crate::config::GitOptions {
+                    enabled: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let merge_conflict = parse(&text).expect("successful parse").unwrap();
+        state.documents.lock().unwrap().insert(
+            uri.clone(),
+            Arc::new(Mutex::new(DocumentState::new_with_conflict(
+                text,
+                0,
+                merge_conflict,
+            ))),
+        );
+        let hover = state.hover(&uri, lsp_types::Position::new(1, 0)).unwrap();
+        let diagnostics = state.document_diagnostics(&uri).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+
+        let lsp_types::HoverContents::Markup(contents) = hover.unwrap().contents else {
+            panic!("expected markup");
+        };
+        assert!(contents.value.contains("Write ours (Test, "));
+        assert!(contents.value.contains("Write theirs (Test, "));
+        let (_, diagnostics) = diagnostics.unwrap();
+        let related = diagnostics[0].related_information.as_ref().unwrap();
+        assert_eq!(2, related.len());
+        assert_eq!(1, related[0].location.range.start.line);
+        assert!(related[0].message.contains("Write ours"));
+        assert!(related[1].message.contains("Write theirs"));
+    }
+
+    /// A server with git integration enabled and `notes.txt` of the repository at `root` open.
+    fn git_state(root: &Path) -> (ServerState, lsp_types::Uri) {
+        let path = root.join("notes.txt");
+        let uri = config_file::path_to_uri(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let (sender, _receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let state = ServerState::new(
+            sender,
+            Config {
+                git: crate::config::GitOptions {
+                    enabled: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let merge_conflict = parse(&text).expect("successful parse").unwrap();
+        state.documents.lock().unwrap().insert(
+            uri.clone(),
+            Arc::new(Mutex::new(DocumentState::new_with_conflict(
+                text,
+                0,
+                merge_conflict,
+            ))),
+        );
+        (state, uri)
+    }
+
+    /// The code action titles for the first conflict in `notes.txt` of the repository at `root`,
+    /// with git integration enabled. Removes the repository.
+    fn git_action_titles(root: std::path::PathBuf) -> Vec<String> {
+        let (state, uri) = git_state(&root);
+        let actions = state
+            .code_action(lsp_types::CodeActionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri },
+                range: lsp_types::Range::new(
+                    lsp_types::Position::new(1, 0),
+                    lsp_types::Position::new(1, 0),
+                ),
+                context: Default::default(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        actions.into_iter().map(|action| action.title).collect()
+    }
+
+    #[rstest]
+    fn action_titles_name_the_commits_behind_the_markers() {
+        let titles = git_action_titles(crate::test_helpers::conflicted_repository("titles"));
+        assert!(titles[0].starts_with("Keep HEAD ("));
+        assert!(titles[0].ends_with(": Write ours)"));
+        assert!(titles[1].starts_with("Keep feature ("));
+        assert!(titles[1].ends_with(": Write theirs)"));
+    }
+
+    #[rstest]
+    fn action_titles_say_which_side_is_upstream_during_a_rebase() {
+        let titles = git_action_titles(crate::test_helpers::rebasing_repository("rebase-titles"));
+        assert_eq!("Keep upstream (main)", titles[0]);
+        assert!(titles[1].starts_with("Keep your commit ("));
+        assert_eq!("Keep your commit (1234567)".len(), titles[1].len());
+    }
+
+    #[rstest]
+    fn diagnostics_hovers_and_lenses_say_which_side_is_upstream_during_a_rebase() {
+        let root = crate::test_helpers::rebasing_repository("rebase-names");
+        let (state, uri) = git_state(&root);
+        let diagnostics = state.document_diagnostics(&uri).unwrap();
+        let hover = state.hover(&uri, lsp_types::Position::new(1, 0)).unwrap();
+        let lenses = state.code_lens(&uri).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+
+        let (_, diagnostics) = diagnostics.unwrap();
+        assert!(
+            diagnostics[0]
+                .message
+                .starts_with("merge conflict: upstream (main) vs your commit ("),
+            "{}",
+            diagnostics[0].message
+        );
+        let lsp_types::HoverContents::Markup(contents) = hover.unwrap().contents else {
+            panic!("expected markup");
+        };
+        assert!(contents.value.starts_with("**upstream (main)**"));
+        assert!(contents.value.contains("**your commit ("));
+        let titles: Vec<_> = lenses
+            .into_iter()
+            .map(|lens| lens.command.unwrap().title)
+            .collect();
+        assert_eq!("Accept upstream (main)", titles[0]);
+        assert!(titles[1].starts_with("Accept your commit ("));
+    }
+
+    #[rstest]
+    #[case::resolved("resolved", "resolved\n", true)]
+    #[case::conflicted(
+        "conflicted",
+        "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\n",
+        false
+    )]
+    #[case::unterminated("unterminated", "<<<<<<< HEAD\nours\n", false)]
+    #[case::unterminated_with_separator(
+        "unterminated-separator",
+        "<<<<<<< HEAD\nours\n=======\ntheirs\n",
+        false
+    )]
+    fn saving_a_resolved_file_stages_it(
+        #[case] name: &str,
+        #[case] text: &str,
+        #[case] staged: bool,
+    ) {
+        let root = crate::test_helpers::conflicted_repository(&format!("auto-stage-{name}"));
+        let path = root.join("notes.txt");
+        std::fs::write(&path, text).unwrap();
+        let uri = config_file::path_to_uri(&path).unwrap();
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(
+            sender,
+            Config {
+                git: crate::config::GitOptions {
+                    enabled: true,
+                    auto_stage: true,
+                },
+                ..Default::default()
+            },
+        );
+        let notification = lsp_server::Notification {
+            method: "textDocument/didSave".to_owned(),
+            params: serde_json::json!({ "textDocument": { "uri": uri } }),
+        };
+        on_notification_message(&mut state, notification).unwrap();
+        let conflicted = git::Repository::discover(&root)
+            .unwrap()
+            .conflicted_paths()
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert_eq!(staged, conflicted.is_empty());
+        let shown = receiver.try_iter().any(|message| {
+            matches!(message, lsp_server::Message::Notification(notification)
+                if notification.method == "window/showMessage")
+        });
+        assert_eq!(staged, shown);
+    }
+
+    #[rstest]
+    #[case::keep("Keep file", true)]
+    #[case::delete("Delete file", false)]
+    fn delete_modify_conflicts_are_resolved_as_a_whole(#[case] title: &str, #[case] kept: bool) {
+        let root = crate::test_helpers::delete_modify_repository(&format!("delete-modify-{kept}"));
+        let path = root.join("notes.txt");
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(
+            sender,
+            Config {
+                git: crate::config::GitOptions {
+                    enabled: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        state.client.delete_files = true;
+        state.workspace_root = Some(root.clone());
+        publish_conflicted_files(&state).unwrap();
+        let published = published_diagnostics(&receiver);
+        let uri = published[0].uri.clone();
+        let actions = state
+            .code_action(lsp_types::CodeActionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri: uri.clone() },
+                range: lsp_types::Range::default(),
+                context: Default::default(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .unwrap();
+        let action = actions
+            .into_iter()
+            .find(|action| action.title == title)
+            .expect("a file conflict action");
+        // Play the client: apply the edit, then run the command.
+        if action.edit.is_some() {
+            std::fs::remove_file(&path).unwrap();
+        }
+        let command = action.command.expect("a command");
+        let request = lsp_server::Request {
+            id: 1.into(),
+            method: "workspace/executeCommand".to_owned(),
+            params: serde_json::json!({
+                "command": command.command,
+                "arguments": command.arguments,
+            }),
+        };
+        let response = on_execute_command_request(&mut state, request)
+            .unwrap()
+            .unwrap();
+        let conflicted = git::Repository::discover(&root)
+            .unwrap()
+            .conflicted_paths()
+            .unwrap();
+        let exists = path.exists();
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert_eq!(
+            vec!["merge conflict: deleted in theirs, modified in ours"],
+            published[0]
+                .diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.message.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(serde_json::json!(true)), response.result);
+        assert!(conflicted.is_empty());
+        assert_eq!(kept, exists);
+        let cleared = published_diagnostics(&receiver);
+        assert_eq!(1, cleared.len());
+        assert!(cleared[0].diagnostics.is_empty());
+    }
+
+    #[rstest]
+    #[case::supported(true, vec!["Keep file", "Delete file"])]
+    #[case::unsupported(false, vec!["Keep file"])]
+    fn deleting_a_file_needs_client_support(
+        #[case] delete_files: bool,
+        #[case] expected: Vec<&str>,
+    ) {
+        let root = crate::test_helpers::delete_modify_repository(&format!(
+            "delete-support-{delete_files}"
+        ));
+        let (sender, receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(sender, Config::default());
+        state.client.delete_files = delete_files;
+        state.workspace_root = Some(root.clone());
+        publish_conflicted_files(&state).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        let uri = published_diagnostics(&receiver)[0].uri.clone();
+        let actions = state
+            .code_action(lsp_types::CodeActionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri },
+                range: lsp_types::Range::default(),
+                context: Default::default(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .unwrap();
+        assert_eq!(
+            expected,
+            actions
+                .iter()
+                .map(|action| action.title.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[rstest]
+    fn stage_file_command_needs_git() {
+        let (mut state, _receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let request = lsp_server::Request {
+            id: 1.into(),
+            method: "workspace/executeCommand".to_owned(),
+            params: serde_json::json!({
+                "command": STAGE_FILE_COMMAND,
+                "arguments": [uri()],
+            }),
+        };
+        let response = on_execute_command_request(&mut state, request)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            lsp_server::ErrorCode::InvalidRequest as i32,
+            response.error.unwrap().code
+        );
+    }
+
+    #[rstest]
+    fn mark_resolved_command_needs_hg() {
+        let (mut state, _receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let request = lsp_server::Request {
+            id: 1.into(),
+            method: "workspace/executeCommand".to_owned(),
+            params: serde_json::json!({
+                "command": MARK_RESOLVED_COMMAND,
+                "arguments": [uri()],
+            }),
+        };
+        let response = on_execute_command_request(&mut state, request)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            lsp_server::ErrorCode::InvalidRequest as i32,
+            response.error.unwrap().code
+        );
+    }
+
+    #[rstest]
+    fn configuration_change_republishes_with_new_settings() {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let notification = lsp_server::Notification {
+            method: "workspace/didChangeConfiguration".to_owned(),
+            params: serde_json::json!({ "settings": { "diagnosticSeverity": "warning" } }),
+        };
+        on_notification_message(&mut state, notification).unwrap();
+
+        let published = published_diagnostics(&receiver);
+        assert_eq!(1, published.len());
+        assert!(!published[0].diagnostics.is_empty());
+        assert!(
+            published[0]
+                .diagnostics
+                .iter()
+                .all(|diagnostic| diagnostic.severity
+                    == Some(lsp_types::DiagnosticSeverity::WARNING))
+        );
+        assert_eq!(
+            crate::config::Severity::Warning,
+            state.config().diagnostic_severity
+        );
+    }
+
+    #[rstest]
+    fn configuration_change_clears_newly_excluded_documents() {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let vendored: lsp_types::Uri = "file:///vendor/lib.c".parse().unwrap();
+        state.documents.lock().unwrap().insert(
+            vendored.clone(),
+            Arc::new(Mutex::new(DocumentState::new(
+                String::new(),
+                TEXT1_WITH_CONFLICTS.to_owned(),
+                0,
+            ))),
+        );
+        let notification = lsp_server::Notification {
+            method: "workspace/didChangeConfiguration".to_owned(),
+            params: serde_json::json!({ "settings": { "exclude": ["/vendor/**"] } }),
+        };
+        on_notification_message(&mut state, notification).unwrap();
+
+        let published = published_diagnostics(&receiver);
+        assert_eq!(2, published.len());
+        let cleared = published
+            .iter()
+            .find(|params| params.uri == vendored)
+            .expect("vendored diagnostics published");
+        assert!(cleared.diagnostics.is_empty());
+        let documents = state.documents.lock().unwrap();
+        assert!(!documents.contains_key(&vendored));
+        assert!(documents.contains_key(&uri()));
+    }
+
+    #[rstest]
+    fn configuration_change_without_settings_pulls_them() {
+        let (mut state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        state.client.configuration = true;
+        let notification = lsp_server::Notification {
+            method: "workspace/didChangeConfiguration".to_owned(),
+            params: serde_json::json!({ "settings": null }),
+        };
+        on_notification_message(&mut state, notification).unwrap();
+
+        let requests = sent_requests(&receiver);
+        assert_eq!(1, requests.len());
+        assert_eq!("workspace/configuration", requests[0].method);
+
+        let response = lsp_server::Response::new_ok(
+            requests[0].id.clone(),
+            serde_json::json!([{ "diagnosticSource": "pulled" }]),
+        );
+        handle_message(&mut state, response.into()).expect("response handled");
+        assert_eq!("pulled", state.config().diagnostic_source);
+        let published = published_diagnostics(&receiver);
+        assert_eq!(1, published.len());
+        assert_eq!(
+            Some("pulled".to_owned()),
+            published[0].diagnostics[0].source
+        );
+    }
+
+    #[rstest]
+    fn large_documents_parsed_on_save_skip_change_updates() {
+        let (sender, _receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let mut state = ServerState::new(
+            sender,
+            Config {
+                max_file_size: 16,
+                large_files: crate::config::LargeFiles::OnSave,
+                ..Default::default()
+            },
+        );
+        let opened = state
+            .add_document(lsp_types::TextDocumentItem {
+                uri: uri(),
+                language_id: String::new(),
+                version: 0,
+                text: TEXT1_WITH_CONFLICTS.to_owned(),
+            })
+            .unwrap();
+        assert_eq!(Some((uri(), 0)), opened, "parsed when opened");
+
+        let change = lsp_server::Notification {
+            method: "textDocument/didChange".to_owned(),
+            params: serde_json::json!({
+                "textDocument": { "uri": uri(), "version": 1 },
+                "contentChanges": [{
+                    "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } },
+                    "text": "new line\n"
+                }]
+            }),
+        };
+        assert_eq!(None, on_notification_message(&mut state, change).unwrap());
+
+        let save = lsp_server::Notification {
+            method: "textDocument/didSave".to_owned(),
+            params: serde_json::json!({ "textDocument": { "uri": uri() } }),
+        };
+        assert_eq!(
+            Some((uri(), 1)),
+            on_notification_message(&mut state, save).unwrap()
+        );
+    }
+
+    #[rstest]
+    fn small_documents_ignore_saves() {
+        let (mut state, _receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let save = lsp_server::Notification {
+            method: "textDocument/didSave".to_owned(),
+            params: serde_json::json!({ "textDocument": { "uri": uri() } }),
+        };
+        assert_eq!(None, on_notification_message(&mut state, save).unwrap());
+    }
+
+    #[rstest]
+    fn invalid_configuration_change_keeps_current_settings() {
+        let (mut state, _receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let notification = lsp_server::Notification {
+            method: "workspace/didChangeConfiguration".to_owned(),
+            params: serde_json::json!({ "settings": { "diagnosticSeverity": "loud" } }),
+        };
+        on_notification_message(&mut state, notification).unwrap();
+        assert_eq!(Config::default(), *state.config());
+    }
+
+    #[rstest]
+    fn code_lens_request_offers_resolutions_above_each_conflict() {
+        let (mut state, _receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let params = lsp_types::CodeLensParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: uri() },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let request = lsp_server::Request {
+            id: 1.into(),
+            method: <lsp_types::request::CodeLensRequest as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        };
+
+        let response = on_code_lens_request(&mut state, request)
+            .expect("successful response")
+            .expect("a response");
+        let lenses: Vec<lsp_types::CodeLens> =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+
+        let summary: Vec<_> = lenses
+            .iter()
+            .map(|lens| {
+                let command = lens.command.as_ref().expect("lens has a command");
+                assert_eq!(RESOLVE_COMMAND, command.command);
+                (
+                    lens.range.start.line,
+                    command.title.as_str(),
+                    command.arguments.clone().unwrap(),
+                )
+            })
+            .collect();
+        let arguments = |index: usize, resolution: &str| {
+            vec![
+                serde_json::json!("file://foo.txt"),
+                serde_json::json!(index),
+                serde_json::json!(resolution),
+            ]
+        };
+        assert_eq!(
+            vec![
+                (2, "Accept Ours", arguments(0, "ours")),
+                (2, "Accept Theirs", arguments(0, "theirs")),
+                (2, "Accept Both", arguments(0, "both")),
+                (8, "Accept Ours", arguments(1, "ours")),
+                (8, "Accept Theirs", arguments(1, "theirs")),
+                (8, "Accept Both", arguments(1, "both")),
+            ],
+            summary
+        );
+    }
+
+    #[rstest]
+    #[case(false, serde_json::Value::Null)]
+    #[case(true, serde_json::json!({ "data": [2, 0, 12, 0, 0, 1, 0, 9, 1, 0, 1, 0, 7, 0, 0] }))]
+    fn semantic_tokens_follow_feature_toggle(
+        #[case] enabled: bool,
+        #[case] expected: serde_json::Value,
+    ) {
+        let (mut state, _receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        state.features.lock().unwrap().semantic_tokens = enabled;
+        let params = lsp_types::SemanticTokensRangeParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: uri() },
+            range: Range!((2, 0), (4, 0)),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let request = lsp_server::Request {
+            id: 1.into(),
+            method: <lsp_types::request::SemanticTokensRangeRequest as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        };
+
+        let response = on_semantic_tokens_range_request(&mut state, request)
+            .expect("successful response")
+            .expect("a response");
+        assert_eq!(Some(expected), response.result);
+    }
+
+    fn document_highlight_request(position: lsp_types::Position) -> lsp_server::Request {
+        let params = lsp_types::DocumentHighlightParams {
+            text_document_position_params: lsp_types::TextDocumentPositionParams {
+                text_document: lsp_types::TextDocumentIdentifier { uri: uri() },
+                position,
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        lsp_server::Request {
+            id: 1.into(),
+            method:
+                <lsp_types::request::DocumentHighlightRequest as lsp_types::request::Request>::METHOD
+                    .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    #[rstest]
+    #[case::opening_marker(lsp_types::Position::new(1, 0))]
+    #[case::ancestor_marker(lsp_types::Position::new(4, 3))]
+    #[case::closing_marker(lsp_types::Position::new(9, 15))]
+    fn document_highlight_on_marker_covers_every_marker_of_the_conflict(
+        #[case] position: lsp_types::Position,
+    ) {
+        let (mut state, _receiver) = state_with_conflicts(DIFF3_TEXT);
+        let response =
+            on_document_highlight_request(&mut state, document_highlight_request(position))
+                .expect("successful response")
+                .expect("a response");
+        let highlights: Vec<lsp_types::DocumentHighlight> =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+        let ranges: Vec<_> = highlights
+            .into_iter()
+            .map(|highlight| highlight.range)
+            .collect();
+        assert_eq!(
+            vec![
+                Range!((1, 0), (1, 12)),
+                Range!((4, 0), (4, 12)),
+                Range!((6, 0), (6, 7)),
+                Range!((9, 0), (9, 15)),
+            ],
+            ranges
+        );
+    }
+
+    #[rstest]
+    #[case::before_conflict(lsp_types::Position::new(0, 0))]
+    #[case::inside_side(lsp_types::Position::new(2, 0))]
+    fn document_highlight_off_marker_is_empty(#[case] position: lsp_types::Position) {
+        let (mut state, _receiver) = state_with_conflicts(DIFF3_TEXT);
+        let response =
+            on_document_highlight_request(&mut state, document_highlight_request(position))
+                .expect("successful response")
+                .expect("a response");
+        assert_eq!(Some(serde_json::json!([])), response.result);
+    }
+
+    fn selection_range_request(positions: Vec<lsp_types::Position>) -> lsp_server::Request {
+        let params = lsp_types::SelectionRangeParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: uri() },
+            positions,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        lsp_server::Request {
+            id: 1.into(),
+            method:
+                <lsp_types::request::SelectionRangeRequest as lsp_types::request::Request>::METHOD
+                    .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    #[rstest]
+    fn selection_range_expands_from_side_to_conflict() {
+        let (mut state, _receiver) = state_with_conflicts(DIFF3_TEXT);
+        let response = on_selection_range_request(
+            &mut state,
+            selection_range_request(vec![
+                lsp_types::Position::new(2, 2),
+                lsp_types::Position::new(5, 0),
+                lsp_types::Position::new(4, 0),
+                lsp_types::Position::new(8, 1),
+            ]),
+        )
+        .expect("successful response")
+        .expect("a response");
+        let ranges: Vec<lsp_types::SelectionRange> =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+
+        let conflict = lsp_types::SelectionRange {
+            range: Range!((1, 0), (9, 15)),
+            parent: None,
+        };
+        let side = |range| lsp_types::SelectionRange {
+            range,
+            parent: Some(Box::new(conflict.clone())),
+        };
+        assert_eq!(
+            vec![
+                side(Range!((2, 0), (4, 0))),
+                side(Range!((5, 0), (6, 0))),
+                conflict.clone(),
+                side(Range!((7, 0), (9, 0))),
+            ],
+            ranges
+        );
+    }
+
+    #[rstest]
+    fn selection_range_outside_conflicts_is_empty() {
+        let (mut state, _receiver) = state_with_conflicts(DIFF3_TEXT);
+        let response = on_selection_range_request(
+            &mut state,
+            selection_range_request(vec![lsp_types::Position::new(0, 3)]),
+        )
+        .expect("successful response")
+        .expect("a response");
+        let ranges: Vec<lsp_types::SelectionRange> =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+        assert_eq!(
+            vec![lsp_types::SelectionRange {
+                range: Range!((0, 3), (0, 3)),
+                parent: None,
+            }],
+            ranges
+        );
+    }
+
+    fn workspace_diagnostic_request(partial_result_token: Option<&str>) -> lsp_server::Request {
+        let params = lsp_types::WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: Vec::new(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: lsp_types::PartialResultParams {
+                partial_result_token: partial_result_token
+                    .map(|token| lsp_types::NumberOrString::String(token.to_owned())),
+            },
+        };
+        lsp_server::Request {
+            id: 1.into(),
+            method:
+                <lsp_types::request::WorkspaceDiagnosticRequest as lsp_types::request::Request>::METHOD
+                    .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    /// Two tracked documents: `uri()` with two conflicts and a clean `file://bar.txt`.
+    fn state_with_two_documents() -> (
+        ServerState,
+        crossbeam_channel::Receiver<lsp_server::Message>,
+    ) {
+        let (state, receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        state.features.lock().unwrap().pull_diagnostics = true;
+        state.documents.lock().unwrap().insert(
+            "file://bar.txt".parse().unwrap(),
+            Arc::new(Mutex::new(DocumentState::new(
+                String::new(),
+                TEXT1_RESOLVED.to_string(),
+                3,
+            ))),
+        );
+        (state, receiver)
+    }
+
+    fn report_summary(
+        reports: &[lsp_types::WorkspaceDocumentDiagnosticReport],
+    ) -> Vec<(String, Option<i64>, usize)> {
+        reports
+            .iter()
+            .map(|report| match report {
+                lsp_types::WorkspaceDocumentDiagnosticReport::Full(full) => (
+                    full.uri.as_str().to_owned(),
+                    full.version,
+                    full.full_document_diagnostic_report.items.len(),
+                ),
+                unchanged => panic!("unexpected report: {unchanged:?}"),
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn workspace_diagnostic_reports_every_document() {
+        let (mut state, receiver) = state_with_two_documents();
+        assert!(!state.push_diagnostics());
+
+        let response =
+            on_workspace_diagnostic_request(&mut state, workspace_diagnostic_request(None))
+                .expect("successful response")
+                .expect("a response");
+        let report: lsp_types::WorkspaceDiagnosticReport =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+
+        assert_eq!(
+            vec![
+                ("file://bar.txt".to_owned(), Some(3), 0),
+                ("file://foo.txt".to_owned(), Some(0), 2),
+            ],
+            report_summary(&report.items)
+        );
+        assert!(receiver.try_iter().next().is_none());
+    }
+
+    #[rstest]
+    fn workspace_diagnostic_streams_partial_results() {
+        let (mut state, receiver) = state_with_two_documents();
+
+        let response = on_workspace_diagnostic_request(
+            &mut state,
+            workspace_diagnostic_request(Some("diagnostics")),
+        )
+        .expect("successful response")
+        .expect("a response");
+        let report: lsp_types::WorkspaceDiagnosticReport =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+        assert!(report.items.is_empty());
+
+        let streamed: Vec<_> = receiver
+            .try_iter()
+            .filter_map(|message| match message {
+                lsp_server::Message::Notification(notification)
+                    if notification.method == "$/progress" =>
+                {
+                    assert_eq!("diagnostics", notification.params["token"]);
+                    serde_json::from_value::<lsp_types::WorkspaceDiagnosticReportPartialResult>(
+                        notification.params["value"].clone(),
+                    )
+                    .ok()
+                }
+                _ => None,
+            })
+            .flat_map(|partial| partial.items)
+            .collect();
+        assert_eq!(
+            vec![
+                ("file://bar.txt".to_owned(), Some(3), 0),
+                ("file://foo.txt".to_owned(), Some(0), 2),
+            ],
+            report_summary(&streamed)
+        );
+    }
+
+    fn code_action_request(range: lsp_types::Range) -> lsp_server::Request {
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: uri() },
+            range,
+            context: Default::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        lsp_server::Request {
+            id: 1.into(),
+            method: <lsp_types::request::CodeActionRequest as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    fn code_action_resolve_request(action: &lsp_types::CodeAction) -> lsp_server::Request {
+        lsp_server::Request {
+            id: 2.into(),
+            method:
+                <lsp_types::request::CodeActionResolveRequest as lsp_types::request::Request>::METHOD
+                    .to_owned(),
+            params: serde_json::to_value(action).unwrap(),
+        }
+    }
+
+    fn lazy_code_actions(
+        state: &mut ServerState,
+        range: lsp_types::Range,
+    ) -> Vec<lsp_types::CodeAction> {
+        state.client.resolve_code_action_edits = true;
+        let response = on_code_action_request(state, code_action_request(range))
+            .expect("successful response")
+            .expect("a response");
+        serde_json::from_value(response.result.unwrap()).unwrap()
+    }
+
+    #[rstest]
+    fn code_action_edits_are_computed_on_resolve() {
+        let (mut state, _receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let actions = lazy_code_actions(&mut state, Range!((2, 0), (2, 1)));
+        assert!(actions.iter().all(|action| action.edit.is_none()));
+        assert_eq!("Keep THEIRS", actions[1].title);
+
+        let response =
+            on_code_action_resolve_request(&mut state, code_action_resolve_request(&actions[1]))
+                .expect("successful response")
+                .expect("a response");
+        let resolved: lsp_types::CodeAction =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+
+        assert_eq!("Keep THEIRS", resolved.title);
+        #[allow(clippy::mutable_key_type)]
+        let changes = resolved
+            .edit
+            .expect("resolved edit")
+            .changes
+            .expect("valid changes");
+        assert_eq!(
+            TEXT1_WITH_CONFLICTS.replacen(
+                crate::conflict_text!("OURS", "plain old", "THEIRS", "new and improved"),
+                "new and improved\n",
+                1
+            ),
+            apply_edits(TEXT1_WITH_CONFLICTS, &changes[&uri()])
+        );
+    }
+
+    #[rstest]
+    fn combined_code_action_edits_are_computed_on_resolve() {
+        let text = concat!(
+            crate::conflict_text!("OURS", "one", "THEIRS", "uno"),
+            crate::conflict_text!("OURS", "two", "THEIRS", "dos"),
+        );
+        let (mut state, _receiver) = state_with_conflicts(text);
+        let actions = lazy_code_actions(&mut state, Range!((0, 0), (9, 0)));
+        assert_eq!("Keep THEIRS in all 2 selected conflicts", actions[1].title);
+
+        let response =
+            on_code_action_resolve_request(&mut state, code_action_resolve_request(&actions[1]))
+                .expect("successful response")
+                .expect("a response");
+        let resolved: lsp_types::CodeAction =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+        #[allow(clippy::mutable_key_type)]
+        let changes = resolved
+            .edit
+            .expect("resolved edit")
+            .changes
+            .expect("valid changes");
+        assert_eq!("uno\ndos\n", apply_edits(text, &changes[&uri()]));
+    }
+
+    #[rstest]
+    fn resolving_a_code_action_after_an_edit_is_rejected() {
+        let (mut state, _receiver) = state_with_conflicts(TEXT1_WITH_CONFLICTS);
+        let actions = lazy_code_actions(&mut state, Range!((2, 0), (2, 1)));
+        state
+            .document_did_change(
+                lsp_types::VersionedTextDocumentIdentifier {
+                    uri: uri(),
+                    version: 1,
+                },
+                vec![lsp_types::TextDocumentContentChangeEvent {
+                    range: Some(Range!((0, 0), (0, 0))),
+                    range_length: None,
+                    text: "new line\n".to_owned(),
+                }],
+            )
+            .unwrap();
+
+        let response =
+            on_code_action_resolve_request(&mut state, code_action_resolve_request(&actions[0]))
+                .expect("successful response")
+                .expect("a response");
+        assert_eq!(
+            Some(lsp_server::ErrorCode::ContentModified as i32),
+            response.error.map(|error| error.code)
+        );
+    }
+
+    const DIFF3_TEXT: &str = concat!(
+        "intro\n",
+        crate::diff3_conflict_text!(
+            "HEAD",
+            "ours 0\nours 1",
+            "base",
+            "base 0",
+            "feature",
+            "theirs 0\ntheirs 1"
+        ),
+        "outro\n",
+    );
+
+    fn resolve_lines_request(arguments: serde_json::Value) -> lsp_server::Request {
+        let params = lsp_types::ExecuteCommandParams {
+            command: RESOLVE_LINES_COMMAND.to_owned(),
+            arguments: serde_json::from_value(arguments).unwrap(),
+            work_done_progress_params: Default::default(),
+        };
+        lsp_server::Request {
+            id: 1.into(),
+            method: <lsp_types::request::ExecuteCommand as lsp_types::request::Request>::METHOD
+                .to_owned(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    #[rstest]
+    fn resolve_lines_command_assembles_interleaved_lines() {
+        let (mut state, _receiver) = state_with_conflicts(DIFF3_TEXT);
+        let request = resolve_lines_request(serde_json::json!([
+            "file://foo.txt",
+            0,
+            [["theirs", 0], ["ours", 1], ["ancestor", 0], ["ours", 0]]
+        ]));
+
+        let response = on_execute_command_request(&mut state, request)
+            .expect("successful response")
+            .expect("a response");
+        let edit: lsp_types::WorkspaceEdit =
+            serde_json::from_value(response.result.unwrap()).unwrap();
+
+        #[allow(clippy::mutable_key_type)]
+        let changes = edit.changes.expect("valid changes");
+        assert_eq!(
+            Some(&vec![lsp_types::TextEdit {
+                range: Range!((1, 0), (10, 0)),
+                new_text: "theirs 0\nours 1\nbase 0\nours 0\n".to_owned(),
+            }]),
+            changes.get(&uri())
+        );
+    }
+
+    #[rstest]
+    #[case(serde_json::json!(["file://foo.txt", 0, [["ours", 2]]]))]
+    #[case(serde_json::json!(["file://foo.txt", 0, [["ancestor", 1]]]))]
+    #[case(serde_json::json!(["file://foo.txt", 1, [["ours", 0]]]))]
+    #[case(serde_json::json!(["file://foo.txt", 0, [["mine", 0]]]))]
+    #[case(serde_json::json!(["file://foo.txt", 0]))]
+    fn resolve_lines_command_rejects_invalid_selections(#[case] arguments: serde_json::Value) {
+        let (mut state, _receiver) = state_with_conflicts(DIFF3_TEXT);
+        let response = on_execute_command_request(&mut state, resolve_lines_request(arguments))
+            .expect("successful response")
+            .expect("a response");
+        assert_eq!(
+            Some(lsp_server::ErrorCode::InvalidParams as i32),
+            response.error.map(|error| error.code)
+        );
+    }
 }
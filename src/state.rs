@@ -1,14 +1,32 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicI32, Ordering},
+    },
+    thread,
+    time::Instant,
 };
 
 use crossbeam_channel::Sender;
 use lsp_textdocument::FullTextDocument;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    parser::{ConflictRegion, MergeConflict, parse, range_for_diagnostic_conflict},
-    server::LSPResult,
+    config::{Config, Features, LargeFiles},
+    config_file,
+    git::{self, CommitInfo, FileConflict},
+    merge,
+    parser::{
+        ConflictRegion, ConflictStyle, DEFAULT_MARKER_SIZE, MergeConflict, ParseError,
+        ParseOptions, PositionEncoding, SideLabels, SideNames, Superset, Syntax, line_ending,
+        lsp_lines, may_be_marker, may_have_conflicts, parse_with_options, range_for_conflict_edit,
+        range_for_diagnostic_conflict, text_between,
+    },
+    resolution::{self, Choice, Resolution, Side},
+    server::{LSPResult, RESOLVE_COMMAND, STAGE_FILE_COMMAND, TAKE_VERSION_COMMAND},
 };
 
 /// A file open in the editor. Tracks the document and any merge conflicts it might have.
@@ -16,21 +34,101 @@ use crate::{
 pub struct DocumentState {
     pub document: FullTextDocument,
     pub merge_conflict: Option<MergeConflict>,
+    /// Document version `merge_conflict` was parsed from. `None` until the first parse.
+    pub parsed_version: Option<i32>,
+    /// Hash of the content `merge_conflict` was parsed from, see [`content_hash`]. `None` until
+    /// the first parse and after edits moved the conflicts.
+    pub parsed_hash: Option<u64>,
+    /// Units of the `character` offsets exchanged with the client.
+    pub encoding: PositionEncoding,
+    /// Which conflict markers are recognised.
+    pub style: ConflictStyle,
+    /// Content longer than this many bytes is not parsed, or only on save. `0` means no limit.
+    pub max_size: usize,
+    /// Whether content over `max_size` is skipped or parsed on save.
+    pub large_files: LargeFiles,
+    /// Length of the conflict markers, from git's `conflict-marker-size` attribute.
+    pub marker_size: usize,
+    /// Whether edits moved `merge_conflict` since `process_update` last returned it, so its
+    /// diagnostics need publishing again although nothing was parsed.
+    pub moved: bool,
+    /// Number of updates scheduled for the document. A worker whose update is no longer the
+    /// latest leaves parsing and publishing to the newer one.
+    pub generation: u64,
+}
+
+/// The lines a change replaces, from before it was applied, when they lie outside every conflict
+/// and none of them could be a marker line.
+#[derive(Debug)]
+struct PlainEdit {
+    first_line: u32,
+    last_line: u32,
+    /// Bytes of the replaced lines, line endings included.
+    bytes: std::ops::Range<usize>,
+    lines: usize,
+    /// Length of the whole text.
+    len: usize,
 }
 
 impl DocumentState {
-    pub fn new(content: String, version: i32) -> Self {
+    pub fn new(language_id: String, content: String, version: i32) -> Self {
         Self {
-            document: FullTextDocument::new(String::new(), version, content),
+            document: FullTextDocument::new(language_id, version, content),
             merge_conflict: None,
+            parsed_version: None,
+            parsed_hash: None,
+            encoding: PositionEncoding::default(),
+            style: ConflictStyle::default(),
+            max_size: 0,
+            large_files: LargeFiles::default(),
+            marker_size: DEFAULT_MARKER_SIZE,
+            moved: false,
+            generation: 0,
         }
     }
 
+    pub fn with_encoding(mut self, encoding: PositionEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Take the parsing options from the user's configuration.
+    pub fn with_config(mut self, config: &Config) -> Self {
+        self.style = config.conflict_style;
+        self.max_size = config.max_file_size;
+        self.large_files = config.large_files;
+        self
+    }
+
+    pub fn with_marker_size(mut self, marker_size: usize) -> Self {
+        self.marker_size = marker_size;
+        self
+    }
+
+    fn over_size_limit(&self) -> bool {
+        self.max_size != 0 && self.document.get_content(None).len() > self.max_size
+    }
+
+    /// True when changes should not trigger a parse because the content is over the size
+    /// limit and only parsed on save. The last parsed conflicts stand in until then.
+    pub fn defers_parse(&self) -> bool {
+        self.large_files == LargeFiles::OnSave && self.over_size_limit()
+    }
+
     #[cfg(test)]
     pub fn new_with_conflict(content: String, version: i32, conflict: MergeConflict) -> Self {
         Self {
             document: FullTextDocument::new(String::new(), version, content),
             merge_conflict: Some(conflict),
+            parsed_version: Some(version),
+            parsed_hash: None,
+            encoding: PositionEncoding::default(),
+            style: ConflictStyle::default(),
+            max_size: 0,
+            large_files: LargeFiles::default(),
+            marker_size: DEFAULT_MARKER_SIZE,
+            moved: false,
+            generation: 0,
         }
     }
 
@@ -38,12 +136,245 @@ impl DocumentState {
         self.document.version()
     }
 
+    /// Whether the changes since the last parse brought the content back to what was parsed,
+    /// as when a client sends the whole unchanged text again. If so the conflicts are marked
+    /// current for this version.
+    pub fn revalidate(&mut self) -> bool {
+        let unchanged = self.is_stale()
+            && self.parsed_hash == Some(content_hash(self.document.get_content(None)));
+        if unchanged {
+            self.parsed_version = Some(self.version());
+        }
+        unchanged
+    }
+
+    /// Whether the update worker has anything to parse or publish for the document.
+    pub fn needs_update(&self) -> bool {
+        self.is_stale() || self.moved
+    }
+
+    /// True when the document changed after `merge_conflict` was last computed.
+    pub fn is_stale(&self) -> bool {
+        self.parsed_version != Some(self.version())
+    }
+
     #[cfg(test)]
     pub fn content(&self) -> &str {
         self.document.get_content(None)
     }
 
+    /// The `languageId` the client opened the document with. Empty if it was never opened.
+    pub fn language_id(&self) -> &str {
+        self.document.language_id()
+    }
+
+    /// Parse the current content using the rules for the document's language.
+    ///
+    /// Content over the size limit is reported as having no conflicts, unless it is parsed
+    /// on save.
+    pub fn parse(&self) -> Result<Option<MergeConflict>, ParseError> {
+        let content = self.document.get_content(None);
+        if self.large_files == LargeFiles::Skip && self.over_size_limit() {
+            tracing::debug!(
+                "not parsing {} bytes, over the {} byte limit",
+                content.len(),
+                self.max_size
+            );
+            return Ok(None);
+        }
+        parse_with_options(
+            content,
+            ParseOptions {
+                syntax: Syntax::from_language_id(self.language_id()),
+                encoding: self.encoding,
+                style: self.style,
+                marker_size: self.marker_size,
+            },
+        )
+    }
+
+    /// Apply content changes whose positions are in the client's encoding.
+    ///
+    /// `FullTextDocument` counts characters in UTF-16, so each change is converted against the
+    /// text left by the changes before it. Positions `FullTextDocument` would panic on or lose
+    /// track of its lines over are moved first; see [`DocumentState::valid_position`]. A range
+    /// ending before it starts is an error, and the changes after it are not applied.
+    ///
+    /// A change to lines outside the conflicts moves them along instead of leaving them to be
+    /// parsed again, see [`DocumentState::plain_edit`]. Any other change makes them stale.
+    pub fn apply_changes(
+        &mut self,
+        changes: &[lsp_types::TextDocumentContentChangeEvent],
+        version: i32,
+    ) -> anyhow::Result<()> {
+        for change in changes {
+            let mut change = change.clone();
+            if let Some(range) = change.range.as_mut() {
+                let content = self.document.get_content(None);
+                for position in [&mut range.start, &mut range.end] {
+                    *position = self.valid_position(content, *position);
+                }
+                if range.end < range.start {
+                    anyhow::bail!("change range ends before it starts: {range:?}");
+                }
+                // The length is in the client's encoding too; the range alone is enough.
+                change.range_length = None;
+            }
+            let edit = change.range.and_then(|range| self.plain_edit(range));
+            self.document.update(&[change], version);
+            if !edit.is_some_and(|edit| self.shift_conflicts(edit)) {
+                // Even when the client sent the same version again.
+                self.parsed_version = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// The lines `range` is about to replace, when up-to-date conflicts can simply be moved past
+    /// the change: the lines are outside every conflict and none of them could be a marker.
+    ///
+    /// Only plain text qualifies. In Markdown a new fence and in reStructuredText a new title
+    /// can change what the markers after them mean.
+    fn plain_edit(&self, range: lsp_types::Range) -> Option<PlainEdit> {
+        if self.is_stale() || Syntax::from_language_id(self.language_id()) != Syntax::Plain {
+            return None;
+        }
+        let (first_line, last_line) = (range.start.line, range.end.line);
+        if let Some(merge_conflict) = &self.merge_conflict
+            && merge_conflict
+                .conflicts
+                .iter()
+                .any(|region| region.head <= last_line && first_line <= region.end)
+        {
+            return None;
+        }
+        let content = self.document.get_content(None);
+        let offset = |line: u32| {
+            lsp_lines(content)
+                .nth(line as usize)
+                .map_or(content.len(), |(offset, _)| offset)
+        };
+        let bytes = offset(first_line)..offset(last_line + 1);
+        let replaced = &content[bytes.clone()];
+        if lsp_lines(replaced).any(|(_, line)| may_be_marker(line)) {
+            return None;
+        }
+        Some(PlainEdit {
+            first_line,
+            last_line,
+            lines: lsp_lines(replaced).count(),
+            bytes,
+            len: content.len(),
+        })
+    }
+
+    /// Move the conflicts after `edit`, now applied, by the lines and bytes it added or removed.
+    /// `false` when the new lines could hold a marker after all, and the conflicts need parsing.
+    fn shift_conflicts(&mut self, edit: PlainEdit) -> bool {
+        let content = self.document.get_content(None);
+        let Some(inserted) = (edit.bytes.end + content.len())
+            .checked_sub(edit.len)
+            .and_then(|end| content.get(edit.bytes.start..end))
+        else {
+            return false;
+        };
+        // A `\n` right after a bare `\r` joins two lines into one.
+        let joined = inserted.starts_with('\n') && content[..edit.bytes.start].ends_with('\r');
+        if joined || lsp_lines(inserted).any(|(_, line)| may_be_marker(line)) {
+            return false;
+        }
+        let lines = lsp_lines(inserted).count() as i64 - edit.lines as i64;
+        let bytes = content.len() as isize - edit.len as isize;
+        if let Some(merge_conflict) = self.merge_conflict.as_mut()
+            && (lines != 0 || bytes != 0)
+        {
+            let lines = lines as i32;
+            let mut shifted = false;
+            for region in merge_conflict
+                .conflicts
+                .iter_mut()
+                .filter(|region| region.head > edit.last_line)
+            {
+                shift_region(region, lines, bytes);
+                shifted = true;
+            }
+            for warning in merge_conflict
+                .warnings
+                .iter_mut()
+                .filter(|warning| warning.line > edit.last_line)
+            {
+                warning.line = warning.line.saturating_add_signed(lines);
+                shifted = true;
+            }
+            // Diagnostics only hold lines; moved bytes alone need not be published.
+            self.moved |= shifted && lines != 0;
+        }
+        // The conflicts no longer match the content parsed before.
+        self.parsed_hash = None;
+        tracing::debug!(
+            "moved conflicts past lines {}-{} by {lines} line(s)",
+            edit.first_line,
+            edit.last_line
+        );
+        self.parsed_version = Some(self.version());
+        true
+    }
+
+    /// `position`, in the client's encoding, as a UTF-16 position within `content`.
+    ///
+    /// Positions inside a character move to its end and positions past the end of the document
+    /// to the document's end. UTF-16 offsets past the end of a line carry over to the start of
+    /// the next one, as `FullTextDocument` itself does for lines ending in `\n`.
+    fn valid_position(&self, content: &str, position: lsp_types::Position) -> lsp_types::Position {
+        let Some((_, line)) = lsp_lines(content).nth(position.line as usize) else {
+            return end_of_text(content);
+        };
+        let character = self.encoding.to_utf16(line, position.character);
+        if character as usize > line.encode_utf16().count() {
+            let next_line = lsp_types::Position {
+                line: position.line + 1,
+                character: 0,
+            };
+            return self.valid_position(content, next_line);
+        }
+        lsp_types::Position {
+            line: position.line,
+            character,
+        }
+    }
+
+    /// Conflicts matching the current content.
+    ///
+    /// The update worker may not have caught up with the latest edit yet. Offsets from stale
+    /// conflicts would produce edits that corrupt the file, so a stale cache is replaced by a
+    /// fresh parse. The cache itself is left alone; it belongs to the update worker. Documents
+    /// only parsed on save are parsed here too, as their lines move between saves.
+    pub fn current_conflicts(&self) -> Result<Cow<'_, Option<MergeConflict>>, ParseError> {
+        if self.is_stale() {
+            tracing::debug!("conflicts are stale, reparsing version {}", self.version());
+            Ok(Cow::Owned(self.parse()?))
+        } else {
+            Ok(Cow::Borrowed(&self.merge_conflict))
+        }
+    }
+
+    /// Conflicts to report as diagnostics: the current ones, except that documents only parsed
+    /// on save keep reporting those found at the last save.
+    pub fn diagnosed_conflicts(&self) -> Result<Cow<'_, Option<MergeConflict>>, ParseError> {
+        if self.defers_parse() {
+            Ok(Cow::Borrowed(&self.merge_conflict))
+        } else {
+            self.current_conflicts()
+        }
+    }
+
     pub fn process_update(&mut self) -> anyhow::Result<Option<MergeConflict>> {
+        if !self.is_stale() {
+            tracing::debug!("conflicts are up to date, not parsing");
+            let moved = std::mem::take(&mut self.moved);
+            return Ok(self.merge_conflict.clone().filter(|_| moved));
+        }
+        self.moved = false;
         let content = self.document.get_content(None);
 
         // Previous / new here refer to the conflicts on the document.
@@ -56,13 +387,17 @@ impl DocumentState {
         // [data]   | [new]  | send diagnostics, ensure new value in state
         // None     | [new]  | send diagnostics, ensure new value in state
 
-        if !content.contains(crate::parser::MARKER_HEAD) {
+        if !may_have_conflicts(content, self.marker_size) {
             // No conflict marker in new document. Clear out anything that was there previously.
             self.merge_conflict.take();
+            self.parsed_version = Some(self.version());
+            self.parsed_hash = Some(content_hash(content));
             return Ok(None);
         }
 
-        let merge_conflict = parse(content)?;
+        let merge_conflict = self.parse()?;
+        self.parsed_version = Some(self.version());
+        self.parsed_hash = Some(content_hash(content));
 
         match (self.merge_conflict.as_ref(), merge_conflict.as_ref()) {
             (None, None) => {
@@ -86,11 +421,428 @@ impl DocumentState {
     }
 }
 
+/// Hash of a document's content, to tell whether it changed without keeping a copy.
+pub fn content_hash(content: &str) -> u64 {
+    let mut hasher = std::hash::DefaultHasher::new();
+    std::hash::Hash::hash(content, &mut hasher);
+    std::hash::Hasher::finish(&hasher)
+}
+
+/// Move `region` down by `lines` lines and `bytes` bytes, or up when they are negative.
+fn shift_region(region: &mut ConflictRegion, lines: i32, bytes: isize) {
+    for line in [&mut region.head, &mut region.branch, &mut region.end]
+        .into_iter()
+        .chain(region.ancestor.as_mut())
+        .chain(region.yours.as_mut())
+    {
+        *line = line.saturating_add_signed(lines);
+    }
+    let marker_bytes = &mut region.bytes;
+    for range in [
+        &mut marker_bytes.head,
+        &mut marker_bytes.branch,
+        &mut marker_bytes.end,
+    ]
+    .into_iter()
+    .chain(marker_bytes.ancestor.as_mut())
+    .chain(marker_bytes.yours.as_mut())
+    {
+        range.start = range.start.saturating_add_signed(bytes);
+        range.end = range.end.saturating_add_signed(bytes);
+    }
+}
+
+/// One entry in the response to the list conflicts command.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConflictSummary {
+    pub range: lsp_types::Range,
+    pub ours_name: Option<String>,
+    pub theirs_name: Option<String>,
+    pub has_ancestor: bool,
+}
+
+/// One side of a conflict with the commit that last changed its lines.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SideCommit {
+    pub name: String,
+    /// The side's lines, without the markers.
+    pub range: lsp_types::Range,
+    pub commit: Option<CommitInfo>,
+}
+
+impl SideCommit {
+    fn describe(&self) -> String {
+        match &self.commit {
+            Some(commit) => format!("{} last changed in {commit}", self.name),
+            None => format!("{}: no commit found", self.name),
+        }
+    }
+}
+
+/// What git knows about the sides of a document's conflicts, shown in action titles.
+///
+/// `commits` are the commits the conflicts' marker names point to, by name. During a rebase
+/// `HEAD` is the upstream branch and their side the user's own commit, so `names` replace the
+/// marker names with ones saying so.
+///
+/// `submodule_commits` are the commits of submodule pointer conflicts, by commit id, found in
+/// the submodules.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GitSides {
+    pub commits: HashMap<String, CommitInfo>,
+    pub names: SideNames,
+    pub submodule_commits: HashMap<String, CommitInfo>,
+}
+
+/// A document's version and content with the conflicts found in it, for publishing them.
+pub type DocumentConflicts = (lsp_types::Uri, i32, String, Option<MergeConflict>);
+
+/// Where each side of a conflict comes from, found with git.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConflictCommits {
+    pub region: ConflictRegion,
+    pub ours: SideCommit,
+    pub theirs: SideCommit,
+}
+
+/// Point `diagnostics` at the commits behind the sides of their conflicts with
+/// `relatedInformation`.
+pub fn add_commit_information(
+    diagnostics: &mut [lsp_types::Diagnostic],
+    uri: &lsp_types::Uri,
+    commits: &[ConflictCommits],
+) {
+    for diagnostic in diagnostics {
+        let Some(conflict) = commits
+            .iter()
+            .find(|conflict| range_for_diagnostic_conflict(&conflict.region) == diagnostic.range)
+        else {
+            continue;
+        };
+        diagnostic.related_information = Some(
+            [&conflict.ours, &conflict.theirs]
+                .into_iter()
+                .map(|side| lsp_types::DiagnosticRelatedInformation {
+                    location: lsp_types::Location {
+                        uri: uri.clone(),
+                        range: side.range,
+                    },
+                    message: side.describe(),
+                })
+                .collect(),
+        );
+    }
+}
+
+/// Code action kind of the action resolving every conflict with `defaultStrategy`.
+pub const FIX_ALL_KIND: &str = "source.fixAll.mergeConflict";
+
+/// Method the semantic token providers are registered under.
+pub const SEMANTIC_TOKENS_METHOD: &str = "textDocument/semanticTokens";
+
+/// Semantic token types for conflict lines, in legend order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConflictTokenType {
+    Marker,
+    Ours,
+    Theirs,
+    Ancestor,
+}
+
+impl ConflictTokenType {
+    pub const ALL: [Self; 4] = [Self::Marker, Self::Ours, Self::Theirs, Self::Ancestor];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Marker => "conflictMarker",
+            Self::Ours => "conflictOurs",
+            Self::Theirs => "conflictTheirs",
+            Self::Ancestor => "conflictAncestor",
+        }
+    }
+}
+
+/// Identifies a code action whose edit is left for `codeAction/resolve`.
+///
+/// The actions offered for a set of conflicts are deterministic for a document version, so the
+/// action's position in that list is enough to rebuild its edit.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionData {
+    pub uri: lsp_types::Uri,
+    /// Document version the action was offered for.
+    pub version: i32,
+    /// Indexes of the conflicts the action covers.
+    pub conflicts: Vec<usize>,
+    /// Range of the request the action was offered for.
+    pub range: lsp_types::Range,
+    /// Position of the action in the list offered for those conflicts.
+    pub action: usize,
+}
+
+/// A server-to-client request waiting for its response.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PendingRequest {
+    /// `workspace/applyEdit` of `edits` to `uri`, computed from document `version`.
+    ApplyEdit {
+        uri: lsp_types::Uri,
+        version: i32,
+        edits: Vec<lsp_types::TextEdit>,
+    },
+    /// `window/showMessageRequest` offering to send a rejected edit again.
+    RetryApplyEdit {
+        uri: lsp_types::Uri,
+        version: i32,
+        edits: Vec<lsp_types::TextEdit>,
+    },
+    /// A request whose result carries nothing, e.g. registration or refresh. Only a failure
+    /// is worth reporting.
+    Acknowledged { method: String },
+    /// `workspace/configuration` pulling the server's settings.
+    Configuration,
+}
+
+/// Optional client features the server adapts to, read from the client capabilities.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ClientSupport {
+    /// Providers are registered through `client/registerCapability` instead of being read
+    /// from the static capabilities.
+    pub dynamic_registration: bool,
+    /// Code action edits are resolved lazily through `codeAction/resolve`.
+    pub resolve_code_action_edits: bool,
+    /// `workspace/diagnostic/refresh` is understood.
+    pub diagnostic_refresh: bool,
+    /// `window/showMessageRequest` is understood.
+    pub show_message_request: bool,
+    /// Settings can be pulled with `workspace/configuration`.
+    pub configuration: bool,
+    /// `workspace/applyEdit` is understood.
+    pub apply_edit: bool,
+    /// Workspace edits may delete files.
+    pub delete_files: bool,
+    /// Units of `character` offsets agreed with the client.
+    pub position_encoding: PositionEncoding,
+}
+
+impl ClientSupport {
+    /// Read from the raw `capabilities` of `initialize` rather than the typed
+    /// [`lsp_types::ClientCapabilities`], which expects `workspace.diagnostic` where the
+    /// specification and clients use `workspace.diagnostics`.
+    pub fn from_capabilities(raw_capabilities: &serde_json::Value) -> Self {
+        let capabilities: lsp_types::ClientCapabilities =
+            serde_json::from_value(raw_capabilities.clone()).unwrap_or_else(|e| {
+                tracing::warn!("ignoring invalid client capabilities: {e}");
+                Default::default()
+            });
+        let text_document = capabilities.text_document.clone().unwrap_or_default();
+        // Providers are either all registered dynamically or all announced statically, so
+        // only register dynamically when the client supports it for every provider.
+        let dynamic_registration = [
+            text_document
+                .code_action
+                .as_ref()
+                .and_then(|code_action| code_action.dynamic_registration),
+            text_document
+                .code_lens
+                .as_ref()
+                .and_then(|code_lens| code_lens.dynamic_registration),
+            text_document
+                .semantic_tokens
+                .as_ref()
+                .and_then(|semantic_tokens| semantic_tokens.dynamic_registration),
+            text_document
+                .diagnostic
+                .as_ref()
+                .and_then(|diagnostic| diagnostic.dynamic_registration),
+        ]
+        .iter()
+        .all(|dynamic| *dynamic == Some(true));
+        let resolve_code_action_edits = text_document
+            .code_action
+            .as_ref()
+            .and_then(|code_action| code_action.resolve_support.as_ref())
+            .is_some_and(|support| support.properties.iter().any(|property| property == "edit"));
+        let diagnostic_refresh = raw_capabilities
+            .pointer("/workspace/diagnostics/refreshSupport")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false);
+        let show_message_request = capabilities
+            .window
+            .as_ref()
+            .is_some_and(|window| window.show_message.is_some());
+        let configuration = capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.configuration)
+            .unwrap_or(false);
+        let apply_edit = capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.apply_edit)
+            .unwrap_or(false);
+        let delete_files = capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .and_then(|workspace_edit| workspace_edit.resource_operations.as_ref())
+            .is_some_and(|operations| {
+                operations.contains(&lsp_types::ResourceOperationKind::Delete)
+            });
+        let position_encoding = PositionEncoding::negotiate(
+            capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref()),
+        );
+        Self {
+            dynamic_registration,
+            resolve_code_action_edits,
+            diagnostic_refresh,
+            show_message_request,
+            configuration,
+            apply_edit,
+            delete_files,
+            position_encoding,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ServerStatus {
     Running,
     ShutdownRequested,
-    ExitReceived,
+    /// The client sent `exit`. Exiting is only clean when `shutdown` came first.
+    ExitReceived {
+        after_shutdown: bool,
+    },
+}
+
+impl ServerStatus {
+    /// Process exit code once the main loop ends: 0 after a shutdown request, 1 otherwise.
+    pub fn exit_code(self) -> std::process::ExitCode {
+        match self {
+            Self::ShutdownRequested
+            | Self::ExitReceived {
+                after_shutdown: true,
+            } => std::process::ExitCode::SUCCESS,
+            Self::Running
+            | Self::ExitReceived {
+                after_shutdown: false,
+            } => std::process::ExitCode::FAILURE,
+        }
+    }
+}
+
+/// Most document update workers running at once. More waiting updates queue for them.
+pub const MAX_UPDATE_WORKERS: usize = 4;
+
+/// An update of a document waiting in the [`UpdateQueue`].
+#[derive(Debug)]
+pub struct QueuedUpdate {
+    pub version: i32,
+    /// From [`ServerState::schedule_update`].
+    pub generation: u64,
+    /// When the change behind it arrived, to debounce from.
+    pub queued: Instant,
+}
+
+/// Document updates waiting for a worker. A newer update of a document replaces the one still
+/// waiting, so a burst of changes is parsed once.
+#[derive(Debug, Default)]
+pub struct UpdateQueue {
+    /// Documents with a waiting update, oldest first.
+    order: VecDeque<lsp_types::Uri>,
+    waiting: HashMap<lsp_types::Uri, QueuedUpdate>,
+    /// Workers currently taking updates from the queue.
+    workers: usize,
+}
+
+impl UpdateQueue {
+    /// Queue `update` of `uri`, replacing any update of it still waiting but keeping its place.
+    pub fn push(&mut self, uri: lsp_types::Uri, update: QueuedUpdate) {
+        if self.waiting.insert(uri.clone(), update).is_none() {
+            self.order.push_back(uri);
+        }
+    }
+
+    /// The update waiting longest.
+    pub fn pop(&mut self) -> Option<(lsp_types::Uri, QueuedUpdate)> {
+        let uri = self.order.pop_front()?;
+        let update = self.waiting.remove(&uri)?;
+        Some((uri, update))
+    }
+}
+
+/// What git found for a document's conflicts, kept until the repository's [`git::Heads`] move.
+#[derive(Clone, Debug, Default)]
+struct GitCache {
+    /// `None` when the document is not in a repository.
+    repository: Option<git::Repository>,
+    heads: git::Heads,
+    /// The commit that last changed a side, by revision and the side's text.
+    last_commits: HashMap<(String, String), Option<CommitInfo>>,
+    /// The commits marker names point to, by name.
+    commits: HashMap<String, Option<CommitInfo>>,
+    /// Submodule commits, by commit id.
+    submodule_commits: HashMap<String, Option<CommitInfo>>,
+    /// What a rebase in progress replays onto, once looked up.
+    rebase_onto: Option<Option<String>>,
+}
+
+/// The commit `name` points to in `repository`, looked up once and kept in `commits`.
+fn commit(
+    repository: &git::Repository,
+    commits: &mut HashMap<String, Option<CommitInfo>>,
+    name: &str,
+) -> Option<CommitInfo> {
+    commits
+        .entry(name.to_owned())
+        .or_insert_with(|| {
+            repository
+                .commit(name)
+                .inspect_err(|e| tracing::warn!("git: {e:#}"))
+                .ok()
+                .flatten()
+        })
+        .clone()
+}
+
+/// What to call the sides while `repository` is in the middle of a rebase: `HEAD` is the
+/// upstream branch replayed onto and the other side the user's own commit. Empty otherwise.
+///
+/// `commits` and `rebase_onto` keep what git was asked, as in [`GitCache`].
+pub fn rebase_names(
+    repository: &git::Repository,
+    commits: &mut HashMap<String, Option<CommitInfo>>,
+    rebase_onto: &mut Option<Option<String>>,
+) -> SideNames {
+    if repository.operation() != Some(git::Operation::Rebase) {
+        return SideNames::default();
+    }
+    let onto = rebase_onto.get_or_insert_with(|| {
+        repository
+            .rebase_onto()
+            .inspect_err(|e| tracing::warn!("git: {e:#}"))
+            .ok()
+            .flatten()
+    });
+    let ours = match onto {
+        Some(onto) => format!("upstream ({onto})"),
+        None => "upstream".to_owned(),
+    };
+    let replayed = commit(
+        repository,
+        commits,
+        git::Operation::Rebase.theirs_revision(),
+    );
+    let theirs = match replayed {
+        Some(commit) => format!("your commit ({})", commit.short_sha),
+        None => "your commit".to_owned(),
+    };
+    SideNames {
+        ours: Some(ours),
+        theirs: Some(theirs),
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -98,448 +850,4278 @@ pub struct ServerState {
     pub status: ServerStatus,
     pub sender: Arc<Mutex<crossbeam_channel::Sender<lsp_server::Message>>>,
     pub documents: Arc<Mutex<HashMap<lsp_types::Uri, Arc<Mutex<DocumentState>>>>>,
+    /// Replaced as a whole when the client's settings change; see [`ServerState::config`].
+    config: Arc<Mutex<Arc<Config>>>,
+    /// Document update workers that may still be running.
+    pub pending_updates: Arc<Mutex<Vec<thread::JoinHandle<()>>>>,
+    /// Document updates not yet taken by a worker.
+    pub update_queue: Arc<Mutex<UpdateQueue>>,
+    /// Providers currently enabled. Starts from `config.features`.
+    pub features: Arc<Mutex<Features>>,
+    /// What the client supports beyond the basics.
+    pub client: ClientSupport,
+    /// Methods currently registered with the client via `client/registerCapability`.
+    pub registered: Arc<Mutex<HashSet<String>>>,
+    /// Requests sent to the client whose responses still need handling, by request id.
+    pub pending_requests: Arc<Mutex<HashMap<lsp_server::RequestId, PendingRequest>>>,
+    /// The first workspace folder, or the root the client gave, as a local path.
+    pub workspace_root: Option<PathBuf>,
+    /// Files the repository has whole-file conflicts over, found at startup with git.
+    pub file_conflicts: Arc<Mutex<HashMap<lsp_types::Uri, FileConflict>>>,
+    /// Files diagnostics were published for from disk, until they are opened or resolved.
+    pub unopened: Arc<Mutex<HashSet<lsp_types::Uri>>>,
+    /// What git found for open documents, by document.
+    git_cache: Arc<Mutex<HashMap<lsp_types::Uri, GitCache>>>,
+    next_request_id: Arc<AtomicI32>,
 }
 
 impl ServerState {
-    pub fn new(sender: Sender<lsp_server::Message>) -> Self {
+    pub fn new(sender: Sender<lsp_server::Message>, config: Config) -> Self {
         Self {
             status: ServerStatus::Running,
             sender: Arc::new(Mutex::new(sender)),
             documents: Arc::new(Mutex::new(HashMap::new())),
+            features: Arc::new(Mutex::new(config.features.clone())),
+            config: Arc::new(Mutex::new(Arc::new(config))),
+            pending_updates: Arc::new(Mutex::new(Vec::new())),
+            update_queue: Arc::new(Mutex::new(UpdateQueue::default())),
+            client: ClientSupport::default(),
+            registered: Arc::new(Mutex::new(HashSet::new())),
+            pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            workspace_root: None,
+            file_conflicts: Arc::new(Mutex::new(HashMap::new())),
+            unopened: Arc::new(Mutex::new(HashSet::new())),
+            git_cache: Arc::new(Mutex::new(HashMap::new())),
+            next_request_id: Arc::new(AtomicI32::new(0)),
         }
     }
 
-    pub fn add_document(&self, text_document: lsp_types::TextDocumentItem) -> LSPResult {
-        tracing::debug!("content: {:?}", text_document.text);
-        let mut documents = self.documents.lock().map_err(|e| {
-            tracing::error!("poisoned mutex: {e}");
-            anyhow::anyhow!("poisoned mutex: {e}")
-        })?;
-        // Always insert. Even if there was a previous version, didOpen means a new version of the file opened.
-        documents.insert(
-            text_document.uri.clone(),
-            Arc::new(Mutex::new(DocumentState::new(
-                text_document.text,
-                text_document.version,
-            ))),
-        );
-        Ok(Some((text_document.uri, text_document.version)))
+    /// Send a server-to-client request. Returns the id used so the response can be matched.
+    pub fn send_request(
+        &self,
+        method: &str,
+        params: impl serde::Serialize,
+    ) -> anyhow::Result<lsp_server::RequestId> {
+        let id = lsp_server::RequestId::from(self.next_request_id.fetch_add(1, Ordering::SeqCst));
+        let request = lsp_server::Request::new(id.clone(), method.to_owned(), params);
+        let sender = self.sender.lock().unwrap_or_else(PoisonError::into_inner);
+        sender.send(request.into())?;
+        Ok(id)
     }
 
-    pub fn document_did_change(
+    /// Send a server-to-client request and remember it so its response can be acted on.
+    pub fn send_tracked_request(
         &self,
-        text_document: lsp_types::VersionedTextDocumentIdentifier,
-        content_changes: Vec<lsp_types::TextDocumentContentChangeEvent>,
-    ) -> LSPResult {
-        tracing::debug!("content changes: {:?}", content_changes);
+        method: &str,
+        params: impl serde::Serialize,
+        pending: PendingRequest,
+    ) -> anyhow::Result<lsp_server::RequestId> {
+        let mut pending_requests = self
+            .pending_requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let id = self.send_request(method, params)?;
+        pending_requests.insert(id.clone(), pending);
+        Ok(id)
+    }
 
-        let doc_state = {
-            let mut documents = self.documents.lock().map_err(|e| {
-                tracing::error!("poisoned mutex: {e}");
-                anyhow::anyhow!("poisoned mutex: {e}")
-            })?;
-            let Some(doc_state) = documents.get_mut(&text_document.uri) else {
-                tracing::debug!("failed to find document: {:?}", text_document.uri);
-                return Ok(None);
-            };
-            Arc::clone(doc_state)
-        };
-        let mut locked_doc_state = doc_state.lock().map_err(|e| {
-            tracing::error!("poisoned mutex: {e}");
-            anyhow::anyhow!("poisoned mutex: {e}")
-        })?;
-        if locked_doc_state.version() > text_document.version {
-            tracing::debug!(
-                "Version skew detected! {} v. {}",
-                locked_doc_state.version(),
-                text_document.version
-            );
-        }
-        tracing::debug!("applying changes");
-        locked_doc_state
-            .document
-            .update(&content_changes, text_document.version);
-        Ok(Some((text_document.uri.clone(), text_document.version)))
+    /// Remove and return the request a response answers, if it was tracked.
+    pub fn take_pending_request(
+        &self,
+        id: &lsp_server::RequestId,
+    ) -> anyhow::Result<Option<PendingRequest>> {
+        let mut pending_requests = self
+            .pending_requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(pending_requests.remove(id))
     }
 
-    pub fn remove_document(&self, text_document: lsp_types::TextDocumentIdentifier) -> LSPResult {
-        let mut documents = self.documents.lock().map_err(|e| {
-            tracing::error!("poisoned mutex: {e}");
-            anyhow::anyhow!("poisoned mutex: {e}")
-        })?;
-        if documents.remove(&text_document.uri).is_some() {
-            tracing::debug!("Clearing {:?} from list of documents", text_document.uri);
-        }
-        Ok(None)
+    /// Ask the client to refresh what it shows for `method`, e.g. `workspace/diagnostic/refresh`.
+    pub fn refresh(&self, method: &str) -> anyhow::Result<lsp_server::RequestId> {
+        self.send_tracked_request(
+            method,
+            serde_json::Value::Null,
+            PendingRequest::Acknowledged {
+                method: method.to_owned(),
+            },
+        )
     }
 
-    pub fn code_action(
-        &self,
-        params: lsp_types::CodeActionParams,
-    ) -> anyhow::Result<Vec<lsp_types::CodeAction>> {
+    /// Whether the content of `uri`, as the client has it, has nothing left to resolve. `None`
+    /// when it is not open.
+    pub fn document_resolved(&self, uri: &lsp_types::Uri) -> anyhow::Result<Option<bool>> {
         let document_state = {
-            let documents = self.documents.lock().map_err(|e| {
-                tracing::error!("poisoned mutex: {e}");
-                anyhow::anyhow!("poisoned mutex: {e}")
-            })?;
-            let Some(document_state) = documents.get(&params.text_document.uri) else {
-                tracing::debug!("{:?} not found", params.text_document.uri);
-                return Ok(Vec::new());
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                return Ok(None);
             };
             Arc::clone(document_state)
         };
-
-        let locked_document_state = document_state.lock().map_err(|e| {
-            tracing::error!("poisoned mutex: {e}");
-            anyhow::anyhow!("poisoned mutex: {e}")
-        })?;
-        let Some(merge_conflict) = locked_document_state.merge_conflict.as_ref() else {
-            return Ok(Vec::new());
-        };
-        let Some(conflict) = merge_conflict
-            .conflicts()
-            .find(|conflict| conflict.is_in_range(&params.range))
-        else {
-            return Ok(Vec::new());
-        };
-        let actions = conflict_as_code_actions(
-            conflict,
-            &params.text_document.uri,
-            &locked_document_state.document,
-            &locked_document_state.merge_conflict,
-        );
-        Ok(actions)
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(Some(
+            locked_document_state
+                .parse()?
+                .is_none_or(|merge_conflict| merge_conflict.is_resolved()),
+        ))
     }
 
-    pub fn on_document_update(
-        &self,
-        uri: &lsp_types::Uri,
-        version: i32,
-    ) -> anyhow::Result<Option<MergeConflict>> {
-        let doc_state = {
-            let documents = self.documents.lock().map_err(|e| {
-                tracing::error!("poisoned mutex: {e}");
-                anyhow::anyhow!("poisoned mutex: {e}")
-            })?;
-            let Some(doc_state) = documents.get(uri) else {
-                tracing::debug!("No entry to {uri:?}");
+    /// Current version of `uri`, if it is open.
+    pub fn document_version(&self, uri: &lsp_types::Uri) -> anyhow::Result<Option<i32>> {
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
                 return Ok(None);
             };
-            Arc::clone(doc_state)
+            Arc::clone(document_state)
         };
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(Some(locked_document_state.version()))
+    }
 
-        let mut locked_doc_state = doc_state.lock().map_err(|e| {
-            tracing::error!("poisoned mutex: {e}");
-            anyhow::anyhow!("poisoned mutex: {e}")
-        })?;
+    /// The configuration in effect now. Later settings changes do not affect the snapshot.
+    pub fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.config.lock().unwrap_or_else(PoisonError::into_inner))
+    }
 
-        if version >= locked_doc_state.version() {
-            // Update version via a no-op change to keep FullTextDocument in sync.
-            locked_doc_state.document.update(&[], version);
-        } else {
-            tracing::debug!("Missed update, skipping.");
-            return Ok(None);
+    /// Switch to `config` and reparse every open document with it.
+    ///
+    /// Returns the version, content and conflicts of each document whose diagnostics need
+    /// publishing. Documents the new configuration excludes, by path or language, are dropped
+    /// and reported without conflicts.
+    pub fn set_config(&self, config: Config) -> anyhow::Result<Vec<DocumentConflicts>> {
+        let features = config.features.clone();
+        let config = Arc::new(config);
+        *self.config.lock().unwrap_or_else(PoisonError::into_inner) = Arc::clone(&config);
+        self.set_features(features)?;
+
+        // Documents are reparsed one at a time without holding on to the others, which stay
+        // available to requests and updates meanwhile.
+        let documents: Vec<_> = self
+            .documents
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(uri, document_state)| (uri.clone(), Arc::clone(document_state)))
+            .collect();
+        let mut updates = Vec::with_capacity(documents.len());
+        let mut excluded = Vec::new();
+        for (uri, document_state) in &documents {
+            let mut locked_document_state = document_state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if config.is_excluded(uri, locked_document_state.language_id()) {
+                tracing::debug!("dropping newly excluded {:?}", uri);
+                excluded.push((uri, document_state));
+                updates.push((
+                    uri.clone(),
+                    locked_document_state.version(),
+                    String::new(),
+                    None,
+                ));
+                continue;
+            }
+            locked_document_state.style = config.conflict_style;
+            locked_document_state.max_size = config.max_file_size;
+            locked_document_state.large_files = config.large_files;
+            let merge_conflict = match locked_document_state.parse() {
+                Ok(merge_conflict) => merge_conflict,
+                Err(e) => {
+                    tracing::debug!("reparse of {:?} failed, reporting no conflicts: {e}", uri);
+                    None
+                }
+            };
+            locked_document_state.merge_conflict = merge_conflict.clone();
+            locked_document_state.parsed_version = Some(locked_document_state.version());
+            locked_document_state.parsed_hash = Some(content_hash(
+                locked_document_state.document.get_content(None),
+            ));
+            updates.push((
+                uri.clone(),
+                locked_document_state.version(),
+                locked_document_state.document.get_content(None).to_owned(),
+                merge_conflict,
+            ));
+        }
+        let mut documents = self
+            .documents
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (uri, document_state) in excluded {
+            // Unless it was reopened in the meantime.
+            if documents
+                .get(uri)
+                .is_some_and(|current| Arc::ptr_eq(current, document_state))
+            {
+                documents.remove(uri);
+                self.git_cache
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .remove(uri);
+            }
         }
+        Ok(updates)
+    }
 
-        let _span = tracing::debug_span!("parse", ?uri).entered();
-        locked_doc_state.process_update()
+    /// Whether `uri` changed in a way its update worker has to parse or publish. Unknown
+    /// documents did not.
+    pub fn needs_update(&self, uri: &lsp_types::Uri) -> bool {
+        self.with_document(uri, |document_state| document_state.needs_update())
+            .unwrap_or(false)
     }
-}
 
-fn conflict_as_code_actions(
-    region: &ConflictRegion,
-    uri: &lsp_types::Uri,
-    document: &FullTextDocument,
-    merge_conflict: &Option<MergeConflict>,
-) -> Vec<lsp_types::CodeAction> {
-    macro_rules! as_string_with_default {
-        ($s:expr, $option:expr, $default:expr) => {
-            match $option.as_ref() {
-                Some(value) => format!($s, value),
-                None => format!($s, $default),
-            }
+    /// Whether changes to `uri` wait for a save before being parsed. Unknown documents do not.
+    pub fn parse_deferred(&self, uri: &lsp_types::Uri) -> anyhow::Result<bool> {
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                return Ok(false);
+            };
+            Arc::clone(document_state)
         };
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(locked_document_state.defers_parse())
     }
 
-    let diagnostic = lsp_types::Diagnostic::from(region);
-    let range = range_for_diagnostic_conflict(region);
+    pub fn feature_enabled(&self, method: &str) -> bool {
+        let features = self.features.lock().unwrap_or_else(PoisonError::into_inner);
+        enabled_methods(&features).contains(&method)
+    }
 
-    let current_conflict = merge_conflict
-        .as_ref()
-        .expect("valid merge conflict reference");
+    /// Replace the enabled features and bring the client's registrations in line with them.
+    pub fn set_features(&self, features: Features) -> anyhow::Result<()> {
+        *self.features.lock().unwrap_or_else(PoisonError::into_inner) = features;
+        self.sync_registrations()
+    }
 
-    let mut items = vec![
-        {
-            let edit = make_text_edit(document, range, &[region.head_range()]);
-            make_code_action(
-                as_string_with_default!("Keep {}", current_conflict.head, "HEAD"),
-                uri,
-                edit,
-                diagnostic.clone(),
-            )
-        },
-        {
-            let edit = make_text_edit(document, range, &[region.branch_range()]);
-            make_code_action(
-                as_string_with_default!("Keep {}", current_conflict.branch, "branch"),
+    /// Register newly enabled providers and unregister disabled ones.
+    ///
+    /// Does nothing for clients without dynamic registration; they only see the static capabilities.
+    pub fn sync_registrations(&self) -> anyhow::Result<()> {
+        if !self.client.dynamic_registration {
+            return Ok(());
+        }
+        let wanted: HashSet<String> = {
+            let features = self.features.lock().unwrap_or_else(PoisonError::into_inner);
+            enabled_methods(&features)
+                .into_iter()
+                .map(String::from)
+                .collect()
+        };
+        let mut registered = self
+            .registered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut registrations: Vec<lsp_types::Registration> = wanted
+            .difference(&registered)
+            .map(|method| lsp_types::Registration {
+                // The method doubles as the registration id; each is registered at most once.
+                id: method.clone(),
+                method: method.clone(),
+                register_options: registration_options(method),
+            })
+            .collect();
+        registrations.sort_by(|a, b| a.method.cmp(&b.method));
+        let unregisterations: Vec<lsp_types::Unregistration> = registered
+            .difference(&wanted)
+            .map(|method| lsp_types::Unregistration {
+                id: method.clone(),
+                method: method.clone(),
+            })
+            .collect();
+
+        if !registrations.is_empty() {
+            tracing::info!("registering {} capability(ies)", registrations.len());
+            let method =
+                <lsp_types::request::RegisterCapability as lsp_types::request::Request>::METHOD;
+            self.send_tracked_request(
+                method,
+                lsp_types::RegistrationParams { registrations },
+                PendingRequest::Acknowledged {
+                    method: method.to_owned(),
+                },
+            )?;
+        }
+        if !unregisterations.is_empty() {
+            tracing::info!("unregistering {} capability(ies)", unregisterations.len());
+            let method =
+                <lsp_types::request::UnregisterCapability as lsp_types::request::Request>::METHOD;
+            self.send_tracked_request(
+                method,
+                lsp_types::UnregistrationParams { unregisterations },
+                PendingRequest::Acknowledged {
+                    method: method.to_owned(),
+                },
+            )?;
+        }
+        *registered = wanted;
+        Ok(())
+    }
+
+    /// Remember a spawned document update worker so shutdown can wait for it.
+    pub fn track_update(&self, handle: thread::JoinHandle<()>) {
+        let mut pending = self
+            .pending_updates
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Clean up finished handles while we are here.
+        pending.retain(|h| !h.is_finished());
+        pending.push(handle);
+    }
+
+    /// Block until every tracked document update worker has finished.
+    pub fn wait_for_pending_updates(&self) {
+        let handles = std::mem::take(
+            &mut *self
+                .pending_updates
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        tracing::debug!("waiting on {} document update worker(s)", handles.len());
+        for handle in handles {
+            if handle.join().is_err() {
+                tracing::error!("document update worker panicked");
+            }
+        }
+    }
+
+    /// Start a new generation of updates for `uri`, superseding any update already scheduled.
+    /// Returns the generation to hand to [`ServerState::is_latest_update`].
+    pub fn schedule_update(&self, uri: &lsp_types::Uri) -> u64 {
+        self.with_document(uri, |document_state| {
+            document_state.generation += 1;
+            document_state.generation
+        })
+        .unwrap_or_default()
+    }
+
+    /// Whether no update for `uri` was scheduled after the one of `generation`. A closed
+    /// document has no updates left to run.
+    pub fn is_latest_update(&self, uri: &lsp_types::Uri, generation: u64) -> bool {
+        self.with_document(uri, |document_state| {
+            document_state.generation == generation
+        })
+        .unwrap_or(false)
+    }
+
+    /// Queue an update of `uri` at `version` for the update workers. Returns whether another
+    /// worker should be started for it; the caller starts it and hands it to
+    /// [`ServerState::track_update`].
+    pub fn queue_update(&self, uri: lsp_types::Uri, version: i32) -> bool {
+        let generation = self.schedule_update(&uri);
+        let mut queue = self
+            .update_queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        queue.push(
+            uri,
+            QueuedUpdate {
+                version,
+                generation,
+                queued: Instant::now(),
+            },
+        );
+        let start = queue.workers < MAX_UPDATE_WORKERS;
+        if start {
+            queue.workers += 1;
+        }
+        start
+    }
+
+    /// The next update for a worker to run. `None` when the queue is empty, and the worker asking
+    /// is expected to finish.
+    pub fn next_update(&self) -> Option<(lsp_types::Uri, QueuedUpdate)> {
+        let mut queue = self
+            .update_queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let next = queue.pop();
+        if next.is_none() {
+            queue.workers -= 1;
+        }
+        next
+    }
+
+    /// Send `message` unless an update of `uri` was scheduled after `generation`. Checking and
+    /// sending under the document's lock keeps a superseded update from publishing after the
+    /// newer one. Returns whether it was sent.
+    pub fn send_if_latest(
+        &self,
+        uri: &lsp_types::Uri,
+        generation: u64,
+        message: lsp_server::Message,
+    ) -> bool {
+        self.with_document(uri, |document_state| {
+            if document_state.generation != generation {
+                return false;
+            }
+            let sender = self.sender.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(e) = sender.send(message) {
+                tracing::error!("Failed to send message: {e}");
+            }
+            true
+        })
+        .unwrap_or(false)
+    }
+
+    /// The content of `uri` and the conflicts last parsed for it, if it is open.
+    pub fn document_conflicts(
+        &self,
+        uri: &lsp_types::Uri,
+    ) -> Option<(String, Option<MergeConflict>)> {
+        self.with_document(uri, |document_state| {
+            (
+                document_state.document.get_content(None).to_owned(),
+                document_state.merge_conflict.clone(),
+            )
+        })
+    }
+
+    /// Run `f` on the open document of `uri`.
+    fn with_document<T>(
+        &self,
+        uri: &lsp_types::Uri,
+        f: impl FnOnce(&mut DocumentState) -> T,
+    ) -> Option<T> {
+        let document_state = Arc::clone(
+            self.documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(uri)?,
+        );
+        let mut locked = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Some(f(&mut locked))
+    }
+
+    /// Length of the conflict markers in the file of `uri`. Git integration reads it from the
+    /// `conflict-marker-size` attribute, otherwise it is git's default.
+    pub fn marker_size(&self, uri: &lsp_types::Uri) -> usize {
+        if !self.config().git.enabled {
+            return DEFAULT_MARKER_SIZE;
+        }
+        let Some(path) = config_file::uri_to_path(uri) else {
+            return DEFAULT_MARKER_SIZE;
+        };
+        git::Repository::discover(&path)
+            .and_then(|repository| {
+                repository
+                    .conflict_marker_size(&path)
+                    .inspect_err(|e| tracing::warn!("git: {e:#}"))
+                    .ok()
+                    .flatten()
+            })
+            .unwrap_or(DEFAULT_MARKER_SIZE)
+    }
+
+    pub fn add_document(&self, text_document: lsp_types::TextDocumentItem) -> LSPResult {
+        tracing::debug!("content: {:?}", text_document.text);
+        let config = self.config();
+        if config.is_excluded(&text_document.uri, &text_document.language_id) {
+            tracing::debug!("ignoring excluded {:?}", text_document.uri);
+            return Ok(None);
+        }
+        let marker_size = self.marker_size(&text_document.uri);
+        let mut documents = self
+            .documents
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Always insert. Even if there was a previous version, didOpen means a new version of the file opened.
+        documents.insert(
+            text_document.uri.clone(),
+            Arc::new(Mutex::new(
+                DocumentState::new(
+                    text_document.language_id,
+                    text_document.text,
+                    text_document.version,
+                )
+                .with_encoding(self.client.position_encoding)
+                .with_marker_size(marker_size)
+                .with_config(&config),
+            )),
+        );
+        Ok(Some((text_document.uri, text_document.version)))
+    }
+
+    /// Apply a `didChange` notification's content changes to the tracked document.
+    ///
+    /// Changes are applied in array order. Per the LSP spec each change's range refers to the
+    /// document *after* every earlier change in the same notification has been applied, so
+    /// positions are resolved against the progressively updated text rather than the original.
+    ///
+    /// A change for a document that was never opened is only accepted when it contains a
+    /// whole-document replacement; otherwise `None` is returned and the change is dropped.
+    pub fn document_did_change(
+        &self,
+        text_document: lsp_types::VersionedTextDocumentIdentifier,
+        content_changes: Vec<lsp_types::TextDocumentContentChangeEvent>,
+    ) -> LSPResult {
+        tracing::debug!("content changes: {:?}", content_changes);
+
+        // Everything before the last whole-document replacement is overwritten by it.
+        let full_replacement = content_changes
+            .iter()
+            .rposition(|change| change.range.is_none());
+
+        let doc_state = self
+            .documents
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&text_document.uri)
+            .map(Arc::clone);
+        let Some(doc_state) = doc_state else {
+            tracing::debug!("failed to find document: {:?}", text_document.uri);
+            // A whole-document replacement carries everything needed to start tracking.
+            // Anything after it in the batch is relative to that text, so apply it too.
+            let Some(full) = full_replacement else {
+                return Ok(None);
+            };
+            let config = self.config();
+            // The language is unknown without a didOpen, so only the path can exclude it.
+            if config.is_excluded(&text_document.uri, "") {
+                tracing::debug!("ignoring excluded {:?}", text_document.uri);
+                return Ok(None);
+            }
+            tracing::debug!(
+                "creating {:?} from whole-document change",
+                text_document.uri
+            );
+            // Looked up before taking the documents lock, as it may run git.
+            let marker_size = self.marker_size(&text_document.uri);
+            let mut document_state = DocumentState::new(
+                String::new(),
+                content_changes[full].text.clone(),
+                text_document.version,
+            )
+            .with_encoding(self.client.position_encoding)
+            .with_marker_size(marker_size)
+            .with_config(&config);
+            document_state.apply_changes(&content_changes[full + 1..], text_document.version)?;
+            self.documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(
+                    text_document.uri.clone(),
+                    Arc::new(Mutex::new(document_state)),
+                );
+            return Ok(Some((text_document.uri, text_document.version)));
+        };
+        let mut locked_doc_state = doc_state.lock().unwrap_or_else(PoisonError::into_inner);
+        if locked_doc_state.version() > text_document.version {
+            tracing::debug!(
+                "Version skew detected! {} v. {}",
+                locked_doc_state.version(),
+                text_document.version
+            );
+        }
+        let content_changes = match full_replacement {
+            Some(full) => {
+                tracing::debug!("replacing whole document");
+                &content_changes[full..]
+            }
+            None => {
+                tracing::debug!("applying changes");
+                &content_changes[..]
+            }
+        };
+        locked_doc_state.apply_changes(content_changes, text_document.version)?;
+        // Only hashed after whole-document replacements; small edits rarely undo themselves.
+        if full_replacement.is_some() && locked_doc_state.revalidate() {
+            tracing::debug!("{:?}: content unchanged", text_document.uri);
+        }
+        Ok(Some((text_document.uri.clone(), text_document.version)))
+    }
+
+    pub fn remove_document(&self, text_document: lsp_types::TextDocumentIdentifier) -> LSPResult {
+        let mut documents = self
+            .documents
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if documents.remove(&text_document.uri).is_some() {
+            tracing::debug!("Clearing {:?} from list of documents", text_document.uri);
+        }
+        self.git_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&text_document.uri);
+        Ok(None)
+    }
+
+    /// Summaries of every conflict currently known for the document. Clean or unknown documents have none.
+    pub fn list_conflicts(&self, uri: &lsp_types::Uri) -> anyhow::Result<Vec<ConflictSummary>> {
+        let names = self.side_names(uri);
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(Vec::new());
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(merge_conflict) = locked_document_state.merge_conflict.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let summaries = merge_conflict
+            .conflicts()
+            .map(|region| ConflictSummary {
+                range: range_for_diagnostic_conflict(region),
+                ours_name: names
+                    .ours
+                    .clone()
+                    .or_else(|| merge_conflict.head_name_for(region).map(String::from)),
+                theirs_name: names
+                    .theirs
+                    .clone()
+                    .or_else(|| merge_conflict.branch_name_for(region).map(String::from)),
+                has_ancestor: region.ancestor.is_some(),
+            })
+            .collect();
+        Ok(summaries)
+    }
+
+    /// Compute the edits resolving the conflict at `index` in `uri`.
+    ///
+    /// Returns the document version the edits apply to, or `None` if the document is not open
+    /// or has no such conflict (or, for [`Resolution::Ancestor`], the conflict has no ancestor).
+    pub fn resolve_conflict(
+        &self,
+        uri: &lsp_types::Uri,
+        index: usize,
+        choice: &Choice,
+    ) -> anyhow::Result<Option<(i32, Vec<lsp_types::TextEdit>)>> {
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(None);
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current_conflict = locked_document_state.current_conflicts()?;
+        let Some(region) = (*current_conflict).as_ref().and_then(|merge_conflict| {
+            merge_conflict
+                .conflicts()
+                .nth(index)
+                .filter(|region| !merge_conflict.has_nested(region))
+        }) else {
+            return Ok(None);
+        };
+        let content = locked_document_state.document.get_content(None);
+        let Some(edits) = resolution::choice_edits(content, region, choice) else {
+            return Ok(None);
+        };
+        Ok(Some((locked_document_state.version(), edits)))
+    }
+
+    /// Whether an edit sent to `uri` is still waiting for the client's answer.
+    fn edit_pending(&self, uri: &lsp_types::Uri) -> bool {
+        self.pending_requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .any(|pending| match pending {
+                PendingRequest::ApplyEdit {
+                    uri: pending_uri, ..
+                } => pending_uri == uri,
+                _ => false,
+            })
+    }
+
+    /// Compute the edits resolving every conflict in `uri` whose sides are identical.
+    ///
+    /// `None` when the document is not at `version`, has no such conflict, or an edit sent
+    /// to it is still waiting for the client's answer.
+    pub fn identical_sides_edits(
+        &self,
+        uri: &lsp_types::Uri,
+        version: i32,
+    ) -> anyhow::Result<Option<Vec<lsp_types::TextEdit>>> {
+        if self.edit_pending(uri) {
+            return Ok(None);
+        }
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(None);
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if locked_document_state.version() != version {
+            return Ok(None);
+        }
+        let current_conflict = locked_document_state.current_conflicts()?;
+        let Some(merge_conflict) = current_conflict.as_ref() else {
+            return Ok(None);
+        };
+        let content = locked_document_state.document.get_content(None);
+        let edits: Vec<_> = merge_conflict
+            .resolvable()
+            .filter(|region| region.sides_identical(content))
+            .filter_map(|region| resolution::edits(content, region, Resolution::Ours))
+            .flatten()
+            .collect();
+        Ok((!edits.is_empty()).then_some(edits))
+    }
+
+    /// Compute the edit replacing the conflict at `index` in `uri` with the selected lines.
+    ///
+    /// Each selection is a side and a 0-based line within that side; lines are emitted in the
+    /// order given. Returns `None` if the document is not open, there is no such conflict, or a
+    /// selection names a missing side or a line past the end of its side.
+    pub fn resolve_lines(
+        &self,
+        uri: &lsp_types::Uri,
+        index: usize,
+        selections: &[(Side, usize)],
+    ) -> anyhow::Result<Option<lsp_types::TextEdit>> {
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(None);
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current_conflict = locked_document_state.current_conflicts()?;
+        let Some(region) = (*current_conflict).as_ref().and_then(|merge_conflict| {
+            merge_conflict
+                .conflicts()
+                .nth(index)
+                .filter(|region| !merge_conflict.has_nested(region))
+        }) else {
+            return Ok(None);
+        };
+        let mut new_text = String::new();
+        for (side, line) in selections {
+            let content = locked_document_state.document.get_content(None);
+            let Some(side_text) = side.text(region, content) else {
+                tracing::debug!("conflict {index} has no {side:?} side");
+                return Ok(None);
+            };
+            let Some(text) = nth_line(side_text, *line) else {
+                tracing::debug!("line {line} is outside the {side:?} side of conflict {index}");
+                return Ok(None);
+            };
+            new_text.push_str(text);
+        }
+        Ok(Some(lsp_types::TextEdit {
+            range: range_for_conflict_edit(
+                locked_document_state.document.get_content(None),
+                region,
+            ),
+            new_text,
+        }))
+    }
+
+    /// The text of `uri` once `edits`, computed against `version`, are applied, and the
+    /// conflicts left in it.
+    ///
+    /// Used after the client accepts a `workspace/applyEdit` so diagnostics can be refreshed
+    /// before its `didChange` arrives. The tracked document is not modified: the client's
+    /// `didChange` will carry the same edit, and applying it twice would corrupt the text.
+    /// Returns `None` when the document is gone or has already moved past `version`, in which
+    /// case the regular update worker publishes.
+    pub fn conflicts_after_edit(
+        &self,
+        uri: &lsp_types::Uri,
+        version: i32,
+        edits: &[lsp_types::TextEdit],
+    ) -> anyhow::Result<Option<(String, Option<MergeConflict>)>> {
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(None);
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if locked_document_state.version() != version {
+            tracing::debug!(
+                "{:?} already at version {}, edit was synced",
+                uri,
+                locked_document_state.version()
+            );
+            return Ok(None);
+        }
+        let mut edited = DocumentState::new(
+            locked_document_state.language_id().to_owned(),
+            locked_document_state.document.get_content(None).to_owned(),
+            version,
+        )
+        .with_encoding(locked_document_state.encoding)
+        .with_marker_size(locked_document_state.marker_size)
+        .with_config(&self.config());
+        // Edits in one batch all refer to the original text. Applying them back to front keeps
+        // the earlier ranges valid.
+        let mut edits = edits.to_vec();
+        edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+        let changes: Vec<_> = edits
+            .into_iter()
+            .map(|edit| lsp_types::TextDocumentContentChangeEvent {
+                range: Some(edit.range),
+                range_length: None,
+                text: edit.new_text,
+            })
+            .collect();
+        edited.apply_changes(&changes, version)?;
+        let merge_conflict = edited.parse()?;
+        Ok(Some((
+            edited.document.get_content(None).to_owned(),
+            merge_conflict,
+        )))
+    }
+
+    /// Whether diagnostics are published to the client rather than pulled by it.
+    pub fn push_diagnostics(&self) -> bool {
+        !self.feature_enabled(
+            <lsp_types::request::DocumentDiagnosticRequest as lsp_types::request::Request>::METHOD,
+        )
+    }
+
+    /// Diagnostics for the current content of `uri` and the version they describe.
+    ///
+    /// Returns `None` if the document is not open.
+    pub fn document_diagnostics(
+        &self,
+        uri: &lsp_types::Uri,
+    ) -> anyhow::Result<Option<(i32, Vec<lsp_types::Diagnostic>)>> {
+        let commits = self.conflict_commits(uri, |_| true)?;
+        let names = self.side_names(uri);
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(None);
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let diagnostics = match locked_document_state.diagnosed_conflicts() {
+            Ok(current_conflict) => match current_conflict.as_ref() {
+                Some(merge_conflict) => {
+                    let config = self.config();
+                    let mut diagnostics = merge_conflict.diagnostics(
+                        locked_document_state.document.get_content(None),
+                        &config.diagnostic_source,
+                        config.diagnostic_severity.into(),
+                        &names,
+                        &config.labels,
+                    );
+                    add_commit_information(&mut diagnostics, uri, &commits);
+                    diagnostics
+                }
+                None => Vec::new(),
+            },
+            Err(e) => {
+                tracing::debug!("reparse failed, reporting no diagnostics: {e}");
+                Vec::new()
+            }
+        };
+        let diagnostics = self
+            .file_conflict_diagnostic(uri)?
+            .into_iter()
+            .chain(diagnostics)
+            .collect();
+        Ok(Some((locked_document_state.version(), diagnostics)))
+    }
+
+    /// The file conflict the repository has over `uri`, if any.
+    fn file_conflict(&self, uri: &lsp_types::Uri) -> anyhow::Result<Option<FileConflict>> {
+        Ok(self
+            .file_conflicts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(uri)
+            .copied())
+    }
+
+    /// The whole-file diagnostic of `uri` when the repository has a file conflict over it.
+    pub fn file_conflict_diagnostic(
+        &self,
+        uri: &lsp_types::Uri,
+    ) -> anyhow::Result<Option<lsp_types::Diagnostic>> {
+        let Some(conflict) = self.file_conflict(uri)? else {
+            return Ok(None);
+        };
+        let config = self.config();
+        Ok(Some(lsp_types::Diagnostic {
+            message: format!("merge conflict: {}", conflict.description()),
+            source: Some(config.diagnostic_source.clone()),
+            severity: Some(config.diagnostic_severity.into()),
+            ..Default::default()
+        }))
+    }
+
+    /// The actions for a file the repository has a file conflict over.
+    ///
+    /// A deleted file can be kept or, when the client can delete files, deleted, and either is
+    /// staged with [`STAGE_FILE_COMMAND`] afterwards. A binary file takes one side's version with [`TAKE_VERSION_COMMAND`].
+    fn file_conflict_actions(
+        &self,
+        uri: &lsp_types::Uri,
+    ) -> anyhow::Result<Vec<lsp_types::CodeAction>> {
+        let (Some(conflict), Some(diagnostic)) = (
+            self.file_conflict(uri)?,
+            self.file_conflict_diagnostic(uri)?,
+        ) else {
+            return Ok(Vec::new());
+        };
+        if conflict == FileConflict::Binary {
+            return Ok([("ours", Side::Ours), ("theirs", Side::Theirs)]
+                .into_iter()
+                .map(|(name, side)| lsp_types::CodeAction {
+                    title: format!("Take {name}"),
+                    kind: Some(lsp_types::CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    command: Some(lsp_types::Command {
+                        title: format!("Take {name}"),
+                        command: TAKE_VERSION_COMMAND.to_owned(),
+                        arguments: Some(vec![serde_json::json!(uri), serde_json::json!(side)]),
+                    }),
+                    ..Default::default()
+                })
+                .collect());
+        }
+        let stage = |title: &str| lsp_types::Command {
+            title: title.to_owned(),
+            command: STAGE_FILE_COMMAND.to_owned(),
+            arguments: Some(vec![serde_json::json!(uri)]),
+        };
+        // The work tree has the modified version already, keeping it only needs staging.
+        let keep = lsp_types::CodeAction {
+            title: "Keep file".to_owned(),
+            kind: Some(lsp_types::CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            command: Some(stage("Stage the kept file")),
+            ..Default::default()
+        };
+        if !self.client.delete_files {
+            return Ok(vec![keep]);
+        }
+        let delete = lsp_types::WorkspaceEdit {
+            document_changes: Some(lsp_types::DocumentChanges::Operations(vec![
+                lsp_types::DocumentChangeOperation::Op(lsp_types::ResourceOp::Delete(
+                    lsp_types::DeleteFile {
+                        uri: uri.clone(),
+                        options: None,
+                    },
+                )),
+            ])),
+            ..Default::default()
+        };
+        Ok(vec![
+            keep,
+            lsp_types::CodeAction {
+                title: "Delete file".to_owned(),
+                kind: Some(lsp_types::CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic]),
+                edit: Some(delete),
+                command: Some(stage("Stage the deletion")),
+                ..Default::default()
+            },
+        ])
+    }
+
+    /// Full diagnostic reports for every tracked document, and every file with a file
+    /// conflict whether open or not, ordered by URI.
+    pub fn workspace_diagnostics(
+        &self,
+    ) -> anyhow::Result<Vec<lsp_types::WorkspaceDocumentDiagnosticReport>> {
+        let mut uris: Vec<lsp_types::Uri> = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            documents.keys().cloned().collect()
+        };
+        uris.extend(
+            self.file_conflicts
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .keys()
+                .cloned(),
+        );
+        uris.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        uris.dedup();
+
+        let mut reports = Vec::with_capacity(uris.len());
+        for uri in uris {
+            // The document may have been closed since the snapshot.
+            let (version, items) = match self.document_diagnostics(&uri)? {
+                Some((version, items)) => (Some(version.into()), items),
+                None => match self.file_conflict_diagnostic(&uri)? {
+                    Some(diagnostic) => (None, vec![diagnostic]),
+                    None => continue,
+                },
+            };
+            reports.push(lsp_types::WorkspaceDocumentDiagnosticReport::Full(
+                lsp_types::WorkspaceFullDocumentDiagnosticReport {
+                    uri,
+                    version,
+                    full_document_diagnostic_report: lsp_types::FullDocumentDiagnosticReport {
+                        result_id: None,
+                        items,
+                    },
+                },
+            ));
+        }
+        Ok(reports)
+    }
+
+    /// Semantic tokens for every conflict line in `uri`, optionally limited to the lines `range`
+    /// touches.
+    pub fn semantic_tokens(
+        &self,
+        uri: &lsp_types::Uri,
+        range: Option<lsp_types::Range>,
+    ) -> anyhow::Result<Vec<lsp_types::SemanticToken>> {
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(Vec::new());
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current_conflict = match locked_document_state.current_conflicts() {
+            Ok(current_conflict) => current_conflict,
+            Err(e) => {
+                tracing::debug!("reparse failed, returning no tokens: {e}");
+                return Ok(Vec::new());
+            }
+        };
+        let Some(merge_conflict) = current_conflict.as_ref() else {
+            return Ok(Vec::new());
+        };
+        Ok(conflict_semantic_tokens(
+            locked_document_state.document.get_content(None),
+            merge_conflict,
+            range,
+            locked_document_state.encoding,
+        ))
+    }
+
+    pub fn code_lens(&self, uri: &lsp_types::Uri) -> anyhow::Result<Vec<lsp_types::CodeLens>> {
+        let names = self.side_names(uri);
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(Vec::new());
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current_conflict = match locked_document_state.current_conflicts() {
+            Ok(current_conflict) => current_conflict,
+            Err(e) => {
+                tracing::debug!("reparse failed, offering no lenses: {e}");
+                return Ok(Vec::new());
+            }
+        };
+        let Some(merge_conflict) = current_conflict.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let labels = &self.config().labels;
+        let lenses = merge_conflict
+            .conflicts()
+            .enumerate()
+            .filter(|(_, region)| !merge_conflict.has_nested(region))
+            .flat_map(|(index, region)| conflict_as_code_lenses(index, region, uri, &names, labels))
+            .collect();
+        Ok(lenses)
+    }
+
+    /// One selection range per position: the content of the side under the cursor, whose
+    /// parent is the whole conflict. Positions outside any conflict select nothing.
+    pub fn selection_ranges(
+        &self,
+        uri: &lsp_types::Uri,
+        positions: &[lsp_types::Position],
+    ) -> anyhow::Result<Vec<lsp_types::SelectionRange>> {
+        let empty_ranges = || {
+            positions
+                .iter()
+                .map(|position| empty_selection_range(*position))
+                .collect()
+        };
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(empty_ranges());
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current_conflict = match locked_document_state.current_conflicts() {
+            Ok(current_conflict) => current_conflict,
+            Err(e) => {
+                tracing::debug!("reparse failed, selecting nothing: {e}");
+                return Ok(empty_ranges());
+            }
+        };
+        let Some(merge_conflict) = current_conflict.as_ref() else {
+            return Ok(empty_ranges());
+        };
+        Ok(positions
+            .iter()
+            .map(|position| {
+                merge_conflict
+                    .conflicts()
+                    .find(|region| (region.head..=region.end).contains(&position.line))
+                    .map_or_else(
+                        || empty_selection_range(*position),
+                        |region| conflict_selection_range(region, *position),
+                    )
+            })
+            .collect())
+    }
+
+    /// The commits that last changed each side of the conflicts in `uri` that `wanted` picks,
+    /// in document order.
+    ///
+    /// Empty unless git integration is enabled and the document's repository is in the middle
+    /// of a merge, rebase, cherry-pick or revert.
+    pub fn conflict_commits(
+        &self,
+        uri: &lsp_types::Uri,
+        wanted: impl Fn(&ConflictRegion) -> bool,
+    ) -> anyhow::Result<Vec<ConflictCommits>> {
+        let config = self.config();
+        if !config.git.enabled {
+            return Ok(Vec::new());
+        }
+        let Some(path) = config_file::uri_to_path(uri) else {
+            return Ok(Vec::new());
+        };
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(Vec::new());
+            };
+            Arc::clone(document_state)
+        };
+        // Copied out so git runs without holding the document.
+        let (content, merge_conflict) = {
+            let locked_document_state = document_state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Ok(Some(merge_conflict)) = locked_document_state
+                .current_conflicts()
+                .map(Cow::into_owned)
+            else {
+                return Ok(Vec::new());
+            };
+            (
+                locked_document_state.document.get_content(None).to_owned(),
+                merge_conflict,
+            )
+        };
+        let mut cache = self.git_cache(uri, &path);
+        let GitCache {
+            repository: Some(repository),
+            last_commits,
+            commits,
+            rebase_onto,
+            ..
+        } = &mut cache
+        else {
+            return Ok(Vec::new());
+        };
+        let Some(operation) = repository.operation() else {
+            tracing::debug!("{}: no operation in progress", path.display());
+            return Ok(Vec::new());
+        };
+        let labels = &config.labels;
+        let names = rebase_names(repository, commits, rebase_onto);
+        let mut side = |name: &str, revision: &str, (start, end): (u32, u32)| {
+            let text = text_between(&content, (start, end));
+            let commit = last_commits
+                .entry((revision.to_owned(), text.to_owned()))
+                .or_insert_with(|| {
+                    repository
+                        .last_commit(revision, &path, text)
+                        .inspect_err(|e| tracing::warn!("git: {e:#}"))
+                        .ok()
+                        .flatten()
+                })
+                .clone();
+            SideCommit {
+                name: name.to_owned(),
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: start + 1,
+                        character: 0,
+                    },
+                    end: lsp_types::Position {
+                        line: end,
+                        character: 0,
+                    },
+                },
+                commit,
+            }
+        };
+        let commits = merge_conflict
+            .conflicts()
+            .filter(|region| wanted(region))
+            .map(|region| {
+                let ours_name = merge_conflict
+                    .ours_name(region, &names, labels)
+                    .unwrap_or("HEAD");
+                let theirs_name = merge_conflict
+                    .theirs_name(region, &names, labels)
+                    .unwrap_or("branch");
+                ConflictCommits {
+                    region: region.clone(),
+                    ours: side(ours_name, "HEAD", region.head_range()),
+                    theirs: side(
+                        theirs_name,
+                        operation.theirs_revision(),
+                        region.branch_range(),
+                    ),
+                }
+            })
+            .collect();
+        self.git_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(uri.clone(), cache);
+        Ok(commits)
+    }
+
+    /// What git found for `uri` at `path` so far, emptied when the repository's heads moved
+    /// since. Only looks for the repository the first time.
+    fn git_cache(&self, uri: &lsp_types::Uri, path: &Path) -> GitCache {
+        let cached = self
+            .git_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(uri)
+            .cloned();
+        let repository = match &cached {
+            Some(cache) => cache.repository.clone(),
+            None => git::Repository::discover(path),
+        };
+        let heads = repository
+            .as_ref()
+            .map(git::Repository::heads)
+            .unwrap_or_default();
+        match cached {
+            Some(cache) if cache.heads == heads => cache,
+            _ => GitCache {
+                repository,
+                heads,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// What git knows about the sides of the conflicts in `uri`, for action titles. Empty
+    /// unless git integration is enabled; names that are not revisions get no commit.
+    ///
+    /// Runs git without holding the document, and only for what the repository's cache lacks.
+    fn git_sides(&self, uri: &lsp_types::Uri) -> GitSides {
+        if !self.config().git.enabled {
+            return GitSides::default();
+        }
+        let Some(path) = config_file::uri_to_path(uri) else {
+            return GitSides::default();
+        };
+        let Some((content, merge_conflict)) = self
+            .with_document(uri, |document_state| {
+                let merge_conflict = document_state.current_conflicts().ok()?.into_owned()?;
+                Some((
+                    document_state.document.get_content(None).to_owned(),
+                    merge_conflict,
+                ))
+            })
+            .flatten()
+        else {
+            return GitSides::default();
+        };
+        let mut cache = self.git_cache(uri, &path);
+        let GitCache {
+            repository: Some(repository),
+            commits,
+            submodule_commits,
+            rebase_onto,
+            ..
+        } = &mut cache
+        else {
+            return GitSides::default();
+        };
+        let shas: HashSet<&str> = merge_conflict
+            .conflicts()
+            .filter_map(|region| region.submodule_commits(&content))
+            .flat_map(|(ours, theirs)| [ours, theirs])
+            .collect();
+        let mut sides = GitSides {
+            submodule_commits: shas
+                .into_iter()
+                .filter_map(|sha| {
+                    let commit = submodule_commits
+                        .entry(sha.to_owned())
+                        .or_insert_with(|| {
+                            repository
+                                .submodule_commit(sha)
+                                .inspect_err(|e| tracing::warn!("git: {e:#}"))
+                                .ok()
+                                .flatten()
+                        })
+                        .clone()?;
+                    Some((sha.to_owned(), commit))
+                })
+                .collect(),
+            names: rebase_names(repository, commits, rebase_onto),
+            ..Default::default()
+        };
+        // The marker names of a rebase are replaced, so their commits are not shown.
+        if sides.names == SideNames::default() {
+            let names: HashSet<&str> = merge_conflict
+                .conflicts()
+                .flat_map(|region| {
+                    [
+                        merge_conflict.head_name_for(region),
+                        merge_conflict.branch_name_for(region),
+                    ]
+                })
+                .flatten()
+                .collect();
+            sides.commits = names
+                .into_iter()
+                .filter_map(|name| Some((name.to_owned(), commit(repository, commits, name)?)))
+                .collect();
+        }
+        self.git_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(uri.clone(), cache);
+        sides
+    }
+
+    /// Names replacing the marker names of the conflicts in `uri` while its repository is in
+    /// the middle of a rebase. Empty otherwise, or unless git integration is enabled.
+    pub fn side_names(&self, uri: &lsp_types::Uri) -> SideNames {
+        if !self.config().git.enabled {
+            return SideNames::default();
+        }
+        let Some(path) = config_file::uri_to_path(uri) else {
+            return SideNames::default();
+        };
+        let mut cache = self.git_cache(uri, &path);
+        let GitCache {
+            repository: Some(repository),
+            commits,
+            rebase_onto,
+            ..
+        } = &mut cache
+        else {
+            return SideNames::default();
+        };
+        let names = rebase_names(repository, commits, rebase_onto);
+        self.git_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(uri.clone(), cache);
+        names
+    }
+
+    /// A hover on a conflict naming the commits that last changed each of its sides, when git
+    /// integration is enabled.
+    pub fn hover(
+        &self,
+        uri: &lsp_types::Uri,
+        position: lsp_types::Position,
+    ) -> anyhow::Result<Option<lsp_types::Hover>> {
+        let commits = self.conflict_commits(uri, |region| {
+            region.head <= position.line && position.line <= region.end
+        })?;
+        let Some(conflict) = commits.into_iter().next() else {
+            return Ok(None);
+        };
+        let value = [&conflict.ours, &conflict.theirs]
+            .into_iter()
+            .map(|side| match &side.commit {
+                Some(commit) => format!(
+                    "**{}**: `{}` {} ({}, {})",
+                    side.name, commit.short_sha, commit.subject, commit.author, commit.date
+                ),
+                None => format!("**{}**: no commit found", side.name),
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Ok(Some(lsp_types::Hover {
+            contents: lsp_types::HoverContents::Markup(lsp_types::MarkupContent {
+                kind: lsp_types::MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range_for_diagnostic_conflict(&conflict.region)),
+        }))
+    }
+
+    /// When `position` is on a conflict marker, highlight every marker of that conflict.
+    pub fn document_highlights(
+        &self,
+        uri: &lsp_types::Uri,
+        position: lsp_types::Position,
+    ) -> anyhow::Result<Vec<lsp_types::DocumentHighlight>> {
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(uri) else {
+                tracing::debug!("{:?} not found", uri);
+                return Ok(Vec::new());
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current_conflict = match locked_document_state.current_conflicts() {
+            Ok(current_conflict) => current_conflict,
+            Err(e) => {
+                tracing::debug!("reparse failed, highlighting nothing: {e}");
+                return Ok(Vec::new());
+            }
+        };
+        let Some(merge_conflict) = current_conflict.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let Some(region) = merge_conflict
+            .conflicts()
+            .find(|region| conflict_marker_lines(region).any(|line| line == position.line))
+        else {
+            return Ok(Vec::new());
+        };
+        Ok(conflict_marker_highlights(
+            locked_document_state.document.get_content(None),
+            region,
+            locked_document_state.encoding,
+        ))
+    }
+
+    pub fn code_action(
+        &self,
+        params: lsp_types::CodeActionParams,
+    ) -> anyhow::Result<Vec<lsp_types::CodeAction>> {
+        let mut actions = self.conflict_code_actions(&params)?;
+        // After the numbered conflict actions, which a resolve rebuilds. These have their edits.
+        actions.extend(self.file_conflict_actions(&params.text_document.uri)?);
+        // Filter after numbering the actions so a resolve rebuilds the same list.
+        if let Some(only) = &params.context.only {
+            actions.retain(|action| action_kind_matches(action, only));
+        }
+        Ok(actions)
+    }
+
+    /// The actions for the conflicts `params` picks, unfiltered.
+    fn conflict_code_actions(
+        &self,
+        params: &lsp_types::CodeActionParams,
+    ) -> anyhow::Result<Vec<lsp_types::CodeAction>> {
+        // Before locking the document for the actions: it may run git.
+        let sides = self.git_sides(&params.text_document.uri);
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(&params.text_document.uri) else {
+                tracing::debug!("{:?} not found", params.text_document.uri);
+                return Ok(Vec::new());
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let current_conflict = match locked_document_state.current_conflicts() {
+            Ok(current_conflict) => current_conflict,
+            Err(e) => {
+                tracing::debug!("reparse failed, offering no actions: {e}");
+                return Ok(Vec::new());
+            }
+        };
+        let Some(merge_conflict) = current_conflict.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let config = self.config();
+        // The diagnostics the client says the request is for pick the conflict when they are
+        // ours; some clients send ranges that only touch the conflict's last line.
+        let mut indexes: Vec<usize> = merge_conflict
+            .conflicts()
+            .enumerate()
+            .filter(|(_, region)| {
+                params.context.diagnostics.iter().any(|diagnostic| {
+                    diagnostic.source.as_deref() == Some(config.diagnostic_source.as_str())
+                        && range_for_diagnostic_conflict(region) == diagnostic.range
+                })
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if indexes.is_empty() {
+            // The innermost conflict wins when they are nested.
+            indexes = match merge_conflict
+                .conflicts()
+                .enumerate()
+                .filter(|(_, conflict)| conflict.is_in_range(&params.range))
+                .last()
+            {
+                Some((index, _)) => vec![index],
+                // A selection spanning several conflicts gets the actions for each of them.
+                // Anywhere else only the document-wide fix is offered.
+                None => merge_conflict
+                    .conflicts()
+                    .enumerate()
+                    .filter(|(_, region)| touches_conflict(&params.range, region))
+                    .map(|(index, _)| index)
+                    .collect(),
+            };
+        }
+        let mut actions = conflicts_as_code_actions(
+            &indexes,
+            &params.range,
+            &params.text_document.uri,
+            &locked_document_state.document,
+            merge_conflict,
+            &sides,
+            &config,
+        );
+        if self.client.resolve_code_action_edits {
+            // Leave the edits to codeAction/resolve; only the chosen action needs one.
+            for (position, action) in actions.iter_mut().enumerate() {
+                let data = CodeActionData {
+                    uri: params.text_document.uri.clone(),
+                    version: locked_document_state.version(),
+                    conflicts: indexes.clone(),
+                    range: params.range,
+                    action: position,
+                };
+                action.edit = None;
+                action.data = Some(serde_json::to_value(data)?);
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Fill in the edit of an action offered without one.
+    ///
+    /// Returns `None` when the document changed since the action was offered, or the action no
+    /// longer exists.
+    pub fn resolve_code_action(
+        &self,
+        mut action: lsp_types::CodeAction,
+        data: CodeActionData,
+    ) -> anyhow::Result<Option<lsp_types::CodeAction>> {
+        let document_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(document_state) = documents.get(&data.uri) else {
+                tracing::debug!("{:?} not found", data.uri);
+                return Ok(None);
+            };
+            Arc::clone(document_state)
+        };
+
+        let locked_document_state = document_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if locked_document_state.version() != data.version {
+            tracing::debug!(
+                "{:?} changed from version {} to {}",
+                data.uri,
+                data.version,
+                locked_document_state.version()
+            );
+            return Ok(None);
+        }
+        let current_conflict = locked_document_state.current_conflicts()?;
+        let Some(merge_conflict) = current_conflict.as_ref() else {
+            return Ok(None);
+        };
+        if data
+            .conflicts
+            .iter()
+            .any(|index| *index >= merge_conflict.conflicts.len())
+        {
+            return Ok(None);
+        }
+        // Only the edit is needed, the title was sent with the action.
+        let Some(resolved) = conflicts_as_code_actions(
+            &data.conflicts,
+            &data.range,
+            &data.uri,
+            &locked_document_state.document,
+            merge_conflict,
+            &GitSides::default(),
+            &self.config(),
+        )
+        .into_iter()
+        .nth(data.action) else {
+            return Ok(None);
+        };
+        action.edit = resolved.edit;
+        Ok(Some(action))
+    }
+
+    pub fn on_document_update(
+        &self,
+        uri: &lsp_types::Uri,
+        version: i32,
+    ) -> anyhow::Result<Option<MergeConflict>> {
+        let doc_state = {
+            let documents = self
+                .documents
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(doc_state) = documents.get(uri) else {
+                tracing::debug!("No entry to {uri:?}");
+                return Ok(None);
+            };
+            Arc::clone(doc_state)
+        };
+
+        let mut locked_doc_state = doc_state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if version >= locked_doc_state.version() {
+            // Update version via a no-op change to keep FullTextDocument in sync.
+            locked_doc_state.document.update(&[], version);
+        } else {
+            tracing::debug!("Missed update, skipping.");
+            return Ok(None);
+        }
+
+        let _span = tracing::debug_span!("parse", ?uri).entered();
+        locked_doc_state.process_update()
+    }
+}
+
+/// LSP methods served for the enabled features.
+fn enabled_methods(features: &Features) -> Vec<&'static str> {
+    let mut methods = Vec::new();
+    if features.code_action {
+        methods
+            .push(<lsp_types::request::CodeActionRequest as lsp_types::request::Request>::METHOD);
+    }
+    if features.code_lens {
+        methods.push(<lsp_types::request::CodeLensRequest as lsp_types::request::Request>::METHOD);
+    }
+    if features.semantic_tokens {
+        methods.push(SEMANTIC_TOKENS_METHOD);
+    }
+    if features.pull_diagnostics {
+        methods.push(
+            <lsp_types::request::DocumentDiagnosticRequest as lsp_types::request::Request>::METHOD,
+        );
+    }
+    methods
+}
+
+fn registration_options(method: &str) -> Option<serde_json::Value> {
+    // The registration options are the provider options plus a document selector.
+    // A null selector means the client's own document selector applies.
+    let mut options = match method {
+        <lsp_types::request::CodeActionRequest as lsp_types::request::Request>::METHOD => {
+            serde_json::to_value(code_action_options()).ok()?
+        }
+        <lsp_types::request::CodeLensRequest as lsp_types::request::Request>::METHOD => {
+            serde_json::to_value(code_lens_options()).ok()?
+        }
+        SEMANTIC_TOKENS_METHOD => serde_json::to_value(semantic_tokens_options()).ok()?,
+        <lsp_types::request::DocumentDiagnosticRequest as lsp_types::request::Request>::METHOD => {
+            serde_json::to_value(diagnostic_options()).ok()?
+        }
+        _ => return None,
+    };
+    options["documentSelector"] = serde_json::Value::Null;
+    Some(options)
+}
+
+pub fn code_action_options() -> lsp_types::CodeActionOptions {
+    lsp_types::CodeActionOptions {
+        code_action_kinds: Some(vec![
+            lsp_types::CodeActionKind::QUICKFIX,
+            lsp_types::CodeActionKind::new(FIX_ALL_KIND),
+        ]),
+        resolve_provider: Some(true),
+        ..Default::default()
+    }
+}
+
+/// Actions for the conflicts at `indexes`: the single-conflict actions for one index, the
+/// combined actions for several. When the document has other conflicts as well, actions
+/// resolving all of them follow. The `defaultStrategy` fix for the whole document comes last,
+/// also when `indexes` is empty.
+///
+/// `range` is the client's selection; picking lines inside a single conflict adds an action
+/// keeping only those. Conflicts with others nested in them get no actions until those are
+/// resolved.
+fn conflicts_as_code_actions(
+    indexes: &[usize],
+    range: &lsp_types::Range,
+    uri: &lsp_types::Uri,
+    document: &FullTextDocument,
+    merge_conflict: &MergeConflict,
+    sides: &GitSides,
+    config: &Config,
+) -> Vec<lsp_types::CodeAction> {
+    let regions: Vec<&ConflictRegion> = indexes
+        .iter()
+        .filter_map(|index| merge_conflict.conflicts.get(*index))
+        .filter(|region| !merge_conflict.has_nested(region))
+        .collect();
+    let mut items = match regions.as_slice() {
+        [] => Vec::new(),
+        [region] => selected_lines_code_action(
+            region,
+            range,
+            uri,
+            document,
+            merge_conflict,
+            &sides.names,
+            config,
+        )
+        .into_iter()
+        .chain(conflict_as_code_actions(
+            region,
+            uri,
+            document,
+            merge_conflict,
+            sides,
+            config,
+        ))
+        .collect(),
+        regions => selected_conflicts_as_code_actions(
+            regions,
+            uri,
+            document,
+            merge_conflict,
+            sides,
+            config,
+        ),
+    };
+    if !regions.is_empty() && merge_conflict.resolvable().count() > regions.len() {
+        items.extend(file_conflicts_as_code_actions(
+            uri,
+            document.get_content(None),
+            merge_conflict,
+            &sides.names,
+            config,
+        ));
+    }
+    items.extend(fix_all_code_action(
+        uri,
+        document.get_content(None),
+        merge_conflict,
+        &sides.names,
+        config,
+    ));
+    items
+}
+
+pub fn code_lens_options() -> lsp_types::CodeLensOptions {
+    lsp_types::CodeLensOptions {
+        resolve_provider: Some(false),
+    }
+}
+
+pub fn diagnostic_options() -> lsp_types::DiagnosticOptions {
+    lsp_types::DiagnosticOptions {
+        inter_file_dependencies: false,
+        workspace_diagnostics: true,
+        ..Default::default()
+    }
+}
+
+pub fn semantic_tokens_options() -> lsp_types::SemanticTokensOptions {
+    lsp_types::SemanticTokensOptions {
+        legend: lsp_types::SemanticTokensLegend {
+            token_types: ConflictTokenType::ALL
+                .iter()
+                .map(|token_type| lsp_types::SemanticTokenType::new(token_type.name()))
+                .collect(),
+            token_modifiers: Vec::new(),
+        },
+        range: Some(true),
+        full: Some(lsp_types::SemanticTokensFullOptions::Bool(true)),
+        ..Default::default()
+    }
+}
+
+/// Classify each line of `region`: the marker lines and the content of each side.
+///
+/// Lines inside no side's range are markers, whichever order the sides come in.
+fn conflict_line_types(region: &ConflictRegion) -> impl Iterator<Item = (u32, ConflictTokenType)> {
+    let inside = |(start, end): (u32, u32), line: u32| start < line && line < end;
+    (region.head..=region.end).map(move |line| {
+        let token_type = if inside(region.head_range(), line) {
+            ConflictTokenType::Ours
+        } else if inside(region.branch_range(), line) {
+            ConflictTokenType::Theirs
+        } else if region
+            .ancestor_range()
+            .is_some_and(|range| inside(range, line))
+        {
+            ConflictTokenType::Ancestor
+        } else {
+            ConflictTokenType::Marker
+        };
+        (line, token_type)
+    })
+}
+
+fn conflict_marker_lines(region: &ConflictRegion) -> impl Iterator<Item = u32> {
+    conflict_line_types(region)
+        .filter(|(_, token_type)| *token_type == ConflictTokenType::Marker)
+        .map(|(line, _)| line)
+}
+
+/// One highlight per marker line of `region`, covering the marker and its label.
+fn conflict_marker_highlights(
+    content: &str,
+    region: &ConflictRegion,
+    encoding: PositionEncoding,
+) -> Vec<lsp_types::DocumentHighlight> {
+    let lines: Vec<&str> = lsp_lines(content).map(|(_, line)| line).collect();
+    conflict_marker_lines(region)
+        .map(|line| {
+            let length = lines
+                .get(line as usize)
+                .map_or(0, |text| encoding.len(text) as u32);
+            lsp_types::DocumentHighlight {
+                range: lsp_types::Range {
+                    start: lsp_types::Position { line, character: 0 },
+                    end: lsp_types::Position {
+                        line,
+                        character: length,
+                    },
+                },
+                kind: Some(lsp_types::DocumentHighlightKind::TEXT),
+            }
+        })
+        .collect()
+}
+
+/// Encode one whole-line token per non-empty conflict line, relative to the previous token as
+/// the protocol requires.
+fn conflict_semantic_tokens(
+    content: &str,
+    merge_conflict: &MergeConflict,
+    range: Option<lsp_types::Range>,
+    encoding: PositionEncoding,
+) -> Vec<lsp_types::SemanticToken> {
+    let lines: Vec<&str> = lsp_lines(content).map(|(_, line)| line).collect();
+    let mut tokens = Vec::new();
+    let mut previous_line = 0;
+    for (line, token_type) in merge_conflict.conflicts().flat_map(conflict_line_types) {
+        if range.is_some_and(|range| line < range.start.line || line > range.end.line) {
+            continue;
+        }
+        let length = lines
+            .get(line as usize)
+            .map_or(0, |text| encoding.len(text) as u32);
+        if length == 0 {
+            continue;
+        }
+        tokens.push(lsp_types::SemanticToken {
+            delta_line: line - previous_line,
+            delta_start: 0,
+            length,
+            token_type: token_type as u32,
+            token_modifiers_bitset: 0,
+        });
+        previous_line = line;
+    }
+    tokens
+}
+
+/// "Accept Ours | Accept Theirs | Accept Both" lenses shown above a conflict's opening marker.
+/// `names`, during a rebase, or else configured labels replace "Ours" and "Theirs".
+///
+/// Each runs the resolve command, which computes the same edit as the matching code action.
+fn conflict_as_code_lenses(
+    index: usize,
+    region: &ConflictRegion,
+    uri: &lsp_types::Uri,
+    names: &SideNames,
+    labels: &SideLabels,
+) -> Vec<lsp_types::CodeLens> {
+    let position = lsp_types::Position {
+        line: region.head,
+        character: 0,
+    };
+    let range = lsp_types::Range {
+        start: position,
+        end: position,
+    };
+    [
+        (
+            names
+                .ours
+                .as_deref()
+                .or(labels.ours.as_deref())
+                .unwrap_or("Ours"),
+            Resolution::Ours,
+        ),
+        (
+            names
+                .theirs
+                .as_deref()
+                .or(labels.theirs.as_deref())
+                .unwrap_or("Theirs"),
+            Resolution::Theirs,
+        ),
+        ("Both", Resolution::Both),
+    ]
+    .into_iter()
+    .map(|(side, resolution)| lsp_types::CodeLens {
+        range,
+        command: Some(lsp_types::Command {
+            title: format!("Accept {side}"),
+            command: RESOLVE_COMMAND.to_owned(),
+            arguments: Some(vec![
+                serde_json::json!(uri),
+                serde_json::json!(index),
+                serde_json::json!(resolution),
+            ]),
+        }),
+        data: None,
+    })
+    .collect()
+}
+
+fn empty_selection_range(position: lsp_types::Position) -> lsp_types::SelectionRange {
+    lsp_types::SelectionRange {
+        range: lsp_types::Range {
+            start: position,
+            end: position,
+        },
+        parent: None,
+    }
+}
+
+/// Selection for a position inside `region`: the lines of the side it is in, then the whole
+/// conflict. Marker lines and empty sides go straight to the whole conflict.
+fn conflict_selection_range(
+    region: &ConflictRegion,
+    position: lsp_types::Position,
+) -> lsp_types::SelectionRange {
+    let conflict = lsp_types::SelectionRange {
+        range: range_for_diagnostic_conflict(region),
+        parent: None,
+    };
+    let side = [
+        Some(region.head_range()),
+        region.ancestor_range(),
+        Some(region.branch_range()),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|(start, end)| *start < position.line && position.line < *end);
+    match side {
+        Some((start, end)) => lsp_types::SelectionRange {
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: start + 1,
+                    character: 0,
+                },
+                end: lsp_types::Position {
+                    line: end,
+                    character: 0,
+                },
+            },
+            parent: Some(Box::new(conflict)),
+        },
+        None => conflict,
+    }
+}
+
+fn conflict_as_code_actions(
+    region: &ConflictRegion,
+    uri: &lsp_types::Uri,
+    document: &FullTextDocument,
+    current_conflict: &MergeConflict,
+    sides: &GitSides,
+    config: &Config,
+) -> Vec<lsp_types::CodeAction> {
+    let content = document.get_content(None);
+    let range = range_for_conflict_edit(content, region);
+
+    let diagnostic = current_conflict.diagnostic(
+        content,
+        region,
+        &config.diagnostic_source,
+        config.diagnostic_severity.into(),
+        &sides.names,
+        &config.labels,
+    );
+    let labels = &config.labels;
+    let ours = current_conflict
+        .ours_name(region, &sides.names, labels)
+        .unwrap_or("HEAD");
+    let theirs = current_conflict
+        .theirs_name(region, &sides.names, labels)
+        .unwrap_or("branch");
+    let prefer = |resolution: Resolution, action: lsp_types::CodeAction| {
+        preferred_if(action, config.default_strategy == Some(resolution))
+    };
+    // Only the ancestor can be missing; a resolution the conflict cannot take is left out.
+    let resolve_to = |title: String, resolution: Resolution| {
+        resolution::edits(content, region, resolution)
+            .map(|edits| make_code_action(title, uri, edits, diagnostic.clone()))
+    };
+    // Keeping an empty side applies the other branch's deletion of the block. The path each
+    // side had, when a rename left them different, and the commit a marker's name points to,
+    // when known, follow it.
+    let paths = region.renamed_paths();
+    let keep = |name: &str, path: Option<&str>, commit: Option<&CommitInfo>, empty: bool| {
+        let mut title = if empty {
+            format!("Apply deletion from {name}")
+        } else {
+            format!("Keep {name}")
+        };
+        if let Some(path) = path {
+            title.push_str(&format!(" in {path}"));
+        }
+        match commit {
+            Some(commit) => format!("{title} ({}: {})", commit.short_sha, commit.subject),
+            None => title,
+        }
+    };
+    // The sides of a submodule pointer conflict are commits of the submodule, named by their
+    // id rather than by the branch the marker names.
+    let submodule = region.submodule_commits(content);
+    let keep_commit = |name: &str, sha: &str| match sides.submodule_commits.get(sha) {
+        Some(commit) => format!("Keep {name} ({}: {})", commit.short_sha, commit.subject),
+        None => format!("Keep {name} ({})", sha.get(..7).unwrap_or(sha)),
+    };
+
+    let mut items = Vec::new();
+    if region.sides_identical(content) {
+        // Either side is right, so this comes first and is always preferred.
+        items.extend(
+            resolve_to("Resolve (sides identical)".to_string(), Resolution::Ours)
+                .map(|action| preferred_if(action, true)),
+        );
+    }
+    if let Some(side) = region.superset_side(content) {
+        // Keeping the side that already has the other change is almost always right.
+        let (name, resolution) = match side {
+            Superset::Ours => (ours, Resolution::Ours),
+            Superset::Theirs => (theirs, Resolution::Theirs),
+        };
+        items.extend(
+            resolve_to(
+                format!("Keep {name} (contains the other change)"),
+                resolution,
+            )
+            .map(|action| preferred_if(action, true)),
+        );
+    }
+    if region.sides_differ_only_in_whitespace(content) {
+        for (side, resolution) in [(ours, Resolution::Ours), (theirs, Resolution::Theirs)] {
+            items.extend(resolve_to(
+                format!("Resolve whitespace-only conflict (keep {side} formatting)"),
+                resolution,
+            ));
+        }
+    }
+    if let Some((ours_version, theirs_version)) =
+        merge::version_change(region.ours_text(content), region.theirs_text(content))
+        && let Some(ordering) = merge::compare_versions(ours_version, theirs_version)
+        && ordering.is_ne()
+    {
+        let ours_side = (ours_version, Resolution::Ours);
+        let theirs_side = (theirs_version, Resolution::Theirs);
+        let (higher, lower) = if ordering.is_gt() {
+            (ours_side, theirs_side)
+        } else {
+            (theirs_side, ours_side)
+        };
+        for (title, (version, resolution)) in [
+            ("Keep higher version", higher),
+            ("Keep lower version", lower),
+        ] {
+            items.extend(resolve_to(format!("{title} ({version})"), resolution));
+        }
+    }
+    if config.is_union_file(uri) {
+        items.push(make_code_action(
+            "Keep both, newest entries first".to_string(),
+            uri,
+            vec![lsp_types::TextEdit {
+                range,
+                new_text: merge::union_newest_first(
+                    region.ours_text(content),
+                    region.theirs_text(content),
+                ),
+            }],
+            diagnostic.clone(),
+        ));
+    }
+    items.extend(
+        [
+            (
+                match submodule {
+                    Some((sha, _)) => keep_commit(ours, sha),
+                    None => keep(
+                        ours,
+                        paths.map(|(head, _)| head),
+                        current_conflict
+                            .head_name_for(region)
+                            .and_then(|name| sides.commits.get(name)),
+                        region.ours_empty(),
+                    ),
+                },
+                Resolution::Ours,
+            ),
+            (
+                match submodule {
+                    Some((_, sha)) => keep_commit(theirs, sha),
+                    None => keep(
+                        theirs,
+                        paths.map(|(_, branch)| branch),
+                        current_conflict
+                            .branch_name_for(region)
+                            .and_then(|name| sides.commits.get(name)),
+                        region.theirs_empty(),
+                    ),
+                },
+                Resolution::Theirs,
+            ),
+            ("Keep both".to_string(), Resolution::Both),
+        ]
+        .into_iter()
+        .filter_map(|(title, resolution)| Some(prefer(resolution, resolve_to(title, resolution)?))),
+    );
+    items.push(make_code_action(
+        "Keep both with separator".to_string(),
+        uri,
+        vec![make_separated_text_edit(
+            document,
+            range,
+            region.ours_text(content),
+            region.theirs_text(content),
+            &config.both_separator,
+        )],
+        diagnostic.clone(),
+    ));
+    if let Some(new_text) = merge::import_union(
+        document.language_id(),
+        region.ours_text(content),
+        region.theirs_text(content),
+    ) {
+        items.push(make_code_action(
+            "Merge imports (sorted union)".to_string(),
+            uri,
+            vec![lsp_types::TextEdit { range, new_text }],
+            diagnostic.clone(),
+        ));
+    }
+    if let Some(new_text) =
+        merge::union_deduplicated(region.ours_text(content), region.theirs_text(content))
+    {
+        items.push(make_code_action(
+            "Merge both (dedupe)".to_string(),
+            uri,
+            vec![lsp_types::TextEdit { range, new_text }],
+            diagnostic.clone(),
+        ));
+    }
+
+    if let Some(ancestor) = region.ancestor_text(content) {
+        if let Some(new_text) = merge::three_way(
+            ancestor,
+            region.ours_text(content),
+            region.theirs_text(content),
+        ) {
+            items.push(make_code_action(
+                "Merge automatically".to_string(),
                 uri,
-                edit,
+                vec![lsp_types::TextEdit { range, new_text }],
                 diagnostic.clone(),
+            ));
+        }
+
+        let ancestor = current_conflict
+            .ancestor_name_for(region)
+            .or(labels.ancestor.as_deref())
+            .unwrap_or("ancestor");
+        // diff3 conflicts can also keep the ancestor next to either side, or all three.
+        for (title, resolution) in [
+            (format!("Keep {ancestor}"), Resolution::Ancestor),
+            (
+                format!("Keep {ours} + {ancestor}"),
+                Resolution::OursAndAncestor,
+            ),
+            (
+                format!("Keep {ancestor} + {theirs}"),
+                Resolution::AncestorAndTheirs,
+            ),
+            ("Keep all three".to_string(), Resolution::All),
+        ] {
+            items.extend(resolve_to(title, resolution).map(|action| prefer(resolution, action)));
+        }
+    }
+
+    // Always the last item.
+    items.extend(
+        resolve_to("Drop all".to_string(), Resolution::Neither)
+            .map(|action| prefer(Resolution::Neither, action)),
+    );
+
+    tracing::info!(
+        "offering {} code action(s) for conflict at lines {}-{} in {:?}",
+        items.len(),
+        region.head,
+        region.end,
+        uri,
+    );
+    items
+}
+
+/// An action resolving `region` to just the lines of its sides that `range` selects.
+///
+/// `None` for an empty selection, one without any lines of the sides, or one covering the
+/// whole conflict. A selection ending at the start of a line does not include that line.
+fn selected_lines_code_action(
+    region: &ConflictRegion,
+    range: &lsp_types::Range,
+    uri: &lsp_types::Uri,
+    document: &FullTextDocument,
+    merge_conflict: &MergeConflict,
+    names: &SideNames,
+    config: &Config,
+) -> Option<lsp_types::CodeAction> {
+    if range.start == range.end {
+        return None;
+    }
+    let last = if range.end.character == 0 && range.end.line > range.start.line {
+        range.end.line - 1
+    } else {
+        range.end.line
+    };
+    let first = range.start.line.max(region.head);
+    let last = last.min(region.end);
+    if first > last || (first == region.head && last == region.end) {
+        return None;
+    }
+    let markers: Vec<u32> = conflict_marker_lines(region).collect();
+    let content = document.get_content(None);
+    let starts: Vec<usize> = lsp_lines(content)
+        .map(|(start, _)| start)
+        .skip(first as usize)
+        .take((last - first) as usize + 2)
+        .collect();
+    let new_text: String = (first..=last)
+        .zip(
+            starts
+                .iter()
+                .zip(starts.iter().skip(1).chain([&content.len()])),
+        )
+        .filter(|(line, _)| !markers.contains(line))
+        .map(|(_, (start, end))| &content[*start..*end])
+        .collect();
+    if new_text.is_empty() {
+        return None;
+    }
+    let diagnostic = merge_conflict.diagnostic(
+        content,
+        region,
+        &config.diagnostic_source,
+        config.diagnostic_severity.into(),
+        names,
+        &config.labels,
+    );
+    Some(make_code_action(
+        "Keep only the selected lines".to_string(),
+        uri,
+        vec![lsp_types::TextEdit {
+            range: range_for_conflict_edit(content, region),
+            new_text,
+        }],
+        diagnostic,
+    ))
+}
+
+/// Whether `range` covers any line of `region`. A selection ending at the start of a line does
+/// not include that line.
+fn touches_conflict(range: &lsp_types::Range, region: &ConflictRegion) -> bool {
+    let last = if range.end.character == 0 && range.end.line > range.start.line {
+        range.end.line - 1
+    } else {
+        range.end.line
+    };
+    region.head <= last && region.end >= range.start.line
+}
+
+/// Actions applying the same choice to every selected region in one edit, followed by each
+/// region's own actions.
+fn selected_conflicts_as_code_actions(
+    regions: &[&ConflictRegion],
+    uri: &lsp_types::Uri,
+    document: &FullTextDocument,
+    merge_conflict: &MergeConflict,
+    sides: &GitSides,
+    config: &Config,
+) -> Vec<lsp_types::CodeAction> {
+    let count = regions.len();
+    let mut items: Vec<lsp_types::CodeAction> = combined_code_actions(
+        regions,
+        &format!("in all {count} selected conflicts"),
+        uri,
+        document.get_content(None),
+        merge_conflict,
+        &sides.names,
+        config,
+    )
+    .into_iter()
+    .map(|(resolution, action)| preferred_if(action, config.default_strategy == Some(resolution)))
+    .collect();
+    for region in regions {
+        // The same titles repeat for every conflict, the line tells them apart. Only the
+        // combined action is preferred.
+        items.extend(
+            conflict_as_code_actions(region, uri, document, merge_conflict, sides, config)
+                .into_iter()
+                .map(|action| lsp_types::CodeAction {
+                    title: format!("{} (line {})", action.title, region.head + 1),
+                    is_preferred: None,
+                    ..action
+                }),
+        );
+    }
+
+    tracing::info!(
+        "offering {} code action(s) for {} selected conflicts in {:?}",
+        items.len(),
+        count,
+        uri,
+    );
+    items
+}
+
+/// Actions resolving every conflict in the document the same way, e.g. in a regenerated
+/// lockfile. Never preferred, so "fix all" commands stay with the single conflict.
+fn file_conflicts_as_code_actions(
+    uri: &lsp_types::Uri,
+    content: &str,
+    merge_conflict: &MergeConflict,
+    names: &SideNames,
+    config: &Config,
+) -> Vec<lsp_types::CodeAction> {
+    let regions: Vec<&ConflictRegion> = merge_conflict.resolvable().collect();
+    combined_code_actions(
+        &regions,
+        &format!("in all {} conflicts in file", regions.len()),
+        uri,
+        content,
+        merge_conflict,
+        names,
+        config,
+    )
+    .into_iter()
+    .map(|(_, action)| action)
+    .collect()
+}
+
+/// The `source.fixAll` action: every conflict resolved with the configured default strategy.
+///
+/// Conflicts without the side the strategy keeps, e.g. an ancestor without diff3 markers, are
+/// left alone. `None` without a default strategy or anything to resolve.
+fn fix_all_code_action(
+    uri: &lsp_types::Uri,
+    content: &str,
+    merge_conflict: &MergeConflict,
+    names: &SideNames,
+    config: &Config,
+) -> Option<lsp_types::CodeAction> {
+    let resolution = config.default_strategy?;
+    let (edits, diagnostics): (Vec<_>, Vec<_>) = merge_conflict
+        .resolvable()
+        .filter_map(|region| {
+            let edits = resolution::edits(content, region, resolution)?;
+            let diagnostic = merge_conflict.diagnostic(
+                content,
+                region,
+                &config.diagnostic_source,
+                config.diagnostic_severity.into(),
+                names,
+                &config.labels,
+            );
+            Some((edits, diagnostic))
+        })
+        .unzip();
+    if edits.is_empty() {
+        return None;
+    }
+    let mut action = make_multi_edit_code_action(
+        format!(
+            "Resolve {} conflict(s) with {}",
+            edits.len(),
+            resolution.name()
+        ),
+        uri,
+        edits.into_iter().flatten().collect(),
+        diagnostics,
+    );
+    action.kind = Some(lsp_types::CodeActionKind::new(FIX_ALL_KIND));
+    Some(action)
+}
+
+/// Keep ours, theirs or both in every region, with `scope` ending each title.
+fn combined_code_actions(
+    regions: &[&ConflictRegion],
+    scope: &str,
+    uri: &lsp_types::Uri,
+    content: &str,
+    merge_conflict: &MergeConflict,
+    names: &SideNames,
+    config: &Config,
+) -> Vec<(Resolution, lsp_types::CodeAction)> {
+    let diagnostics: Vec<lsp_types::Diagnostic> = regions
+        .iter()
+        .map(|region| {
+            merge_conflict.diagnostic(
+                content,
+                region,
+                &config.diagnostic_source,
+                config.diagnostic_severity.into(),
+                names,
+                &config.labels,
+            )
+        })
+        .collect();
+    let combined = |side: &str, resolution: Resolution| {
+        let edits = regions
+            .iter()
+            .filter_map(|region| resolution::edits(content, region, resolution))
+            .flatten()
+            .collect();
+        (
+            resolution,
+            make_multi_edit_code_action(
+                format!("Keep {side} {scope}"),
+                uri,
+                edits,
+                diagnostics.clone(),
+            ),
+        )
+    };
+    let ours = shared_name(
+        &regions
+            .iter()
+            .map(|region| {
+                names
+                    .ours
+                    .as_deref()
+                    .or(merge_conflict.head_name_for(region))
+            })
+            .collect::<Vec<_>>(),
+        config.labels.ours.as_deref(),
+        "HEAD",
+        "ours",
+    );
+    let theirs = shared_name(
+        &regions
+            .iter()
+            .map(|region| {
+                names
+                    .theirs
+                    .as_deref()
+                    .or(merge_conflict.branch_name_for(region))
+            })
+            .collect::<Vec<_>>(),
+        config.labels.theirs.as_deref(),
+        "branch",
+        "theirs",
+    );
+    vec![
+        combined(ours, Resolution::Ours),
+        combined(theirs, Resolution::Theirs),
+        combined("both", Resolution::Both),
+    ]
+}
+
+/// The name of a side in several conflicts, from `names`, the one each conflict gives it. When
+/// they have none, `label` or else `default`; when they disagree, `generic`.
+fn shared_name<'a>(
+    names: &[Option<&'a str>],
+    label: Option<&'a str>,
+    default: &'a str,
+    generic: &'a str,
+) -> &'a str {
+    match names.first() {
+        Some(first) if names.iter().any(|name| name != first) => generic,
+        first => first.copied().flatten().or(label).unwrap_or(default),
+    }
+}
+
+/// The position just past the last character of `text`, in UTF-16.
+fn end_of_text(text: &str) -> lsp_types::Position {
+    if text.ends_with(['\r', '\n']) {
+        lsp_types::Position {
+            line: u32::try_from(lsp_lines(text).count()).unwrap_or(u32::MAX),
+            character: 0,
+        }
+    } else {
+        let (line, (_, last)) = lsp_lines(text).enumerate().last().unwrap_or((0, (0, "")));
+        lsp_types::Position {
+            line: u32::try_from(line).unwrap_or(u32::MAX),
+            character: u32::try_from(last.encode_utf16().count()).unwrap_or(u32::MAX),
+        }
+    }
+}
+
+/// One line of `text`, including its line ending.
+fn nth_line(text: &str, line: usize) -> Option<&str> {
+    let mut starts = lsp_lines(text).map(|(start, _)| start).skip(line);
+    let start = starts.next()?;
+    let end = starts.next().unwrap_or(text.len());
+    Some(&text[start..end])
+}
+
+/// Keep both regions with a separator line between them.
+///
+/// The separator is only inserted when neither side already has it at the join,
+/// so keeping both sides never stacks up duplicate separators.
+fn make_separated_text_edit(
+    document: &FullTextDocument,
+    range: lsp_types::Range,
+    first: &str,
+    second: &str,
+    separator: &str,
+) -> lsp_types::TextEdit {
+    let ending = line_ending(document.get_content(None));
+    let first_ends_with_separator =
+        lsp_lines(first).last().map(|(_, line)| line) == Some(separator);
+    let second_starts_with_separator =
+        lsp_lines(second).next().map(|(_, line)| line) == Some(separator);
+    let new_text = if first_ends_with_separator || second_starts_with_separator {
+        format!("{first}{second}")
+    } else {
+        format!("{first}{separator}{ending}{second}")
+    };
+    lsp_types::TextEdit { range, new_text }
+}
+
+fn make_code_action(
+    title: String,
+    uri: &lsp_types::Uri,
+    edits: Vec<lsp_types::TextEdit>,
+    diagnostic: lsp_types::Diagnostic,
+) -> lsp_types::CodeAction {
+    make_multi_edit_code_action(title, uri, edits, vec![diagnostic])
+}
+
+/// Whether `action` is of one of the `only` kinds a client asked for. Kinds are hierarchical,
+/// so `source` also asks for `source.fixAll`.
+fn action_kind_matches(action: &lsp_types::CodeAction, only: &[lsp_types::CodeActionKind]) -> bool {
+    let Some(kind) = &action.kind else {
+        return false;
+    };
+    only.iter().any(|wanted| {
+        kind.as_str()
+            .strip_prefix(wanted.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+/// Mark `action` as the one to apply for "fix all" style commands when `preferred`.
+fn preferred_if(mut action: lsp_types::CodeAction, preferred: bool) -> lsp_types::CodeAction {
+    if preferred {
+        action.is_preferred = Some(true);
+    }
+    action
+}
+
+/// A code action applying several non-overlapping edits to one document in a single step.
+fn make_multi_edit_code_action(
+    title: String,
+    uri: &lsp_types::Uri,
+    edits: Vec<lsp_types::TextEdit>,
+    diagnostics: Vec<lsp_types::Diagnostic>,
+) -> lsp_types::CodeAction {
+    lsp_types::CodeAction {
+        title,
+        kind: Some(lsp_types::CodeActionKind::QUICKFIX),
+        diagnostics: Some(diagnostics),
+        edit: Some(lsp_types::WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use crate::test_helpers::{
+        TEXT1_WITH_CONFLICTS, TEXT2_RESOLVED, TEXT2_WITH_CONFLICTS, apply_edits,
+        conflicts_for_text2_with_conflicts, populated_state,
+    };
+    use crate::{conflict_text, diff3_conflict_text};
+
+    use super::*;
+    use crate::parser::{MarkerBytes, parse};
+
+    #[fixture]
+    fn uri() -> lsp_types::Uri {
+        "file://foo.txt".parse().unwrap()
+    }
+
+    #[fixture]
+    fn version(#[default(0)] value: i32) -> i32 {
+        value
+    }
+
+    /// The actions offered for the first conflict in `text`, with default settings.
+    fn conflict_actions(
+        uri: &lsp_types::Uri,
+        language_id: &str,
+        text: &str,
+    ) -> Vec<lsp_types::CodeAction> {
+        let document = FullTextDocument::new(language_id.to_string(), 0, text.to_string());
+        let merge_conflict = parse(text).unwrap().unwrap();
+        conflict_as_code_actions(
+            &merge_conflict.conflicts[0],
+            uri,
+            &document,
+            &merge_conflict,
+            &GitSides::default(),
+            &Config::default(),
+        )
+    }
+
+    /// `text` after applying `action`'s edits to it.
+    fn applied(uri: &lsp_types::Uri, text: &str, action: &lsp_types::CodeAction) -> String {
+        #[allow(clippy::mutable_key_type)]
+        let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+        apply_edits(text, &changes[uri])
+    }
+
+    /// `text` after applying the action titled `title` for its first conflict, or `None` when
+    /// no such action is offered.
+    fn apply_action(
+        uri: &lsp_types::Uri,
+        language_id: &str,
+        text: &str,
+        title: &str,
+    ) -> Option<String> {
+        conflict_actions(uri, language_id, text)
+            .iter()
+            .find(|action| action.title == title)
+            .map(|action| applied(uri, text, action))
+    }
+
+    #[rstest]
+    fn on_document_update_when_document_without_conflicts_opened_no_conflicts_returned(
+        uri: lsp_types::Uri,
+        #[with(2, TEXT2_RESOLVED, None)] populated_state: ServerState,
+    ) {
+        let result = populated_state.on_document_update(&uri, 3);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(3, locked_document_state.version());
+        let conflict = result.unwrap();
+        assert!(conflict.is_none());
+    }
+
+    #[rstest]
+    fn on_document_update_when_document_has_conflicts_previously_but_not_last_generation_changed_no_conflicts_returned(
+        uri: lsp_types::Uri,
+        #[with(2, TEXT2_RESOLVED)] populated_state: ServerState,
+    ) {
+        let result = populated_state.on_document_update(&uri, 3);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(3, locked_document_state.version());
+        assert!(
+            locked_document_state.merge_conflict.is_none(),
+            "{:?}",
+            locked_document_state.merge_conflict
+        );
+        let conflict = result.unwrap();
+        assert!(conflict.is_none());
+    }
+
+    #[rstest]
+    fn on_document_update_version_missed_no_conflicts_returned(
+        uri: lsp_types::Uri,
+        #[with(6, TEXT2_RESOLVED)] populated_state: ServerState,
+    ) {
+        let result = populated_state.on_document_update(&uri, 3);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(6, locked_document_state.version());
+        assert!(locked_document_state.merge_conflict.is_none());
+        let conflict = result.unwrap();
+        assert!(conflict.is_none());
+    }
+
+    #[rstest]
+    fn on_document_update_version_initial_version_with_no_conflicts_no_conflicts_returned(
+        uri: lsp_types::Uri,
+        #[with(0, TEXT2_RESOLVED, None)] populated_state: ServerState,
+    ) {
+        let result = populated_state.on_document_update(&uri, 0);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(0, locked_document_state.version());
+        assert!(locked_document_state.merge_conflict.is_none());
+        let conflict = result.unwrap();
+        assert!(conflict.is_none());
+    }
+
+    #[rstest]
+    fn on_document_update_version_initial_version_with_conflicts_returns_conflicts(
+        uri: lsp_types::Uri,
+        #[with(0, TEXT2_WITH_CONFLICTS, None)] populated_state: ServerState,
+    ) {
+        let result = populated_state.on_document_update(&uri, 0);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(0, locked_document_state.version());
+        let merge_conflict = conflicts_for_text2_with_conflicts();
+        assert_eq!(
+            Some(merge_conflict.clone()),
+            locked_document_state.merge_conflict
+        );
+        let conflict = result.unwrap().unwrap();
+        assert_eq!(merge_conflict, conflict);
+    }
+
+    #[rstest]
+    fn on_document_update_when_document_has_conflicts_previously_and_is_resolved_returns_no_conflicts(
+        uri: lsp_types::Uri,
+        #[with(2, TEXT2_RESOLVED, Some(conflicts_for_text2_with_conflicts()))]
+        populated_state: ServerState,
+    ) {
+        let result = populated_state.on_document_update(&uri, 3);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(3, locked_document_state.version());
+        assert_eq!(locked_document_state.merge_conflict, None);
+        let conflict = result.unwrap();
+        assert!(conflict.is_none());
+    }
+
+    #[rstest]
+    fn on_document_update_when_document_has_conflicts_returns_conflicts(
+        uri: lsp_types::Uri,
+        #[with(2, TEXT2_WITH_CONFLICTS, None)] populated_state: ServerState,
+    ) {
+        let result = populated_state.on_document_update(&uri, 3);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(3, locked_document_state.version());
+        assert_eq!(
+            Some(conflicts_for_text2_with_conflicts()),
+            locked_document_state.merge_conflict
+        );
+        let conflict = result.unwrap().unwrap();
+        assert_eq!(conflicts_for_text2_with_conflicts(), conflict);
+    }
+
+    #[rstest]
+    fn on_document_update_when_document_has_conflicts_and_change_affecting_them_updated_returns_conflicts(
+        uri: lsp_types::Uri,
+        #[with(2, &format!("new text\n{}", TEXT2_WITH_CONFLICTS), Some(conflicts_for_text2_with_conflicts()))]
+        populated_state: ServerState,
+    ) {
+        let result = populated_state.on_document_update(&uri, 3);
+        let documents = populated_state.documents.lock().unwrap();
+        let document_state = documents.get(&uri).unwrap();
+        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
+        assert_eq!(3, locked_document_state.version());
+        let merge_conflict = MergeConflict {
+            head: None,
+            branch: None,
+            ancestor: None,
+            conflicts: vec![
+                ConflictRegion {
+                    head: 3,
+                    branch: 5,
+                    end: 7,
+                    last_char: 7,
+                    ancestor: None,
+                    ancestor_label: None,
+                    yours: None,
+                    depth: 0,
+                    marker_size: 7,
+                    head_path: None,
+                    branch_path: None,
+                    head_label: None,
+                    branch_label: None,
+                    bytes: MarkerBytes {
+                        head: 23..31,
+                        ancestor: None,
+                        branch: 41..49,
+                        yours: None,
+                        end: 66..74,
+                    },
+                },
+                ConflictRegion {
+                    head: 9,
+                    branch: 11,
+                    end: 13,
+                    last_char: 7,
+                    ancestor: None,
+                    ancestor_label: None,
+                    yours: None,
+                    depth: 0,
+                    marker_size: 7,
+                    head_path: None,
+                    branch_path: None,
+                    head_label: None,
+                    branch_label: None,
+                    bytes: MarkerBytes {
+                        head: 80..88,
+                        ancestor: None,
+                        branch: 109..117,
+                        yours: None,
+                        end: 129..137,
+                    },
+                },
+            ],
+            warnings: Vec::new(),
+        };
+        assert_eq!(
+            Some(merge_conflict.clone()),
+            locked_document_state.merge_conflict
+        );
+        let conflict = result.unwrap().unwrap();
+        assert_eq!(merge_conflict, conflict);
+    }
+
+    #[rstest]
+    fn separated_edit_places_separator_once_between_sides() {
+        let document = FullTextDocument::new(String::new(), 0, TEXT1_WITH_CONFLICTS.to_string());
+        let merge_conflict = parse(TEXT1_WITH_CONFLICTS).unwrap().unwrap();
+        let region = &merge_conflict.conflicts[0];
+        let edit = make_separated_text_edit(
+            &document,
+            range_for_conflict_edit(document.get_content(None), region),
+            region.ours_text(document.get_content(None)),
+            region.theirs_text(document.get_content(None)),
+            "# >>> incoming",
+        );
+        assert_eq!(
+            "plain old\n# >>> incoming\nnew and improved\n",
+            edit.new_text
+        );
+        assert_eq!(1, edit.new_text.matches("# >>> incoming").count());
+    }
+
+    #[rstest]
+    fn separated_edit_does_not_duplicate_existing_separator() {
+        let text = conflict_text!("ours\n# >>> incoming", "theirs");
+        let document = FullTextDocument::new(String::new(), 0, text.to_string());
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let region = &merge_conflict.conflicts[0];
+        let edit = make_separated_text_edit(
+            &document,
+            range_for_conflict_edit(document.get_content(None), region),
+            region.ours_text(document.get_content(None)),
+            region.theirs_text(document.get_content(None)),
+            "# >>> incoming",
+        );
+        assert_eq!("ours\n# >>> incoming\ntheirs\n", edit.new_text);
+    }
+
+    #[rstest]
+    #[case::commit_subject(
+        conflict_text!("HEAD", "ours", "1a2b3c4 (Fix the widget)", "theirs"),
+        "Keep HEAD",
+        "Keep 1a2b3c4"
+    )]
+    #[case::path_ref(
+        conflict_text!("ours:path/to/file", "ours", "theirs:path/to/file", "theirs"),
+        "Keep ours",
+        "Keep theirs"
+    )]
+    fn code_action_titles_use_short_labels(
+        uri: lsp_types::Uri,
+        #[case] text: &str,
+        #[case] head_title: &str,
+        #[case] branch_title: &str,
+    ) {
+        let actions = conflict_actions(&uri, "", text);
+        assert_eq!(head_title, actions[0].title);
+        assert_eq!(branch_title, actions[1].title);
+    }
+
+    #[rstest]
+    #[case::ancestor("Keep base", "base\n")]
+    #[case::ours_and_ancestor("Keep HEAD + base", "ours\nbase\n")]
+    #[case::ancestor_and_theirs("Keep base + feature", "base\ntheirs\n")]
+    #[case::all("Keep all three", "ours\nbase\ntheirs\n")]
+    fn diff3_conflicts_offer_ancestor_combinations(
+        uri: lsp_types::Uri,
+        #[case] title: &str,
+        #[case] new_text: &str,
+    ) {
+        let text = diff3_conflict_text!("HEAD", "ours", "base", "base", "feature", "theirs");
+        assert_eq!(
+            Some(new_text),
+            apply_action(&uri, "", text, title).as_deref()
+        );
+        let actions = conflict_actions(&uri, "", text);
+        assert_eq!("Drop all", actions.last().unwrap().title);
+    }
+
+    #[rstest]
+    #[case::within_ours((2, 0), (3, 2), Some("ours 0\nours 1\n"))]
+    #[case::ending_at_line_start((3, 0), (4, 0), Some("ours 1\n"))]
+    #[case::across_markers((3, 1), (7, 1), Some("ours 1\nbase\ntheirs 0\n"))]
+    #[case::empty((2, 1), (2, 1), None)]
+    #[case::only_a_marker((4, 0), (4, 7), None)]
+    #[case::whole_conflict((1, 0), (9, 7), None)]
+    fn selected_lines_become_the_resolution(
+        uri: lsp_types::Uri,
+        #[case] start: (u32, u32),
+        #[case] end: (u32, u32),
+        #[case] expected: Option<&str>,
+    ) {
+        let text = concat!(
+            "before\n",
+            diff3_conflict_text!(
+                "HEAD",
+                "ours 0\nours 1",
+                "base",
+                "base",
+                "feature",
+                "theirs 0\ntheirs 1"
+            ),
+            "after\n"
+        );
+        let document = FullTextDocument::new(String::new(), 0, text.to_string());
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let range = lsp_types::Range {
+            start: lsp_types::Position {
+                line: start.0,
+                character: start.1,
+            },
+            end: lsp_types::Position {
+                line: end.0,
+                character: end.1,
+            },
+        };
+        let actions = conflicts_as_code_actions(
+            &[0],
+            &range,
+            &uri,
+            &document,
+            &merge_conflict,
+            &GitSides::default(),
+            &Config::default(),
+        );
+        let selected = actions
+            .iter()
+            .find(|action| action.title == "Keep only the selected lines")
+            .map(|action| {
+                #[allow(clippy::mutable_key_type)]
+                let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+                changes[&uri][0].new_text.as_str()
+            });
+        assert_eq!(expected, selected);
+    }
+
+    #[rstest]
+    fn submodule_pointer_conflicts_keep_a_commit(uri: lsp_types::Uri) {
+        let text = concat!(
+            "<<<<<<< HEAD\n",
+            "Subproject commit a1b2c3d4e5f60718293a4b5c6d7e8f9012345678\n",
+            "=======\n",
+            "Subproject commit d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8091a2b3c4d\n",
+            ">>>>>>> feature\n",
+        );
+        let document = FullTextDocument::new(String::new(), 0, text.to_string());
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let sides = GitSides {
+            submodule_commits: HashMap::from([(
+                "d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8091a2b3c4d".to_owned(),
+                CommitInfo {
+                    short_sha: "d4e5f6a".to_owned(),
+                    author: "Test".to_owned(),
+                    date: "2024-01-01".to_owned(),
+                    subject: "Bump the widget".to_owned(),
+                },
+            )]),
+            ..Default::default()
+        };
+        let actions = conflicts_as_code_actions(
+            &[0],
+            &lsp_types::Range::default(),
+            &uri,
+            &document,
+            &merge_conflict,
+            &sides,
+            &Config::default(),
+        );
+        let titles: Vec<_> = actions.iter().map(|action| action.title.as_str()).collect();
+        assert!(titles.contains(&"Keep HEAD (a1b2c3d)"));
+        assert!(titles.contains(&"Keep feature (d4e5f6a: Bump the widget)"));
+    }
+
+    #[rstest]
+    fn renamed_sides_name_their_path(uri: lsp_types::Uri) {
+        let text = concat!(
+            "<<<<<<< HEAD:src/old_name.rs\n",
+            "ours\n",
+            "=======\n",
+            "theirs\n",
+            ">>>>>>> feature:src/new_name.rs\n",
+        );
+        let document = FullTextDocument::new(String::new(), 0, text.to_string());
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let actions = conflicts_as_code_actions(
+            &[0],
+            &lsp_types::Range::default(),
+            &uri,
+            &document,
+            &merge_conflict,
+            &GitSides::default(),
+            &Config::default(),
+        );
+        let titles: Vec<_> = actions.iter().map(|action| action.title.as_str()).collect();
+        assert!(titles.contains(&"Keep HEAD in src/old_name.rs"));
+        assert!(titles.contains(&"Keep feature in src/new_name.rs"));
+    }
+
+    #[rstest]
+    #[case::outer(0, 0)]
+    #[case::inner(1, 4)]
+    fn nested_conflicts_are_resolved_inside_out(
+        uri: lsp_types::Uri,
+        #[case] index: usize,
+        #[case] expected: usize,
+    ) {
+        let text = concat!(
+            "<<<<<<< HEAD\n",
+            "ours\n",
+            "||||||| merged common ancestors\n",
+            "<<<<<<< Temporary merge branch 1\n",
+            "base 1\n",
+            "=======\n",
+            "base 2\n",
+            ">>>>>>> Temporary merge branch 2\n",
+            "=======\n",
+            "theirs\n",
+            ">>>>>>> feature\n",
+        );
+        let document = FullTextDocument::new(String::new(), 0, text.to_string());
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let actions = conflicts_as_code_actions(
+            &[index],
+            &lsp_types::Range::default(),
+            &uri,
+            &document,
+            &merge_conflict,
+            &GitSides::default(),
+            &Config::default(),
+        );
+        let keeps = actions
+            .iter()
+            .filter(|action| action.title.starts_with("Keep"))
+            .count();
+        assert_eq!(expected, keeps);
+    }
+
+    #[rstest]
+    #[case::quickfix(vec![lsp_types::CodeActionKind::QUICKFIX], true)]
+    #[case::source(vec![lsp_types::CodeActionKind::SOURCE], false)]
+    #[case::refactor(vec![lsp_types::CodeActionKind::REFACTOR], false)]
+    #[case::several(
+        vec![lsp_types::CodeActionKind::SOURCE, lsp_types::CodeActionKind::QUICKFIX],
+        true
+    )]
+    fn code_actions_are_filtered_by_requested_kinds(
+        uri: lsp_types::Uri,
+        #[with(0, TEXT2_WITH_CONFLICTS, Some(conflicts_for_text2_with_conflicts()))]
+        populated_state: ServerState,
+        #[case] only: Vec<lsp_types::CodeActionKind>,
+        #[case] offered: bool,
+    ) {
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 3,
+                    character: 0,
+                },
+                end: lsp_types::Position {
+                    line: 3,
+                    character: 0,
+                },
+            },
+            context: lsp_types::CodeActionContext {
+                only: Some(only),
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = populated_state.code_action(params).unwrap();
+        assert_eq!(offered, !actions.is_empty());
+    }
+
+    #[rstest]
+    #[case::ours("merge", 1)]
+    #[case::other_source("rust-analyzer", 0)]
+    fn code_action_diagnostics_pick_the_conflict(
+        uri: lsp_types::Uri,
+        #[with(0, TEXT2_WITH_CONFLICTS, Some(conflicts_for_text2_with_conflicts()))]
+        populated_state: ServerState,
+        #[case] source: &str,
+        #[case] expected: usize,
+    ) {
+        let merge_conflict = parse(TEXT2_WITH_CONFLICTS).unwrap().unwrap();
+        let diagnostic = lsp_types::Diagnostic {
+            source: Some(source.to_owned()),
+            ..merge_conflict.diagnostic(
+                TEXT2_WITH_CONFLICTS,
+                &merge_conflict.conflicts[1],
+                "merge",
+                lsp_types::DiagnosticSeverity::ERROR,
+                &SideNames::default(),
+                &SideLabels::default(),
             )
-        },
-        {
-            let edit = make_text_edit(
-                document,
-                range,
-                &[region.head_range(), region.branch_range()],
+        };
+        // The range only touches the first conflict's closing marker.
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 6,
+                    character: 0,
+                },
+                end: lsp_types::Position {
+                    line: 6,
+                    character: 0,
+                },
+            },
+            context: lsp_types::CodeActionContext {
+                diagnostics: vec![diagnostic],
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = populated_state.code_action(params).unwrap();
+        #[allow(clippy::mutable_key_type)]
+        let changes = actions[0].edit.as_ref().unwrap().changes.as_ref().unwrap();
+        let edit_line = changes.values().next().unwrap()[0].range.start.line;
+        assert_eq!(merge_conflict.conflicts[expected].head, edit_line);
+    }
+
+    #[rstest]
+    #[case::no_strategy(None, None)]
+    #[case::theirs(
+        Some(Resolution::Theirs),
+        Some((
+            "Resolve 2 conflict(s) with theirs",
+            "\nThis is some\nnew and improved\ntext.\nCool stuff.\n\nFinal text\n"
+        ))
+    )]
+    #[case::no_ancestors(Some(Resolution::Ancestor), None)]
+    fn fix_all_resolves_every_conflict_with_the_default_strategy(
+        uri: lsp_types::Uri,
+        #[case] default_strategy: Option<Resolution>,
+        #[case] expected: Option<(&str, &str)>,
+    ) {
+        let state = populated_state(
+            0,
+            TEXT2_WITH_CONFLICTS,
+            Some(conflicts_for_text2_with_conflicts()),
+        );
+        state
+            .set_config(Config {
+                default_strategy,
+                ..Default::default()
+            })
+            .unwrap();
+        // Outside any conflict, as a "fix all on save" request would be.
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri: uri.clone() },
+            range: lsp_types::Range::default(),
+            context: lsp_types::CodeActionContext {
+                only: Some(vec![lsp_types::CodeActionKind::SOURCE_FIX_ALL]),
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = state.code_action(params).unwrap();
+        let fix_all = actions.first().map(|action| {
+            assert_eq!(
+                Some(lsp_types::CodeActionKind::new(FIX_ALL_KIND)),
+                action.kind
             );
-            make_code_action("Keep both".to_string(), uri, edit, diagnostic.clone())
-        },
-    ];
+            #[allow(clippy::mutable_key_type)]
+            let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+            (
+                action.title.as_str(),
+                apply_edits(TEXT2_WITH_CONFLICTS, &changes[&uri]),
+            )
+        });
+        assert_eq!(
+            expected,
+            fix_all
+                .as_ref()
+                .map(|(title, text)| (*title, text.as_str()))
+        );
+        assert!(actions.len() <= 1);
+    }
+
+    #[rstest]
+    #[case::exact("source.fixAll", "source.fixAll", true)]
+    #[case::parent("source.fixAll", "source", true)]
+    #[case::child("source", "source.fixAll", false)]
+    #[case::shared_prefix("sourceful", "source", false)]
+    fn action_kinds_match_hierarchically(
+        #[case] kind: &str,
+        #[case] wanted: &str,
+        #[case] expected: bool,
+    ) {
+        let action = lsp_types::CodeAction {
+            kind: Some(lsp_types::CodeActionKind::from(kind.to_owned())),
+            ..Default::default()
+        };
+        assert_eq!(
+            expected,
+            action_kind_matches(
+                &action,
+                &[lsp_types::CodeActionKind::from(wanted.to_owned())]
+            )
+        );
+    }
+
+    #[rstest]
+    fn identical_sides_offer_a_preferred_resolution_first(uri: lsp_types::Uri) {
+        let text = concat!("before\n", conflict_text!("OURS", "same", "THEIRS", "same"));
+        let actions = conflict_actions(&uri, "", text);
+        assert_eq!("Resolve (sides identical)", actions[0].title);
+        assert_eq!(Some(true), actions[0].is_preferred);
+        assert_eq!("before\nsame\n", applied(&uri, text, &actions[0]));
+        assert_eq!("Keep OURS", actions[1].title);
+    }
+
+    #[rstest]
+    fn superset_side_is_offered_first(uri: lsp_types::Uri) {
+        let text = conflict_text!("OURS", "use a;\nuse b;", "THEIRS", "use a;");
+        let actions = conflict_actions(&uri, "", text);
+        assert_eq!("Keep OURS (contains the other change)", actions[0].title);
+        assert_eq!(Some(true), actions[0].is_preferred);
+        assert_eq!("use a;\nuse b;\n", applied(&uri, text, &actions[0]));
+    }
+
+    #[rstest]
+    fn empty_side_is_offered_as_a_deletion(uri: lsp_types::Uri) {
+        let text = "before\n<<<<<<< OURS\nfn old() {}\n=======\n>>>>>>> THEIRS\nafter\n";
+        let actions = conflict_actions(&uri, "", text);
+        let titles: Vec<_> = actions[..2].iter().map(|a| a.title.as_str()).collect();
+        assert_eq!(vec!["Keep OURS", "Apply deletion from THEIRS"], titles);
+        assert_eq!("before\nafter\n", applied(&uri, text, &actions[1]));
+    }
+
+    #[rstest]
+    #[case::yours("Keep YOURS", "before\nours\nafter\n")]
+    #[case::theirs("Keep THEIRS", "before\ntheirs\nafter\n")]
+    #[case::original("Keep ORIGINAL", "before\nbase\nafter\n")]
+    #[case::both("Keep both", "before\nours\ntheirs\nafter\n")]
+    fn perforce_conflicts_keep_the_chosen_side(
+        uri: lsp_types::Uri,
+        #[case] title: &str,
+        #[case] expected: &str,
+    ) {
+        let text = concat!(
+            "before\n",
+            ">>>> ORIGINAL //depot/notes.txt#1\n",
+            "base\n",
+            "==== THEIRS //depot/notes.txt#2\n",
+            "theirs\n",
+            "==== YOURS //client/notes.txt\n",
+            "ours\n",
+            "<<<<\n",
+            "after\n",
+        );
+        assert_eq!(
+            Some(expected),
+            apply_action(&uri, "", text, title).as_deref()
+        );
+    }
+
+    #[rstest]
+    #[case::ours(
+        0,
+        "Resolve whitespace-only conflict (keep OURS formatting)",
+        "\tvalue\n"
+    )]
+    #[case::theirs(
+        1,
+        "Resolve whitespace-only conflict (keep THEIRS formatting)",
+        "    value\n"
+    )]
+    fn whitespace_only_conflicts_offer_either_formatting(
+        uri: lsp_types::Uri,
+        #[case] index: usize,
+        #[case] title: &str,
+        #[case] expected: &str,
+    ) {
+        let text = conflict_text!("OURS", "\tvalue", "THEIRS", "    value");
+        let actions = conflict_actions(&uri, "", text);
+        assert_eq!(title, actions[index].title);
+        assert_eq!(expected, applied(&uri, text, &actions[index]));
+        assert_eq!("Keep OURS", actions[2].title);
+    }
+
+    #[rstest]
+    fn merge_both_keeps_shared_lines_once(uri: lsp_types::Uri) {
+        let text = concat!(
+            "[\n",
+            conflict_text!(
+                "HEAD",
+                "  \"a\",\n  \"b\",",
+                "feature",
+                "  \"a\",\n  \"c\","
+            ),
+            "]\n"
+        );
+        assert_eq!(
+            Some("[\n  \"a\",\n  \"b\",\n  \"c\",\n]\n"),
+            apply_action(&uri, "", text, "Merge both (dedupe)").as_deref()
+        );
+    }
+
+    #[rstest]
+    #[case::separate_lines("x = 1\ny = 1", "x = 2\ny = 1", "x = 1\ny = 2", Some("x = 2\ny = 2\n"))]
+    #[case::same_line("x = 1", "x = 2", "x = 3", None)]
+    fn merge_automatically_is_offered_when_clean(
+        uri: lsp_types::Uri,
+        #[case] ancestor: &str,
+        #[case] ours: &str,
+        #[case] theirs: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let text = format!(
+            "<<<<<<< HEAD\n{ours}\n||||||| base\n{ancestor}\n=======\n{theirs}\n>>>>>>> feature\n"
+        );
+        assert_eq!(
+            expected,
+            apply_action(&uri, "", &text, "Merge automatically").as_deref()
+        );
+    }
+
+    #[rstest]
+    fn import_conflicts_offer_a_sorted_union(uri: lsp_types::Uri) {
+        let text = concat!(
+            "use std::io;\n",
+            conflict_text!(
+                "HEAD",
+                "use crate::b;",
+                "feature",
+                "use crate::a;\nuse crate::b;"
+            ),
+            "\nfn main() {}\n"
+        );
+        assert_eq!(
+            Some("use std::io;\nuse crate::a;\nuse crate::b;\n\nfn main() {}\n"),
+            apply_action(&uri, "rust", text, "Merge imports (sorted union)").as_deref()
+        );
+    }
+
+    #[rstest]
+    fn union_files_offer_newest_entries_first() {
+        let uri: lsp_types::Uri = "file:///project/CHANGELOG.md".parse().unwrap();
+        let text = concat!(
+            "# Changelog\n",
+            conflict_text!(
+                "HEAD",
+                "## 0.3.0\n- Fix parsing",
+                "feature",
+                "## 0.4.0\n- Add lenses"
+            ),
+            "## 0.2.0\n"
+        );
+        assert_eq!(
+            Some("# Changelog\n## 0.4.0\n- Add lenses\n## 0.3.0\n- Fix parsing\n## 0.2.0\n"),
+            apply_action(&uri, "markdown", text, "Keep both, newest entries first").as_deref()
+        );
+    }
+
+    #[rstest]
+    #[case::higher(0, "Keep higher version (2.0.0)", "{\n  \"version\": \"2.0.0\",\n}\n")]
+    #[case::lower(1, "Keep lower version (1.4.1)", "{\n  \"version\": \"1.4.1\",\n}\n")]
+    fn version_bumps_offer_higher_and_lower(
+        uri: lsp_types::Uri,
+        #[case] index: usize,
+        #[case] title: &str,
+        #[case] expected: &str,
+    ) {
+        let text = concat!(
+            "{\n",
+            conflict_text!(
+                "HEAD",
+                "  \"version\": \"2.0.0\",",
+                "feature",
+                "  \"version\": \"1.4.1\","
+            ),
+            "}\n"
+        );
+        let actions = conflict_actions(&uri, "json", text);
+        assert_eq!(title, actions[index].title);
+        assert_eq!(expected, applied(&uri, text, &actions[index]));
+    }
+
+    #[rstest]
+    fn drop_all_removes_the_whole_conflict_block(uri: lsp_types::Uri) {
+        let text = concat!(
+            "before\n",
+            diff3_conflict_text!("HEAD", "ours", "base", "base", "feature", "theirs"),
+            "after\n"
+        );
+        let actions = conflict_actions(&uri, "", text);
+        let drop_all = actions.last().unwrap();
+        assert_eq!("Drop all", drop_all.title);
+        #[allow(clippy::mutable_key_type)]
+        let changes = drop_all.edit.as_ref().unwrap().changes.as_ref().unwrap();
+        let edit = &changes[&uri][0];
+        // Every marker and all three sides go, leaving the surrounding lines.
+        assert_eq!("", edit.new_text);
+        assert_eq!(
+            lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 1,
+                    character: 0
+                },
+                end: lsp_types::Position {
+                    line: 8,
+                    character: 0
+                },
+            },
+            edit.range
+        );
+    }
+
+    #[rstest]
+    #[case::none(None, None)]
+    #[case::theirs(Some(Resolution::Theirs), Some(1))]
+    #[case::neither(Some(Resolution::Neither), Some(4))]
+    fn default_strategy_marks_its_action_preferred(
+        uri: lsp_types::Uri,
+        #[case] default_strategy: Option<Resolution>,
+        #[case] expected: Option<usize>,
+    ) {
+        let document = FullTextDocument::new(String::new(), 0, TEXT1_WITH_CONFLICTS.to_string());
+        let merge_conflict = parse(TEXT1_WITH_CONFLICTS).unwrap().unwrap();
+        let config = Config {
+            default_strategy,
+            diagnostic_severity: crate::config::Severity::Warning,
+            ..Default::default()
+        };
+        let actions = conflict_as_code_actions(
+            &merge_conflict.conflicts[0],
+            &uri,
+            &document,
+            &merge_conflict,
+            &GitSides::default(),
+            &config,
+        );
+        let preferred: Vec<usize> = actions
+            .iter()
+            .enumerate()
+            .filter(|(_, action)| action.is_preferred == Some(true))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(expected.into_iter().collect::<Vec<_>>(), preferred);
+        let diagnostic = &actions[0].diagnostics.as_ref().unwrap()[0];
+        assert_eq!(
+            Some(lsp_types::DiagnosticSeverity::WARNING),
+            diagnostic.severity
+        );
+    }
+
+    #[rstest]
+    fn documents_over_the_size_limit_are_not_parsed() {
+        let config = Config {
+            max_file_size: TEXT1_WITH_CONFLICTS.len() - 1,
+            ..Default::default()
+        };
+        let document_state = DocumentState::new(String::new(), TEXT1_WITH_CONFLICTS.to_owned(), 0);
+        assert!(document_state.parse().unwrap().is_some());
+        assert_eq!(None, document_state.with_config(&config).parse().unwrap());
+    }
+
+    #[rstest]
+    fn documents_parsed_on_save_keep_their_conflicts_until_then() {
+        let config = Config {
+            max_file_size: 16,
+            large_files: crate::config::LargeFiles::OnSave,
+            ..Default::default()
+        };
+        let mut document_state =
+            DocumentState::new(String::new(), TEXT1_WITH_CONFLICTS.to_owned(), 0)
+                .with_config(&config);
+        assert!(document_state.defers_parse());
+        let parsed = document_state.process_update().unwrap();
+        assert!(parsed.is_some(), "parsed despite the size");
+
+        // Inside the first conflict, so the conflicts cannot simply be moved.
+        let position = lsp_types::Position::new(3, 0);
+        document_state
+            .apply_changes(
+                &[lsp_types::TextDocumentContentChangeEvent {
+                    range: Some(lsp_types::Range::new(position, position)),
+                    range_length: None,
+                    text: "new line\n".to_owned(),
+                }],
+                1,
+            )
+            .unwrap();
+        assert!(document_state.is_stale());
+        assert_eq!(parsed, *document_state.diagnosed_conflicts().unwrap());
+        // Edits are still computed from where the conflicts are now.
+        let current = document_state.current_conflicts().unwrap();
+        assert_ne!(parsed, *current);
+        assert_eq!(document_state.parse().unwrap(), *current);
+    }
+
+    #[rstest]
+    #[case::before_the_conflicts((1, 0), (1, 0), "A line\nand another\n", false, true)]
+    #[case::removed_line((0, 0), (1, 0), "", false, true)]
+    #[case::after_the_conflicts((13, 0), (14, 0), "", false, false)]
+    #[case::new_marker((1, 0), (1, 0), "=======\n", true, true)]
+    #[case::inside_a_conflict((3, 0), (3, 0), "x", true, true)]
+    fn edits_outside_conflicts_move_them(
+        #[case] start: (u32, u32),
+        #[case] end: (u32, u32),
+        #[case] text: &str,
+        #[case] stale: bool,
+        #[case] published: bool,
+    ) {
+        let mut document_state =
+            DocumentState::new(String::new(), TEXT2_WITH_CONFLICTS.to_owned(), 0);
+        document_state.process_update().unwrap();
+        document_state
+            .apply_changes(
+                &[lsp_types::TextDocumentContentChangeEvent {
+                    range: Some(lsp_types::Range::new(
+                        lsp_types::Position::new(start.0, start.1),
+                        lsp_types::Position::new(end.0, end.1),
+                    )),
+                    range_length: None,
+                    text: text.to_owned(),
+                }],
+                1,
+            )
+            .unwrap();
+        assert_eq!(stale, document_state.is_stale());
+        if !stale {
+            assert_eq!(
+                document_state.parse().unwrap(),
+                document_state.merge_conflict
+            );
+        }
+        // Moved conflicts are published once, like freshly parsed ones.
+        assert_eq!(
+            published,
+            document_state.process_update().unwrap().is_some()
+        );
+        assert_eq!(None, document_state.process_update().unwrap());
+    }
+
+    #[rstest]
+    fn queued_updates_of_a_document_coalesce() {
+        let mut queue = UpdateQueue::default();
+        let update = |version| QueuedUpdate {
+            version,
+            generation: version as u64,
+            queued: Instant::now(),
+        };
+        let (first, second): (lsp_types::Uri, lsp_types::Uri) = (
+            "file:///a.txt".parse().unwrap(),
+            "file:///b.txt".parse().unwrap(),
+        );
+        queue.push(first.clone(), update(1));
+        queue.push(second.clone(), update(2));
+        queue.push(first.clone(), update(3));
 
-    if let Some(ancestor_range) = region.ancestor_range() {
-        let edit = make_text_edit(document, range, &[ancestor_range]);
-        items.push(make_code_action(
-            as_string_with_default!("Keep {}", current_conflict.ancestor, "ancestor"),
-            uri,
-            edit,
-            diagnostic.clone(),
-        ));
+        let popped: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|(uri, update)| (uri, update.version))
+            .collect();
+        assert_eq!(vec![(first, 3), (second, 2)], popped);
     }
 
-    let edit = make_text_edit(document, range, &[]);
-    // Always the last item.
-    items.push(make_code_action(
-        "Drop all".to_string(),
-        uri,
-        edit,
-        diagnostic.clone(),
-    ));
+    #[rstest]
+    fn reconfiguring_does_not_hold_up_other_documents() {
+        let (sender, _receiver) = crossbeam_channel::unbounded::<lsp_server::Message>();
+        let state = ServerState::new(sender, Config::default());
+        let (busy, other): (lsp_types::Uri, lsp_types::Uri) = (
+            "file:///busy.txt".parse().unwrap(),
+            "file:///other.txt".parse().unwrap(),
+        );
+        for uri in [&busy, &other] {
+            state
+                .add_document(lsp_types::TextDocumentItem {
+                    uri: uri.clone(),
+                    language_id: String::new(),
+                    version: 0,
+                    text: TEXT1_WITH_CONFLICTS.to_owned(),
+                })
+                .unwrap();
+        }
+        let busy_document = Arc::clone(state.documents.lock().unwrap().get(&busy).unwrap());
+        let parsing = busy_document.lock().unwrap();
 
-    tracing::info!(
-        "offering {} code action(s) for conflict at lines {}-{} in {:?}",
-        items.len(),
-        region.head,
-        region.end,
-        uri,
-    );
-    items
-}
+        let reconfiguring = {
+            let state = state.clone();
+            thread::spawn(move || state.set_config(Config::default()))
+        };
+        thread::sleep(std::time::Duration::from_millis(50));
+        let (done, version) = crossbeam_channel::bounded(1);
+        {
+            let state = state.clone();
+            thread::spawn(move || done.send(state.document_version(&other).unwrap()));
+        }
+        let version = version.recv_timeout(std::time::Duration::from_secs(5));
 
-fn make_text_edit(
-    document: &FullTextDocument,
-    range: lsp_types::Range,
-    kept_regions: &[(u32, u32)],
-) -> lsp_types::TextEdit {
-    let content = document.get_content(None);
-    let mut lines: Vec<&str> = Vec::with_capacity(kept_regions.len());
-    for (start, end) in kept_regions {
-        let start = document.offset_at(lsp_types::Position {
-            // start is the marker, we want the content. Move down one line.
-            line: start + 1,
-            character: 0,
-        }) as usize;
-        let end = document.offset_at(lsp_types::Position {
-            line: *end,
-            character: 0,
-        }) as usize;
-        lines.push(&content[start..end]);
+        drop(parsing);
+        assert_eq!(2, reconfiguring.join().unwrap().unwrap().len());
+        assert_eq!(Ok(Some(0)), version);
     }
-    let new_text = lines.join("");
-    lsp_types::TextEdit { range, new_text }
-}
 
-fn make_code_action(
-    title: String,
-    uri: &lsp_types::Uri,
-    edit: lsp_types::TextEdit,
-    diagnostic: lsp_types::Diagnostic,
-) -> lsp_types::CodeAction {
-    let is_preferred = None;
-    lsp_types::CodeAction {
-        title,
-        is_preferred,
-        kind: Some(lsp_types::CodeActionKind::QUICKFIX),
-        diagnostics: Some(vec![diagnostic]),
-        edit: Some(lsp_types::WorkspaceEdit {
-            changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+    #[rstest]
+    #[case::path("file:///src/Cargo.lock", "toml")]
+    #[case::language("file:///repo/.git/COMMIT_EDITMSG", "git-commit")]
+    fn excluded_documents_are_not_tracked(#[case] uri: &str, #[case] language_id: &str) {
+        let (connection, _client) = lsp_server::Connection::memory();
+        let state = ServerState::new(
+            connection.sender,
+            Config {
+                exclude: vec!["**/*.lock".to_owned()],
+                exclude_languages: vec!["git-commit".to_owned()],
+                ..Default::default()
+            },
+        );
+        let uri: lsp_types::Uri = uri.parse().unwrap();
+        let result = state.add_document(lsp_types::TextDocumentItem {
+            uri: uri.clone(),
+            language_id: language_id.to_owned(),
+            version: 0,
+            text: TEXT1_WITH_CONFLICTS.to_owned(),
+        });
+        assert_eq!(None, result.unwrap());
+        assert!(!state.documents.lock().unwrap().contains_key(&uri));
+    }
+
+    #[rstest]
+    fn labels_replace_fallback_names_in_titles(uri: lsp_types::Uri) {
+        let text = conflict_text!("ours", "theirs");
+        let document = FullTextDocument::new(String::new(), 0, text.to_string());
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let labels = SideLabels {
+            ours: Some("Current change".to_owned()),
+            theirs: Some("Incoming change".to_owned()),
+            ancestor: None,
+        };
+        let config = Config {
+            labels: labels.clone(),
             ..Default::default()
-        }),
-        ..Default::default()
+        };
+        let actions = conflict_as_code_actions(
+            &merge_conflict.conflicts[0],
+            &uri,
+            &document,
+            &merge_conflict,
+            &GitSides::default(),
+            &config,
+        );
+        assert_eq!("Keep Current change", actions[0].title);
+        assert_eq!("Keep Incoming change", actions[1].title);
+        assert_eq!(
+            "merge conflict: Current change vs Incoming change",
+            actions[0].diagnostics.as_ref().unwrap()[0].message
+        );
+
+        let titles: Vec<_> = conflict_as_code_lenses(
+            0,
+            &merge_conflict.conflicts[0],
+            &uri,
+            &SideNames::default(),
+            &labels,
+        )
+        .into_iter()
+        .map(|lens| lens.command.unwrap().title)
+        .collect();
+        assert_eq!(
+            vec![
+                "Accept Current change",
+                "Accept Incoming change",
+                "Accept Both"
+            ],
+            titles
+        );
     }
-}
 
-#[cfg(test)]
-mod test {
-    use rstest::*;
+    #[rstest]
+    fn code_action_after_unparsed_edit_uses_current_offsets(
+        uri: lsp_types::Uri,
+        #[with(1, TEXT2_WITH_CONFLICTS, Some(conflicts_for_text2_with_conflicts()))]
+        populated_state: ServerState,
+    ) {
+        // Two new lines at the top shift the first conflict from lines 2-6 down to 4-8.
+        let insert_at_top = lsp_types::TextDocumentContentChangeEvent {
+            range: Some(lsp_types::Range::default()),
+            range_length: None,
+            text: "new\nlines\n".to_string(),
+        };
+        populated_state
+            .document_did_change(
+                lsp_types::VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 2,
+                },
+                vec![insert_at_top],
+            )
+            .unwrap();
 
-    use crate::test_helpers::{
-        TEXT2_RESOLVED, TEXT2_WITH_CONFLICTS, conflicts_for_text2_with_conflicts, populated_state,
-    };
+        let position = lsp_types::Position {
+            line: 4,
+            character: 0,
+        };
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: position,
+                end: position,
+            },
+            context: Default::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = populated_state.code_action(params).unwrap();
 
-    use super::*;
+        #[allow(clippy::mutable_key_type)]
+        let changes = actions[0].edit.as_ref().unwrap().changes.as_ref().unwrap();
+        let edits = changes.values().next().unwrap();
+        assert_eq!(4, edits[0].range.start.line);
+        assert_eq!(9, edits.last().unwrap().range.end.line);
+        let text = format!("new\nlines\n{TEXT2_WITH_CONFLICTS}");
+        assert_eq!(
+            text.replacen(
+                conflict_text!("plain old", "new and improved"),
+                "plain old\n",
+                1
+            ),
+            apply_edits(&text, edits)
+        );
+    }
 
-    #[fixture]
-    fn uri() -> lsp_types::Uri {
-        "file://foo.txt".parse().unwrap()
+    #[rstest]
+    fn separated_edit_keeps_carriage_return_line_endings() {
+        let text = TEXT1_WITH_CONFLICTS.replace('\n', "\r");
+        let document = FullTextDocument::new(String::new(), 0, text.clone());
+        let merge_conflict = parse(&text).unwrap().unwrap();
+        let region = &merge_conflict.conflicts[0];
+        let edit = make_separated_text_edit(
+            &document,
+            range_for_conflict_edit(document.get_content(None), region),
+            region.ours_text(document.get_content(None)),
+            region.theirs_text(document.get_content(None)),
+            "",
+        );
+        assert_eq!("plain old\r\rnew and improved\r", edit.new_text);
     }
 
-    #[fixture]
-    fn version(#[default(0)] value: i32) -> i32 {
-        value
+    #[rstest]
+    fn selection_across_conflicts_offers_combined_action(uri: lsp_types::Uri) {
+        let text = concat!(
+            "start\n",
+            conflict_text!("OURS", "first ours", "THEIRS", "first theirs"),
+            "\n",
+            conflict_text!("OURS", "second ours", "THEIRS", "second theirs"),
+            "end\n",
+        );
+        let state = populated_state(0, text, parse(text).unwrap());
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 2,
+                    character: 0,
+                },
+                end: lsp_types::Position {
+                    line: 9,
+                    character: 0,
+                },
+            },
+            context: Default::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = state.code_action(params).unwrap();
+
+        let keep_ours: Vec<_> = actions
+            .iter()
+            .filter(|action| action.title == "Keep OURS in all 2 selected conflicts")
+            .collect();
+        assert_eq!(1, keep_ours.len());
+        #[allow(clippy::mutable_key_type)]
+        let changes = keep_ours[0]
+            .edit
+            .as_ref()
+            .unwrap()
+            .changes
+            .as_ref()
+            .unwrap();
+        let edits = changes.values().next().unwrap();
+        assert_eq!(
+            "start\nfirst ours\n\nsecond ours\nend\n",
+            apply_edits(text, edits)
+        );
+        assert_eq!(2, keep_ours[0].diagnostics.as_ref().unwrap().len());
     }
 
     #[rstest]
-    fn on_document_update_when_document_without_conflicts_opened_no_conflicts_returned(
+    fn conflict_in_a_file_with_others_offers_file_wide_actions(
         uri: lsp_types::Uri,
-        #[with(2, TEXT2_RESOLVED, None)] populated_state: ServerState,
+        #[with(0, TEXT2_WITH_CONFLICTS, Some(conflicts_for_text2_with_conflicts()))]
+        populated_state: ServerState,
     ) {
-        let result = populated_state.on_document_update(&uri, 3);
-        let documents = populated_state.documents.lock().unwrap();
-        let document_state = documents.get(&uri).unwrap();
-        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
-        assert_eq!(3, locked_document_state.version());
-        let conflict = result.unwrap();
-        assert!(conflict.is_none());
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 3,
+                    character: 0,
+                },
+                end: lsp_types::Position {
+                    line: 3,
+                    character: 0,
+                },
+            },
+            context: Default::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = populated_state.code_action(params).unwrap();
+
+        let titles: Vec<_> = actions
+            .iter()
+            .skip_while(|action| action.title != "Drop all")
+            .skip(1)
+            .map(|action| action.title.as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "Keep HEAD in all 2 conflicts in file",
+                "Keep branch in all 2 conflicts in file",
+                "Keep both in all 2 conflicts in file",
+            ],
+            titles
+        );
+        let keep_theirs = &actions[actions.len() - 2];
+        assert_eq!(None, keep_theirs.is_preferred);
+        #[allow(clippy::mutable_key_type)]
+        let changes = keep_theirs.edit.as_ref().unwrap().changes.as_ref().unwrap();
+        assert_eq!(
+            "\nThis is some\nnew and improved\ntext.\nCool stuff.\n\nFinal text\n",
+            apply_edits(TEXT2_WITH_CONFLICTS, changes.values().next().unwrap())
+        );
     }
 
     #[rstest]
-    fn on_document_update_when_document_has_conflicts_previously_but_not_last_generation_changed_no_conflicts_returned(
-        uri: lsp_types::Uri,
-        #[with(2, TEXT2_RESOLVED)] populated_state: ServerState,
+    #[case::agreed(&[Some("main"), Some("main")], "main")]
+    #[case::unnamed(&[None, None], "HEAD")]
+    #[case::differing(&[Some("main"), Some("topic")], "ours")]
+    #[case::partly_named(&[Some("main"), None], "ours")]
+    fn combined_actions_name_a_side_only_when_its_conflicts_agree(
+        #[case] names: &[Option<&str>],
+        #[case] expected: &str,
     ) {
-        let result = populated_state.on_document_update(&uri, 3);
-        let documents = populated_state.documents.lock().unwrap();
-        let document_state = documents.get(&uri).unwrap();
-        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
-        assert_eq!(3, locked_document_state.version());
-        assert!(
-            locked_document_state.merge_conflict.is_none(),
-            "{:?}",
-            locked_document_state.merge_conflict
+        assert_eq!(expected, shared_name(names, None, "HEAD", "ours"));
+    }
+
+    #[rstest]
+    fn only_conflict_in_file_offers_no_file_wide_actions(uri: lsp_types::Uri) {
+        let text = concat!(
+            "start\n",
+            conflict_text!("OURS", "ours", "THEIRS", "theirs"),
+            "end\n"
         );
-        let conflict = result.unwrap();
-        assert!(conflict.is_none());
+        let state = populated_state(0, text, parse(text).unwrap());
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 2,
+                    character: 0,
+                },
+                end: lsp_types::Position {
+                    line: 2,
+                    character: 0,
+                },
+            },
+            context: Default::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = state.code_action(params).unwrap();
+        assert_eq!("Drop all", actions.last().unwrap().title);
     }
 
     #[rstest]
-    fn on_document_update_version_missed_no_conflicts_returned(
+    fn selection_across_distant_conflicts_offers_actions_for_each(
         uri: lsp_types::Uri,
-        #[with(6, TEXT2_RESOLVED)] populated_state: ServerState,
+        #[with(0, TEXT2_WITH_CONFLICTS, Some(conflicts_for_text2_with_conflicts()))]
+        populated_state: ServerState,
     ) {
-        let result = populated_state.on_document_update(&uri, 3);
-        let documents = populated_state.documents.lock().unwrap();
-        let document_state = documents.get(&uri).unwrap();
-        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
-        assert_eq!(6, locked_document_state.version());
-        assert!(locked_document_state.merge_conflict.is_none());
-        let conflict = result.unwrap();
-        assert!(conflict.is_none());
+        // The conflicts in TEXT2_WITH_CONFLICTS are separated by a line of text.
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 3,
+                    character: 0,
+                },
+                end: lsp_types::Position {
+                    line: 9,
+                    character: 0,
+                },
+            },
+            context: Default::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = populated_state.code_action(params).unwrap();
+        let titles: Vec<_> = actions.iter().map(|action| action.title.as_str()).collect();
+        assert_eq!(
+            vec![
+                "Keep HEAD in all 2 selected conflicts",
+                "Keep branch in all 2 selected conflicts",
+                "Keep both in all 2 selected conflicts",
+            ],
+            titles[..3]
+        );
+        assert!(titles.contains(&"Keep HEAD (line 3)"));
+        assert!(titles.contains(&"Drop all (line 9)"));
+        assert!(actions.iter().all(|action| action.is_preferred.is_none()));
     }
 
     #[rstest]
-    fn on_document_update_version_initial_version_with_no_conflicts_no_conflicts_returned(
-        uri: lsp_types::Uri,
-        #[with(0, TEXT2_RESOLVED, None)] populated_state: ServerState,
+    #[case(None, vec![(1, 12, 0), (1, 4, 1), (1, 12, 0), (1, 4, 3), (1, 7, 0), (1, 6, 2), (1, 15, 0)])]
+    #[case(
+        Some(lsp_types::Range {
+            start: lsp_types::Position { line: 4, character: 0 },
+            end: lsp_types::Position { line: 6, character: 2 },
+        }),
+        vec![(4, 4, 3), (1, 7, 0), (1, 6, 2)]
+    )]
+    fn semantic_tokens_classify_each_conflict_line(
+        #[case] range: Option<lsp_types::Range>,
+        #[case] expected: Vec<(u32, u32, u32)>,
     ) {
-        let result = populated_state.on_document_update(&uri, 0);
-        let documents = populated_state.documents.lock().unwrap();
-        let document_state = documents.get(&uri).unwrap();
-        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
-        assert_eq!(0, locked_document_state.version());
-        assert!(locked_document_state.merge_conflict.is_none());
-        let conflict = result.unwrap();
-        assert!(conflict.is_none());
+        let text = concat!(
+            "intro\n",
+            diff3_conflict_text!("HEAD", "ours", "base", "base", "feature", "theirs"),
+        );
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let tokens: Vec<_> =
+            conflict_semantic_tokens(text, &merge_conflict, range, PositionEncoding::Utf16)
+                .into_iter()
+                .map(|token| {
+                    assert_eq!(0, token.delta_start);
+                    (token.delta_line, token.length, token.token_type)
+                })
+                .collect();
+        assert_eq!(expected, tokens);
     }
 
     #[rstest]
-    fn on_document_update_version_initial_version_with_conflicts_returns_conflicts(
-        uri: lsp_types::Uri,
-        #[with(0, TEXT2_WITH_CONFLICTS, None)] populated_state: ServerState,
-    ) {
-        let result = populated_state.on_document_update(&uri, 0);
-        let documents = populated_state.documents.lock().unwrap();
-        let document_state = documents.get(&uri).unwrap();
-        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
-        assert_eq!(0, locked_document_state.version());
-        let merge_conflict = conflicts_for_text2_with_conflicts();
+    fn code_action_after_edit_next_to_astral_and_cjk_characters(uri: lsp_types::Uri) {
+        let text = crate::conflict_text!("OURS", "漢字😀ok", "THEIRS", "かな🎉");
+        let populated_state = populated_state(0, text, None);
+        // '漢' and '字' are one UTF-16 unit each and '😀' is two, so character 4 is just before
+        // "ok" even though that is byte 10.
+        let insert = lsp_types::TextDocumentContentChangeEvent {
+            range: Some(lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 1,
+                    character: 4,
+                },
+                end: lsp_types::Position {
+                    line: 1,
+                    character: 4,
+                },
+            }),
+            range_length: None,
+            text: "🚀".to_string(),
+        };
+        populated_state
+            .document_did_change(
+                lsp_types::VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: 1,
+                },
+                vec![insert],
+            )
+            .unwrap();
+
+        let position = lsp_types::Position {
+            line: 1,
+            character: 0,
+        };
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier { uri },
+            range: lsp_types::Range {
+                start: position,
+                end: position,
+            },
+            context: Default::default(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let actions = populated_state.code_action(params).unwrap();
+
+        let content = text.replacen("漢字😀ok", "漢字😀🚀ok", 1);
+        let new_texts: Vec<_> = actions
+            .iter()
+            .take(3)
+            .map(|action| {
+                #[allow(clippy::mutable_key_type)]
+                let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+                apply_edits(&content, changes.values().next().unwrap())
+            })
+            .collect();
         assert_eq!(
-            Some(merge_conflict.clone()),
-            locked_document_state.merge_conflict
+            vec!["漢字😀🚀ok\n", "かな🎉\n", "漢字😀🚀ok\nかな🎉\n"],
+            new_texts
         );
-        let conflict = result.unwrap().unwrap();
-        assert_eq!(merge_conflict, conflict);
     }
 
     #[rstest]
-    fn on_document_update_when_document_has_conflicts_previously_and_is_resolved_returns_no_conflicts(
-        uri: lsp_types::Uri,
-        #[with(2, TEXT2_RESOLVED, Some(conflicts_for_text2_with_conflicts()))]
-        populated_state: ServerState,
+    #[case::utf8(PositionEncoding::Utf8, 6)]
+    #[case::utf16(PositionEncoding::Utf16, 3)]
+    #[case::utf32(PositionEncoding::Utf32, 2)]
+    fn changes_are_applied_in_position_encoding(
+        #[case] encoding: PositionEncoding,
+        #[case] character: u32,
     ) {
-        let result = populated_state.on_document_update(&uri, 3);
-        let documents = populated_state.documents.lock().unwrap();
-        let document_state = documents.get(&uri).unwrap();
-        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
-        assert_eq!(3, locked_document_state.version());
-        assert_eq!(locked_document_state.merge_conflict, None);
-        let conflict = result.unwrap();
-        assert!(conflict.is_none());
+        let mut document_state =
+            DocumentState::new(String::new(), "é😀x\n".to_owned(), 0).with_encoding(encoding);
+        let position = lsp_types::Position { line: 0, character };
+        document_state
+            .apply_changes(
+                &[
+                    lsp_types::TextDocumentContentChangeEvent {
+                        range: Some(lsp_types::Range {
+                            start: position,
+                            end: position,
+                        }),
+                        range_length: None,
+                        text: "Y".to_owned(),
+                    },
+                    // Positions of a later change refer to the text after the first one.
+                    lsp_types::TextDocumentContentChangeEvent {
+                        range: Some(lsp_types::Range {
+                            start: position,
+                            end: lsp_types::Position {
+                                line: 0,
+                                character: character + 1,
+                            },
+                        }),
+                        range_length: None,
+                        text: "Z".to_owned(),
+                    },
+                ],
+                1,
+            )
+            .unwrap();
+        assert_eq!("é😀Zx\n", document_state.content());
     }
 
     #[rstest]
-    fn on_document_update_when_document_has_conflicts_returns_conflicts(
-        uri: lsp_types::Uri,
-        #[with(2, TEXT2_WITH_CONFLICTS, None)] populated_state: ServerState,
+    #[case::past_the_end((9, 0), (9, 4), Ok("😀x\nyZ"))]
+    #[case::inside_a_character((0, 1), (0, 1), Ok("😀Zx\ny"))]
+    #[case::past_the_end_of_a_line((0, 9), (1, 0), Ok("😀x\nZy"))]
+    #[case::reversed((1, 0), (0, 0), Err(()))]
+    fn malformed_changes_do_not_panic(
+        #[case] start: (u32, u32),
+        #[case] end: (u32, u32),
+        #[case] expected: Result<&str, ()>,
     ) {
-        let result = populated_state.on_document_update(&uri, 3);
-        let documents = populated_state.documents.lock().unwrap();
-        let document_state = documents.get(&uri).unwrap();
-        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
-        assert_eq!(3, locked_document_state.version());
+        let mut document_state = DocumentState::new(String::new(), "😀x\ny".to_owned(), 0);
+        let position = |(line, character)| lsp_types::Position { line, character };
+        let result = document_state.apply_changes(
+            &[lsp_types::TextDocumentContentChangeEvent {
+                range: Some(lsp_types::Range {
+                    start: position(start),
+                    end: position(end),
+                }),
+                range_length: None,
+                text: "Z".to_owned(),
+            }],
+            1,
+        );
         assert_eq!(
-            Some(conflicts_for_text2_with_conflicts()),
-            locked_document_state.merge_conflict
+            expected,
+            result.map(|()| document_state.content()).map_err(|_| ())
         );
-        let conflict = result.unwrap().unwrap();
-        assert_eq!(conflicts_for_text2_with_conflicts(), conflict);
     }
 
     #[rstest]
-    fn on_document_update_when_document_has_conflicts_and_change_affecting_them_updated_returns_conflicts(
-        uri: lsp_types::Uri,
-        #[with(2, &format!("new text\n{}", TEXT2_WITH_CONFLICTS), Some(conflicts_for_text2_with_conflicts()))]
-        populated_state: ServerState,
-    ) {
-        let result = populated_state.on_document_update(&uri, 3);
-        let documents = populated_state.documents.lock().unwrap();
-        let document_state = documents.get(&uri).unwrap();
-        let locked_document_state = document_state.lock().expect("poisoned mutex: {e}");
-        assert_eq!(3, locked_document_state.version());
-        let merge_conflict = MergeConflict {
-            head: None,
-            branch: None,
-            ancestor: None,
-            conflicts: vec![
-                ConflictRegion {
-                    head: 3,
-                    branch: 5,
-                    end: 7,
-                    ancestor: None,
-                },
-                ConflictRegion {
-                    head: 9,
-                    branch: 11,
-                    end: 13,
-                    ancestor: None,
+    fn client_support_defaults_to_nothing() {
+        assert_eq!(
+            ClientSupport::default(),
+            ClientSupport::from_capabilities(&serde_json::json!({}))
+        );
+    }
+
+    #[rstest]
+    fn client_support_is_read_from_capabilities() {
+        let capabilities = serde_json::json!({
+            "workspace": {
+                "applyEdit": true,
+                "configuration": true,
+                "diagnostics": { "refreshSupport": true },
+                "workspaceEdit": { "resourceOperations": ["create", "delete"] }
+            },
+            "window": { "showMessage": {} },
+            "textDocument": {
+                "codeAction": {
+                    "dynamicRegistration": true,
+                    "resolveSupport": { "properties": ["edit"] }
                 },
-            ],
-        };
+                "codeLens": { "dynamicRegistration": true }
+            }
+        });
         assert_eq!(
-            Some(merge_conflict.clone()),
-            locked_document_state.merge_conflict
+            ClientSupport {
+                // Semantic tokens and diagnostics are not dynamically registrable here.
+                dynamic_registration: false,
+                resolve_code_action_edits: true,
+                diagnostic_refresh: true,
+                show_message_request: true,
+                configuration: true,
+                apply_edit: true,
+                delete_files: true,
+                position_encoding: PositionEncoding::Utf16,
+            },
+            ClientSupport::from_capabilities(&capabilities)
         );
-        let conflict = result.unwrap().unwrap();
-        assert_eq!(merge_conflict, conflict);
+    }
+
+    #[rstest]
+    fn semantic_tokens_skip_blank_lines() {
+        let text = conflict_text!("ours\n", "theirs");
+        let merge_conflict = parse(text).unwrap().unwrap();
+        let lines: Vec<_> =
+            conflict_semantic_tokens(text, &merge_conflict, None, PositionEncoding::Utf16)
+                .into_iter()
+                .scan(0, |line, token| {
+                    *line += token.delta_line;
+                    Some(*line)
+                })
+                .collect();
+        assert_eq!(vec![0, 1, 3, 4, 5], lines);
     }
 }
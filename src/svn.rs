@@ -0,0 +1,111 @@
+//! Subversion's companion files: the versions it leaves next to a conflicted file.
+//!
+//! After `svn update` the file's own changes are in `file.mine` and the ancestor and their
+//! version in `file.rOLD` and `file.rNEW`. After `svn merge` they are `file.working`,
+//! `file.merge-left.rOLD` and `file.merge-right.rNEW`. Only those files are read; `svn` itself
+//! is never run.
+
+use std::path::{Path, PathBuf};
+
+use crate::git::Stages;
+
+/// The versions Subversion left next to `path`, in the shape of git's index stages. `None`
+/// when there are none.
+pub fn conflict_files(path: &Path) -> anyhow::Result<Option<Stages>> {
+    let (Some(dir), Some(name)) = (
+        path.parent(),
+        path.file_name().and_then(|name| name.to_str()),
+    ) else {
+        return Ok(None);
+    };
+    let mut ours = None;
+    let mut merge_left = None;
+    let mut merge_right = None;
+    let mut revisions: Vec<(u64, PathBuf)> = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(suffix) = file_name
+            .to_str()
+            .and_then(|file_name| file_name.strip_prefix(name))
+            .and_then(|suffix| suffix.strip_prefix('.'))
+        else {
+            continue;
+        };
+        if suffix == "mine" || suffix == "working" {
+            ours = Some(entry.path());
+        } else if let Some(revision) = suffix.strip_prefix("merge-left.").and_then(revision) {
+            merge_left = Some((revision, entry.path()));
+        } else if let Some(revision) = suffix.strip_prefix("merge-right.").and_then(revision) {
+            merge_right = Some((revision, entry.path()));
+        } else if let Some(revision) = revision(suffix) {
+            revisions.push((revision, entry.path()));
+        }
+    }
+    revisions.sort();
+    // With two revisions the older one is the ancestor, the newer one theirs.
+    let (base, theirs) = match (merge_left, merge_right, revisions.as_slice()) {
+        (left, right, _) if left.is_some() || right.is_some() => (left, right),
+        (_, _, [old, .., new]) => (Some(old.clone()), Some(new.clone())),
+        (_, _, [new]) => (None, Some(new.clone())),
+        (_, _, []) => (None, None),
+    };
+    if ours.is_none() && base.is_none() && theirs.is_none() {
+        return Ok(None);
+    }
+    let read = |path: Option<PathBuf>| path.map(std::fs::read_to_string).transpose();
+    Ok(Some(Stages {
+        base: read(base.map(|(_, path)| path))?,
+        ours: read(ours)?,
+        theirs: read(theirs.map(|(_, path)| path))?,
+    }))
+}
+
+/// The number of an `r1234` suffix.
+fn revision(suffix: &str) -> Option<u64> {
+    suffix.strip_prefix('r')?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::*;
+
+    use super::*;
+
+    /// A directory holding `notes.txt` and the given companion files, each containing its suffix.
+    fn working_copy(name: &str, suffixes: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mca-svn-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), "conflicted\n").unwrap();
+        std::fs::write(dir.join("notes.txt.orig"), "unrelated\n").unwrap();
+        for suffix in suffixes {
+            std::fs::write(dir.join(format!("notes.txt.{suffix}")), suffix).unwrap();
+        }
+        dir
+    }
+
+    #[rstest]
+    #[case::update("update", &["mine", "r9", "r10"], Some(("r9", "mine", "r10")))]
+    #[case::merge(
+        "merge",
+        &["working", "merge-left.r3", "merge-right.r7"],
+        Some(("merge-left.r3", "working", "merge-right.r7"))
+    )]
+    #[case::none("none", &[], None)]
+    fn conflict_files_finds_each_version(
+        #[case] name: &str,
+        #[case] suffixes: &[&str],
+        #[case] expected: Option<(&str, &str, &str)>,
+    ) {
+        let dir = working_copy(name, suffixes);
+        let files = conflict_files(&dir.join("notes.txt"));
+        let _ = std::fs::remove_dir_all(&dir);
+        let expected = expected.map(|(base, ours, theirs)| Stages {
+            base: Some(base.to_owned()),
+            ours: Some(ours.to_owned()),
+            theirs: Some(theirs.to_owned()),
+        });
+        assert_eq!(expected, files.unwrap());
+    }
+}
@@ -4,8 +4,9 @@ use crossbeam_channel::unbounded;
 use rstest::*;
 
 use crate::{
+    config::Config,
     conflict_text,
-    parser::{ConflictRegion, MergeConflict},
+    parser::{ConflictRegion, MarkerBytes, MergeConflict},
     state::ServerState,
 };
 
@@ -57,7 +58,7 @@ pub fn state() -> ServerState {
         sender: writer_sender,
         receiver: reader_receiver,
     };
-    ServerState::new(connection.sender)
+    ServerState::new(connection.sender, Config::default())
 }
 
 #[fixture]
@@ -77,7 +78,7 @@ pub fn populated_state(
                 Some(conflict) => {
                     DocumentState::new_with_conflict(text.to_string(), version, conflict)
                 }
-                None => DocumentState::new(text.to_string(), version),
+                None => DocumentState::new(String::new(), text.to_string(), version),
             })),
         );
     }
@@ -96,21 +97,54 @@ pub fn conflicts_for_text2_with_conflicts() -> MergeConflict {
                 head: 2,
                 branch: 4,
                 end: 6,
+                last_char: 7,
                 ancestor: None,
+                ancestor_label: None,
+                yours: None,
+                depth: 0,
+                marker_size: 7,
+                head_path: None,
+                branch_path: None,
+                head_label: None,
+                branch_label: None,
+                bytes: MarkerBytes {
+                    head: 14..22,
+                    ancestor: None,
+                    branch: 32..40,
+                    yours: None,
+                    end: 57..65,
+                },
             },
             ConflictRegion {
                 head: 8,
                 branch: 10,
                 end: 12,
+                last_char: 7,
                 ancestor: None,
+                ancestor_label: None,
+                yours: None,
+                depth: 0,
+                marker_size: 7,
+                head_path: None,
+                branch_path: None,
+                head_label: None,
+                branch_label: None,
+                bytes: MarkerBytes {
+                    head: 71..79,
+                    ancestor: None,
+                    branch: 100..108,
+                    yours: None,
+                    end: 120..128,
+                },
             },
         ],
+        warnings: Vec::new(),
     }
 }
 
-///! Macros for assembling conflict marker text in tests without literal markers in source.
-///!
-///! Literal markers in `.rs` files would confuse the parser if it ever scanned its own source.
+// Macros for assembling conflict marker text in tests without literal markers in source.
+//
+// Literal markers in `.rs` files would confuse the parser if it ever scanned its own source.
 #[macro_export]
 macro_rules! conflict_text {
     ($head:expr, $branch:expr) => {
@@ -167,6 +201,125 @@ macro_rules! diff3_conflict_text {
     };
 }
 
+/// `text` with `edits` applied. Like a client applying a `WorkspaceEdit`, every edit's range
+/// refers to the original text.
+pub fn apply_edits(text: &str, edits: &[lsp_types::TextEdit]) -> String {
+    let mut document = lsp_textdocument::FullTextDocument::new(String::new(), 0, text.to_owned());
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+    let changes: Vec<_> = edits
+        .into_iter()
+        .map(|edit| lsp_types::TextDocumentContentChangeEvent {
+            range: Some(edit.range),
+            range_length: None,
+            text: edit.new_text,
+        })
+        .collect();
+    document.update(&changes, 1);
+    document.get_content(None).to_owned()
+}
+
+/// A fresh repository in the temp directory, named after `name`, stopped in a merge that
+/// conflicts in `notes.txt`. Callers remove it when done.
+pub fn conflicted_repository(name: &str) -> std::path::PathBuf {
+    diverged_repository(
+        name,
+        "ours\n",
+        Some("theirs\n"),
+        &["merge", "--quiet", "feature"],
+    )
+}
+
+/// Like [`conflicted_repository`], but stopped rebasing `feature` onto `main`.
+pub fn rebasing_repository(name: &str) -> std::path::PathBuf {
+    diverged_repository(
+        name,
+        "ours\n",
+        Some("theirs\n"),
+        &["rebase", "--quiet", "main", "feature"],
+    )
+}
+
+/// Like [`conflicted_repository`], but `feature` deleted `notes.txt`, so the merge stopped
+/// with a delete/modify conflict and our version in the work tree.
+pub fn delete_modify_repository(name: &str) -> std::path::PathBuf {
+    diverged_repository(name, "ours\n", None, &["merge", "--quiet", "feature"])
+}
+
+/// Like [`conflicted_repository`], but both sides made `notes.txt` binary, so the merge left
+/// our version in the work tree without markers.
+pub fn binary_conflict_repository(name: &str) -> std::path::PathBuf {
+    diverged_repository(
+        name,
+        "ours\0",
+        Some("theirs\0"),
+        &["merge", "--quiet", "feature"],
+    )
+}
+
+/// A repository whose `main` branch changes `notes.txt` to `ours` and whose `feature` branch
+/// changes it to `theirs`, or deletes the file, after running `git` with `conflicting` to bring
+/// them together.
+fn diverged_repository(
+    name: &str,
+    ours: &str,
+    theirs: Option<&str>,
+    conflicting: &[&str],
+) -> std::path::PathBuf {
+    let root = std::env::temp_dir().join(format!("mca-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let run = |args: &[&str]| {
+        std::process::Command::new("git")
+            .arg("-C")
+            .arg(&root)
+            .args([
+                "-c",
+                "user.name=Test",
+                "-c",
+                "user.email=test@example.com",
+                "-c",
+                "init.defaultBranch=main",
+            ])
+            .args(args)
+            .output()
+            .expect("git runs")
+    };
+    let git = |args: &[&str]| {
+        let output = run(args);
+        assert!(
+            output.status.success(),
+            "git {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    let commit = |text: &str, subject: &str| {
+        std::fs::write(root.join("notes.txt"), text).unwrap();
+        git(&["commit", "--quiet", "--all", "--message", subject]);
+    };
+    git(&["init", "--quiet"]);
+    std::fs::write(root.join("notes.txt"), "base\n").unwrap();
+    git(&["add", "notes.txt"]);
+    git(&["commit", "--quiet", "--message", "Add notes"]);
+    git(&["checkout", "--quiet", "-b", "feature"]);
+    match theirs {
+        Some(theirs) => commit(theirs, "Write theirs"),
+        None => {
+            git(&["rm", "--quiet", "notes.txt"]);
+            git(&["commit", "--quiet", "--message", "Delete notes"]);
+        }
+    }
+    git(&["checkout", "--quiet", "main"]);
+    commit(ours, "Write ours");
+    // Stopping on the conflict is what makes git exit with an error here.
+    let output = run(conflicting);
+    assert!(
+        !output.status.success(),
+        "git {conflicting:?} did not stop on a conflict"
+    );
+    root
+}
+
 #[allow(dead_code)]
 pub fn init_logging() {
     static ONCE: std::sync::Once = std::sync::Once::new();
@@ -0,0 +1,178 @@
+//! How the server talks to its client: stdio, a TCP socket, or a pipe.
+//!
+//! Stdio and TCP come from `lsp_server`. Pipes are connected here: a Unix domain socket
+//! on Unix and a named pipe on Windows. As with the VS Code client's pipe transport, the
+//! client creates the pipe and the server connects to it.
+
+use std::{
+    io::{self, BufReader, Read, Write},
+    path::PathBuf,
+    thread,
+};
+
+use anyhow::Context;
+use crossbeam_channel::bounded;
+use lsp_server::{Connection, IoThreads, Message};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Transport {
+    Stdio,
+    /// Listen on this localhost TCP port and serve the first client that connects.
+    Socket(u16),
+    /// Connect to the pipe the client created with this name.
+    Pipe(PathBuf),
+}
+
+/// The reader and writer threads behind a [`Connection`].
+pub enum Threads {
+    Lsp(IoThreads),
+    Pipe {
+        reader: thread::JoinHandle<io::Result<()>>,
+        writer: thread::JoinHandle<io::Result<()>>,
+    },
+}
+
+impl Threads {
+    /// Wait for both threads to finish once the connection closes.
+    pub fn join(self) -> io::Result<()> {
+        match self {
+            Self::Lsp(io_threads) => io_threads.join(),
+            Self::Pipe { reader, writer } => {
+                let reader = reader
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e));
+                let writer = writer
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e));
+                reader.and(writer)
+            }
+        }
+    }
+}
+
+pub fn connect(transport: &Transport) -> anyhow::Result<(Connection, Threads)> {
+    match transport {
+        Transport::Stdio => {
+            let (connection, io_threads) = Connection::stdio();
+            Ok((connection, Threads::Lsp(io_threads)))
+        }
+        Transport::Socket(port) => {
+            let address = (std::net::Ipv4Addr::LOCALHOST, *port);
+            tracing::info!("listening on {}:{port}", address.0);
+            let (connection, io_threads) = Connection::listen(address)
+                .with_context(|| format!("failed to accept a client on port {port}"))?;
+            Ok((connection, Threads::Lsp(io_threads)))
+        }
+        Transport::Pipe(name) => {
+            let pipe = open_pipe(name)
+                .with_context(|| format!("failed to connect to pipe '{}'", name.display()))?;
+            Ok(stream_transport(pipe.try_clone()?, pipe))
+        }
+    }
+}
+
+#[cfg(unix)]
+type Pipe = std::os::unix::net::UnixStream;
+
+#[cfg(windows)]
+type Pipe = std::fs::File;
+
+#[cfg(unix)]
+fn open_pipe(name: &std::path::Path) -> io::Result<Pipe> {
+    Pipe::connect(name)
+}
+
+#[cfg(windows)]
+fn open_pipe(name: &std::path::Path) -> io::Result<Pipe> {
+    const PIPE_PREFIX: &str = r"\\.\pipe\";
+    let path = if name.starts_with(PIPE_PREFIX) {
+        name.to_path_buf()
+    } else {
+        std::path::Path::new(PIPE_PREFIX).join(name)
+    };
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+}
+
+/// Run LSP messages over a byte stream on a reader and a writer thread, like `lsp_server`
+/// does for stdio.
+fn stream_transport(
+    reader: impl Read + Send + 'static,
+    mut writer: impl Write + Send + 'static,
+) -> (Connection, Threads) {
+    let (reader_sender, reader_receiver) = bounded::<Message>(0);
+    let reader = thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        while let Some(message) = Message::read(&mut reader)? {
+            let is_exit = matches!(
+                &message,
+                Message::Notification(notification)
+                    if notification.method
+                        == <lsp_types::notification::Exit as lsp_types::notification::Notification>::METHOD
+            );
+            if reader_sender.send(message).is_err() || is_exit {
+                break;
+            }
+        }
+        Ok(())
+    });
+    let (writer_sender, writer_receiver) = bounded::<Message>(0);
+    let writer = thread::spawn(move || {
+        writer_receiver
+            .into_iter()
+            .try_for_each(|message| message.write(&mut writer))
+    });
+    let connection = Connection {
+        sender: writer_sender,
+        receiver: reader_receiver,
+    };
+    (connection, Threads::Pipe { reader, writer })
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::os::unix::net::UnixListener;
+
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    fn pipe_transport_exchanges_messages() {
+        let path = std::env::temp_dir().join(format!("mca-pipe-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).expect("bind pipe");
+
+        let (connection, threads) =
+            connect(&Transport::Pipe(path.clone())).expect("connected to pipe");
+        let (mut client, _) = listener.accept().expect("server connected");
+
+        let request = lsp_server::Request::new(1.into(), "shutdown".to_owned(), ());
+        Message::from(request.clone())
+            .write(&mut client)
+            .expect("request sent");
+        match connection.receiver.recv().expect("request received") {
+            Message::Request(received) => assert_eq!(request.method, received.method),
+            other => panic!("unexpected message: {other:?}"),
+        }
+
+        let response = lsp_server::Response::new_ok(1.into(), ());
+        connection
+            .sender
+            .send(response.clone().into())
+            .expect("response queued");
+        let mut client_reader = BufReader::new(client.try_clone().unwrap());
+        match Message::read(&mut client_reader).expect("response read") {
+            Some(Message::Response(received)) => assert_eq!(response.id, received.id),
+            other => panic!("unexpected message: {other:?}"),
+        }
+
+        let exit = lsp_server::Notification::new("exit".to_owned(), ());
+        Message::from(exit).write(&mut client).expect("exit sent");
+        drop(connection);
+        threads.join().expect("threads finish cleanly");
+        let _ = std::fs::remove_file(&path);
+    }
+}